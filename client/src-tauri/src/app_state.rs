@@ -46,8 +46,15 @@ pub struct AppState {
     /// modifier is held — releasing the trigger key alone doesn't stop PTT.
     /// When false, releasing the trigger key immediately stops PTT.
     pub ptt_hold_mode: Arc<AtomicBool>,
+    /// Whisper key binding shared with the global key listener (None = unbound).
+    pub whisper_binding: Arc<std::sync::RwLock<Option<PttBinding>>>,
     /// Persistent user configuration (std Mutex — config saves are fast sync ops).
     pub config: std::sync::Mutex<crate::config::AppConfig>,
+    /// How to reconnect if the connection drops (None = don't).
+    pub reconnect: std::sync::Mutex<Option<ReconnectInfo>>,
+    /// Bumped by connect, disconnect and each reconnect run, so a stale
+    /// reconnect run notices it has been superseded and stops.
+    pub reconnect_generation: AtomicU64,
 }
 
 impl AppState {
@@ -59,11 +66,27 @@ impl AppState {
             signal: Arc::new(std::sync::Mutex::new(SignalState::default())),
             ptt_binding: Arc::new(std::sync::RwLock::new(PttBinding::default())),
             ptt_hold_mode: Arc::new(AtomicBool::new(true)),
+            whisper_binding: Arc::new(std::sync::RwLock::new(None)),
             config: std::sync::Mutex::new(crate::config::AppConfig::default()),
+            reconnect: std::sync::Mutex::new(None),
+            reconnect_generation: AtomicU64::new(0),
         }
     }
 }
 
+/// What the client remembers about a user-initiated connection so it can
+/// reconnect on its own and put the user back where they were.
+pub struct ReconnectInfo {
+    pub address: String,
+    pub username: String,
+    pub accept_invalid_certs: bool,
+    /// From the last `Authenticated`; lets the server hand back the same
+    /// session if we return within its grace period.
+    pub resume_token: Option<Vec<u8>>,
+    /// Passwords channels were joined with, for rejoining after a reconnect.
+    pub channel_passwords: HashMap<ChannelId, String>,
+}
+
 /// A message waiting for encryption to become available.
 pub struct PendingMessage {
     /// Channel message (channel_id) or direct message (target_user_id).
@@ -172,6 +195,8 @@ pub struct ActiveConnection {
     pub playback_stream: Option<voipc_audio::playback::PlaybackStream>,
     /// Token for authenticating UDP voice packets.
     pub udp_token: u64,
+    /// Optional features the server agreed to use on this connection.
+    pub capabilities: Capabilities,
     // ── Screen share state ──
     /// Whether this client is currently screen sharing.
     pub is_screen_sharing: bool,
@@ -211,6 +236,13 @@ pub struct ActiveConnection {
     pub current_media_key: Arc<std::sync::Mutex<Option<MediaKey>>>,
     /// Current channel ID — tracked for AAD construction in media encryption.
     pub current_channel_id: Arc<AtomicU32>,
+    // ── Whisper state ──
+    /// Whether the whisper key is held — voice goes to the whisper list instead of the channel.
+    pub whispering: Arc<AtomicBool>,
+    /// Whether the capture task was started by the whisper key (and should stop with it).
+    pub whisper_owns_capture: bool,
+    /// Whisper keys by sender session_id, including our own.
+    pub whisper_keys: Arc<std::sync::Mutex<HashMap<u32, MediaKey>>>,
     // ── Voice activation state ──
     /// Voice mode: 0 = PTT, 1 = VAD, 2 = Always On. Shared with capture task.
     pub voice_mode: Arc<AtomicU8>,
@@ -226,6 +258,18 @@ pub struct ActiveConnection {
     pub user_volumes: Arc<std::sync::Mutex<HashMap<u32, f32>>>,
 }
 
+impl ActiveConnection {
+    /// Fails with a message naming `feature` unless the server agreed to
+    /// `capabilities`.
+    pub fn require(&self, capabilities: Capabilities, feature: &str) -> Result<(), String> {
+        if self.capabilities.contains(capabilities) {
+            Ok(())
+        } else {
+            Err(format!("This server does not support {}", feature))
+        }
+    }
+}
+
 /// Voice activation mode.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
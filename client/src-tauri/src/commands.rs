@@ -5,9 +5,10 @@ use serde::Serialize;
 use tauri::{Emitter, State};
 
 use voipc_protocol::messages::ClientMessage;
+use voipc_protocol::types::{Capabilities, WhisperTarget};
 use voipc_protocol::voice::VoicePacket;
 
-use crate::app_state::{AppState, PendingMessage, PendingTarget};
+use crate::app_state::{AppState, PendingMessage, PendingTarget, ReconnectInfo};
 use crate::crypto::{self, ChatArchive, ChatMessage};
 use crate::network;
 use crate::screenshare;
@@ -41,19 +42,38 @@ pub async fn connect(
     // Install the ring crypto provider (idempotent — only the first call succeeds)
     let _ = rustls::crypto::ring::default_provider().install_default();
 
-    network::connect_to_server(
+    // Stop any reconnect in progress; this connection replaces it
+    state.reconnect_generation.fetch_add(1, Ordering::Relaxed);
+    *state.reconnect.lock().unwrap_or_else(|p| p.into_inner()) = None;
+
+    let accept_invalid_certs = accept_invalid_certs.unwrap_or(false);
+    let connected = network::connect_to_server(
         &state,
         app_handle,
+        address.clone(),
+        username.clone(),
+        accept_invalid_certs,
+        None,
+    )
+    .await?;
+
+    *state.reconnect.lock().unwrap_or_else(|p| p.into_inner()) = Some(ReconnectInfo {
         address,
         username,
-        accept_invalid_certs.unwrap_or(false),
-    )
-    .await
+        accept_invalid_certs,
+        resume_token: Some(connected.resume_token),
+        channel_passwords: HashMap::new(),
+    });
+    Ok(connected.user_id)
 }
 
 /// Disconnect from the current server.
 #[tauri::command]
 pub async fn disconnect(state: State<'_, AppState>) -> Result<(), String> {
+    // Also cancels a reconnect in progress
+    state.reconnect_generation.fetch_add(1, Ordering::Relaxed);
+    *state.reconnect.lock().unwrap_or_else(|p| p.into_inner()) = None;
+
     let mut conn = state.connection.write().await;
     if let Some(mut connection) = conn.take() {
         // Stop any ongoing voice capture
@@ -115,6 +135,12 @@ pub async fn join_channel(
 ) -> Result<(), String> {
     let mut conn = state.connection.write().await;
     let connection = conn.as_mut().ok_or("Not connected")?;
+    // Remembered for rejoining after a reconnect
+    if let Some(ref password) = password {
+        if let Some(info) = state.reconnect.lock().unwrap_or_else(|p| p.into_inner()).as_mut() {
+            info.channel_passwords.insert(channel_id, password.clone());
+        }
+    }
     network::send_tcp_message(
         &connection.tcp_tx,
         &ClientMessage::JoinChannel {
@@ -156,12 +182,15 @@ pub async fn join_channel(
     Ok(())
 }
 
-/// Create a new channel.
+/// Create a new channel, optionally as a sub-channel of `parent_id` and
+/// optionally permanent.
 #[tauri::command]
 pub async fn create_channel(
     state: State<'_, AppState>,
     name: String,
     password: Option<String>,
+    parent_id: Option<u32>,
+    permanent: Option<bool>,
 ) -> Result<(), String> {
     if name.is_empty() || name.len() > 128 {
         return Err("channel name must be 1-128 characters".into());
@@ -175,7 +204,12 @@ pub async fn create_channel(
     let connection = conn.as_ref().ok_or("Not connected")?;
     network::send_tcp_message(
         &connection.tcp_tx,
-        &ClientMessage::CreateChannel { name, password },
+        &ClientMessage::CreateChannel {
+            name,
+            password,
+            parent_id,
+            permanent: permanent.unwrap_or(false),
+        },
     )
     .await
 }
@@ -218,6 +252,126 @@ pub async fn kick_user(
     .await
 }
 
+/// Server-mute or unmute a user (requires Kick in their channel).
+#[tauri::command]
+pub async fn set_server_muted(
+    state: State<'_, AppState>,
+    user_id: u32,
+    muted: bool,
+) -> Result<(), String> {
+    let conn = state.connection.read().await;
+    let connection = conn.as_ref().ok_or("Not connected")?;
+    network::send_tcp_message(
+        &connection.tcp_tx,
+        &ClientMessage::SetServerMuted { user_id, muted },
+    )
+    .await
+}
+
+/// Grant or revoke priority speaker (requires Edit in the user's channel).
+#[tauri::command]
+pub async fn set_priority_speaker(
+    state: State<'_, AppState>,
+    user_id: u32,
+    enabled: bool,
+) -> Result<(), String> {
+    let conn = state.connection.read().await;
+    let connection = conn.as_ref().ok_or("Not connected")?;
+    network::send_tcp_message(
+        &connection.tcp_tx,
+        &ClientMessage::SetPrioritySpeaker { user_id, enabled },
+    )
+    .await
+}
+
+/// Move another user into a channel (requires Move in both channels).
+#[tauri::command]
+pub async fn move_user(
+    state: State<'_, AppState>,
+    user_id: u32,
+    channel_id: u32,
+) -> Result<(), String> {
+    let conn = state.connection.read().await;
+    let connection = conn.as_ref().ok_or("Not connected")?;
+    network::send_tcp_message(
+        &connection.tcp_tx,
+        &ClientMessage::MoveUser { user_id, channel_id },
+    )
+    .await
+}
+
+/// Change a channel's description and topic (requires Edit in the channel).
+#[tauri::command]
+pub async fn set_channel_topic(
+    state: State<'_, AppState>,
+    channel_id: u32,
+    description: String,
+    topic: String,
+) -> Result<(), String> {
+    if topic.len() > 256 {
+        return Err("topic too long".into());
+    }
+    if description.len() > 1024 {
+        return Err("description too long".into());
+    }
+    let conn = state.connection.read().await;
+    let connection = conn.as_ref().ok_or("Not connected")?;
+    network::send_tcp_message(
+        &connection.tcp_tx,
+        &ClientMessage::SetChannelTopic {
+            channel_id,
+            description,
+            topic,
+        },
+    )
+    .await
+}
+
+/// Register the current username to this device's identity key.
+#[tauri::command]
+pub async fn register_account(state: State<'_, AppState>) -> Result<(), String> {
+    let conn = state.connection.read().await;
+    let connection = conn.as_ref().ok_or("Not connected")?;
+    network::send_tcp_message(&connection.tcp_tx, &ClientMessage::RegisterAccount).await
+}
+
+/// Ban a connected user from the server (requires the ban permission).
+#[tauri::command]
+pub async fn ban_user(
+    state: State<'_, AppState>,
+    user_id: u32,
+    reason: String,
+    duration_secs: Option<u64>,
+) -> Result<(), String> {
+    let conn = state.connection.read().await;
+    let connection = conn.as_ref().ok_or("Not connected")?;
+    network::send_tcp_message(
+        &connection.tcp_tx,
+        &ClientMessage::BanUser {
+            user_id,
+            reason,
+            duration_secs,
+        },
+    )
+    .await
+}
+
+/// Remove a server ban by ID.
+#[tauri::command]
+pub async fn unban_user(state: State<'_, AppState>, ban_id: u32) -> Result<(), String> {
+    let conn = state.connection.read().await;
+    let connection = conn.as_ref().ok_or("Not connected")?;
+    network::send_tcp_message(&connection.tcp_tx, &ClientMessage::UnbanUser { ban_id }).await
+}
+
+/// Request the server's active ban list.
+#[tauri::command]
+pub async fn list_bans(state: State<'_, AppState>) -> Result<(), String> {
+    let conn = state.connection.read().await;
+    let connection = conn.as_ref().ok_or("Not connected")?;
+    network::send_tcp_message(&connection.tcp_tx, &ClientMessage::ListBans).await
+}
+
 /// Request the user list of a channel without joining it (preview).
 #[tauri::command]
 pub async fn request_channel_users(
@@ -584,7 +738,9 @@ pub(crate) async fn do_start_transmit(state: &AppState) -> Result<(), String> {
     let connection = conn.as_mut().ok_or("Not connected")?;
 
     if connection.transmitting.load(Ordering::Relaxed) {
-        return Ok(()); // Already transmitting
+        // Already transmitting — keep capturing after the whisper key is released
+        connection.whisper_owns_capture = false;
+        return Ok(());
     }
 
     if connection.current_channel_id.load(Ordering::Relaxed) == 0 {
@@ -609,6 +765,8 @@ pub(crate) async fn do_start_transmit(state: &AppState) -> Result<(), String> {
         connection.voice_tx.clone(),
         connection.current_media_key.clone(),
         connection.current_channel_id.clone(),
+        connection.whispering.clone(),
+        connection.whisper_keys.clone(),
         connection.voice_mode.clone(),
         connection.vad_threshold_db.clone(),
         connection.current_audio_level.clone(),
@@ -658,6 +816,87 @@ pub async fn stop_transmit(state: State<'_, AppState>) -> Result<(), String> {
     do_stop_transmit(&state).await
 }
 
+/// Start whispering (whisper key pressed). Voice goes to the whisper list set with
+/// `set_whisper_list` until the key is released, starting capture if needed.
+pub(crate) async fn do_start_whisper(state: &AppState) -> Result<(), String> {
+    let owns_capture = {
+        let conn = state.connection.read().await;
+        let connection = conn.as_ref().ok_or("Not connected")?;
+        if connection.whispering.swap(true, Ordering::Relaxed) {
+            return Ok(()); // Already whispering
+        }
+        !connection.transmitting.load(Ordering::Relaxed)
+    };
+
+    if owns_capture {
+        if let Err(e) = do_start_transmit(state).await {
+            if let Some(c) = state.connection.read().await.as_ref() {
+                c.whispering.store(false, Ordering::Relaxed);
+            }
+            return Err(e);
+        }
+        if let Some(c) = state.connection.write().await.as_mut() {
+            c.whisper_owns_capture = true;
+        }
+    }
+
+    tracing::info!("Whisper pressed");
+    Ok(())
+}
+
+/// Stop whispering (whisper key released).
+pub(crate) async fn do_stop_whisper(state: &AppState) -> Result<(), String> {
+    let owns_capture = {
+        let mut conn = state.connection.write().await;
+        let connection = conn.as_mut().ok_or("Not connected")?;
+        if !connection.whispering.swap(false, Ordering::Relaxed) {
+            return Ok(()); // Not whispering
+        }
+        std::mem::take(&mut connection.whisper_owns_capture)
+    };
+
+    if owns_capture {
+        do_stop_transmit(state).await?;
+    } else if let Some(c) = state.connection.read().await.as_ref() {
+        // Capture keeps running for the channel — tell whisper recipients we stopped
+        let eot = VoicePacket::end_of_transmission(c.session_id, c.udp_token, 0);
+        let _ = c.voice_tx.send(eot.to_bytes()).await;
+    }
+
+    tracing::info!("Whisper released");
+    Ok(())
+}
+
+/// Start whispering (on-screen whisper button pressed).
+#[tauri::command]
+pub async fn start_whisper(state: State<'_, AppState>) -> Result<(), String> {
+    do_start_whisper(&state).await
+}
+
+/// Stop whispering (on-screen whisper button released).
+#[tauri::command]
+pub async fn stop_whisper(state: State<'_, AppState>) -> Result<(), String> {
+    do_stop_whisper(&state).await
+}
+
+/// Set who whispers go to. An empty list clears it.
+#[tauri::command]
+pub async fn set_whisper_list(
+    state: State<'_, AppState>,
+    targets: Vec<WhisperTarget>,
+) -> Result<(), String> {
+    let conn = state.connection.read().await;
+    let connection = conn.as_ref().ok_or("Not connected")?;
+    connection.require(Capabilities::WHISPER, "whispering")?;
+    if targets.is_empty() {
+        // The server sends no new key for an empty list
+        if let Ok(mut keys) = connection.whisper_keys.lock() {
+            keys.remove(&connection.session_id);
+        }
+    }
+    network::send_tcp_message(&connection.tcp_tx, &ClientMessage::SetWhisperList { targets }).await
+}
+
 /// Check if a JS KeyboardEvent.code string is a recognized key code.
 fn is_valid_key_code(code: &str) -> bool {
     matches!(
@@ -798,6 +1037,38 @@ pub async fn set_ptt_key(
     Ok(())
 }
 
+/// Change the whisper key binding. An empty string unbinds it.
+#[tauri::command]
+pub async fn set_whisper_key(
+    state: State<'_, AppState>,
+    key_code: String,
+) -> Result<(), String> {
+    let binding = if key_code.is_empty() {
+        None
+    } else {
+        Some(
+            parse_ptt_binding(&key_code)
+                .ok_or_else(|| format!("Unsupported key binding: {key_code}"))?,
+        )
+    };
+
+    {
+        let mut whisper = state.whisper_binding.write().unwrap();
+        *whisper = binding;
+    }
+
+    {
+        let mut config = state.config.lock().unwrap();
+        config.whisper_key = key_code.clone();
+        if let Err(e) = crate::config::save_config(&config) {
+            tracing::warn!("Failed to save config: {e}");
+        }
+    }
+
+    tracing::info!("Whisper key changed to: {key_code:?}");
+    Ok(())
+}
+
 /// Set PTT hold mode. When true, for combo bindings (e.g. Ctrl+Space), holding the modifier
 /// keeps PTT active after releasing the trigger key. When false, releasing the trigger key
 /// immediately stops PTT.
@@ -1015,6 +1286,14 @@ pub async fn start_screen_share(
 
     #[cfg(not(target_os = "android"))]
     {
+        state
+            .connection
+            .read()
+            .await
+            .as_ref()
+            .ok_or("Not connected")?
+            .require(Capabilities::SCREEN_SHARE, "screen sharing")?;
+
         // Open the screen capture session for the selected source.
         // This must happen BEFORE acquiring the connection lock because it may await
         // user interaction (e.g. portal dialog on Linux).
@@ -1080,6 +1359,7 @@ pub async fn watch_screen_share(
 ) -> Result<(), String> {
     let mut conn = state.connection.write().await;
     let connection = conn.as_mut().ok_or("Not connected")?;
+    connection.require(Capabilities::SCREEN_SHARE, "screen sharing")?;
 
     network::send_tcp_message(
         &connection.tcp_tx,
@@ -1135,6 +1415,7 @@ pub async fn request_keyframe(
 pub async fn toggle_screen_audio(state: State<'_, AppState>) -> Result<bool, String> {
     let conn = state.connection.read().await;
     let connection = conn.as_ref().ok_or("Not connected")?;
+    connection.require(Capabilities::SCREEN_SHARE_AUDIO, "screen share audio")?;
     let was_enabled = connection
         .screen_audio_enabled
         .fetch_xor(true, Ordering::Relaxed);
@@ -1927,6 +2208,7 @@ pub async fn distribute_media_key(
 ) -> Result<(), String> {
     let conn = state.connection.read().await;
     let connection = conn.as_ref().ok_or("Not connected")?;
+    connection.require(Capabilities::ENCRYPTED_MEDIA, "encrypted media")?;
     network::send_tcp_message(
         &connection.tcp_tx,
         &ClientMessage::DistributeMediaKey {
@@ -2027,6 +2309,7 @@ pub async fn reset_config(state: State<'_, AppState>) -> Result<(), String> {
         *ptt = crate::app_state::PttBinding::default();
     }
     state.ptt_hold_mode.store(true, Ordering::Relaxed);
+    *state.whisper_binding.write().unwrap() = None;
 
     // Reset config
     {
@@ -75,6 +75,8 @@ pub struct AppConfig {
     // PTT
     pub ptt_key: String,
     pub ptt_hold_mode: bool,
+    /// Whisper key binding ("" = unbound).
+    pub whisper_key: String,
 
     // Mute/Deafen (restored on next connect)
     pub muted: bool,
@@ -107,6 +109,7 @@ impl Default for AppConfig {
             vad_threshold_db: -40.0,
             ptt_key: "Space".into(),
             ptt_hold_mode: true,
+            whisper_key: String::new(),
             muted: false,
             deafened: false,
             remember_connection: false,
@@ -155,6 +158,43 @@ pub fn default_chat_history_path() -> PathBuf {
     data_dir().join("chat_history.bin")
 }
 
+/// Returns the path to `identity.json` (long-term Signal identity key pair).
+pub fn identity_path() -> PathBuf {
+    data_dir().join("identity.json")
+}
+
+/// Load the persisted identity key pair, if any. Registered accounts on
+/// servers are bound to this key, so it must survive restarts.
+pub fn load_identity() -> Option<voipc_crypto::SerializableIdentityKeyPair> {
+    let path = identity_path();
+    let contents = std::fs::read_to_string(&path).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(identity) => Some(identity),
+        Err(e) => {
+            tracing::warn!("Failed to parse {}: {e} — generating a new identity", path.display());
+            None
+        }
+    }
+}
+
+/// Save the identity key pair atomically, readable only by the current user.
+pub fn save_identity(identity: &voipc_crypto::SerializableIdentityKeyPair) -> Result<(), String> {
+    let path = identity_path();
+    let tmp_path = path.with_extension("json.tmp");
+    let json = serde_json::to_string(identity)
+        .map_err(|e| format!("Failed to serialize identity: {e}"))?;
+    std::fs::write(&tmp_path, json)
+        .map_err(|e| format!("Failed to write {}: {e}", tmp_path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(&tmp_path, std::fs::Permissions::from_mode(0o600));
+    }
+    std::fs::rename(&tmp_path, &path)
+        .map_err(|e| format!("Failed to rename identity: {e}"))?;
+    Ok(())
+}
+
 /// Resolves the chat history file path from config.
 /// If `chat_history_path` is set, uses that; otherwise falls back to the default.
 pub fn resolve_chat_history_path(config: &AppConfig) -> PathBuf {
@@ -1,4 +1,4 @@
-//! Global keyboard listener for PTT — works even when the window is unfocused.
+//! Global keyboard listener for PTT and whisper — works even when the window is unfocused.
 //!
 //! - **Linux**: Tries `evdev` first (reads `/dev/input/event*` directly — works on X11 + Wayland,
 //!   requires user in `input` group). Falls back to `rdev` (X11 XRecord — no special permissions).
@@ -11,6 +11,7 @@ pub fn spawn_listener(
     _handle: tauri::AppHandle,
     _ptt_binding: std::sync::Arc<std::sync::RwLock<crate::app_state::PttBinding>>,
     _ptt_hold_mode: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    _whisper_binding: std::sync::Arc<std::sync::RwLock<Option<crate::app_state::PttBinding>>>,
 ) {
     tracing::info!("Global PTT: not available on Android (using touch UI + volume keys)");
 }
@@ -32,11 +33,12 @@ use crate::commands;
 
 #[cfg(not(target_os = "android"))]
 /// Spawn a background thread that monitors global keyboard events and triggers
-/// PTT start/stop. Keys are NOT consumed — they still propagate to all applications.
+/// PTT and whisper start/stop. Keys are NOT consumed — they still propagate to all applications.
 pub fn spawn_listener(
     handle: tauri::AppHandle,
     ptt_binding: Arc<std::sync::RwLock<PttBinding>>,
     ptt_hold_mode: Arc<AtomicBool>,
+    whisper_binding: Arc<std::sync::RwLock<Option<PttBinding>>>,
 ) {
     std::thread::spawn(move || {
         // On Linux, try evdev first (best — works on Wayland + X11).
@@ -49,7 +51,7 @@ pub fn spawn_listener(
                     "Global PTT: monitoring {} keyboard(s) via evdev",
                     keyboards.len()
                 );
-                run_evdev_loop(keyboards, &handle, &ptt_binding, &ptt_hold_mode);
+                run_evdev_loop(keyboards, &handle, &ptt_binding, &ptt_hold_mode, &whisper_binding);
                 return;
             }
             tracing::info!(
@@ -58,7 +60,37 @@ pub fn spawn_listener(
             );
         }
 
-        run_rdev_loop(handle, ptt_binding, ptt_hold_mode);
+        run_rdev_loop(handle, ptt_binding, ptt_hold_mode, whisper_binding);
+    });
+}
+
+/// Start whispering on the async runtime. Unlike PTT this works in every voice mode.
+#[cfg(not(target_os = "android"))]
+fn spawn_whisper_start(handle: &tauri::AppHandle) {
+    let h = handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let state = h.state::<AppState>();
+        if state.connection.read().await.is_none() {
+            return;
+        }
+        if let Err(e) = commands::do_start_whisper(&state).await {
+            tracing::warn!("Global whisper start failed: {e}");
+        } else {
+            let _ = h.emit("whisper-global-pressed", ());
+        }
+    });
+}
+
+#[cfg(not(target_os = "android"))]
+fn spawn_whisper_stop(handle: &tauri::AppHandle) {
+    let h = handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let state = h.state::<AppState>();
+        if let Err(e) = commands::do_stop_whisper(&state).await {
+            tracing::warn!("Global whisper stop failed: {e}");
+        } else {
+            let _ = h.emit("whisper-global-released", ());
+        }
     });
 }
 
@@ -198,9 +230,11 @@ fn run_rdev_loop(
     handle: tauri::AppHandle,
     ptt_binding: Arc<std::sync::RwLock<PttBinding>>,
     ptt_hold_mode: Arc<AtomicBool>,
+    whisper_binding: Arc<std::sync::RwLock<Option<PttBinding>>>,
 ) {
     let mut held_keys = HashSet::new();
     let mut ptt_active = false;
+    let mut whisper_active = false;
 
     let callback = move |event: rdev::Event| {
         match event.event_type {
@@ -210,6 +244,14 @@ fn run_rdev_loop(
                 let matches = rdev_binding_matches(&held_keys, &b);
                 drop(b);
 
+                let w = whisper_binding.read().unwrap_or_else(|p| p.into_inner());
+                let whisper_matches = w.as_ref().is_some_and(|w| rdev_binding_matches(&held_keys, w));
+                drop(w);
+                if whisper_matches && !whisper_active {
+                    whisper_active = true;
+                    spawn_whisper_start(&handle);
+                }
+
                 if matches && !ptt_active {
                     ptt_active = true;
                     let h = handle.clone();
@@ -247,6 +289,20 @@ fn run_rdev_loop(
                 };
                 drop(b);
 
+                let w = whisper_binding.read().unwrap_or_else(|p| p.into_inner());
+                let whisper_still = w.as_ref().is_some_and(|w| {
+                    if hold {
+                        rdev_binding_held(&held_keys, w)
+                    } else {
+                        rdev_binding_matches(&held_keys, w)
+                    }
+                });
+                drop(w);
+                if !whisper_still && whisper_active {
+                    whisper_active = false;
+                    spawn_whisper_stop(&handle);
+                }
+
                 if !still && ptt_active {
                     ptt_active = false;
                     let h = handle.clone();
@@ -434,6 +490,7 @@ fn run_evdev_loop(
     handle: &tauri::AppHandle,
     ptt_binding: &Arc<std::sync::RwLock<PttBinding>>,
     ptt_hold_mode: &Arc<AtomicBool>,
+    whisper_binding: &Arc<std::sync::RwLock<Option<PttBinding>>>,
 ) {
     use evdev::{InputEventKind, Key};
     use std::os::unix::io::AsRawFd;
@@ -447,6 +504,7 @@ fn run_evdev_loop(
 
     let mut held_keys: HashSet<Key> = HashSet::new();
     let mut ptt_active = false;
+    let mut whisper_active = false;
     let mut last_enum = std::time::Instant::now();
     let mut needs_reenumerate = false;
 
@@ -480,6 +538,16 @@ fn run_evdev_loop(
                                         evdev_binding_matches(&held_keys, &b);
                                     drop(b);
 
+                                    let w = whisper_binding.read().unwrap_or_else(|p| p.into_inner());
+                                    let whisper_matches = w
+                                        .as_ref()
+                                        .is_some_and(|w| evdev_binding_matches(&held_keys, w));
+                                    drop(w);
+                                    if whisper_matches && !whisper_active {
+                                        whisper_active = true;
+                                        spawn_whisper_start(handle);
+                                    }
+
                                     if matches && !ptt_active {
                                         ptt_active = true;
                                         let h = handle.clone();
@@ -533,6 +601,20 @@ fn run_evdev_loop(
                                     };
                                     drop(b);
 
+                                    let w = whisper_binding.read().unwrap_or_else(|p| p.into_inner());
+                                    let whisper_still = w.as_ref().is_some_and(|w| {
+                                        if hold {
+                                            evdev_binding_held(&held_keys, w)
+                                        } else {
+                                            evdev_binding_matches(&held_keys, w)
+                                        }
+                                    });
+                                    drop(w);
+                                    if !whisper_still && whisper_active {
+                                        whisper_active = false;
+                                        spawn_whisper_stop(handle);
+                                    }
+
                                     if !still && ptt_active {
                                         ptt_active = false;
                                         let h = handle.clone();
@@ -4,6 +4,7 @@ mod config;
 mod crypto;
 mod global_keys;
 mod network;
+mod reconnect;
 mod screenshare;
 
 use app_state::AppState;
@@ -105,6 +106,7 @@ pub fn run() {
                 state
                     .ptt_hold_mode
                     .store(cfg.ptt_hold_mode, std::sync::atomic::Ordering::Relaxed);
+                *state.whisper_binding.write().unwrap() = commands::parse_ptt_binding(&cfg.whisper_key);
 
                 // Store loaded config
                 *state.config.lock().unwrap() = cfg;
@@ -131,6 +133,7 @@ pub fn run() {
                 app.handle().clone(),
                 app.state::<AppState>().ptt_binding.clone(),
                 app.state::<AppState>().ptt_hold_mode.clone(),
+                app.state::<AppState>().whisper_binding.clone(),
             );
 
             Ok(())
@@ -142,6 +145,10 @@ pub fn run() {
             commands::create_channel,
             commands::set_channel_password,
             commands::kick_user,
+            commands::set_server_muted,
+            commands::set_priority_speaker,
+            commands::move_user,
+            commands::set_channel_topic,
             commands::request_channel_users,
             commands::send_invite,
             commands::send_poke,
@@ -188,6 +195,11 @@ pub fn run() {
             // Global PTT key binding
             commands::set_ptt_key,
             commands::set_ptt_hold_mode,
+            // Whisper
+            commands::set_whisper_key,
+            commands::set_whisper_list,
+            commands::start_whisper,
+            commands::stop_whisper,
             // Voice activation
             commands::set_voice_mode,
             commands::set_vad_threshold,
@@ -204,6 +216,12 @@ pub fn run() {
             commands::distribute_sender_key,
             commands::distribute_media_key,
             commands::upload_prekeys,
+            // Accounts
+            commands::register_account,
+            // Bans
+            commands::ban_user,
+            commands::unban_user,
+            commands::list_bans,
             // Persistent config
             commands::load_config,
             commands::save_connection_info,
@@ -12,11 +12,12 @@ use tokio_rustls::TlsConnector;
 use tauri::Emitter;
 #[cfg(not(target_os = "android"))]
 use tauri::Manager;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
 use voipc_crypto::media_keys::MediaKey;
 use voipc_protocol::codec::{
-    decode_server_msg, encode_client_msg, try_decode_frame, APP_VERSION, PROTOCOL_VERSION,
+    auth_challenge_payload, decode_server_msg, encode_client_msg, pack_frame, try_decode_frame,
+    APP_VERSION, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
 };
 use voipc_protocol::messages::{ClientMessage, ServerMessage};
 use voipc_protocol::types::*;
@@ -29,36 +30,66 @@ use voipc_protocol::voice::VoicePacket;
 use crate::app_state::{ActiveConnection, AppState, PendingTarget, SignalState};
 use crate::screenshare;
 
+/// A successful `connect_to_server`.
+pub struct Connected {
+    pub user_id: u32,
+    /// True if the server handed back our previous session.
+    pub resumed: bool,
+    /// Token for resuming this session if the connection drops.
+    pub resume_token: Vec<u8>,
+}
+
+/// A failed `connect_to_server`.
+#[derive(Debug)]
+pub struct ConnectError {
+    /// Why the server refused the login, if it did.
+    pub code: Option<AuthErrorCode>,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<String> for ConnectError {
+    fn from(message: String) -> Self {
+        Self { code: None, message }
+    }
+}
+
+impl From<&str> for ConnectError {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl From<ConnectError> for String {
+    fn from(e: ConnectError) -> Self {
+        e.message
+    }
+}
+
 /// Connect to the server, authenticate, spawn background tasks, and store the connection.
-/// Returns the assigned user_id on success.
+/// `resume_token` asks the server to hand back a session that was cut off.
 pub async fn connect_to_server(
     state: &AppState,
     app_handle: tauri::AppHandle,
     address: String,
     username: String,
     accept_invalid_certs: bool,
-) -> Result<u32, String> {
+    resume_token: Option<Vec<u8>>,
+) -> Result<Connected, ConnectError> {
     // Tear down any existing connection first (e.g. after webview reload)
-    {
-        let mut conn = state.connection.write().await;
-        if let Some(mut old) = conn.take() {
-            old.transmitting.store(false, std::sync::atomic::Ordering::Relaxed);
-            old.screen_share_active.store(false, std::sync::atomic::Ordering::Relaxed);
-            if let Some(task) = old.capture_task.take() { let _ = task.await; }
-            if let Some(task) = old.screen_capture_task.take() { let _ = task.await; }
-            let _ = send_tcp_message(&old.tcp_tx, &ClientMessage::Disconnect).await;
-            drop(old.tcp_tx);
-            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
-            for task in old.tasks { task.abort(); }
-            drop(old.voice_tx);
-            drop(old.video_tx);
-            drop(old.screen_audio_tx);
-            drop(old.playback_stream);
-            info!("cleaned up stale connection before reconnecting");
-        }
+    let old = state.connection.write().await.take();
+    if let Some(old) = old {
+        close_connection(old, true).await;
+        info!("cleaned up stale connection before reconnecting");
     }
 
-    let (host, port) = parse_address(&address)?;
+    let (host, port) =
+        voipc_client::tls::parse_address(&address).map_err(|e| e.to_string())?;
 
     // TCP connect
     let tcp_stream = TcpStream::connect((&*host, port))
@@ -67,28 +98,12 @@ pub async fn connect_to_server(
 
     info!("TCP connected to {}", address);
 
-    // TLS handshake
-    let tls_config = if accept_invalid_certs {
-        warn!("Using TOFU certificate pinning (self-signed mode)");
-        rustls::ClientConfig::builder()
-            .dangerous()
-            .with_custom_certificate_verifier(Arc::new(TofuCertVerifier))
-            .with_no_client_auth()
-    } else {
-        let mut root_store = rustls::RootCertStore::empty();
-        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-        rustls::ClientConfig::builder()
-            .with_root_certificates(root_store)
-            .with_no_client_auth()
-    };
-
+    // TLS handshake. Self-signed certificates are pinned on first use in
+    // `tofu_pins.json` and rejected if they change later.
+    let pin_file = crate::config::data_dir().join("tofu_pins.json");
+    let tls_config = voipc_client::tls::client_config(accept_invalid_certs, Some(pin_file));
     let connector = TlsConnector::from(Arc::new(tls_config));
-    let server_name = if let Ok(ip) = host.parse::<std::net::IpAddr>() {
-        rustls::pki_types::ServerName::IpAddress(ip.into())
-    } else {
-        rustls::pki_types::ServerName::try_from(host.clone())
-            .map_err(|e| format!("Invalid server name '{}': {}", host, e))?
-    };
+    let server_name = voipc_client::tls::server_name(&host).map_err(|e| e.to_string())?;
 
     let mut tls_stream = connector
         .connect(server_name, tcp_stream)
@@ -102,7 +117,7 @@ pub async fn connect_to_server(
         let mut signal = state.signal.lock().map_err(|e| e.to_string())?;
         if !signal.initialized {
             info!("initializing Signal Protocol state");
-            let identity_key_pair = voipc_crypto::generate_identity_key_pair();
+            let identity_key_pair = load_or_create_identity();
             let registration_id: u32 = rand::Rng::gen(&mut rand::thread_rng());
             let mut stores =
                 voipc_crypto::SignalStores::new(&identity_key_pair, registration_id);
@@ -190,6 +205,10 @@ pub async fn connect_to_server(
         app_version: APP_VERSION.to_string(),
         identity_key,
         prekey_bundle,
+        virtual_server: None,
+        resume_token,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        capabilities: Capabilities::ALL,
     };
     let data =
         encode_client_msg(&auth_msg).map_err(|e| format!("Failed to encode auth: {}", e))?;
@@ -200,7 +219,7 @@ pub async fn connect_to_server(
 
     // Read until we get the Authenticated or AuthError response
     let mut buf = BytesMut::with_capacity(4096);
-    let (user_id, session_id, udp_port, udp_token) = loop {
+    let (user_id, session_id, udp_port, udp_token, resume_token, resumed, protocol_version, capabilities) = loop {
         let n = tls_stream
             .read_buf(&mut buf)
             .await
@@ -211,10 +230,17 @@ pub async fn connect_to_server(
         }
 
         if let Some(payload) =
-            try_decode_frame(&mut buf).map_err(|e| format!("Frame decode error: {}", e))?
+            try_decode_frame(&mut buf, Capabilities::NONE)
+                .map_err(|e| format!("Frame decode error: {}", e))?
         {
-            let msg = decode_server_msg(&payload)
-                .map_err(|e| format!("Failed to decode response: {}", e))?;
+            let msg = match decode_server_msg(&payload) {
+                Ok(msg) => msg,
+                Err(e) if e.is_unknown_message() => {
+                    debug!("skipping message from a newer server during auth: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(format!("Failed to decode response: {}", e).into()),
+            };
 
             match msg {
                 ServerMessage::Authenticated {
@@ -222,9 +248,54 @@ pub async fn connect_to_server(
                     session_id,
                     udp_port,
                     udp_token,
-                } => break (user_id, session_id, udp_port, udp_token),
-                ServerMessage::AuthError { reason } => {
-                    return Err(format!("Authentication failed: {}", reason));
+                    resume_token,
+                    resumed,
+                    protocol_version,
+                    capabilities,
+                } => {
+                    break (
+                        user_id,
+                        session_id,
+                        udp_port,
+                        udp_token,
+                        resume_token,
+                        resumed,
+                        protocol_version,
+                        capabilities,
+                    )
+                }
+                ServerMessage::AuthError { reason, code } => {
+                    return Err(ConnectError {
+                        code: Some(code),
+                        message: format!("Authentication failed: {}", reason),
+                    });
+                }
+                ServerMessage::AuthChallenge { nonce } => {
+                    let signature = {
+                        let signal = state.signal.lock().map_err(|e| e.to_string())?;
+                        let stores = signal
+                            .stores
+                            .as_ref()
+                            .ok_or("Signal state not initialized")?;
+                        let pair = stores
+                            .identity
+                            .key_pair
+                            .to_identity_key_pair()
+                            .map_err(|e| format!("Invalid identity key: {e}"))?;
+                        voipc_crypto::sign_with_identity(
+                            &pair,
+                            &auth_challenge_payload(&nonce, &username),
+                        )
+                        .map_err(|e| format!("Failed to sign auth challenge: {e}"))?
+                    };
+                    let data = encode_client_msg(&ClientMessage::AuthChallengeResponse {
+                        signature,
+                    })
+                    .map_err(|e| format!("Failed to encode challenge response: {}", e))?;
+                    tls_stream
+                        .write_all(&data)
+                        .await
+                        .map_err(|e| format!("Failed to send challenge response: {}", e))?;
                 }
                 other => {
                     warn!("unexpected message during auth: {:?}", other);
@@ -233,10 +304,20 @@ pub async fn connect_to_server(
         }
     };
 
-    info!(user_id, session_id, udp_port, "authenticated with server");
+    info!(
+        user_id,
+        session_id,
+        udp_port,
+        resumed,
+        protocol_version,
+        capabilities = ?capabilities.names(),
+        "authenticated with server"
+    );
 
     // Reset Signal tracking state for the new connection.
     // User IDs are allocated fresh by the server, so old session tracking is stale.
+    // A resumed session keeps its user ID, but peers may have dropped us in the
+    // meantime, so sessions and sender keys are re-established the same way.
     // Keep `stores` and `initialized` — identity key persists within app session,
     // and old sessions in the store will be overwritten on re-establishment.
     {
@@ -326,9 +407,14 @@ pub async fn connect_to_server(
     let screen_audio_send_count = Arc::new(AtomicU32::new(0));
     let screen_audio_recv_count = Arc::new(AtomicU32::new(0));
     let transmitting = Arc::new(AtomicBool::new(false));
-    let screen_audio_enabled = Arc::new(AtomicBool::new(true));
+    // Desktop audio is only sent if the server forwards it
+    let screen_audio_enabled = Arc::new(AtomicBool::new(
+        capabilities.contains(Capabilities::SCREEN_SHARE_AUDIO),
+    ));
     let current_media_key = Arc::new(std::sync::Mutex::new(None));
     let current_channel_id = Arc::new(AtomicU32::new(0));
+    let whisper_keys: Arc<std::sync::Mutex<HashMap<u32, MediaKey>>> =
+        Arc::new(std::sync::Mutex::new(HashMap::new()));
 
     // Screen share video stats
     let screen_video_frames_sent = Arc::new(AtomicU32::new(0));
@@ -372,13 +458,15 @@ pub async fn connect_to_server(
     let is_deafened = Arc::new(AtomicBool::new(saved_deafened));
 
     // Spawn background tasks
-    let writer_handle = tokio::spawn(tcp_writer_task(write_half, tcp_rx));
+    let writer_handle = tokio::spawn(tcp_writer_task(write_half, tcp_rx, capabilities));
     let reader_handle = tokio::spawn(tcp_reader_task(
         read_half,
         buf,
+        capabilities,
         app_handle.clone(),
         current_media_key.clone(),
         current_channel_id.clone(),
+        whisper_keys.clone(),
         state.signal.clone(),
         tcp_tx.clone(),
         user_id,
@@ -407,6 +495,7 @@ pub async fn connect_to_server(
         screen_audio_recv_count.clone(),
         current_media_key.clone(),
         current_channel_id.clone(),
+        whisper_keys.clone(),
         user_volumes.clone(),
         is_deafened.clone(),
         screen_video_frames_received.clone(),
@@ -442,6 +531,7 @@ pub async fn connect_to_server(
         playback_producer,
         playback_stream: Some(playback_stream),
         udp_token,
+        capabilities,
         is_screen_sharing: false,
         screen_capture_task: None,
         screen_share_active,
@@ -460,6 +550,9 @@ pub async fn connect_to_server(
         screen_video_resolution,
         current_media_key,
         current_channel_id,
+        whispering: Arc::new(AtomicBool::new(false)),
+        whisper_owns_capture: false,
+        whisper_keys,
         voice_mode: Arc::new(AtomicU8::new(
             crate::app_state::VoiceMode::from_str(&saved_voice_mode) as u8,
         )),
@@ -485,7 +578,31 @@ pub async fn connect_to_server(
         }
     }
 
-    Ok(user_id)
+    Ok(Connected {
+        user_id,
+        resumed,
+        resume_token,
+    })
+}
+
+/// Stop a connection's capture and background tasks. `say_goodbye` sends
+/// `Disconnect` first; without it the server keeps the session for resuming.
+pub async fn close_connection(mut old: ActiveConnection, say_goodbye: bool) {
+    old.transmitting.store(false, Ordering::Relaxed);
+    old.screen_share_active.store(false, Ordering::Relaxed);
+    if let Some(task) = old.capture_task.take() { let _ = task.await; }
+    if let Some(task) = old.screen_capture_task.take() { let _ = task.await; }
+    old.capture_session = None;
+    if say_goodbye {
+        let _ = send_tcp_message(&old.tcp_tx, &ClientMessage::Disconnect).await;
+    }
+    drop(old.tcp_tx);
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    for task in old.tasks { task.abort(); }
+    drop(old.voice_tx);
+    drop(old.video_tx);
+    drop(old.screen_audio_tx);
+    drop(old.playback_stream);
 }
 
 /// Send a client message over the TCP control channel.
@@ -501,38 +618,30 @@ pub async fn send_tcp_message(
         .map_err(|_| "TCP send channel closed".to_string())
 }
 
-fn parse_address(address: &str) -> Result<(String, u16), String> {
-    let (host, port_str) = if address.starts_with('[') {
-        // IPv6: [::1]:9987
-        let bracket_end = address
-            .find("]:")
-            .ok_or("Invalid IPv6 address format, expected [host]:port")?;
-        let host = &address[1..bracket_end];
-        let port_str = &address[bracket_end + 2..];
-        (host.to_string(), port_str)
-    } else {
-        let parts: Vec<&str> = address.rsplitn(2, ':').collect();
-        if parts.len() != 2 {
-            return Err("Invalid address format, expected host:port".into());
-        }
-        (parts[1].to_string(), parts[0])
-    };
-    let port: u16 = port_str
-        .parse()
-        .map_err(|_| "Invalid port number".to_string())?;
-    if host.is_empty() {
-        return Err("Host cannot be empty".into());
+/// Load the persisted identity key pair, or generate and persist a new one.
+/// Registered accounts are bound to this key, so it must be stable across runs.
+fn load_or_create_identity() -> libsignal_protocol::IdentityKeyPair {
+    if let Some(pair) = crate::config::load_identity().and_then(|id| id.to_identity_key_pair().ok())
+    {
+        return pair;
+    }
+    let pair = voipc_crypto::generate_identity_key_pair();
+    let serializable = voipc_crypto::SerializableIdentityKeyPair::from_identity_key_pair(&pair);
+    if let Err(e) = crate::config::save_identity(&serializable) {
+        warn!("failed to persist identity key: {}", e);
     }
-    Ok((host, port))
+    pair
 }
 
-/// TCP writer task: sends encoded messages from the channel to the TCP stream.
+/// TCP writer task: sends encoded messages from the channel to the TCP stream,
+/// compressed and chunked as negotiated.
 async fn tcp_writer_task(
     mut write_half: tokio::io::WriteHalf<TlsStream<TcpStream>>,
     mut rx: mpsc::Receiver<Vec<u8>>,
+    capabilities: Capabilities,
 ) {
     while let Some(data) = rx.recv().await {
-        if let Err(e) = write_half.write_all(&data).await {
+        if let Err(e) = write_half.write_all(&pack_frame(data, capabilities)).await {
             error!("TCP write error: {}", e);
             break;
         }
@@ -545,38 +654,34 @@ async fn tcp_writer_task(
 async fn tcp_reader_task(
     mut read_half: tokio::io::ReadHalf<TlsStream<TcpStream>>,
     mut buf: BytesMut,
+    capabilities: Capabilities,
     app_handle: tauri::AppHandle,
     media_key: Arc<std::sync::Mutex<Option<MediaKey>>>,
     channel_id: Arc<AtomicU32>,
+    whisper_keys: Arc<std::sync::Mutex<HashMap<u32, MediaKey>>>,
     signal: Arc<std::sync::Mutex<SignalState>>,
     tcp_tx: mpsc::Sender<Vec<u8>>,
     own_user_id: u32,
     screen_share_active: Arc<AtomicBool>,
     watching_user_id_shared: Arc<AtomicU32>,
 ) {
-    loop {
+    'read: loop {
         match read_half.read_buf(&mut buf).await {
             Ok(0) => {
                 info!("server closed TCP connection");
-                let _ = app_handle.emit(
-                    "connection-lost",
-                    serde_json::json!({"reason": "Server closed connection"}),
-                );
+                crate::reconnect::connection_lost(&app_handle, "Server closed connection".into());
                 break;
             }
             Ok(_) => {}
             Err(e) => {
                 error!("TCP read error: {}", e);
-                let _ = app_handle.emit(
-                    "connection-lost",
-                    serde_json::json!({"reason": format!("Read error: {}", e)}),
-                );
+                crate::reconnect::connection_lost(&app_handle, format!("Read error: {}", e));
                 break;
             }
         }
 
         loop {
-            match try_decode_frame(&mut buf) {
+            match try_decode_frame(&mut buf, capabilities) {
                 Ok(Some(payload)) => match decode_server_msg(&payload) {
                     Ok(msg) => {
                         handle_server_message(
@@ -584,6 +689,7 @@ async fn tcp_reader_task(
                             &app_handle,
                             &media_key,
                             &channel_id,
+                            &whisper_keys,
                             &signal,
                             &tcp_tx,
                             own_user_id,
@@ -592,12 +698,17 @@ async fn tcp_reader_task(
                         )
                         .await;
                     }
+                    Err(e) if e.is_unknown_message() => {
+                        debug!("skipping message from a newer server: {}", e)
+                    }
                     Err(e) => warn!("failed to decode server message: {}", e),
                 },
                 Ok(None) => break,
                 Err(e) => {
+                    // The stream can't be resynchronised past a bad frame
                     error!("frame decode error: {}", e);
-                    break;
+                    crate::reconnect::connection_lost(&app_handle, format!("Frame decode error: {}", e));
+                    break 'read;
                 }
             }
         }
@@ -613,6 +724,7 @@ async fn handle_server_message(
     app_handle: &tauri::AppHandle,
     media_key: &Arc<std::sync::Mutex<Option<MediaKey>>>,
     channel_id_store: &Arc<AtomicU32>,
+    whisper_keys: &Arc<std::sync::Mutex<HashMap<u32, MediaKey>>>,
     signal: &Arc<std::sync::Mutex<SignalState>>,
     tcp_tx: &mpsc::Sender<Vec<u8>>,
     own_user_id: u32,
@@ -642,6 +754,22 @@ async fn handle_server_message(
                 }
                 info!(old_ch, channel_id, "channel changed via UserList");
 
+                // Send our sender key to members we already have sessions
+                // with; the rest get it once their session is established.
+                if channel_id != 0 {
+                    let members: Vec<u32> = {
+                        let sig = signal.lock().unwrap_or_else(|p| { warn!("mutex poisoned, recovering"); p.into_inner() });
+                        users
+                            .iter()
+                            .map(|u| u.user_id)
+                            .filter(|&uid| uid != own_user_id && sig.established_sessions.contains(&uid))
+                            .collect()
+                    };
+                    for uid in members {
+                        distribute_sender_key_to_user(channel_id, uid, own_user_id, signal, tcp_tx).await;
+                    }
+                }
+
                 // Stop screen share and watching on channel change
                 if screen_share_active.swap(false, Ordering::Relaxed) {
                     // Was sharing — tell server to stop
@@ -731,14 +859,32 @@ async fn handle_server_message(
             let rtt = now.saturating_sub(timestamp);
             let _ = app_handle.emit("latency-update", serde_json::json!({"ms": rtt}));
         }
-        ServerMessage::ServerShutdown { reason } => {
+        ServerMessage::ServerShutdown {
+            reason,
+            shutdown_in_secs,
+            reconnect_to,
+        } => {
+            // Without a hint the connection closes later, and we reconnect
+            // once the server is back
             let _ = app_handle.emit(
-                "connection-lost",
-                serde_json::json!({"reason": format!("Server shutdown: {}", reason)}),
+                "server-shutdown",
+                serde_json::json!({
+                    "reason": reason,
+                    "shutdown_in_secs": shutdown_in_secs,
+                    "reconnect_to": reconnect_to,
+                }),
             );
+            if let Some(address) = reconnect_to {
+                crate::reconnect::redirect(app_handle, address);
+            }
         }
         ServerMessage::MovedToChannel { channel_id } => {
+            // The UserList that follows switches channel state and keys
             info!("moved to channel {}", channel_id);
+            let _ = app_handle.emit(
+                "moved-to-channel",
+                serde_json::json!({"channel_id": channel_id}),
+            );
         }
         ServerMessage::ChannelCreated { channel } => {
             let _ = app_handle.emit("channel-created", &channel);
@@ -1043,7 +1189,113 @@ async fn handle_server_message(
                 );
             }
         }
-        ServerMessage::Authenticated { .. } | ServerMessage::AuthError { .. } => {}
+        ServerMessage::AccountRegistered { username } => {
+            info!(username = %username, "account registered");
+            let _ = app_handle.emit(
+                "account-registered",
+                serde_json::json!({"username": username}),
+            );
+        }
+        ServerMessage::AccountError { reason } => {
+            warn!("account error: {}", reason);
+            let _ = app_handle.emit(
+                "account-error",
+                serde_json::json!({"reason": reason}),
+            );
+        }
+        ServerMessage::PermissionsChanged {
+            group,
+            server,
+            channels,
+        } => {
+            info!(?group, "permissions changed");
+            let _ = app_handle.emit(
+                "permissions-changed",
+                serde_json::json!({
+                    "group": group,
+                    "server": server,
+                    "channels": channels,
+                }),
+            );
+        }
+        ServerMessage::Banned { reason } => {
+            warn!("banned from server: {}", reason);
+            crate::reconnect::forget(app_handle);
+            let _ = app_handle.emit("banned", serde_json::json!({"reason": reason}));
+        }
+        ServerMessage::BanList { bans } => {
+            let _ = app_handle.emit("ban-list", serde_json::json!({"bans": bans}));
+        }
+        ServerMessage::BanError { reason } => {
+            warn!("ban error: {}", reason);
+            let _ = app_handle.emit("ban-error", serde_json::json!({"reason": reason}));
+        }
+        ServerMessage::KickedFromServer { reason } => {
+            warn!("kicked from server: {}", reason);
+            crate::reconnect::forget(app_handle);
+            let _ = app_handle.emit("kicked-from-server", serde_json::json!({"reason": reason}));
+        }
+        ServerMessage::Motd { message } => {
+            let _ = app_handle.emit("server-motd", serde_json::json!({"message": message}));
+        }
+        ServerMessage::ServerNotice { message } => {
+            let _ = app_handle.emit("server-notice", serde_json::json!({"message": message}));
+        }
+        ServerMessage::WhisperKey {
+            session_id,
+            key_id,
+            key_bytes,
+        } => {
+            // Whisper keys are per sender; our own arrives after SetWhisperList
+            let Ok(kb) = <[u8; 32]>::try_from(key_bytes.as_slice()) else {
+                warn!("received invalid whisper key length {}", key_bytes.len());
+                return;
+            };
+            let key = MediaKey {
+                key_id,
+                key_bytes: kb,
+                channel_id: 0,
+            };
+            let mut keys = whisper_keys.lock().unwrap_or_else(|p| { warn!("mutex poisoned, recovering"); p.into_inner() });
+            keys.insert(session_id, key);
+            info!(session_id, key_id, "whisper key installed");
+        }
+        ServerMessage::WhisperError { reason } => {
+            warn!("whisper error: {}", reason);
+            let _ = app_handle.emit("whisper-error", serde_json::json!({"reason": reason}));
+        }
+        ServerMessage::UserServerMuted { user_id, muted } => {
+            let _ = app_handle.emit(
+                "user-server-muted",
+                serde_json::json!({"user_id": user_id, "muted": muted}),
+            );
+        }
+        ServerMessage::UserPrioritySpeaker { user_id, enabled } => {
+            let _ = app_handle.emit(
+                "user-priority-speaker",
+                serde_json::json!({"user_id": user_id, "enabled": enabled}),
+            );
+        }
+        ServerMessage::ModerationError { reason } => {
+            warn!("moderation error: {}", reason);
+            let _ = app_handle.emit("moderation-error", serde_json::json!({"reason": reason}));
+        }
+        ServerMessage::UserIdle { user_id, idle } => {
+            let _ = app_handle.emit(
+                "user-idle",
+                serde_json::json!({"user_id": user_id, "idle": idle}),
+            );
+        }
+        ServerMessage::RateLimited { limit, retry_after_ms } => {
+            warn!("rate limited ({:?}), retry after {}ms", limit, retry_after_ms);
+            let _ = app_handle.emit(
+                "rate-limited",
+                serde_json::json!({"limit": limit, "retry_after_ms": retry_after_ms}),
+            );
+        }
+        ServerMessage::Authenticated { .. }
+        | ServerMessage::AuthError { .. }
+        | ServerMessage::AuthChallenge { .. } => {}
     }
 }
 
@@ -1605,6 +1857,7 @@ async fn udp_receiver_task(
     screen_audio_recv_count: Arc<AtomicU32>,
     media_key: Arc<std::sync::Mutex<Option<MediaKey>>>,
     channel_id: Arc<AtomicU32>,
+    whisper_keys: Arc<std::sync::Mutex<HashMap<u32, MediaKey>>>,
     user_volumes: Arc<std::sync::Mutex<HashMap<u32, f32>>>,
     is_deafened: Arc<AtomicBool>,
     screen_video_frames_received: Arc<AtomicU32>,
@@ -1630,6 +1883,7 @@ async fn udp_receiver_task(
         playback_producer: &Arc<std::sync::Mutex<ringbuf::HeapProd<f32>>>,
         is_deafened: &Arc<AtomicBool>,
         user_volumes: &Arc<std::sync::Mutex<HashMap<u32, f32>>>,
+        duck_gain: f32,
     ) {
         while let Some(frame) = jitter.pop() {
             let pcm = match frame {
@@ -1659,7 +1913,8 @@ async fn udp_receiver_task(
 
             let vol = user_volumes.lock()
                 .map(|v| v.get(&session_id).copied().unwrap_or(1.0))
-                .unwrap_or(1.0);
+                .unwrap_or(1.0)
+                * duck_gain;
             if vol > 0.0 {
                 if let Ok(mut producer) = playback_producer.lock() {
                     if (vol - 1.0).abs() < f32::EPSILON {
@@ -1680,6 +1935,16 @@ async fn udp_receiver_task(
     speaking_timeout.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
     const SPEAKING_TIMEOUT_MS: u128 = 500;
 
+    // Priority speakers — other voices are ducked while one of them talks
+    let mut last_priority_time: HashMap<u32, std::time::Instant> = HashMap::new();
+    const PRIORITY_DUCK_GAIN: f32 = 0.3;
+    let duck_gain = |priority: &HashMap<u32, std::time::Instant>, session_id: u32| -> f32 {
+        let ducked = priority
+            .iter()
+            .any(|(&sid, t)| sid != session_id && t.elapsed().as_millis() <= SPEAKING_TIMEOUT_MS);
+        if ducked { PRIORITY_DUCK_GAIN } else { 1.0 }
+    };
+
     loop {
         tokio::select! {
             result = socket.recv_from(&mut buf) => {
@@ -1700,9 +1965,10 @@ async fn udp_receiver_task(
                 }
 
                 match packet_type {
-                    // Voice: OpusVoice (unencrypted) or EncryptedOpusVoice
-                    0x01 | 0x05 => {
-                        let header_size = if packet_type == 0x05 {
+                    // Voice: OpusVoice (unencrypted), EncryptedOpusVoice, EncryptedWhisperVoice
+                    // or EncryptedPriorityVoice (EncryptedOpusVoice marked by the server)
+                    0x01 | 0x05 | 0x06 | 0x07 => {
+                        let header_size = if packet_type != 0x01 {
                             voipc_protocol::voice::ENCRYPTED_VOICE_HEADER_SIZE
                         } else {
                             voipc_protocol::voice::VOICE_HEADER_SIZE
@@ -1716,7 +1982,36 @@ async fn udp_receiver_task(
                             u32::from_be_bytes([buf[13], buf[14], buf[15], buf[16]]);
 
                         // Decrypt if encrypted, otherwise use raw data
-                        let opus_data: Vec<u8> = if packet_type == 0x05 {
+                        let opus_data: Vec<u8> = if packet_type == 0x06 {
+                            // Whisper: encrypted with the sender's whisper key
+                            let raw_encrypted = &buf[header_size..n];
+                            let keys = whisper_keys.lock().unwrap_or_else(|poisoned| {
+                                warn!("whisper key mutex poisoned — recovering");
+                                poisoned.into_inner()
+                            });
+                            let Some(key) = keys.get(&session_id) else {
+                                debug!("Received whisper from session {} before its key", session_id);
+                                continue;
+                            };
+                            let aad = voipc_crypto::build_aad(0, 0x06);
+                            match voipc_crypto::media_decrypt(
+                                key,
+                                session_id,
+                                sequence,
+                                0,
+                                &aad,
+                                raw_encrypted,
+                            ) {
+                                Ok(decrypted) => decrypted,
+                                Err(e) => {
+                                    warn!(
+                                        "Whisper decryption failed from session {}: {}",
+                                        session_id, e
+                                    );
+                                    continue;
+                                }
+                            }
+                        } else if packet_type == 0x05 || packet_type == 0x07 {
                             let raw_encrypted = &buf[header_size..n];
                             let key_guard = media_key.lock().unwrap_or_else(|poisoned| {
                                 warn!("media key mutex poisoned — recovering");
@@ -1725,6 +2020,7 @@ async fn udp_receiver_task(
                             let key_opt = key_guard.as_ref();
                             if let Some(key) = key_opt {
                                 let ch_id = channel_id.load(Ordering::Relaxed);
+                                // Priority packets were encrypted as 0x05 by the sender
                                 let aad = voipc_crypto::build_aad(ch_id, 0x05);
                                 match voipc_crypto::media_decrypt(
                                     key,
@@ -1751,6 +2047,10 @@ async fn udp_receiver_task(
                             buf[header_size..n].to_vec()
                         };
 
+                        if packet_type == 0x07 {
+                            last_priority_time.insert(session_id, std::time::Instant::now());
+                        }
+
                         // Enqueue into per-user jitter buffer (chain push to release borrow)
                         jitter_buffers
                             .entry(session_id)
@@ -1783,12 +2083,17 @@ async fn udp_receiver_task(
                             &playback_producer,
                             &is_deafened,
                             &user_volumes,
+                            duck_gain(&last_priority_time, session_id),
                         );
 
                         last_voice_time.insert(session_id, std::time::Instant::now());
                         let _ = app_handle.emit(
                             "user-speaking",
-                            serde_json::json!({"user_id": session_id, "speaking": true}),
+                            serde_json::json!({
+                                "user_id": session_id,
+                                "speaking": true,
+                                "whisper": packet_type == 0x06,
+                            }),
                         );
                     }
                     // Voice: EndOfTransmission
@@ -1808,12 +2113,14 @@ async fn udp_receiver_task(
                                     &playback_producer,
                                     &is_deafened,
                                     &user_volumes,
+                                    duck_gain(&last_priority_time, session_id),
                                 );
                             }
                             jitter.reset();
                         }
                         // Keep decoder alive for state continuity across PTT cycles
                         last_voice_time.remove(&session_id);
+                        last_priority_time.remove(&session_id);
                         let _ = app_handle.emit(
                             "user-speaking",
                             serde_json::json!({"user_id": session_id, "speaking": false}),
@@ -2133,6 +2440,8 @@ pub fn spawn_capture_encode_task(
     voice_tx: mpsc::Sender<Vec<u8>>,
     media_key: Arc<std::sync::Mutex<Option<MediaKey>>>,
     channel_id: Arc<AtomicU32>,
+    whispering: Arc<AtomicBool>,
+    whisper_keys: Arc<std::sync::Mutex<HashMap<u32, MediaKey>>>,
     voice_mode: Arc<AtomicU8>,
     vad_threshold_db: Arc<AtomicI32>,
     current_audio_level: Arc<AtomicI32>,
@@ -2205,7 +2514,9 @@ pub fn spawn_capture_encode_task(
 
             // Check voice mode to decide whether to send
             let mode = crate::app_state::VoiceMode::from_u8(voice_mode.load(Ordering::Relaxed));
+            let whisper = whispering.load(Ordering::Relaxed);
             let should_send = match mode {
+                _ if whisper => true,                           // Whisper key held: always send
                 crate::app_state::VoiceMode::Ptt => true,       // PTT: always send while transmitting
                 crate::app_state::VoiceMode::Vad => voice_detected,
                 crate::app_state::VoiceMode::AlwaysOn => true,
@@ -2218,6 +2529,42 @@ pub fn spawn_capture_encode_task(
 
             // We have a full frame — encode and send
             match encoder.encode(&pcm_buf) {
+                Ok(opus_data) if whisper => {
+                    let packet = {
+                        let keys = whisper_keys.lock().unwrap_or_else(|poisoned| {
+                            warn!("whisper key mutex poisoned — recovering");
+                            poisoned.into_inner()
+                        });
+                        // Whispers are never sent unencrypted — wait for the key
+                        let Some(key) = keys.get(&session_id) else {
+                            accumulated = 0;
+                            continue;
+                        };
+                        let aad = voipc_crypto::build_aad(0, 0x06);
+                        match voipc_crypto::media_encrypt(
+                            key, session_id, sequence, 0, &aad, &opus_data,
+                        ) {
+                            Ok(encrypted) => VoicePacket::encrypted_whisper(
+                                session_id,
+                                udp_token,
+                                sequence,
+                                key.key_id,
+                                encrypted,
+                            ),
+                            Err(e) => {
+                                warn!("Whisper encryption failed (seq {}): {}", sequence, e);
+                                sequence = sequence.saturating_add(1);
+                                accumulated = 0;
+                                continue;
+                            }
+                        }
+                    };
+                    sequence = sequence.saturating_add(1);
+
+                    if voice_tx.blocking_send(packet.to_bytes()).is_err() {
+                        break;
+                    }
+                }
                 Ok(opus_data) => {
                     let packet = {
                         let key_guard = media_key.lock().unwrap_or_else(|poisoned| {
@@ -2273,156 +2620,3 @@ pub fn spawn_capture_encode_task(
         info!("capture+encode task stopped");
     })
 }
-
-/// TOFU (Trust On First Use) certificate pinning store.
-/// Maps server hostname to SHA-256 fingerprint of the server's certificate.
-/// On first connect, the cert is trusted and stored. On subsequent connects,
-/// the cert must match the stored fingerprint or the connection is rejected.
-/// Persisted to `tofu_pins.json` in the VoIPC data directory.
-static TOFU_STORE: std::sync::LazyLock<std::sync::Mutex<HashMap<String, Vec<u8>>>> =
-    std::sync::LazyLock::new(|| std::sync::Mutex::new(tofu_load_from_disk()));
-
-/// Load TOFU pins from disk. Returns empty map on any error.
-fn tofu_load_from_disk() -> HashMap<String, Vec<u8>> {
-    use base64::{engine::general_purpose::STANDARD, Engine};
-    let path = crate::config::data_dir().join("tofu_pins.json");
-    let Ok(data) = std::fs::read_to_string(&path) else {
-        return HashMap::new();
-    };
-    // Stored as { "host": "base64-encoded-fingerprint", ... }
-    let Ok(map): Result<HashMap<String, String>, _> = serde_json::from_str(&data) else {
-        return HashMap::new();
-    };
-    map.into_iter()
-        .filter_map(|(k, v)| STANDARD.decode(&v).ok().map(|bytes| (k, bytes)))
-        .collect()
-}
-
-/// Save TOFU pins to disk. Errors are logged but non-fatal.
-fn tofu_save_to_disk(store: &HashMap<String, Vec<u8>>) {
-    use base64::{engine::general_purpose::STANDARD, Engine};
-    let path = crate::config::data_dir().join("tofu_pins.json");
-    let b64_map: HashMap<&str, String> = store
-        .iter()
-        .map(|(k, v)| (k.as_str(), STANDARD.encode(v)))
-        .collect();
-    match serde_json::to_string_pretty(&b64_map) {
-        Ok(json) => {
-            if let Err(e) = std::fs::write(&path, json) {
-                warn!("Failed to save TOFU pins: {}", e);
-            }
-        }
-        Err(e) => warn!("Failed to serialize TOFU pins: {}", e),
-    }
-}
-
-/// Certificate verifier that accepts self-signed certs with TOFU pinning.
-/// First connection to a host: accept and pin the certificate fingerprint.
-/// Subsequent connections: reject if the certificate fingerprint changes.
-#[derive(Debug)]
-struct TofuCertVerifier;
-
-impl rustls::client::danger::ServerCertVerifier for TofuCertVerifier {
-    fn verify_server_cert(
-        &self,
-        end_entity: &rustls::pki_types::CertificateDer<'_>,
-        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
-        server_name: &rustls::pki_types::ServerName<'_>,
-        _ocsp_response: &[u8],
-        _now: rustls::pki_types::UnixTime,
-    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
-        // Compute SHA-256 fingerprint of the server's certificate
-        use ring::digest;
-        let fingerprint = digest::digest(&digest::SHA256, end_entity.as_ref());
-        let fp_bytes = fingerprint.as_ref().to_vec();
-        // Canonical host key: lowercase DNS name or standard IP string.
-        // Avoids Debug format which is fragile across rustls versions.
-        let host_key = match server_name {
-            rustls::pki_types::ServerName::DnsName(dns) => dns.as_ref().to_lowercase(),
-            rustls::pki_types::ServerName::IpAddress(ip) => {
-                let std_ip: std::net::IpAddr = match ip {
-                    rustls::pki_types::IpAddr::V4(v4) => {
-                        std::net::IpAddr::V4(std::net::Ipv4Addr::from(*v4.as_ref()))
-                    }
-                    rustls::pki_types::IpAddr::V6(v6) => {
-                        std::net::IpAddr::V6(std::net::Ipv6Addr::from(*v6.as_ref()))
-                    }
-                };
-                std_ip.to_string()
-            }
-            _ => {
-                warn!("TOFU: unknown ServerName variant, using Debug format (fragile across rustls versions)");
-                format!("{:?}", server_name)
-            }
-        };
-
-        let mut store = TOFU_STORE.lock().unwrap_or_else(|p| { warn!("mutex poisoned, recovering"); p.into_inner() });
-
-        if let Some(pinned) = store.get(&host_key) {
-            // We've connected to this host before — verify the fingerprint matches
-            if *pinned != fp_bytes {
-                warn!(
-                    "TOFU: certificate fingerprint changed for {}! Possible MITM attack.",
-                    host_key
-                );
-                return Err(rustls::Error::General(format!(
-                    "Server certificate fingerprint changed for {}. \
-                     This could indicate a man-in-the-middle attack. \
-                     If the server certificate was intentionally changed, \
-                     restart the application to accept the new certificate.",
-                    host_key
-                )));
-            }
-            info!("TOFU: certificate fingerprint matches for {}", host_key);
-        } else {
-            // First connection — pin the certificate and persist to disk
-            info!("TOFU: pinning certificate for {} (first connection)", host_key);
-            store.insert(host_key, fp_bytes);
-            tofu_save_to_disk(&store);
-        }
-
-        Ok(rustls::client::danger::ServerCertVerified::assertion())
-    }
-
-    fn verify_tls12_signature(
-        &self,
-        message: &[u8],
-        cert: &rustls::pki_types::CertificateDer<'_>,
-        dss: &rustls::DigitallySignedStruct,
-    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
-        rustls::crypto::verify_tls12_signature(
-            message,
-            cert,
-            dss,
-            &rustls::crypto::ring::default_provider().signature_verification_algorithms,
-        )
-    }
-
-    fn verify_tls13_signature(
-        &self,
-        message: &[u8],
-        cert: &rustls::pki_types::CertificateDer<'_>,
-        dss: &rustls::DigitallySignedStruct,
-    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
-        rustls::crypto::verify_tls13_signature(
-            message,
-            cert,
-            dss,
-            &rustls::crypto::ring::default_provider().signature_verification_algorithms,
-        )
-    }
-
-    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
-        vec![
-            rustls::SignatureScheme::RSA_PKCS1_SHA256,
-            rustls::SignatureScheme::RSA_PKCS1_SHA384,
-            rustls::SignatureScheme::RSA_PKCS1_SHA512,
-            rustls::SignatureScheme::RSA_PSS_SHA256,
-            rustls::SignatureScheme::RSA_PSS_SHA384,
-            rustls::SignatureScheme::RSA_PSS_SHA512,
-            rustls::SignatureScheme::ECDSA_NISTP256_SHA256,
-            rustls::SignatureScheme::ECDSA_NISTP384_SHA384,
-            rustls::SignatureScheme::ED25519,
-        ]
-    }
-}
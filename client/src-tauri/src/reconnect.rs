@@ -0,0 +1,291 @@
+//! Automatic reconnect after the connection to the server drops.
+//!
+//! The dead connection is closed without saying goodbye, so the server keeps
+//! our session for a while and a quick reconnect resumes it (same user ID,
+//! still in the channel). Otherwise the login is fresh and the previous
+//! channel, screen share and mute/deafen state are restored by hand. Signal
+//! sessions and sender keys are re-established by the usual `UserList`
+//! handling either way.
+
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use tauri::{Emitter, Manager};
+use tracing::{info, warn};
+
+use voipc_protocol::messages::ClientMessage;
+use voipc_protocol::types::{AuthErrorCode, ChannelId, UserId};
+
+use crate::app_state::AppState;
+use crate::network;
+
+/// Attempts before giving up.
+const MAX_ATTEMPTS: u32 = 8;
+/// Delay before the first attempt; doubles with each one after.
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+/// How long to wait for the server to put us back in our channel before
+/// re-watching a screen share there.
+const REJOIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Delay before the given attempt (counting from 1).
+fn backoff(attempt: u32) -> Duration {
+    INITIAL_DELAY
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+        .min(MAX_DELAY)
+}
+
+/// Whether a refused login will not go away by retrying.
+fn is_fatal(code: AuthErrorCode) -> bool {
+    matches!(
+        code,
+        AuthErrorCode::VersionMismatch
+            | AuthErrorCode::Banned
+            | AuthErrorCode::IdentityMismatch
+            | AuthErrorCode::GuestsDisabled
+            | AuthErrorCode::UnknownServer
+    )
+}
+
+/// Stop reconnecting, e.g. because the server kicked or banned us.
+pub fn forget(app_handle: &tauri::AppHandle) {
+    let state = app_handle.state::<AppState>();
+    state.reconnect_generation.fetch_add(1, Ordering::Relaxed);
+    *state.reconnect.lock().unwrap_or_else(|p| p.into_inner()) = None;
+}
+
+/// Move to another server because this one is shutting down. The session
+/// can't be resumed there, so the login is fresh.
+pub fn redirect(app_handle: &tauri::AppHandle, address: String) {
+    let state = app_handle.state::<AppState>();
+    {
+        let mut info = state.reconnect.lock().unwrap_or_else(|p| p.into_inner());
+        let Some(info) = info.as_mut() else {
+            return;
+        };
+        info!("server shutting down, moving to {}", address);
+        info.address = address.clone();
+        info.resume_token = None;
+    }
+    connection_lost(app_handle, format!("Server moved to {}", address));
+}
+
+/// Called when the TCP connection drops. Reconnects in the background if
+/// the user is still meant to be connected, otherwise reports the loss.
+pub fn connection_lost(app_handle: &tauri::AppHandle, reason: String) {
+    let state = app_handle.state::<AppState>();
+    if state.reconnect.lock().unwrap_or_else(|p| p.into_inner()).is_none() {
+        let _ = app_handle.emit("connection-lost", serde_json::json!({"reason": reason}));
+        return;
+    }
+    let generation = state.reconnect_generation.fetch_add(1, Ordering::Relaxed) + 1;
+    tauri::async_runtime::spawn(run(app_handle.clone(), reason, generation));
+}
+
+/// What the dropped connection was doing, to be restored afterwards.
+#[derive(Default)]
+struct Restore {
+    channel_id: ChannelId,
+    watching_user_id: Option<UserId>,
+    was_sharing: bool,
+    muted: bool,
+    deafened: bool,
+}
+
+async fn run(app_handle: tauri::AppHandle, reason: String, generation: u64) {
+    let state = app_handle.state::<AppState>();
+    let current = || state.reconnect_generation.load(Ordering::Relaxed) == generation;
+
+    let old = state.connection.write().await.take();
+    let restore = match old {
+        Some(old) => {
+            let restore = Restore {
+                channel_id: old.current_channel_id.load(Ordering::Relaxed),
+                watching_user_id: old.watching_user_id,
+                was_sharing: old.is_screen_sharing,
+                muted: old.is_muted.load(Ordering::Relaxed),
+                deafened: old.is_deafened.load(Ordering::Relaxed),
+            };
+            network::close_connection(old, false).await;
+            restore
+        }
+        None => Restore::default(),
+    };
+    warn!("connection lost ({}), reconnecting", reason);
+
+    let mut last_error = reason;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let delay = backoff(attempt);
+        let _ = app_handle.emit(
+            "reconnecting",
+            serde_json::json!({
+                "attempt": attempt,
+                "max_attempts": MAX_ATTEMPTS,
+                "delay_ms": delay.as_millis() as u64,
+                "error": last_error,
+            }),
+        );
+        tokio::time::sleep(delay).await;
+
+        let params = if current() {
+            state
+                .reconnect
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .as_ref()
+                .map(|info| {
+                    (
+                        info.address.clone(),
+                        info.username.clone(),
+                        info.accept_invalid_certs,
+                        info.resume_token.clone(),
+                    )
+                })
+        } else {
+            None
+        };
+        let Some((address, username, accept_invalid_certs, resume_token)) = params else {
+            info!("reconnect cancelled");
+            return;
+        };
+
+        match network::connect_to_server(
+            &state,
+            app_handle.clone(),
+            address,
+            username,
+            accept_invalid_certs,
+            resume_token,
+        )
+        .await
+        {
+            Ok(connected) => {
+                if !current() {
+                    // The user disconnected while we were connecting
+                    let conn = state.connection.write().await.take();
+                    if let Some(conn) = conn {
+                        network::close_connection(conn, true).await;
+                    }
+                    return;
+                }
+                if let Some(info) = state.reconnect.lock().unwrap_or_else(|p| p.into_inner()).as_mut() {
+                    info.resume_token = Some(connected.resume_token);
+                }
+                info!(attempt, resumed = connected.resumed, "reconnected");
+                restore_state(&state, &restore, connected.resumed).await;
+                let _ = app_handle.emit(
+                    "reconnected",
+                    serde_json::json!({
+                        "user_id": connected.user_id,
+                        "resumed": connected.resumed,
+                    }),
+                );
+                return;
+            }
+            Err(e) => {
+                warn!(attempt, "reconnect failed: {}", e);
+                if e.code.is_some_and(is_fatal) {
+                    forget(&app_handle);
+                    let _ = app_handle.emit("connection-lost", serde_json::json!({"reason": e.message}));
+                    return;
+                }
+                last_error = e.message;
+            }
+        }
+    }
+
+    if current() {
+        forget(&app_handle);
+        let _ = app_handle.emit(
+            "connection-lost",
+            serde_json::json!({
+                "reason": format!("Gave up after {} attempts: {}", MAX_ATTEMPTS, last_error)
+            }),
+        );
+    }
+}
+
+/// Put the new connection back into the state the old one was in. A resumed
+/// session is still in its channel; a fresh one rejoins it with the password
+/// it was joined with.
+async fn restore_state(state: &AppState, restore: &Restore, resumed: bool) {
+    let (tcp_tx, channel_id_store) = {
+        let conn = state.connection.read().await;
+        let Some(conn) = conn.as_ref() else {
+            return;
+        };
+        // Connecting applies the saved settings; the live toggles may differ
+        conn.is_muted.store(restore.muted, Ordering::Relaxed);
+        conn.is_deafened.store(restore.deafened, Ordering::Relaxed);
+        (conn.tcp_tx.clone(), conn.current_channel_id.clone())
+    };
+    let _ = network::send_tcp_message(&tcp_tx, &ClientMessage::SetMuted { muted: restore.muted }).await;
+    let _ = network::send_tcp_message(&tcp_tx, &ClientMessage::SetDeafened {
+        deafened: restore.deafened,
+    })
+    .await;
+
+    // Our capture ended with the old connection
+    if resumed && restore.was_sharing {
+        let _ = network::send_tcp_message(&tcp_tx, &ClientMessage::StopScreenShare).await;
+    }
+
+    if restore.channel_id == 0 {
+        return;
+    }
+    if !resumed {
+        let password = state
+            .reconnect
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .as_ref()
+            .and_then(|info| info.channel_passwords.get(&restore.channel_id).cloned());
+        let _ = network::send_tcp_message(&tcp_tx, &ClientMessage::JoinChannel {
+            channel_id: restore.channel_id,
+            password,
+        })
+        .await;
+    }
+
+    let Some(sharer_user_id) = restore.watching_user_id else {
+        return;
+    };
+    // Entering the channel resets watching, so wait for its UserList first
+    let deadline = tokio::time::Instant::now() + REJOIN_TIMEOUT;
+    while channel_id_store.load(Ordering::Relaxed) != restore.channel_id {
+        if tokio::time::Instant::now() >= deadline {
+            warn!(channel_id = restore.channel_id, "could not rejoin channel after reconnect");
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let _ = network::send_tcp_message(&tcp_tx, &ClientMessage::WatchScreenShare { sharer_user_id }).await;
+    if let Some(conn) = state.connection.write().await.as_mut() {
+        conn.watching_user_id = Some(sharer_user_id);
+        conn.watching_user_id_shared.store(sharer_user_id, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(2), Duration::from_secs(2));
+        assert_eq!(backoff(5), Duration::from_secs(16));
+        assert_eq!(backoff(6), MAX_DELAY);
+        assert_eq!(backoff(100), MAX_DELAY);
+    }
+
+    #[test]
+    fn only_permanent_errors_stop_reconnecting() {
+        assert!(is_fatal(AuthErrorCode::Banned));
+        assert!(is_fatal(AuthErrorCode::VersionMismatch));
+        assert!(is_fatal(AuthErrorCode::UnknownServer));
+        assert!(!is_fatal(AuthErrorCode::UsernameTaken));
+        assert!(!is_fatal(AuthErrorCode::ServerFull));
+        assert!(!is_fatal(AuthErrorCode::Other));
+    }
+}
@@ -0,0 +1,5 @@
+import { writable } from "svelte/store";
+import type { BanInfo } from "../types.js";
+
+/** Active server bans, as last reported by the server (moderators only). */
+export const bans = writable<BanInfo[]>([]);
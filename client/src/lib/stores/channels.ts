@@ -1,7 +1,44 @@
-import { writable } from "svelte/store";
+import { derived, writable } from "svelte/store";
 import type { ChannelInfo, UserInfo } from "../types.js";
 
 export const channels = writable<ChannelInfo[]>([]);
 export const currentChannelId = writable<number>(0);
 export const previewChannelId = writable<number | null>(null);
 export const previewUsers = writable<UserInfo[]>([]);
+
+export interface ChannelTreeEntry {
+  channel: ChannelInfo;
+  depth: number;
+}
+
+/** Channels in display order: each parent followed by its sub-channels,
+ *  siblings sorted by position then id. Channels whose parent is unknown
+ *  are shown at the top level. */
+export const channelTree = derived(channels, ($channels): ChannelTreeEntry[] => {
+  const ids = new Set($channels.map((c) => c.channel_id));
+  const children = new Map<number | null, ChannelInfo[]>();
+  for (const channel of $channels) {
+    const parent = channel.parent_id !== null && ids.has(channel.parent_id) ? channel.parent_id : null;
+    const siblings = children.get(parent) ?? [];
+    siblings.push(channel);
+    children.set(parent, siblings);
+  }
+
+  const tree: ChannelTreeEntry[] = [];
+  const visited = new Set<number>();
+  const walk = (parent: number | null, depth: number) => {
+    const siblings = (children.get(parent) ?? []).sort(
+      (a, b) => a.position - b.position || a.channel_id - b.channel_id,
+    );
+    for (const channel of siblings) {
+      if (visited.has(channel.channel_id)) continue;
+      visited.add(channel.channel_id);
+      tree.push({ channel, depth });
+      walk(channel.channel_id, depth + 1);
+    }
+  };
+  walk(null, 0);
+  // General always comes first
+  tree.sort((a, b) => Number(b.channel.channel_id === 0) - Number(a.channel.channel_id === 0));
+  return tree;
+});
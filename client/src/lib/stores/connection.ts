@@ -12,3 +12,5 @@ export const isMuted = writable<boolean>(false);
 export const isDeafened = writable<boolean>(false);
 export const isTransmitting = writable<boolean>(false);
 export const acceptSelfSigned = writable<boolean>(false);
+/** Message of the day from the current server (empty = none). */
+export const motd = writable<string>("");
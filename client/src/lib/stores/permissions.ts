@@ -0,0 +1,38 @@
+import { derived, writable } from "svelte/store";
+import type { ChannelPermissions, Permissions, ServerGroup } from "../types.js";
+
+/** Everything allowed — matches the server's defaults until it tells us otherwise. */
+function defaultPermissions(): Permissions {
+  return {
+    join: true,
+    speak: true,
+    screen_share: true,
+    create_channel: true,
+    kick: false,
+    move_users: false,
+    edit: false,
+    ban: false,
+    create_permanent_channel: false,
+  };
+}
+
+export const serverGroup = writable<ServerGroup>("guest");
+export const serverPermissions = writable<Permissions>(defaultPermissions());
+export const channelPermissions = writable<Map<number, Permissions>>(new Map());
+
+/** Lookup for effective permissions in a channel: `$permissionsIn(channelId).kick`. */
+export const permissionsIn = derived(
+  [serverPermissions, channelPermissions],
+  ([$server, $channels]) =>
+    (channelId: number): Permissions => $channels.get(channelId) ?? $server,
+);
+
+export function setPermissions(
+  group: ServerGroup,
+  server: Permissions,
+  channels: ChannelPermissions[],
+) {
+  serverGroup.set(group);
+  serverPermissions.set(server);
+  channelPermissions.set(new Map(channels.map((c) => [c.channel_id, c.permissions])));
+}
@@ -5,6 +5,7 @@ export const outputDevice = writable<string>("");
 export const volume = writable<number>(1.0);
 export const pttKey = writable<string>("Space");
 export const pttHoldMode = writable<boolean>(true);
+export const whisperKey = writable<string>("");
 export const noiseSuppression = writable<boolean>(true);
 
 // Connection persistence
@@ -56,6 +57,7 @@ export interface AppConfig {
   vad_threshold_db: number;
   ptt_key: string;
   ptt_hold_mode: boolean;
+  whisper_key: string;
   muted: boolean;
   deafened: boolean;
   remember_connection: boolean;
@@ -1,4 +1,6 @@
-import { writable } from "svelte/store";
+import { invoke } from "@tauri-apps/api/core";
+import { get, writable } from "svelte/store";
+import type { WhisperTarget } from "../types.js";
 
 export type VoiceMode = "ptt" | "vad" | "always_on";
 
@@ -13,3 +15,24 @@ export const audioLevel = writable<number>(-96);
 
 /** Whether speakerphone is enabled (Android only). Defaults to true. */
 export const speakerMode = writable<boolean>(true);
+
+/** Who the whisper key talks to. Mirrored to the server on every change. */
+export const whisperTargets = writable<WhisperTarget[]>([]);
+
+function sameTarget(a: WhisperTarget, b: WhisperTarget): boolean {
+  return JSON.stringify(a) === JSON.stringify(b);
+}
+
+export function inWhisperList(targets: WhisperTarget[], target: WhisperTarget): boolean {
+  return targets.some((t) => sameTarget(t, target));
+}
+
+/** Add or remove a whisper target and send the new list to the server. */
+export async function toggleWhisperTarget(target: WhisperTarget) {
+  const current = get(whisperTargets);
+  const next = inWhisperList(current, target)
+    ? current.filter((t) => !sameTarget(t, target))
+    : [...current, target];
+  whisperTargets.set(next);
+  await invoke("set_whisper_list", { targets: next });
+}
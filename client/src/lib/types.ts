@@ -5,6 +5,9 @@ export interface UserInfo {
   is_muted: boolean;
   is_deafened: boolean;
   is_screen_sharing: boolean;
+  is_server_muted: boolean;
+  is_priority_speaker: boolean;
+  is_idle: boolean;
 }
 
 export interface ChannelInfo {
@@ -15,6 +18,45 @@ export interface ChannelInfo {
   user_count: number;
   has_password: boolean;
   created_by: number | null;
+  parent_id: number | null;
+  position: number;
+  topic: string;
+}
+
+/** Whisper list entry — a user, a channel, or a channel with all its sub-channels. */
+export type WhisperTarget =
+  | { user: number }
+  | { channel: number }
+  | { channel_tree: number };
+
+export type ServerGroup = "admin" | "moderator" | "member" | "guest";
+
+export interface Permissions {
+  join: boolean;
+  speak: boolean;
+  screen_share: boolean;
+  create_channel: boolean;
+  kick: boolean;
+  move_users: boolean;
+  edit: boolean;
+  ban: boolean;
+  create_permanent_channel: boolean;
+}
+
+export interface BanInfo {
+  ban_id: number;
+  username: string | null;
+  identity_key: string | null;
+  ip: string | null;
+  reason: string;
+  banned_by: string;
+  created_at: number;
+  expires_at: number | null;
+}
+
+export interface ChannelPermissions {
+  channel_id: number;
+  permissions: Permissions;
 }
 
 export interface ConnectionInfo {
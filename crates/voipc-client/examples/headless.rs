@@ -0,0 +1,59 @@
+// Headless client: joins a channel, prints what happens and answers
+// `!ping` in chat. Run with:
+//   cargo run -p voipc-client --example headless -- localhost:9987 Headless 1
+
+use voipc_client::protocol::messages::ServerMessage;
+use voipc_client::{Client, ClientConfig, Event};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "voipc_client=info".into()),
+        )
+        .init();
+    rustls::crypto::ring::default_provider()
+        .install_default()
+        .expect("crypto provider");
+
+    let mut args = std::env::args().skip(1);
+    let address = args.next().unwrap_or_else(|| "127.0.0.1:9987".into());
+    let username = args.next().unwrap_or_else(|| "Headless".into());
+    let channel_id: u32 = args.next().map_or(Ok(1), |c| c.parse())?;
+
+    let mut config = ClientConfig::new(address, username);
+    config.accept_invalid_certs = true;
+    let (client, mut events) = Client::connect(config).await?;
+    println!("connected as user {}", client.user_id());
+    client.join_channel(channel_id, None).await?;
+
+    while let Some(event) = events.recv().await {
+        match event {
+            Event::Message(ServerMessage::UserList { channel_id, users }) => {
+                let names: Vec<_> = users.iter().map(|u| u.username.as_str()).collect();
+                println!("channel {}: {}", channel_id, names.join(", "));
+            }
+            Event::Message(msg) => println!("{:?}", msg),
+            Event::ChannelMessage { username, content, .. } => {
+                println!("<{}> {}", username, content);
+                if content.trim() == "!ping" {
+                    client.send_channel_message("pong").await?;
+                }
+            }
+            Event::DirectMessage { from_user_id, from_username, content, .. } => {
+                println!("[DM] <{}> {}", from_username, content);
+                client.send_direct_message(from_user_id, format!("you said: {}", content)).await?;
+            }
+            Event::Speaking { session_id, speaking } => {
+                println!("session {} {}", session_id, if speaking { "speaking" } else { "quiet" });
+            }
+            Event::Disconnected { reason } => {
+                println!("disconnected: {}", reason);
+                break;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
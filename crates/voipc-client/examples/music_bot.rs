@@ -0,0 +1,221 @@
+// Music bot: sits in a channel and plays WAV or Ogg Vorbis files from a
+// directory on `!play <file>`. Commands work in channel chat and in direct
+// messages. Run with:
+//   cargo run -p voipc-client --example music_bot -- localhost:9987 1 ./music
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use symphonia::core::audio::{SampleBuffer, SignalSpec};
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use voipc_client::bot::Invocation;
+use voipc_client::player::{AudioSource, Frame, Playback, Resampler};
+use voipc_client::protocol::voice::OPUS_FRAME_SIZE;
+use voipc_client::{Client, ClientConfig, Event};
+
+const HELP: &str = "!play <file> plays a file, !stop stops it, !list shows what there is";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "voipc_client=info".into()),
+        )
+        .init();
+    rustls::crypto::ring::default_provider()
+        .install_default()
+        .expect("crypto provider");
+
+    let mut args = std::env::args().skip(1);
+    let address = args.next().unwrap_or_else(|| "127.0.0.1:9987".into());
+    let channel_id: u32 = args.next().map_or(Ok(1), |c| c.parse())?;
+    let music_dir = PathBuf::from(args.next().unwrap_or_else(|| ".".into()));
+
+    let mut config = ClientConfig::new(address, "MusicBot");
+    config.accept_invalid_certs = true;
+    let (client, mut events) = Client::connect(config).await?;
+    let client = Arc::new(client);
+    client.join_channel(channel_id, None).await?;
+    println!(
+        "connected as user {}, playing from {}",
+        client.user_id(),
+        music_dir.display()
+    );
+
+    let mut playing: Option<Playback> = None;
+    while let Some(event) = events.recv().await {
+        if let Event::Disconnected { reason } = &event {
+            println!("disconnected: {}", reason);
+            break;
+        }
+        let Some(inv) = Invocation::from_event(&event, "!") else {
+            continue;
+        };
+        match inv.command.name.as_str() {
+            "play" => {
+                let Some(path) = inv
+                    .command
+                    .arg(0)
+                    .and_then(|name| music_file(&music_dir, name))
+                else {
+                    inv.reply(&client, "usage: !play <file>, see !list").await?;
+                    continue;
+                };
+                let opened = tokio::task::spawn_blocking(move || FileSource::open(&path)).await?;
+                match opened {
+                    Ok(source) => {
+                        stop(&mut playing).await;
+                        let name = &inv.command.args[0];
+                        let reply = match source.duration_secs() {
+                            Some(secs) => {
+                                format!("playing {} ({}:{:02})", name, secs / 60, secs % 60)
+                            }
+                            None => format!("playing {}", name),
+                        };
+                        inv.reply(&client, reply).await?;
+                        playing = Some(Playback::start(client.clone(), source));
+                    }
+                    Err(e) => {
+                        inv.reply(&client, format!("can't play that: {:#}", e))
+                            .await?
+                    }
+                }
+            }
+            "stop" => stop(&mut playing).await,
+            "list" => {
+                let mut names: Vec<String> = std::fs::read_dir(&music_dir)?
+                    .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                    .filter(|name| name.ends_with(".wav") || name.ends_with(".ogg"))
+                    .collect();
+                names.sort();
+                inv.reply(&client, names.join(", ")).await?;
+            }
+            "help" => inv.reply(&client, HELP).await?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+async fn stop(playing: &mut Option<Playback>) {
+    if let Some(mut playback) = playing.take() {
+        playback.stop();
+        let _ = playback.wait().await;
+    }
+}
+
+/// A file directly inside the music directory; no paths.
+fn music_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    let file_name = Path::new(name).file_name()?;
+    (file_name == name).then(|| dir.join(file_name))
+}
+
+/// A file decoded a packet at a time as playback asks for frames, so only
+/// a moment of audio is held in memory however long the file is.
+struct FileSource {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    /// Resampler for the format of the last packet.
+    resampler: Option<(SignalSpec, Resampler)>,
+    /// 48 kHz mono samples decoded but not yet played.
+    pcm: Vec<f32>,
+}
+
+impl FileSource {
+    fn open(path: &Path) -> Result<FileSource> {
+        let file =
+            std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+        let format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )?
+            .format;
+        let track = format.default_track().context("no audio track")?;
+        let track_id = track.id;
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+        Ok(FileSource {
+            format,
+            decoder,
+            track_id,
+            resampler: None,
+            pcm: Vec::new(),
+        })
+    }
+
+    /// Length in seconds, if the file says.
+    fn duration_secs(&self) -> Option<u64> {
+        let params = self.decoder.codec_params();
+        Some(params.n_frames? / u64::from(params.sample_rate?.max(1)))
+    }
+
+    /// Decode the next packet of our track into `pcm`. False at the end of
+    /// the file or on an error that ends decoding.
+    fn decode_packet(&mut self) -> bool {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return false
+                }
+                Err(e) => {
+                    eprintln!("stopped decoding: {}", e);
+                    return false;
+                }
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt packet only costs its own few milliseconds
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(e) => {
+                    eprintln!("stopped decoding: {}", e);
+                    return false;
+                }
+            };
+            let spec = *decoded.spec();
+            let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            buffer.copy_interleaved_ref(decoded);
+            let resampler = match &mut self.resampler {
+                Some((current, resampler)) if *current == spec => resampler,
+                resampler => {
+                    let new = Resampler::new(spec.channels.count(), spec.rate);
+                    &mut resampler.insert((spec, new)).1
+                }
+            };
+            resampler.process(buffer.samples(), &mut self.pcm);
+            return true;
+        }
+    }
+}
+
+impl AudioSource for FileSource {
+    fn next_frame(&mut self) -> Option<Frame> {
+        while self.pcm.len() < OPUS_FRAME_SIZE && self.decode_packet() {}
+        if self.pcm.is_empty() {
+            return None;
+        }
+        let take = self.pcm.len().min(OPUS_FRAME_SIZE);
+        Some(Frame::Pcm(self.pcm.drain(..take).collect()))
+    }
+}
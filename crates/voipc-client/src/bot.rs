@@ -0,0 +1,249 @@
+//! Building blocks for bots: chat commands, replies, and mixing what the
+//! channel says into one stream.
+
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::Result;
+use voipc_protocol::types::{SessionId, UserId};
+
+use crate::{Client, Event};
+
+/// Decoded frames kept per sender in a [`VoiceMixer`]. Anything older is
+/// dropped so the mix doesn't fall behind the conversation.
+const MAX_QUEUED_FRAMES: usize = 10;
+
+/// A chat command such as `!play "Morning News.ogg" 2`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    /// The command name after the prefix, lowercased.
+    pub name: String,
+    /// Whitespace-separated arguments. Double quotes group words into one
+    /// argument, and `\"` is a literal quote.
+    pub args: Vec<String>,
+    /// Everything after the name, as typed.
+    pub rest: String,
+}
+
+impl Command {
+    /// Parse a chat message as a command, or `None` if it doesn't start
+    /// with `prefix` followed by a name.
+    pub fn parse(prefix: &str, text: &str) -> Option<Command> {
+        let body = text.trim().strip_prefix(prefix)?;
+        let (name, rest) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+        if name.is_empty() {
+            return None;
+        }
+        let rest = rest.trim();
+        Some(Command {
+            name: name.to_lowercase(),
+            args: split_args(rest),
+            rest: rest.to_string(),
+        })
+    }
+
+    /// The argument at `index`, if given.
+    pub fn arg(&self, index: usize) -> Option<&str> {
+        self.args.get(index).map(String::as_str)
+    }
+}
+
+fn split_args(text: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quoted = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.as_str().starts_with('"') => {
+                current.push('"');
+                chars.next();
+                in_arg = true;
+            }
+            '"' => {
+                quoted = !quoted;
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+/// Where a command came from, and so where its answer goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyTo {
+    /// The channel the bot is in.
+    Channel,
+    /// A direct message with this user.
+    Direct(UserId),
+}
+
+/// A command someone sent the bot, in channel chat or a direct message.
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub command: Command,
+    pub user_id: UserId,
+    pub username: String,
+    pub reply_to: ReplyTo,
+}
+
+impl Invocation {
+    /// The command in a decrypted chat event, if there is one.
+    pub fn from_event(event: &Event, prefix: &str) -> Option<Invocation> {
+        match event {
+            Event::ChannelMessage {
+                user_id,
+                username,
+                content,
+                ..
+            } => Some(Invocation {
+                command: Command::parse(prefix, content)?,
+                user_id: *user_id,
+                username: username.clone(),
+                reply_to: ReplyTo::Channel,
+            }),
+            Event::DirectMessage {
+                from_user_id,
+                from_username,
+                content,
+                ..
+            } => Some(Invocation {
+                command: Command::parse(prefix, content)?,
+                user_id: *from_user_id,
+                username: from_username.clone(),
+                reply_to: ReplyTo::Direct(*from_user_id),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Answer where the command was sent.
+    pub async fn reply(&self, client: &Client, text: impl Into<String>) -> Result<()> {
+        match self.reply_to {
+            ReplyTo::Channel => client.send_channel_message(text).await,
+            ReplyTo::Direct(user_id) => client.send_direct_message(user_id, text).await,
+        }
+    }
+}
+
+/// Mixes [`Event::Voice`] from everyone speaking into one 48 kHz mono
+/// stream, for bots that record or relay the channel as a whole.
+#[derive(Debug, Default)]
+pub struct VoiceMixer {
+    queues: HashMap<SessionId, VecDeque<Vec<f32>>>,
+}
+
+impl VoiceMixer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a sender's decoded frame.
+    pub fn push(&mut self, session_id: SessionId, pcm: Vec<f32>) {
+        let queue = self.queues.entry(session_id).or_default();
+        if queue.len() == MAX_QUEUED_FRAMES {
+            queue.pop_front();
+        }
+        queue.push_back(pcm);
+    }
+
+    /// Mix the next frame of every sender with audio queued, or `None` when
+    /// nobody has anything. Call it every 20 ms for a continuous stream.
+    pub fn pop(&mut self) -> Option<Vec<f32>> {
+        let frames: Vec<Vec<f32>> = self
+            .queues
+            .values_mut()
+            .filter_map(VecDeque::pop_front)
+            .collect();
+        self.queues.retain(|_, queue| !queue.is_empty());
+        if frames.is_empty() {
+            return None;
+        }
+        let streams: Vec<&[f32]> = frames.iter().map(Vec::as_slice).collect();
+        Some(voipc_audio::mixer::mix_streams(&streams))
+    }
+
+    /// Forget a sender, e.g. when they leave.
+    pub fn remove(&mut self, session_id: SessionId) {
+        self.queues.remove(&session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use voipc_protocol::voice::OPUS_FRAME_SIZE;
+
+    #[test]
+    fn parses_commands_with_quoted_arguments() {
+        let cmd = Command::parse("!", r#"  !Play "Morning News.ogg" 2 say\"hi\"  "#).unwrap();
+        assert_eq!(cmd.name, "play");
+        assert_eq!(cmd.args, vec!["Morning News.ogg", "2", "say\"hi\""]);
+        assert_eq!(cmd.rest, r#""Morning News.ogg" 2 say\"hi\""#);
+        assert_eq!(cmd.arg(1), Some("2"));
+        assert_eq!(cmd.arg(3), None);
+
+        let cmd = Command::parse("!", "!stop").unwrap();
+        assert_eq!(cmd.name, "stop");
+        assert!(cmd.args.is_empty());
+        assert_eq!(Command::parse("!", r#"!say """#).unwrap().args, vec![""]);
+
+        assert_eq!(Command::parse("!", "hello !play"), None);
+        assert_eq!(Command::parse("!", "! play"), None);
+        assert_eq!(Command::parse(".", "!play"), None);
+    }
+
+    #[test]
+    fn invocations_reply_where_they_came_from() {
+        let dm = Event::DirectMessage {
+            from_user_id: 7,
+            from_username: "alice".into(),
+            to_user_id: 1,
+            content: "!help".into(),
+            timestamp: 0,
+        };
+        let inv = Invocation::from_event(&dm, "!").unwrap();
+        assert_eq!(inv.reply_to, ReplyTo::Direct(7));
+        assert_eq!(inv.username, "alice");
+
+        let chat = Event::ChannelMessage {
+            channel_id: 3,
+            user_id: 7,
+            username: "alice".into(),
+            content: "just chatting".into(),
+            timestamp: 0,
+        };
+        assert!(Invocation::from_event(&chat, "!").is_none());
+    }
+
+    #[test]
+    fn mixer_sums_senders_and_bounds_latency() {
+        let mut mixer = VoiceMixer::new();
+        assert!(mixer.pop().is_none());
+
+        mixer.push(1, vec![0.25; OPUS_FRAME_SIZE]);
+        mixer.push(2, vec![0.5; OPUS_FRAME_SIZE]);
+        mixer.push(2, vec![0.1; OPUS_FRAME_SIZE]);
+        assert_eq!(mixer.pop().unwrap()[0], 0.75);
+        assert_eq!(mixer.pop().unwrap()[0], 0.1);
+        assert!(mixer.pop().is_none());
+
+        for i in 0..MAX_QUEUED_FRAMES + 5 {
+            mixer.push(1, vec![i as f32 / 100.0; OPUS_FRAME_SIZE]);
+        }
+        assert_eq!(mixer.pop().unwrap()[0], 0.05);
+    }
+}
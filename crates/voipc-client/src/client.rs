@@ -0,0 +1,813 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::BytesMut;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use tracing::{debug, error, info, warn};
+
+use voipc_crypto::{MediaKey, SerializableIdentityKeyPair};
+use voipc_protocol::codec::{
+    auth_challenge_payload, decode_server_msg, encode_client_msg, pack_frame, try_decode_frame,
+    APP_VERSION, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use voipc_protocol::messages::{ClientMessage, ServerMessage};
+use voipc_protocol::types::{Capabilities, ChannelId, SessionId, UserId};
+use voipc_protocol::voice::VoicePacket;
+
+use crate::event::Event;
+use crate::media::{self, Transmitter};
+use crate::signal::{self, SignalState};
+use crate::tls;
+
+/// Events waiting for the application before control events wait and
+/// media events are dropped.
+const EVENT_QUEUE: usize = 1024;
+
+/// How to connect and log in.
+#[derive(Clone)]
+pub struct ClientConfig {
+    /// Server address, "host:port" or "[v6]:port".
+    pub address: String,
+    pub username: String,
+    /// Accept a self-signed certificate, pinning it on first use.
+    pub accept_invalid_certs: bool,
+    /// File to keep certificate pins in across runs; in memory only if None.
+    pub pin_file: Option<PathBuf>,
+    /// Virtual server to log in to; chosen by TLS server name if None.
+    pub virtual_server: Option<String>,
+    /// Long-term identity key. Registered accounts are bound to it, so
+    /// reuse it across runs; a throwaway one is generated if None.
+    pub identity: Option<SerializableIdentityKeyPair>,
+    /// Token from an earlier connection to resume its session.
+    pub resume_token: Option<Vec<u8>>,
+    /// Optional features to offer the server.
+    pub capabilities: Capabilities,
+}
+
+impl ClientConfig {
+    pub fn new(address: impl Into<String>, username: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            username: username.into(),
+            accept_invalid_certs: false,
+            pin_file: None,
+            virtual_server: None,
+            identity: None,
+            resume_token: None,
+            capabilities: Capabilities::ALL,
+        }
+    }
+}
+
+/// Connection state shared by the client handle and its background tasks.
+pub(crate) struct Shared {
+    pub(crate) user_id: UserId,
+    pub(crate) session_id: SessionId,
+    pub(crate) udp_token: u64,
+    tcp_tx: mpsc::Sender<Vec<u8>>,
+    voice_tx: mpsc::Sender<Vec<u8>>,
+    video_tx: mpsc::Sender<Vec<u8>>,
+    events: mpsc::Sender<Event>,
+    /// Channel we are in; 0 is the lobby.
+    pub(crate) channel_id: AtomicU32,
+    /// User whose screen share we watch; 0 if none.
+    pub(crate) watching_user_id: AtomicU32,
+    /// Server-issued key for the current channel's media.
+    pub(crate) media_key: Mutex<Option<MediaKey>>,
+    /// Whisper keys by sender session.
+    pub(crate) whisper_keys: Mutex<HashMap<SessionId, MediaKey>>,
+    pub(crate) signal: Mutex<SignalState>,
+}
+
+impl Shared {
+    /// Queue a message for the server.
+    pub(crate) async fn send(&self, msg: &ClientMessage) -> Result<()> {
+        let data = encode_client_msg(msg)?;
+        self.tcp_tx.send(data).await.map_err(|_| anyhow!("connection closed"))
+    }
+
+    /// Queue a message for the server without waiting for room.
+    pub(crate) fn try_send(&self, msg: &ClientMessage) -> Result<()> {
+        let data = encode_client_msg(msg)?;
+        self.tcp_tx.try_send(data).map_err(|_| anyhow!("send queue full or closed"))
+    }
+
+    async fn emit(&self, event: Event) {
+        let _ = self.events.send(event).await;
+    }
+
+    /// Emit voice or video, dropping it if the application is behind.
+    pub(crate) fn emit_media(&self, event: Event) {
+        let _ = self.events.try_send(event);
+    }
+}
+
+/// A logged-in connection to a server.
+///
+/// Encrypted chat and media keys are handled internally: Signal sessions and
+/// sender keys are exchanged with everyone we see, and messages arrive as
+/// plaintext [`Event`]s. Needs a multi-threaded Tokio runtime, as libsignal
+/// is driven with `block_in_place`.
+///
+/// Dropping the client closes the connection without saying goodbye, so the
+/// server keeps the session for [`resume_token`](Self::resume_token).
+pub struct Client {
+    shared: Arc<Shared>,
+    username: String,
+    resumed: bool,
+    resume_token: Vec<u8>,
+    protocol_version: u32,
+    capabilities: Capabilities,
+    transmitter: Mutex<Transmitter>,
+    screen_frame_id: AtomicU32,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Client {
+    /// Connect, authenticate and start the background tasks. Events arrive
+    /// on the returned receiver until the connection closes.
+    pub async fn connect(config: ClientConfig) -> Result<(Client, mpsc::Receiver<Event>)> {
+        let (host, port) = tls::parse_address(&config.address)?;
+
+        let tcp_stream = TcpStream::connect((&*host, port))
+            .await
+            .with_context(|| format!("could not connect to {}", config.address))?;
+        info!("TCP connected to {}", config.address);
+
+        let tls_config = tls::client_config(config.accept_invalid_certs, config.pin_file.clone());
+        let mut tls_stream = TlsConnector::from(Arc::new(tls_config))
+            .connect(tls::server_name(&host)?, tcp_stream)
+            .await
+            .context("TLS handshake failed")?;
+        info!("TLS handshake complete");
+
+        let identity = config.identity.clone().unwrap_or_else(|| {
+            SerializableIdentityKeyPair::from_identity_key_pair(&voipc_crypto::generate_identity_key_pair())
+        });
+        let (signal, prekey_bundle) = SignalState::new(&identity)?;
+
+        let auth = ClientMessage::Authenticate {
+            username: config.username.clone(),
+            protocol_version: PROTOCOL_VERSION,
+            app_version: APP_VERSION.to_string(),
+            identity_key: Some(identity.public_key.clone()),
+            prekey_bundle: Some(prekey_bundle),
+            virtual_server: config.virtual_server.clone(),
+            resume_token: config.resume_token.clone(),
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            capabilities: config.capabilities,
+        };
+        tls_stream.write_all(&encode_client_msg(&auth)?).await?;
+
+        let mut buf = BytesMut::with_capacity(4096);
+        let (user_id, session_id, udp_port, udp_token, resume_token, resumed, protocol_version, capabilities) = 'auth: loop {
+            if tls_stream.read_buf(&mut buf).await? == 0 {
+                bail!("server closed connection during authentication");
+            }
+            while let Some(payload) = try_decode_frame(&mut buf, Capabilities::NONE)? {
+                let msg = match decode_server_msg(&payload) {
+                    Ok(msg) => msg,
+                    Err(e) if e.is_unknown_message() => {
+                        debug!("skipping message from a newer server during auth: {}", e);
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                match msg {
+                    ServerMessage::Authenticated {
+                        user_id,
+                        session_id,
+                        udp_port,
+                        udp_token,
+                        resume_token,
+                        resumed,
+                        protocol_version,
+                        capabilities,
+                    } => {
+                        break 'auth (
+                            user_id,
+                            session_id,
+                            udp_port,
+                            udp_token,
+                            resume_token,
+                            resumed,
+                            protocol_version,
+                            capabilities,
+                        )
+                    }
+                    ServerMessage::AuthError { reason, .. } => bail!("authentication failed: {}", reason),
+                    ServerMessage::AuthChallenge { nonce } => {
+                        let signature = signal::sign_challenge(
+                            &identity,
+                            &auth_challenge_payload(&nonce, &config.username),
+                        )?;
+                        let response = ClientMessage::AuthChallengeResponse { signature };
+                        tls_stream.write_all(&encode_client_msg(&response)?).await?;
+                    }
+                    other => warn!("unexpected message during auth: {:?}", other),
+                }
+            }
+        };
+        info!(
+            user_id,
+            session_id,
+            udp_port,
+            resumed,
+            protocol_version,
+            capabilities = ?capabilities.names(),
+            "authenticated with server"
+        );
+
+        let udp_addr = format!("{}:{}", host, udp_port);
+        let server_addr = tokio::net::lookup_host(&udp_addr)
+            .await
+            .with_context(|| format!("failed to resolve UDP address {}", udp_addr))?
+            .next()
+            .ok_or_else(|| anyhow!("no addresses found for {}", udp_addr))?;
+        let udp_socket = Arc::new(bind_udp(server_addr)?);
+
+        // Let the server learn our UDP address
+        let ping = VoicePacket::ping(session_id, udp_token, 0);
+        if let Err(e) = udp_socket.send_to(&ping.to_bytes(), server_addr).await {
+            warn!("UDP ping send failed: {}", e);
+        }
+
+        let (read_half, write_half) = tokio::io::split(tls_stream);
+        let (tcp_tx, tcp_rx) = mpsc::channel(64);
+        let (voice_tx, voice_rx) = mpsc::channel(256);
+        let (video_tx, video_rx) = mpsc::channel(1024);
+        let (events_tx, events_rx) = mpsc::channel(EVENT_QUEUE);
+
+        let shared = Arc::new(Shared {
+            user_id,
+            session_id,
+            udp_token,
+            tcp_tx,
+            voice_tx,
+            video_tx,
+            events: events_tx,
+            channel_id: AtomicU32::new(0),
+            watching_user_id: AtomicU32::new(0),
+            media_key: Mutex::new(None),
+            whisper_keys: Mutex::new(HashMap::new()),
+            signal: Mutex::new(signal),
+        });
+
+        let tasks = vec![
+            tokio::spawn(tcp_writer_task(write_half, tcp_rx, capabilities)),
+            tokio::spawn(tcp_reader_task(read_half, buf, capabilities, shared.clone())),
+            tokio::spawn(media::udp_sender_task(udp_socket.clone(), voice_rx, server_addr)),
+            tokio::spawn(media::udp_sender_task(udp_socket.clone(), video_rx, server_addr)),
+            tokio::spawn(media::udp_receiver_task(udp_socket, shared.clone(), server_addr)),
+        ];
+
+        let client = Client {
+            shared,
+            username: config.username,
+            resumed,
+            resume_token,
+            protocol_version,
+            capabilities,
+            transmitter: Mutex::new(Transmitter::new()?),
+            screen_frame_id: AtomicU32::new(0),
+            tasks,
+        };
+        Ok((client, events_rx))
+    }
+
+    pub fn user_id(&self) -> UserId {
+        self.shared.user_id
+    }
+
+    /// Our session ID, which voice and screen share packets are tagged with.
+    pub fn session_id(&self) -> SessionId {
+        self.shared.session_id
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Channel we are in; 0 is the lobby.
+    pub fn channel_id(&self) -> ChannelId {
+        self.shared.channel_id.load(Ordering::Relaxed)
+    }
+
+    /// True if the server handed back the session named by the resume token.
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// Token for resuming this session if the connection drops.
+    pub fn resume_token(&self) -> &[u8] {
+        &self.resume_token
+    }
+
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    /// Optional features negotiated with the server.
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Send any message to the server.
+    pub async fn send(&self, msg: ClientMessage) -> Result<()> {
+        self.shared.send(&msg).await
+    }
+
+    /// Join a channel. We are in it once its `UserList` arrives.
+    pub async fn join_channel(&self, channel_id: ChannelId, password: Option<String>) -> Result<()> {
+        self.shared.send(&ClientMessage::JoinChannel { channel_id, password }).await
+    }
+
+    /// Send an encrypted message to the current channel. If no member has
+    /// our sender key yet, it goes out as soon as one does.
+    pub async fn send_channel_message(&self, content: impl Into<String>) -> Result<()> {
+        let channel_id = self.channel_id();
+        if channel_id == 0 {
+            bail!("chat is not available in the lobby");
+        }
+        if !signal::send_channel_message(&self.shared, channel_id, content.into()).await? {
+            info!(channel_id, "sender keys not ready, channel message queued");
+        }
+        Ok(())
+    }
+
+    /// Send an encrypted direct message. Without a session with the user
+    /// yet, it goes out once one is set up.
+    pub async fn send_direct_message(&self, user_id: UserId, content: impl Into<String>) -> Result<()> {
+        if !signal::send_direct_message(&self.shared, user_id, content.into()).await? {
+            info!(user_id, "no session yet, direct message queued");
+        }
+        Ok(())
+    }
+
+    /// Speak: encode 48 kHz mono PCM and send it to the current channel.
+    /// Any amount of samples can be passed; a partial 20 ms frame waits for
+    /// the next call. The caller paces the audio in real time.
+    pub async fn transmit_pcm(&self, pcm: &[f32]) -> Result<()> {
+        let packets = self
+            .transmitter
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .push_pcm(&self.shared, pcm);
+        self.send_voice(packets).await
+    }
+
+    /// Speak an already encoded 20 ms Opus frame (48 kHz mono).
+    pub async fn transmit_opus(&self, frame: &[u8]) -> Result<()> {
+        let packet = self
+            .transmitter
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .packet(&self.shared, frame);
+        self.send_voice(packet).await
+    }
+
+    /// Stop speaking, so listeners flush their buffers right away.
+    pub async fn end_transmission(&self) -> Result<()> {
+        let packet = self
+            .transmitter
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .end(&self.shared);
+        self.send_voice([packet]).await
+    }
+
+    async fn send_voice(&self, packets: impl IntoIterator<Item = Vec<u8>>) -> Result<()> {
+        for packet in packets {
+            self.shared
+                .voice_tx
+                .send(packet)
+                .await
+                .map_err(|_| anyhow!("connection closed"))?;
+        }
+        Ok(())
+    }
+
+    /// Start watching a screen share in our channel. Its frames arrive as
+    /// [`Event::ScreenFrame`].
+    pub async fn watch_screen_share(&self, sharer_user_id: UserId) -> Result<()> {
+        self.shared.watching_user_id.store(sharer_user_id, Ordering::Relaxed);
+        self.shared.send(&ClientMessage::WatchScreenShare { sharer_user_id }).await
+    }
+
+    pub async fn stop_watching_screen_share(&self) -> Result<()> {
+        self.shared.watching_user_id.store(0, Ordering::Relaxed);
+        self.shared.send(&ClientMessage::StopWatchingScreenShare).await
+    }
+
+    /// Send one encoded H.265 frame of our screen share, after
+    /// `StartScreenShare` was accepted. Send a keyframe when the server
+    /// relays a `KeyframeRequested`.
+    pub async fn send_screen_frame(&self, data: &[u8], is_keyframe: bool) -> Result<()> {
+        let frame_id = self.screen_frame_id.fetch_add(1, Ordering::Relaxed);
+        for packet in media::screen_frame_packets(&self.shared, frame_id, data, is_keyframe) {
+            self.shared
+                .video_tx
+                .send(packet)
+                .await
+                .map_err(|_| anyhow!("connection closed"))?;
+        }
+        Ok(())
+    }
+
+    /// Log out and close the connection.
+    pub async fn disconnect(self) {
+        let _ = self.shared.send(&ClientMessage::Disconnect).await;
+        // Give the writer a moment to flush the goodbye
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// UDP socket of the server's address family, with buffers large enough to
+/// absorb a burst of ~1400 packets.
+fn bind_udp(server_addr: std::net::SocketAddr) -> Result<UdpSocket> {
+    let (domain, bind_addr): (_, std::net::SocketAddr) = if server_addr.is_ipv4() {
+        (socket2::Domain::IPV4, (std::net::Ipv4Addr::UNSPECIFIED, 0).into())
+    } else {
+        (socket2::Domain::IPV6, (std::net::Ipv6Addr::UNSPECIFIED, 0).into())
+    };
+    let sock = socket2::Socket::new(domain, socket2::Type::DGRAM, Some(socket2::Protocol::UDP))
+        .context("failed to create UDP socket")?;
+    if let Err(e) = sock.set_recv_buffer_size(2 * 1024 * 1024) {
+        warn!("failed to set UDP recv buffer to 2MB: {e}");
+    }
+    if let Err(e) = sock.set_send_buffer_size(2 * 1024 * 1024) {
+        warn!("failed to set UDP send buffer to 2MB: {e}");
+    }
+    sock.bind(&bind_addr.into()).context("failed to bind UDP socket")?;
+    sock.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(sock.into())?)
+}
+
+/// Write queued messages, compressed and chunked as negotiated.
+async fn tcp_writer_task(
+    mut write_half: tokio::io::WriteHalf<TlsStream<TcpStream>>,
+    mut rx: mpsc::Receiver<Vec<u8>>,
+    capabilities: Capabilities,
+) {
+    while let Some(data) = rx.recv().await {
+        if let Err(e) = write_half.write_all(&pack_frame(data, capabilities)).await {
+            error!("TCP write error: {}", e);
+            break;
+        }
+    }
+}
+
+/// Read server messages until the connection closes.
+async fn tcp_reader_task(
+    mut read_half: tokio::io::ReadHalf<TlsStream<TcpStream>>,
+    mut buf: BytesMut,
+    capabilities: Capabilities,
+    shared: Arc<Shared>,
+) {
+    // Messages that arrived together with Authenticated come first
+    let reason = 'read: loop {
+        loop {
+            match try_decode_frame(&mut buf, capabilities) {
+                Ok(Some(payload)) => match decode_server_msg(&payload) {
+                    Ok(msg) => handle_server_message(&shared, msg).await,
+                    Err(e) if e.is_unknown_message() => {
+                        debug!("skipping message from a newer server: {}", e)
+                    }
+                    Err(e) => warn!("failed to decode server message: {}", e),
+                },
+                Ok(None) => break,
+                Err(e) => break 'read format!("frame decode error: {}", e),
+            }
+        }
+        match read_half.read_buf(&mut buf).await {
+            Ok(0) => break "server closed connection".to_string(),
+            Ok(_) => {}
+            Err(e) => break format!("read error: {}", e),
+        }
+    };
+    info!("connection lost: {}", reason);
+    shared.emit(Event::Disconnected { reason }).await;
+}
+
+/// Handle the E2E and media key bookkeeping for a server message and pass
+/// on what the application needs to see.
+async fn handle_server_message(shared: &Shared, msg: ServerMessage) {
+    match msg {
+        ServerMessage::UserList { channel_id, ref users } => {
+            // Covers joins as well as server-initiated moves
+            let old_channel = shared.channel_id.swap(channel_id, Ordering::Relaxed);
+            if old_channel != channel_id {
+                *shared.media_key.lock().unwrap_or_else(|p| p.into_inner()) = None;
+                let members: Vec<UserId> = users.iter().map(|u| u.user_id).collect();
+                signal::channel_changed(shared, channel_id, &members).await;
+                if shared.watching_user_id.swap(0, Ordering::Relaxed) != 0 {
+                    let _ = shared.send(&ClientMessage::StopWatchingScreenShare).await;
+                }
+                info!(old_channel, channel_id, "channel changed");
+            }
+            // Pairwise sessions are needed for direct messages in any channel
+            signal::request_sessions(shared, users.iter().map(|u| u.user_id)).await;
+            shared.emit(Event::Message(msg)).await;
+        }
+        ServerMessage::UserJoined { ref user } => {
+            signal::request_sessions(shared, [user.user_id]).await;
+            shared.emit(Event::Message(msg)).await;
+        }
+        ServerMessage::UserLeft { user_id, .. } => {
+            signal::forget_user(shared, user_id);
+            shared.emit(Event::Message(msg)).await;
+        }
+        ServerMessage::Ping { timestamp } => {
+            // Keepalive; unanswered pings get us disconnected as idle
+            let _ = shared.send(&ClientMessage::Ping { timestamp }).await;
+        }
+        ServerMessage::WatchingScreenShare { sharer_user_id } => {
+            shared.watching_user_id.store(sharer_user_id, Ordering::Relaxed);
+            shared.emit(Event::Message(msg)).await;
+        }
+        ServerMessage::StoppedWatchingScreenShare { .. } => {
+            shared.watching_user_id.store(0, Ordering::Relaxed);
+            shared.emit(Event::Message(msg)).await;
+        }
+        ServerMessage::PreKeyBundle { user_id, bundle } => {
+            signal::handle_prekey_bundle(shared, user_id, &bundle).await;
+        }
+        ServerMessage::PreKeyBundleUnavailable { user_id } => {
+            info!(user_id, "prekey bundle unavailable, no E2E session");
+            signal::bundle_unavailable(shared, user_id);
+        }
+        ServerMessage::SenderKeyReceived {
+            channel_id,
+            from_user_id,
+            distribution_message,
+            message_type,
+        } => {
+            signal::handle_sender_key(shared, channel_id, from_user_id, &distribution_message, message_type)
+                .await;
+        }
+        ServerMessage::EncryptedDirectChatMessage {
+            from_user_id,
+            from_username,
+            to_user_id,
+            ciphertext,
+            message_type,
+            timestamp,
+        } => {
+            // Our own message echoed back; decrypting it would break the ratchet
+            if from_user_id == shared.user_id {
+                return;
+            }
+            match signal::decrypt_direct(shared, from_user_id, &ciphertext, message_type) {
+                Ok(content) => {
+                    shared
+                        .emit(Event::DirectMessage {
+                            from_user_id,
+                            from_username,
+                            to_user_id,
+                            content,
+                            timestamp,
+                        })
+                        .await
+                }
+                Err(e) => warn!(from_user_id, "failed to decrypt direct message: {}", e),
+            }
+        }
+        ServerMessage::EncryptedChannelChatMessage {
+            channel_id,
+            user_id,
+            username,
+            ciphertext,
+            timestamp,
+        } => match signal::decrypt_channel(shared, channel_id, user_id, &ciphertext) {
+            Ok(content) => {
+                shared
+                    .emit(Event::ChannelMessage {
+                        channel_id,
+                        user_id,
+                        username,
+                        content,
+                        timestamp,
+                    })
+                    .await
+            }
+            Err(e) => warn!(user_id, channel_id, "failed to decrypt channel message: {}", e),
+        },
+        ServerMessage::PokeReceived {
+            from_user_id,
+            from_username,
+            ciphertext,
+            message_type,
+        } => match signal::decrypt_direct(shared, from_user_id, &ciphertext, message_type) {
+            Ok(message) => {
+                shared
+                    .emit(Event::Poke {
+                        from_user_id,
+                        from_username,
+                        message,
+                    })
+                    .await
+            }
+            Err(e) => warn!(from_user_id, "failed to decrypt poke: {}", e),
+        },
+        ServerMessage::ChannelMediaKey {
+            channel_id,
+            key_id,
+            key_bytes,
+        } => {
+            let current = shared.channel_id.load(Ordering::Relaxed);
+            if channel_id != current {
+                debug!(channel_id, current, "ignoring media key for another channel");
+                return;
+            }
+            let Ok(key_bytes) = <[u8; 32]>::try_from(key_bytes.as_slice()) else {
+                warn!(channel_id, "received invalid media key length {}", key_bytes.len());
+                return;
+            };
+            *shared.media_key.lock().unwrap_or_else(|p| p.into_inner()) = Some(MediaKey {
+                key_id,
+                key_bytes,
+                channel_id,
+            });
+            info!(channel_id, key_id, "media key installed for channel");
+        }
+        ServerMessage::WhisperKey {
+            session_id,
+            key_id,
+            key_bytes,
+        } => {
+            let Ok(key_bytes) = <[u8; 32]>::try_from(key_bytes.as_slice()) else {
+                warn!("received invalid whisper key length {}", key_bytes.len());
+                return;
+            };
+            let key = MediaKey {
+                key_id,
+                key_bytes,
+                channel_id: 0,
+            };
+            shared
+                .whisper_keys
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .insert(session_id, key);
+            info!(session_id, key_id, "whisper key installed");
+        }
+        ServerMessage::Authenticated { .. }
+        | ServerMessage::AuthError { .. }
+        | ServerMessage::AuthChallenge { .. } => {}
+        other => shared.emit(Event::Message(other)).await,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use voipc_protocol::codec::decode_client_msg;
+    use voipc_protocol::types::UserInfo;
+
+    /// Client state as the background tasks see it, for user 1 in session
+    /// 10, with the server's end of the TCP queue and the event stream.
+    pub(crate) fn test_shared() -> (Shared, mpsc::Receiver<Vec<u8>>, mpsc::Receiver<Event>) {
+        let (tcp_tx, tcp_rx) = mpsc::channel(64);
+        let (events_tx, events_rx) = mpsc::channel(64);
+        let stores = voipc_crypto::SignalStores::new(&voipc_crypto::generate_identity_key_pair(), 1);
+        let shared = Shared {
+            user_id: 1,
+            session_id: 10,
+            udp_token: 99,
+            tcp_tx,
+            voice_tx: mpsc::channel(1).0,
+            video_tx: mpsc::channel(1).0,
+            events: events_tx,
+            channel_id: AtomicU32::new(0),
+            watching_user_id: AtomicU32::new(0),
+            media_key: Mutex::new(None),
+            whisper_keys: Mutex::new(HashMap::new()),
+            signal: Mutex::new(SignalState::from_stores(stores)),
+        };
+        (shared, tcp_rx, events_rx)
+    }
+
+    pub(crate) fn media_key(channel_id: ChannelId, key_id: u16) -> MediaKey {
+        MediaKey {
+            key_id,
+            key_bytes: [key_id as u8; 32],
+            channel_id,
+        }
+    }
+
+    fn user(user_id: UserId, channel_id: ChannelId) -> UserInfo {
+        UserInfo {
+            user_id,
+            username: format!("user{}", user_id),
+            channel_id,
+            is_muted: false,
+            is_deafened: false,
+            is_screen_sharing: false,
+            is_server_muted: false,
+            is_priority_speaker: false,
+            is_idle: false,
+        }
+    }
+
+    fn sent(tcp_rx: &mut mpsc::Receiver<Vec<u8>>) -> Vec<ClientMessage> {
+        std::iter::from_fn(|| tcp_rx.try_recv().ok())
+            .map(|frame| decode_client_msg(&frame[4..]).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn user_list_moves_us_to_its_channel() {
+        let (shared, mut tcp_rx, mut events) = test_shared();
+        *shared.media_key.lock().unwrap() = Some(media_key(0, 1));
+        shared.watching_user_id.store(5, Ordering::Relaxed);
+
+        let users = vec![user(1, 3), user(7, 3)];
+        let list = ServerMessage::UserList {
+            channel_id: 3,
+            users,
+        };
+        handle_server_message(&shared, list.clone()).await;
+        assert_eq!(shared.channel_id.load(Ordering::Relaxed), 3);
+        // The old channel's key and screen share don't carry over
+        assert!(shared.media_key.lock().unwrap().is_none());
+        assert_eq!(shared.watching_user_id.load(Ordering::Relaxed), 0);
+        let msgs = sent(&mut tcp_rx);
+        assert_eq!(msgs.len(), 2);
+        assert!(matches!(msgs[0], ClientMessage::StopWatchingScreenShare));
+        assert!(matches!(msgs[1], ClientMessage::RequestPreKeyBundle { target_user_id: 7 }));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(Event::Message(ServerMessage::UserList { channel_id: 3, .. }))
+        ));
+
+        // A refreshed list of the same channel keeps the key and doesn't
+        // ask for bundles again
+        *shared.media_key.lock().unwrap() = Some(media_key(3, 2));
+        handle_server_message(&shared, list).await;
+        assert_eq!(shared.media_key.lock().unwrap().as_ref().map(|k| k.key_id), Some(2));
+        assert!(sent(&mut tcp_rx).is_empty());
+    }
+
+    #[tokio::test]
+    async fn media_keys_are_installed_for_the_current_channel_only() {
+        let (shared, mut tcp_rx, mut events) = test_shared();
+        shared.channel_id.store(3, Ordering::Relaxed);
+        let key = |channel_id, key_bytes| ServerMessage::ChannelMediaKey {
+            channel_id,
+            key_id: 4,
+            key_bytes,
+        };
+
+        handle_server_message(&shared, key(2, vec![1; 32])).await;
+        handle_server_message(&shared, key(3, vec![1; 16])).await;
+        assert!(shared.media_key.lock().unwrap().is_none());
+        handle_server_message(&shared, key(3, vec![1; 32])).await;
+        {
+            let installed = shared.media_key.lock().unwrap();
+            let installed = installed.as_ref().unwrap();
+            assert_eq!((installed.key_id, installed.channel_id), (4, 3));
+            assert_eq!(installed.key_bytes, [1; 32]);
+        }
+
+        let whisper_key = |session_id, key_bytes| ServerMessage::WhisperKey {
+            session_id,
+            key_id: 6,
+            key_bytes,
+        };
+        handle_server_message(&shared, whisper_key(20, vec![2; 31])).await;
+        handle_server_message(&shared, whisper_key(21, vec![2; 32])).await;
+        {
+            let keys = shared.whisper_keys.lock().unwrap();
+            assert_eq!(keys.len(), 1);
+            assert_eq!(keys[&21].key_id, 6);
+        }
+
+        // Keys are ours to keep; the application sees none of it
+        assert!(events.try_recv().is_err());
+        assert!(sent(&mut tcp_rx).is_empty());
+    }
+
+    #[tokio::test]
+    async fn pings_are_answered() {
+        let (shared, mut tcp_rx, mut events) = test_shared();
+        handle_server_message(&shared, ServerMessage::Ping { timestamp: 42 }).await;
+        assert!(matches!(sent(&mut tcp_rx)[..], [ClientMessage::Ping { timestamp: 42 }]));
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use voipc_protocol::messages::ServerMessage;
+use voipc_protocol::types::{ChannelId, SessionId, UserId};
+
+/// Something that happened on the connection, delivered in order through
+/// the receiver returned by [`Client::connect`](crate::Client::connect).
+///
+/// Voice and screen share events are dropped rather than queued if the
+/// receiver falls behind; everything else waits for it.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A server message the client didn't consume itself: channel and user
+    /// lists, joins and leaves, moderation, screen share notices and so on.
+    Message(ServerMessage),
+    /// A decrypted channel chat message.
+    ChannelMessage {
+        channel_id: ChannelId,
+        user_id: UserId,
+        username: String,
+        content: String,
+        timestamp: u64,
+    },
+    /// A decrypted direct message.
+    DirectMessage {
+        from_user_id: UserId,
+        from_username: String,
+        to_user_id: UserId,
+        content: String,
+        timestamp: u64,
+    },
+    /// A decrypted poke.
+    Poke {
+        from_user_id: UserId,
+        from_username: String,
+        message: String,
+    },
+    /// 20 ms of decoded speech (48 kHz mono) from one sender, in order,
+    /// with lost packets concealed.
+    Voice {
+        session_id: SessionId,
+        pcm: Vec<f32>,
+        /// Whispered to us rather than spoken in the channel.
+        whisper: bool,
+    },
+    /// A sender started or stopped talking.
+    Speaking { session_id: SessionId, speaking: bool },
+    /// A complete H.265 frame of a screen share we are watching, for
+    /// `voipc_video::decoder`.
+    ScreenFrame {
+        session_id: SessionId,
+        data: Vec<u8>,
+        is_keyframe: bool,
+    },
+    /// 20 ms of decoded desktop audio (48 kHz mono) from a screen share.
+    ScreenAudio { session_id: SessionId, pcm: Vec<f32> },
+    /// The connection is gone. No events follow.
+    Disconnected { reason: String },
+}
@@ -0,0 +1,48 @@
+//! Headless VoIPC client: everything needed to take part in a server
+//! without a UI, for bots, tests and tools.
+//!
+//! [`Client::connect`] logs in and returns the client together with a
+//! stream of [`Event`]s. The client takes care of the protocol details:
+//! TLS with certificate pinning, Signal sessions and sender keys for
+//! encrypted chat, channel media keys, and per-sender jitter buffering and
+//! Opus decoding of voice.
+//!
+//! The desktop app only uses [`tls`] from here so far. Moving it onto
+//! [`Client`] is tracked in `desktop-client-migration-todo.md`.
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! use voipc_client::{Client, ClientConfig, Event};
+//!
+//! let mut config = ClientConfig::new("localhost:9987", "EchoBot");
+//! config.accept_invalid_certs = true;
+//! let (client, mut events) = Client::connect(config).await?;
+//! client.join_channel(1, None).await?;
+//!
+//! while let Some(event) = events.recv().await {
+//!     match event {
+//!         Event::ChannelMessage { content, .. } => client.send_channel_message(content).await?,
+//!         Event::Disconnected { .. } => break,
+//!         _ => {}
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Bots build on this with [`bot`] for chat commands and mixing the channel's
+//! voice, and [`player`] for playing files or streams into the channel. See
+//! `examples/music_bot.rs`.
+
+pub mod bot;
+mod client;
+mod event;
+mod media;
+pub mod player;
+mod signal;
+pub mod tls;
+
+pub use client::{Client, ClientConfig};
+pub use event::Event;
+
+pub use voipc_protocol as protocol;
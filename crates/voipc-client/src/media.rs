@@ -0,0 +1,567 @@
+//! Voice and screen share over UDP: receiving, decrypting and decoding what
+//! others send, and encoding and encrypting what we send.
+
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use voipc_audio::decoder::Decoder;
+use voipc_audio::encoder::Encoder;
+use voipc_audio::jitter::{JitterBuffer, JitterFrame};
+use voipc_crypto::MediaKey;
+use voipc_protocol::messages::ClientMessage;
+use voipc_protocol::types::SessionId;
+use voipc_protocol::video::{
+    fragment_frame, FrameAssembler, ScreenShareAudioPacket, VideoPacket, MAX_ENCRYPTED_VIDEO_PAYLOAD_SIZE,
+    MAX_VIDEO_PAYLOAD_SIZE, SCREEN_AUDIO_HEADER_SIZE, VIDEO_HEADER_SIZE,
+};
+use voipc_protocol::voice::{
+    VoicePacket, ENCRYPTED_VOICE_HEADER_SIZE, OPUS_FRAME_SIZE, VOICE_HEADER_SIZE,
+};
+
+use crate::client::Shared;
+use crate::event::Event;
+
+/// Frames a jitter buffer holds back to absorb network jitter (40 ms).
+const JITTER_DELAY: usize = 2;
+/// A sender who has been quiet this long has stopped speaking.
+const SPEAKING_TIMEOUT: Duration = Duration::from_millis(500);
+/// At most one automatic keyframe request per this interval.
+const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Send queued datagrams to the server.
+pub(crate) async fn udp_sender_task(
+    socket: Arc<UdpSocket>,
+    mut rx: mpsc::Receiver<Vec<u8>>,
+    server_addr: std::net::SocketAddr,
+) {
+    while let Some(data) = rx.recv().await {
+        if let Err(e) = socket.send_to(&data, server_addr).await {
+            error!("UDP send error: {}", e);
+        }
+    }
+}
+
+/// One remote speaker's receive state.
+struct Speaker {
+    jitter: JitterBuffer,
+    decoder: Decoder,
+    last_heard: Instant,
+    speaking: bool,
+}
+
+/// Receive voice and screen share packets, decrypt and decode them, and
+/// emit them as events. Media events are dropped if the application falls
+/// behind rather than stalling the socket.
+pub(crate) async fn udp_receiver_task(
+    socket: Arc<UdpSocket>,
+    shared: Arc<Shared>,
+    server_addr: std::net::SocketAddr,
+) {
+    let mut speakers: HashMap<SessionId, Speaker> = HashMap::new();
+    let mut assembler = FrameAssembler::new();
+    let mut screen_session: Option<SessionId> = None;
+    let mut screen_audio_decoder: Option<Decoder> = None;
+    let mut last_keyframe_request = Instant::now() - KEYFRAME_REQUEST_INTERVAL;
+    let mut buf = vec![0u8; 2048];
+    let mut speaking_check = tokio::time::interval(Duration::from_millis(300));
+    speaking_check.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        let n = tokio::select! {
+            result = socket.recv_from(&mut buf) => match result {
+                Ok((0, _)) => continue,
+                Ok((n, _)) => n,
+                Err(e) => {
+                    error!("UDP recv error: {}", e);
+                    break;
+                }
+            },
+            _ = speaking_check.tick() => {
+                // Senders using voice activity stop without an end of transmission
+                for (&session_id, speaker) in speakers.iter_mut() {
+                    if speaker.speaking && speaker.last_heard.elapsed() > SPEAKING_TIMEOUT {
+                        speaker.speaking = false;
+                        shared.emit_media(Event::Speaking { session_id, speaking: false });
+                    }
+                }
+                continue;
+            }
+        };
+        let packet = &buf[..n];
+
+        match packet[0] {
+            // OpusVoice, EncryptedOpusVoice, EncryptedWhisperVoice, EncryptedPriorityVoice
+            0x01 | 0x05 | 0x06 | 0x07 => {
+                let Some(VoiceFrame {
+                    session_id,
+                    sequence,
+                    opus_data,
+                    whisper,
+                }) = open_voice_packet(&shared, packet)
+                else {
+                    continue;
+                };
+
+                let speaker = match speakers.entry(session_id) {
+                    std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+                    std::collections::hash_map::Entry::Vacant(e) => match Decoder::new() {
+                        Ok(decoder) => e.insert(Speaker {
+                            jitter: JitterBuffer::new(JITTER_DELAY),
+                            decoder,
+                            last_heard: Instant::now(),
+                            speaking: false,
+                        }),
+                        Err(err) => {
+                            warn!(session_id, "failed to create Opus decoder: {}", err);
+                            continue;
+                        }
+                    },
+                };
+                if !speaker.speaking {
+                    speaker.speaking = true;
+                    shared.emit_media(Event::Speaking { session_id, speaking: true });
+                }
+                speaker.last_heard = Instant::now();
+                speaker.jitter.push(sequence, opus_data);
+                drain_speaker(&shared, session_id, speaker, whisper);
+            }
+            // EndOfTransmission
+            0x02 => {
+                if n < VOICE_HEADER_SIZE {
+                    continue;
+                }
+                let session_id = u32::from_be_bytes([packet[1], packet[2], packet[3], packet[4]]);
+                // Keep the decoder for state continuity across transmissions
+                if let Some(speaker) = speakers.get_mut(&session_id) {
+                    drain_speaker(&shared, session_id, speaker, false);
+                    speaker.jitter.reset();
+                    if speaker.speaking {
+                        speaker.speaking = false;
+                        shared.emit_media(Event::Speaking { session_id, speaking: false });
+                    }
+                }
+            }
+            // Pong: echo back so the server can measure the round trip
+            0x03 => {
+                buf[0] = 0x04;
+                let _ = socket.send_to(&buf[..n], server_addr).await;
+            }
+            // Video fragments, plain and encrypted
+            packet_type @ (0x10 | 0x11 | 0x13 | 0x14) => {
+                if n < VIDEO_HEADER_SIZE {
+                    continue;
+                }
+                let Ok(mut fragment) = VideoPacket::from_bytes(packet) else {
+                    continue;
+                };
+                if fragment.packet_type.is_encrypted() {
+                    match decrypt_channel_media(
+                        &shared,
+                        fragment.session_id,
+                        fragment.frame_id,
+                        fragment.fragment_index as u32,
+                        packet_type,
+                        &fragment.payload,
+                    ) {
+                        Some(data) => fragment.payload = data,
+                        None => continue,
+                    }
+                }
+
+                if screen_session != Some(fragment.session_id) {
+                    assembler.reset();
+                    screen_audio_decoder = None;
+                    screen_session = Some(fragment.session_id);
+                }
+
+                let result = assembler.add_fragment(&fragment);
+                if result.frame_dropped && last_keyframe_request.elapsed() >= KEYFRAME_REQUEST_INTERVAL {
+                    let sharer_user_id = shared.watching_user_id.load(Ordering::Relaxed);
+                    if sharer_user_id != 0 {
+                        let _ = shared.try_send(&ClientMessage::RequestKeyframe { sharer_user_id });
+                        info!("requested keyframe after frame loss");
+                        last_keyframe_request = Instant::now();
+                    }
+                }
+                if let Some((data, is_keyframe)) = result.frame {
+                    shared.emit_media(Event::ScreenFrame {
+                        session_id: fragment.session_id,
+                        data,
+                        is_keyframe,
+                    });
+                }
+            }
+            // Screen share audio, plain and encrypted
+            0x12 | 0x15 => {
+                if n < SCREEN_AUDIO_HEADER_SIZE {
+                    continue;
+                }
+                let Ok(audio) = ScreenShareAudioPacket::from_bytes(packet) else {
+                    continue;
+                };
+                let opus_data = if audio.encrypted {
+                    match decrypt_channel_media(&shared, audio.session_id, audio.sequence, 0, 0x15, &audio.opus_data) {
+                        Some(data) => data,
+                        None => continue,
+                    }
+                } else {
+                    audio.opus_data
+                };
+
+                if screen_session != Some(audio.session_id) {
+                    screen_audio_decoder = None;
+                    screen_session = Some(audio.session_id);
+                }
+                let decoder = match screen_audio_decoder.as_mut() {
+                    Some(d) => d,
+                    None => match Decoder::new() {
+                        Ok(d) => screen_audio_decoder.insert(d),
+                        Err(e) => {
+                            warn!("failed to create screen audio decoder: {}", e);
+                            continue;
+                        }
+                    },
+                };
+                match decoder.decode(&opus_data) {
+                    Ok(pcm) => shared.emit_media(Event::ScreenAudio {
+                        session_id: audio.session_id,
+                        pcm,
+                    }),
+                    Err(e) => warn!("screen audio decode error: {}", e),
+                }
+            }
+            _ => {}
+        }
+    }
+    info!("UDP receiver task ended");
+}
+
+/// A received voice packet, decrypted.
+#[derive(Debug)]
+struct VoiceFrame {
+    session_id: SessionId,
+    sequence: u32,
+    opus_data: Vec<u8>,
+    whisper: bool,
+}
+
+/// Read a voice packet (0x01, 0x05, 0x06 or 0x07) and decrypt its Opus
+/// frame with the channel's media key or the sender's whisper key. None if
+/// it is truncated or can't be decrypted (yet).
+fn open_voice_packet(shared: &Shared, packet: &[u8]) -> Option<VoiceFrame> {
+    let packet_type = *packet.first()?;
+    let header_size = if packet_type == 0x01 {
+        VOICE_HEADER_SIZE
+    } else {
+        ENCRYPTED_VOICE_HEADER_SIZE
+    };
+    if packet.len() < header_size {
+        return None;
+    }
+    let session_id = u32::from_be_bytes([packet[1], packet[2], packet[3], packet[4]]);
+    let sequence = u32::from_be_bytes([packet[13], packet[14], packet[15], packet[16]]);
+    let payload = &packet[header_size..];
+    let whisper = packet_type == 0x06;
+
+    let opus_data = match packet_type {
+        0x01 => payload.to_vec(),
+        0x06 => {
+            let keys = shared.whisper_keys.lock().unwrap_or_else(|p| p.into_inner());
+            let Some(key) = keys.get(&session_id) else {
+                debug!(session_id, "whisper arrived before its key");
+                return None;
+            };
+            let aad = voipc_crypto::build_aad(0, 0x06);
+            match voipc_crypto::media_decrypt(key, session_id, sequence, 0, &aad, payload) {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!(session_id, "whisper decryption failed: {}", e);
+                    return None;
+                }
+            }
+        }
+        // Priority packets were encrypted as 0x05 by the sender
+        0x05 | 0x07 => decrypt_channel_media(shared, session_id, sequence, 0, 0x05, payload)?,
+        _ => return None,
+    };
+    Some(VoiceFrame {
+        session_id,
+        sequence,
+        opus_data,
+        whisper,
+    })
+}
+
+/// Decrypt a voice, video or screen audio payload with the channel's media key.
+fn decrypt_channel_media(
+    shared: &Shared,
+    session_id: SessionId,
+    sequence: u32,
+    extra: u32,
+    packet_type: u8,
+    payload: &[u8],
+) -> Option<Vec<u8>> {
+    let key = shared.media_key.lock().unwrap_or_else(|p| p.into_inner());
+    let Some(key) = key.as_ref() else {
+        debug!(session_id, "encrypted media arrived before the channel key");
+        return None;
+    };
+    let aad = voipc_crypto::build_aad(shared.channel_id.load(Ordering::Relaxed), packet_type);
+    match voipc_crypto::media_decrypt(key, session_id, sequence, extra, &aad, payload) {
+        Ok(data) => Some(data),
+        Err(e) => {
+            warn!(session_id, packet_type, "media decryption failed: {}", e);
+            None
+        }
+    }
+}
+
+/// Decode whatever the speaker's jitter buffer has ready, concealing losses.
+fn drain_speaker(shared: &Shared, session_id: SessionId, speaker: &mut Speaker, whisper: bool) {
+    while let Some(frame) = speaker.jitter.pop() {
+        let decoded = match frame {
+            JitterFrame::Ready(opus_data) => speaker.decoder.decode(&opus_data),
+            JitterFrame::Lost => speaker.decoder.decode_lost(),
+        };
+        match decoded {
+            Ok(pcm) => shared.emit_media(Event::Voice {
+                session_id,
+                pcm,
+                whisper,
+            }),
+            Err(e) => warn!(session_id, "Opus decode error: {}", e),
+        }
+    }
+}
+
+/// Outgoing voice: buffers PCM into 20 ms frames, encodes and packetizes them.
+pub(crate) struct Transmitter {
+    encoder: Encoder,
+    pcm: Vec<f32>,
+    sequence: u32,
+}
+
+impl Transmitter {
+    pub(crate) fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            encoder: Encoder::new()?,
+            pcm: Vec::with_capacity(OPUS_FRAME_SIZE),
+            sequence: 0,
+        })
+    }
+
+    /// Buffer PCM and encode every complete frame into a voice packet.
+    pub(crate) fn push_pcm(&mut self, shared: &Shared, mut samples: &[f32]) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        while !samples.is_empty() {
+            let take = (OPUS_FRAME_SIZE - self.pcm.len()).min(samples.len());
+            self.pcm.extend_from_slice(&samples[..take]);
+            samples = &samples[take..];
+            if self.pcm.len() < OPUS_FRAME_SIZE {
+                break;
+            }
+            match self.encoder.encode(&self.pcm) {
+                Ok(opus_data) => packets.extend(self.packet(shared, &opus_data)),
+                Err(e) => warn!("Opus encode error: {}", e),
+            }
+            self.pcm.clear();
+        }
+        packets
+    }
+
+    /// Packetize one already encoded 20 ms Opus frame.
+    pub(crate) fn packet(&mut self, shared: &Shared, opus_data: &[u8]) -> Option<Vec<u8>> {
+        let sequence = self.sequence;
+        // Never wrap to 0, which would reuse nonces under the same key
+        self.sequence = self.sequence.saturating_add(1);
+
+        let key = shared.media_key.lock().unwrap_or_else(|p| p.into_inner());
+        let packet = match key.as_ref() {
+            Some(key) => {
+                let aad = voipc_crypto::build_aad(shared.channel_id.load(Ordering::Relaxed), 0x05);
+                match voipc_crypto::media_encrypt(key, shared.session_id, sequence, 0, &aad, opus_data) {
+                    Ok(encrypted) => VoicePacket::encrypted_voice(
+                        shared.session_id,
+                        shared.udp_token,
+                        sequence,
+                        key.key_id,
+                        encrypted,
+                    ),
+                    Err(e) => {
+                        // Never fall back to plaintext
+                        warn!(sequence, "voice encryption failed: {}", e);
+                        return None;
+                    }
+                }
+            }
+            // Only while the channel key is still on its way
+            None => VoicePacket::voice(shared.session_id, shared.udp_token, sequence, opus_data.to_vec()),
+        };
+        Some(packet.to_bytes())
+    }
+
+    /// Drop any partial frame and build the end-of-transmission packet.
+    pub(crate) fn end(&mut self, shared: &Shared) -> Vec<u8> {
+        self.pcm.clear();
+        VoicePacket::end_of_transmission(shared.session_id, shared.udp_token, self.sequence).to_bytes()
+    }
+}
+
+/// Split an encoded H.265 frame into (encrypted) video fragments.
+pub(crate) fn screen_frame_packets(
+    shared: &Shared,
+    frame_id: u32,
+    data: &[u8],
+    is_keyframe: bool,
+) -> Vec<Vec<u8>> {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u32;
+    let key = shared.media_key.lock().unwrap_or_else(|p| p.into_inner());
+    let max_payload = if key.is_some() {
+        MAX_ENCRYPTED_VIDEO_PAYLOAD_SIZE
+    } else {
+        MAX_VIDEO_PAYLOAD_SIZE
+    };
+    let fragments = fragment_frame(
+        data,
+        is_keyframe,
+        shared.session_id,
+        shared.udp_token,
+        frame_id,
+        timestamp,
+        max_payload,
+    );
+    fragments
+        .into_iter()
+        .filter_map(|fragment| match key.as_ref() {
+            Some(key) => encrypt_fragment(shared, key, fragment, is_keyframe),
+            None => Some(fragment),
+        })
+        .map(|fragment| fragment.to_bytes())
+        .collect()
+}
+
+fn encrypt_fragment(
+    shared: &Shared,
+    key: &MediaKey,
+    fragment: VideoPacket,
+    is_keyframe: bool,
+) -> Option<VideoPacket> {
+    let packet_type = if is_keyframe { 0x14 } else { 0x13 };
+    let aad = voipc_crypto::build_aad(shared.channel_id.load(Ordering::Relaxed), packet_type);
+    match voipc_crypto::media_encrypt(
+        key,
+        fragment.session_id,
+        fragment.frame_id,
+        fragment.fragment_index as u32,
+        &aad,
+        &fragment.payload,
+    ) {
+        Ok(encrypted) => Some(VideoPacket::encrypted_fragment(
+            is_keyframe,
+            fragment.session_id,
+            fragment.udp_token,
+            fragment.frame_id,
+            fragment.fragment_index,
+            fragment.fragment_count,
+            fragment.timestamp,
+            key.key_id,
+            encrypted,
+        )),
+        Err(e) => {
+            warn!(frame_id = fragment.frame_id, "video encryption failed: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::{media_key, test_shared};
+    use voipc_protocol::voice::VoicePacketType;
+
+    #[test]
+    fn pcm_is_sent_in_whole_frames_with_rising_sequence() {
+        let (shared, _tcp_rx, _events) = test_shared();
+        let mut tx = Transmitter::new().unwrap();
+
+        // Partial frames wait for the rest
+        assert!(tx.push_pcm(&shared, &[0.1; 500]).is_empty());
+        let packets = tx.push_pcm(&shared, &[0.1; 1000]);
+        assert_eq!(packets.len(), 1);
+        let packets = tx.push_pcm(&shared, &[0.1; 2 * OPUS_FRAME_SIZE]);
+        assert_eq!(packets.len(), 2);
+
+        let sequences: Vec<u32> = packets
+            .iter()
+            .map(|p| VoicePacket::from_bytes(p).unwrap())
+            .map(|p| {
+                assert_eq!(p.packet_type, VoicePacketType::OpusVoice);
+                assert_eq!((p.session_id, p.udp_token), (10, 99));
+                p.sequence
+            })
+            .collect();
+        assert_eq!(sequences, vec![1, 2]);
+
+        // Ending drops the partial frame; the sequence carries on
+        let end = VoicePacket::from_bytes(&tx.end(&shared)).unwrap();
+        assert_eq!((end.packet_type, end.sequence), (VoicePacketType::EndOfTransmission, 3));
+        assert!(tx.push_pcm(&shared, &[0.1; OPUS_FRAME_SIZE - 1]).is_empty());
+
+        // With the channel key, voice goes out encrypted
+        *shared.media_key.lock().unwrap() = Some(media_key(0, 7));
+        let packets = tx.push_pcm(&shared, &[0.1; 1]);
+        let packet = VoicePacket::from_bytes(&packets[0]).unwrap();
+        assert_eq!(packet.packet_type, VoicePacketType::EncryptedOpusVoice);
+        assert_eq!((packet.sequence, packet.key_id), (3, 7));
+    }
+
+    #[test]
+    fn voice_packets_are_opened_with_the_right_key() {
+        let (shared, _tcp_rx, _events) = test_shared();
+        shared.channel_id.store(3, Ordering::Relaxed);
+        let opus = vec![0xfc, 1, 2, 3];
+
+        let plain = VoicePacket::voice(20, 0, 5, opus.clone()).to_bytes();
+        let frame = open_voice_packet(&shared, &plain).unwrap();
+        assert_eq!((frame.session_id, frame.sequence), (20, 5));
+        assert_eq!(frame.opus_data, opus);
+        assert!(!frame.whisper);
+        assert!(open_voice_packet(&shared, &plain[..VOICE_HEADER_SIZE - 1]).is_none());
+
+        // Channel voice needs the channel key; priority voice uses it too
+        let key = media_key(3, 1);
+        let aad = voipc_crypto::build_aad(3, 0x05);
+        let encrypted = voipc_crypto::media_encrypt(&key, 20, 6, 0, &aad, &opus).unwrap();
+        let mut packet = VoicePacket::encrypted_voice(20, 0, 6, 1, encrypted).to_bytes();
+        assert!(open_voice_packet(&shared, &packet).is_none());
+        *shared.media_key.lock().unwrap() = Some(key);
+        assert_eq!(open_voice_packet(&shared, &packet).unwrap().opus_data, opus);
+        packet[0] = 0x07;
+        assert_eq!(open_voice_packet(&shared, &packet).unwrap().opus_data, opus);
+        // Tampering is caught
+        *packet.last_mut().unwrap() ^= 1;
+        assert!(open_voice_packet(&shared, &packet).is_none());
+
+        // Whispers use the sender's whisper key
+        let whisper_key = media_key(0, 2);
+        let aad = voipc_crypto::build_aad(0, 0x06);
+        let encrypted = voipc_crypto::media_encrypt(&whisper_key, 21, 8, 0, &aad, &opus).unwrap();
+        let packet = VoicePacket::encrypted_whisper(21, 0, 8, 2, encrypted).to_bytes();
+        assert!(open_voice_packet(&shared, &packet).is_none());
+        shared.whisper_keys.lock().unwrap().insert(21, whisper_key);
+        let frame = open_voice_packet(&shared, &packet).unwrap();
+        assert_eq!((frame.session_id, frame.sequence), (21, 8));
+        assert_eq!(frame.opus_data, opus);
+        assert!(frame.whisper);
+    }
+}
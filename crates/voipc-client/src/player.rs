@@ -0,0 +1,312 @@
+//! Playing audio into the channel in real time, for music and announcement
+//! bots.
+//!
+//! A source hands out 20 ms frames of 48 kHz mono PCM or Opus. PCM is
+//! encoded with `voipc_audio::encoder::Encoder` on the way out; Opus goes out
+//! as is. Sources are read on a blocking thread, so they may decode files or
+//! wait on a stream.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::debug;
+use voipc_protocol::voice::{OPUS_FRAME_SIZE, OPUS_SAMPLE_RATE};
+
+use crate::Client;
+
+/// One voice frame's worth of audio.
+const FRAME_DURATION: Duration = Duration::from_millis(20);
+
+/// Frames read ahead of playback, so a slow source doesn't stutter at once.
+const PREFETCH_FRAMES: usize = 25;
+
+/// 20 ms of audio to send.
+#[derive(Debug, Clone)]
+pub enum Frame {
+    /// 48 kHz mono samples. A short frame is padded with silence.
+    Pcm(Vec<f32>),
+    /// An encoded Opus frame (48 kHz mono, 20 ms).
+    Opus(Vec<u8>),
+}
+
+/// Something that produces audio frames until it runs out.
+pub trait AudioSource: Send + 'static {
+    /// The next frame, or `None` at the end. May block.
+    fn next_frame(&mut self) -> Option<Frame>;
+}
+
+/// PCM from any sample iterator: a decoded file, a generator, a pipe.
+pub struct PcmSource<I> {
+    samples: I,
+}
+
+impl<I: Iterator<Item = f32> + Send + 'static> PcmSource<I> {
+    /// Samples must be 48 kHz mono; see [`to_48k_mono`] for anything else.
+    pub fn new(samples: impl IntoIterator<IntoIter = I>) -> Self {
+        Self {
+            samples: samples.into_iter(),
+        }
+    }
+}
+
+impl<I: Iterator<Item = f32> + Send + 'static> AudioSource for PcmSource<I> {
+    fn next_frame(&mut self) -> Option<Frame> {
+        let mut frame: Vec<f32> = self.samples.by_ref().take(OPUS_FRAME_SIZE).collect();
+        if frame.is_empty() {
+            return None;
+        }
+        frame.resize(OPUS_FRAME_SIZE, 0.0);
+        Some(Frame::Pcm(frame))
+    }
+}
+
+/// Already encoded Opus frames, e.g. the packets of an Ogg Opus file.
+pub struct OpusSource<I> {
+    frames: I,
+}
+
+impl<I: Iterator<Item = Vec<u8>> + Send + 'static> OpusSource<I> {
+    pub fn new(frames: impl IntoIterator<IntoIter = I>) -> Self {
+        Self {
+            frames: frames.into_iter(),
+        }
+    }
+}
+
+impl<I: Iterator<Item = Vec<u8>> + Send + 'static> AudioSource for OpusSource<I> {
+    fn next_frame(&mut self) -> Option<Frame> {
+        self.frames.next().map(Frame::Opus)
+    }
+}
+
+/// A live stream: whatever is sent on the channel is played until every
+/// sender is dropped.
+impl AudioSource for mpsc::Receiver<Frame> {
+    fn next_frame(&mut self) -> Option<Frame> {
+        self.blocking_recv()
+    }
+}
+
+/// Convert interleaved samples at any rate to 48 kHz mono, averaging the
+/// channels and resampling linearly. Good enough for speech and background
+/// music; use a proper resampler for anything more.
+pub fn to_48k_mono(samples: &[f32], channels: usize, sample_rate: u32) -> Vec<f32> {
+    let channels = channels.max(1);
+    let mono: Vec<f32> = samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    if sample_rate == OPUS_SAMPLE_RATE || mono.len() < 2 {
+        return mono;
+    }
+
+    let step = sample_rate as f64 / OPUS_SAMPLE_RATE as f64;
+    let out_len = (mono.len() as f64 / step) as usize;
+    (0..out_len)
+        .map(|i| {
+            let pos = i as f64 * step;
+            let index = pos as usize;
+            let next = mono[(index + 1).min(mono.len() - 1)];
+            let frac = (pos - index as f64) as f32;
+            mono[index] + (next - mono[index]) * frac
+        })
+        .collect()
+}
+
+/// [`to_48k_mono`] for audio that arrives in pieces, such as the packets of
+/// a file being decoded. Keeps its place between calls, so the pieces join
+/// up without clicks.
+pub struct Resampler {
+    channels: usize,
+    /// Input samples per output sample.
+    step: f64,
+    /// Where the next output sample falls, counted from `last`.
+    pos: f64,
+    /// The last mono sample of the previous piece.
+    last: Option<f32>,
+}
+
+impl Resampler {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        Self {
+            channels: channels.max(1),
+            step: sample_rate as f64 / OPUS_SAMPLE_RATE as f64,
+            pos: 0.0,
+            last: None,
+        }
+    }
+
+    /// Convert the next interleaved samples and append them to `out`.
+    pub fn process(&mut self, samples: &[f32], out: &mut Vec<f32>) {
+        let channels = self.channels;
+        let mono = samples
+            .chunks_exact(channels)
+            .map(|frame| frame.iter().sum::<f32>() / channels as f32);
+        if self.step == 1.0 {
+            out.extend(mono);
+            return;
+        }
+
+        let input: Vec<f32> = self.last.into_iter().chain(mono).collect();
+        let Some(&last) = input.last() else {
+            return;
+        };
+        let end = (input.len() - 1) as f64;
+        while self.pos < end {
+            let index = self.pos as usize;
+            let frac = (self.pos - index as f64) as f32;
+            out.push(input[index] + (input[index + 1] - input[index]) * frac);
+            self.pos += self.step;
+        }
+        self.pos -= end;
+        self.last = Some(last);
+    }
+}
+
+/// Audio being played. Dropping it stops playback.
+pub struct Playback {
+    stop: Option<oneshot::Sender<()>>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl Playback {
+    /// Start playing `source` into the client's channel, one frame every
+    /// 20 ms. Start a new playback only after the last one stopped, or the
+    /// two will talk over each other.
+    pub fn start(client: Arc<Client>, mut source: impl AudioSource) -> Playback {
+        let (frame_tx, mut frame_rx) = mpsc::channel(PREFETCH_FRAMES);
+        tokio::task::spawn_blocking(move || {
+            while let Some(frame) = source.next_frame() {
+                if frame_tx.blocking_send(frame).is_err() {
+                    break; // playback stopped
+                }
+            }
+        });
+
+        let (stop, mut stopped) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(FRAME_DURATION);
+            // After a stall, carry on at the normal pace rather than bursting
+            // more audio than listeners' jitter buffers can take.
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let result = async {
+                loop {
+                    let frame = tokio::select! {
+                        _ = &mut stopped => break,
+                        frame = frame_rx.recv() => match frame {
+                            Some(frame) => frame,
+                            None => break,
+                        },
+                    };
+                    ticker.tick().await;
+                    match frame {
+                        Frame::Pcm(mut pcm) => {
+                            pcm.resize(OPUS_FRAME_SIZE, 0.0);
+                            client.transmit_pcm(&pcm).await?;
+                        }
+                        Frame::Opus(opus) => client.transmit_opus(&opus).await?,
+                    }
+                }
+                Ok(())
+            }
+            .await;
+            debug!("playback finished");
+            // Let listeners flush their buffers instead of waiting them out
+            let _ = client.end_transmission().await;
+            result
+        });
+
+        Playback {
+            stop: Some(stop),
+            task,
+        }
+    }
+
+    /// Stop playing. Safe to call more than once.
+    pub fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+    }
+
+    /// Whether the source ran out or playback was stopped.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Wait until the source runs out or playback is stopped. Fails if the
+    /// connection went away mid-way.
+    pub async fn wait(mut self) -> Result<()> {
+        let _stop = self.stop.take();
+        (&mut self.task)
+            .await
+            .map_err(|e| anyhow!("playback task failed: {}", e))?
+    }
+}
+
+impl Drop for Playback {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pcm_source_pads_the_last_frame() {
+        let mut source = PcmSource::new(vec![0.5; OPUS_FRAME_SIZE + 10]);
+        let Some(Frame::Pcm(first)) = source.next_frame() else {
+            panic!("expected PCM")
+        };
+        assert_eq!(first, vec![0.5; OPUS_FRAME_SIZE]);
+        let Some(Frame::Pcm(last)) = source.next_frame() else {
+            panic!("expected PCM")
+        };
+        assert_eq!(last.len(), OPUS_FRAME_SIZE);
+        assert_eq!(last[9], 0.5);
+        assert_eq!(last[10], 0.0);
+        assert!(source.next_frame().is_none());
+    }
+
+    #[test]
+    fn converts_to_48k_mono() {
+        // Stereo at 24 kHz: one second becomes 48000 mono samples
+        let stereo: Vec<f32> = (0..24_000).flat_map(|i| [i as f32, 0.0]).collect();
+        let mono = to_48k_mono(&stereo, 2, 24_000);
+        assert_eq!(mono.len(), 48_000);
+        assert_eq!(mono[0], 0.0);
+        assert_eq!(mono[1], 0.25);
+        assert_eq!(mono[2], 0.5);
+
+        let already = vec![0.1; 960];
+        assert_eq!(to_48k_mono(&already, 1, 48_000), already);
+    }
+
+    #[test]
+    fn resampler_joins_pieces_seamlessly() {
+        let stereo: Vec<f32> = (0..4410).flat_map(|i| [i as f32 / 4410.0, 0.0]).collect();
+        let whole = to_48k_mono(&stereo, 2, 44_100);
+
+        let mut resampler = Resampler::new(2, 44_100);
+        let mut pieces = Vec::new();
+        for piece in stereo.chunks(2 * 137) {
+            resampler.process(piece, &mut pieces);
+        }
+        // Only the very last sample waits for more input
+        assert!(whole.len() - pieces.len() <= 1);
+        for (a, b) in pieces.iter().zip(&whole) {
+            assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+        }
+
+        let mut same_rate = Resampler::new(1, 48_000);
+        let mut out = Vec::new();
+        same_rate.process(&[0.1, 0.2], &mut out);
+        assert_eq!(out, vec![0.1, 0.2]);
+    }
+}
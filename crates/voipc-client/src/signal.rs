@@ -0,0 +1,445 @@
+//! End-to-end encrypted chat with the Signal Protocol: pairwise sessions
+//! for direct messages, sender keys for channel messages.
+//!
+//! Sessions are set up on their own as users show up: we ask the server for
+//! the prekey bundle of everyone we see, and once a session exists we send
+//! that user our sender key for the current channel. Messages sent before
+//! the keys are in place wait in a queue for up to a minute.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use tracing::{info, warn};
+
+use voipc_crypto::SignalStores;
+use voipc_protocol::messages::ClientMessage;
+use voipc_protocol::types::{ChannelId, OneTimePreKey, PreKeyBundleData, UserId};
+
+use crate::client::Shared;
+
+/// How long a queued message waits for its keys before being dropped.
+const PENDING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A message waiting for a session or sender keys.
+struct PendingMessage {
+    target: PendingTarget,
+    content: String,
+    queued_at: Instant,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PendingTarget {
+    Channel(ChannelId),
+    Direct(UserId),
+}
+
+/// Signal stores plus what we know about each peer's keys.
+pub(crate) struct SignalState {
+    stores: SignalStores,
+    /// Users whose prekey bundle we asked for.
+    pending_sessions: HashSet<UserId>,
+    /// Users we have a pairwise session with.
+    established_sessions: HashSet<UserId>,
+    /// channel_id → users we've sent our sender key to.
+    sender_key_distributed: HashMap<ChannelId, HashSet<UserId>>,
+    /// channel_id → users whose sender key we've received.
+    sender_key_received: HashMap<ChannelId, HashSet<UserId>>,
+    pending_messages: Vec<PendingMessage>,
+}
+
+impl SignalState {
+    /// Fresh stores for `identity`, with prekeys generated. Returns the
+    /// state and the bundle to publish in `Authenticate`.
+    pub(crate) fn new(
+        identity: &voipc_crypto::SerializableIdentityKeyPair,
+    ) -> Result<(Self, PreKeyBundleData)> {
+        let pair = identity.to_identity_key_pair().context("invalid identity key")?;
+        let registration_id: u32 = rand::Rng::gen(&mut rand::thread_rng());
+        let mut stores = SignalStores::new(&pair, registration_id);
+
+        // libsignal futures are !Send, so they can't be awaited here
+        let prekeys = tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(voipc_crypto::prekey::generate_prekeys(
+                &mut stores,
+                &pair,
+                1,
+                voipc_crypto::prekey::INITIAL_PREKEY_COUNT,
+            ))
+        })
+        .context("failed to generate prekeys")?;
+
+        let bundle = PreKeyBundleData {
+            registration_id: prekeys.registration_id,
+            device_id: prekeys.device_id,
+            identity_key: identity.public_key.clone(),
+            signed_prekey_id: prekeys.signed_prekey_id,
+            signed_prekey: prekeys.signed_prekey_public,
+            signed_prekey_signature: prekeys.signed_prekey_signature,
+            prekeys: prekeys
+                .one_time_prekeys
+                .into_iter()
+                .map(|k| OneTimePreKey {
+                    id: k.id,
+                    public_key: k.public_key,
+                })
+                .collect(),
+        };
+        Ok((Self::from_stores(stores), bundle))
+    }
+
+    /// State around stores that already hold our keys.
+    pub(crate) fn from_stores(stores: SignalStores) -> Self {
+        Self {
+            stores,
+            pending_sessions: HashSet::new(),
+            established_sessions: HashSet::new(),
+            sender_key_distributed: HashMap::new(),
+            sender_key_received: HashMap::new(),
+            pending_messages: Vec::new(),
+        }
+    }
+
+    /// Take the queued messages for `target` that haven't expired.
+    fn take_pending(&mut self, target: PendingTarget) -> Vec<String> {
+        let (matching, remaining) = std::mem::take(&mut self.pending_messages)
+            .into_iter()
+            .partition::<Vec<_>, _>(|m| m.target == target);
+        self.pending_messages = remaining;
+        let total = matching.len();
+        let fresh: Vec<String> = matching
+            .into_iter()
+            .filter(|m| m.queued_at.elapsed() < PENDING_TIMEOUT)
+            .map(|m| m.content)
+            .collect();
+        if fresh.len() < total {
+            warn!(expired = total - fresh.len(), "dropped expired queued messages");
+        }
+        fresh
+    }
+}
+
+/// Run a libsignal operation on the stores. The futures are !Send, so they
+/// are driven on this thread; the client needs a multi-threaded runtime.
+fn with_stores<R>(
+    shared: &Shared,
+    f: impl AsyncFnOnce(&mut SignalStores) -> Result<R>,
+) -> Result<R> {
+    tokio::task::block_in_place(|| {
+        let mut signal = shared.signal.lock().unwrap_or_else(|p| p.into_inner());
+        tokio::runtime::Handle::current().block_on(f(&mut signal.stores))
+    })
+}
+
+/// Sign the server's account challenge with our identity key.
+pub(crate) fn sign_challenge(
+    identity: &voipc_crypto::SerializableIdentityKeyPair,
+    payload: &[u8],
+) -> Result<Vec<u8>> {
+    let pair = identity.to_identity_key_pair().context("invalid identity key")?;
+    voipc_crypto::sign_with_identity(&pair, payload).context("failed to sign auth challenge")
+}
+
+/// Ask for the prekey bundles of users we have no session with yet.
+pub(crate) async fn request_sessions(shared: &Shared, users: impl IntoIterator<Item = UserId>) {
+    let to_request: Vec<UserId> = {
+        let mut signal = shared.signal.lock().unwrap_or_else(|p| p.into_inner());
+        users
+            .into_iter()
+            .filter(|&uid| {
+                uid != shared.user_id
+                    && !signal.established_sessions.contains(&uid)
+                    && signal.pending_sessions.insert(uid)
+            })
+            .collect()
+    };
+    for target_user_id in to_request {
+        info!(target_user_id, "requesting prekey bundle for E2E session");
+        if let Err(e) = shared.send(&ClientMessage::RequestPreKeyBundle { target_user_id }).await {
+            warn!(target_user_id, "failed to request prekey bundle: {}", e);
+        }
+    }
+}
+
+/// A user left the server; their sessions and sender keys are gone with them.
+pub(crate) fn forget_user(shared: &Shared, user_id: UserId) {
+    let mut signal = shared.signal.lock().unwrap_or_else(|p| p.into_inner());
+    signal.pending_sessions.remove(&user_id);
+    signal.established_sessions.remove(&user_id);
+    for users in signal.sender_key_distributed.values_mut() {
+        users.remove(&user_id);
+    }
+    for users in signal.sender_key_received.values_mut() {
+        users.remove(&user_id);
+    }
+}
+
+/// We ended up in another channel: its sender keys start over, and members
+/// we already have a session with get ours right away.
+pub(crate) async fn channel_changed(shared: &Shared, channel_id: ChannelId, members: &[UserId]) {
+    let ready: Vec<UserId> = {
+        let mut signal = shared.signal.lock().unwrap_or_else(|p| p.into_inner());
+        signal.sender_key_distributed.remove(&channel_id);
+        signal.sender_key_received.remove(&channel_id);
+        members
+            .iter()
+            .copied()
+            .filter(|uid| *uid != shared.user_id && signal.established_sessions.contains(uid))
+            .collect()
+    };
+    if channel_id == 0 {
+        return;
+    }
+    for uid in ready {
+        distribute_sender_key(shared, channel_id, uid).await;
+    }
+}
+
+/// A bundle we asked for arrived: set up the session, send what was queued
+/// for that user and hand them our sender key.
+pub(crate) async fn handle_prekey_bundle(shared: &Shared, user_id: UserId, bundle: &PreKeyBundleData) {
+    let otp = bundle.prekeys.first();
+    let result = with_stores(shared, async |stores| {
+        voipc_crypto::session::establish_session(
+            stores,
+            user_id,
+            bundle.registration_id,
+            bundle.device_id,
+            &bundle.identity_key,
+            bundle.signed_prekey_id,
+            &bundle.signed_prekey,
+            &bundle.signed_prekey_signature,
+            otp.map(|k| k.id),
+            otp.map(|k| k.public_key.as_slice()),
+        )
+        .await
+    });
+    if let Err(e) = result {
+        warn!(user_id, "failed to establish E2E session: {}", e);
+        bundle_unavailable(shared, user_id);
+        return;
+    }
+    info!(user_id, "E2E session established");
+    session_established(shared, user_id);
+
+    let queued = shared
+        .signal
+        .lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .take_pending(PendingTarget::Direct(user_id));
+    for content in queued {
+        if let Err(e) = send_direct_encrypted(shared, user_id, &content).await {
+            warn!(user_id, "failed to send queued direct message: {}", e);
+        }
+    }
+
+    let channel_id = shared.channel_id.load(Ordering::Relaxed);
+    if channel_id != 0 {
+        distribute_sender_key(shared, channel_id, user_id).await;
+    }
+}
+
+/// The server has no bundle for the user; stop waiting for one.
+pub(crate) fn bundle_unavailable(shared: &Shared, user_id: UserId) {
+    let mut signal = shared.signal.lock().unwrap_or_else(|p| p.into_inner());
+    signal.pending_sessions.remove(&user_id);
+}
+
+fn session_established(shared: &Shared, user_id: UserId) {
+    let mut signal = shared.signal.lock().unwrap_or_else(|p| p.into_inner());
+    signal.pending_sessions.remove(&user_id);
+    signal.established_sessions.insert(user_id);
+}
+
+/// Send our sender key for a channel to one member, encrypted pairwise.
+async fn distribute_sender_key(shared: &Shared, channel_id: ChannelId, target_user_id: UserId) {
+    let own_user_id = shared.user_id;
+    let result = with_stores(shared, async |stores| {
+        let distribution =
+            voipc_crypto::group::create_distribution_message(stores, own_user_id, channel_id).await?;
+        voipc_crypto::session::encrypt_message(stores, target_user_id, &distribution).await
+    });
+    let (distribution_message, message_type) = match result {
+        Ok(encrypted) => encrypted,
+        Err(e) => {
+            warn!(target_user_id, channel_id, "failed to create sender key: {}", e);
+            return;
+        }
+    };
+    let msg = ClientMessage::DistributeSenderKey {
+        channel_id,
+        target_user_id,
+        distribution_message,
+        message_type,
+    };
+    if let Err(e) = shared.send(&msg).await {
+        warn!(target_user_id, "failed to send sender key: {}", e);
+        return;
+    }
+    info!(target_user_id, channel_id, "sender key distributed");
+    shared
+        .signal
+        .lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .sender_key_distributed
+        .entry(channel_id)
+        .or_default()
+        .insert(target_user_id);
+    flush_channel_queue(shared, channel_id).await;
+}
+
+/// Another member's sender key: store it, send ours back if they don't
+/// have it yet.
+pub(crate) async fn handle_sender_key(
+    shared: &Shared,
+    channel_id: ChannelId,
+    from_user_id: UserId,
+    ciphertext: &[u8],
+    message_type: u8,
+) {
+    let result = with_stores(shared, async |stores| {
+        let distribution =
+            voipc_crypto::session::decrypt_message(stores, from_user_id, ciphertext, message_type)
+                .await?;
+        voipc_crypto::group::process_distribution_message(stores, from_user_id, channel_id, &distribution)
+            .await
+    });
+    if let Err(e) = result {
+        warn!(from_user_id, channel_id, "failed to process sender key: {}", e);
+        return;
+    }
+    info!(from_user_id, channel_id, "sender key received");
+
+    let reciprocate = {
+        let mut signal = shared.signal.lock().unwrap_or_else(|p| p.into_inner());
+        // A PreKeySignalMessage sets up the session on our side too
+        if message_type == 1 {
+            signal.pending_sessions.remove(&from_user_id);
+            signal.established_sessions.insert(from_user_id);
+        }
+        signal
+            .sender_key_received
+            .entry(channel_id)
+            .or_default()
+            .insert(from_user_id);
+        !signal
+            .sender_key_distributed
+            .get(&channel_id)
+            .is_some_and(|users| users.contains(&from_user_id))
+    };
+    if reciprocate {
+        distribute_sender_key(shared, channel_id, from_user_id).await;
+    }
+}
+
+/// Send the channel messages queued while no one had our sender key.
+async fn flush_channel_queue(shared: &Shared, channel_id: ChannelId) {
+    let queued = shared
+        .signal
+        .lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .take_pending(PendingTarget::Channel(channel_id));
+    for content in queued {
+        if let Err(e) = send_channel_encrypted(shared, channel_id, &content).await {
+            warn!(channel_id, "failed to send queued channel message: {}", e);
+        }
+    }
+}
+
+/// Send a channel message, or queue it until a member has our sender key.
+/// Returns false if it was queued.
+pub(crate) async fn send_channel_message(shared: &Shared, channel_id: ChannelId, content: String) -> Result<bool> {
+    let ready = {
+        let mut signal = shared.signal.lock().unwrap_or_else(|p| p.into_inner());
+        let ready = signal
+            .sender_key_distributed
+            .get(&channel_id)
+            .is_some_and(|users| !users.is_empty());
+        if !ready {
+            signal.pending_messages.push(PendingMessage {
+                target: PendingTarget::Channel(channel_id),
+                content: content.clone(),
+                queued_at: Instant::now(),
+            });
+        }
+        ready
+    };
+    if ready {
+        send_channel_encrypted(shared, channel_id, &content).await?;
+    }
+    Ok(ready)
+}
+
+async fn send_channel_encrypted(shared: &Shared, channel_id: ChannelId, content: &str) -> Result<()> {
+    let own_user_id = shared.user_id;
+    let ciphertext = with_stores(shared, async |stores| {
+        voipc_crypto::group::encrypt_group_message(stores, own_user_id, channel_id, content.as_bytes()).await
+    })?;
+    shared.send(&ClientMessage::SendEncryptedChannelMessage { ciphertext }).await
+}
+
+/// Send a direct message, or queue it and ask for the user's prekey bundle
+/// if there is no session yet. Returns false if it was queued.
+pub(crate) async fn send_direct_message(shared: &Shared, target_user_id: UserId, content: String) -> Result<bool> {
+    let ready = {
+        let mut signal = shared.signal.lock().unwrap_or_else(|p| p.into_inner());
+        let ready = signal.established_sessions.contains(&target_user_id);
+        if !ready {
+            signal.pending_messages.push(PendingMessage {
+                target: PendingTarget::Direct(target_user_id),
+                content: content.clone(),
+                queued_at: Instant::now(),
+            });
+        }
+        ready
+    };
+    if ready {
+        send_direct_encrypted(shared, target_user_id, &content).await?;
+    } else {
+        request_sessions(shared, [target_user_id]).await;
+    }
+    Ok(ready)
+}
+
+async fn send_direct_encrypted(shared: &Shared, target_user_id: UserId, content: &str) -> Result<()> {
+    let (ciphertext, message_type) = with_stores(shared, async |stores| {
+        voipc_crypto::session::encrypt_message(stores, target_user_id, content.as_bytes()).await
+    })?;
+    shared
+        .send(&ClientMessage::SendEncryptedDirectMessage {
+            target_user_id,
+            ciphertext,
+            message_type,
+        })
+        .await
+}
+
+/// Decrypt a pairwise message (direct message or poke).
+pub(crate) fn decrypt_direct(
+    shared: &Shared,
+    from_user_id: UserId,
+    ciphertext: &[u8],
+    message_type: u8,
+) -> Result<String> {
+    let plaintext = with_stores(shared, async |stores| {
+        voipc_crypto::session::decrypt_message(stores, from_user_id, ciphertext, message_type).await
+    })?;
+    if message_type == 1 {
+        session_established(shared, from_user_id);
+    }
+    String::from_utf8(plaintext).map_err(|_| anyhow!("message is not valid UTF-8"))
+}
+
+/// Decrypt a channel message with its sender's sender key.
+pub(crate) fn decrypt_channel(
+    shared: &Shared,
+    channel_id: ChannelId,
+    user_id: UserId,
+    ciphertext: &[u8],
+) -> Result<String> {
+    let plaintext = with_stores(shared, async |stores| {
+        voipc_crypto::group::decrypt_group_message(stores, user_id, channel_id, ciphertext).await
+    })?;
+    String::from_utf8(plaintext).map_err(|_| anyhow!("message is not valid UTF-8"))
+}
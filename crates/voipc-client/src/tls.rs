@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use tracing::{info, warn};
+
+/// Split "host:port" or "[v6]:port" into host and port.
+pub fn parse_address(address: &str) -> Result<(String, u16)> {
+    let (host, port_str) = if let Some(rest) = address.strip_prefix('[') {
+        // IPv6: [::1]:9987
+        let Some((host, port_str)) = rest.split_once("]:") else {
+            bail!("invalid IPv6 address format, expected [host]:port");
+        };
+        (host, port_str)
+    } else {
+        let Some((host, port_str)) = address.rsplit_once(':') else {
+            bail!("invalid address format, expected host:port");
+        };
+        (host, port_str)
+    };
+    let port: u16 = port_str.parse().context("invalid port number")?;
+    if host.is_empty() {
+        bail!("host cannot be empty");
+    }
+    Ok((host.to_string(), port))
+}
+
+/// The TLS server name for a host, which may be an IP address.
+pub fn server_name(host: &str) -> Result<rustls::pki_types::ServerName<'static>> {
+    if let Ok(ip) = host.parse::<std::net::IpAddr>() {
+        return Ok(rustls::pki_types::ServerName::IpAddress(ip.into()));
+    }
+    rustls::pki_types::ServerName::try_from(host.to_string())
+        .with_context(|| format!("invalid server name '{}'", host))
+}
+
+/// TLS settings for connecting to a server. Self-signed certificates are
+/// only accepted with TOFU pinning: the first certificate seen for a host is
+/// trusted, and a different one later is rejected.
+pub fn client_config(accept_invalid_certs: bool, pin_file: Option<PathBuf>) -> rustls::ClientConfig {
+    if accept_invalid_certs {
+        warn!("using TOFU certificate pinning (self-signed mode)");
+        rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(TofuCertVerifier::new(pin_file)))
+            .with_no_client_auth()
+    } else {
+        let mut root_store = rustls::RootCertStore::empty();
+        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        rustls::ClientConfig::builder()
+            .with_root_certificates(root_store)
+            .with_no_client_auth()
+    }
+}
+
+/// Certificate verifier that accepts self-signed certs with TOFU pinning.
+/// Pins map the host to the SHA-256 fingerprint of its certificate and are
+/// kept in memory, plus in `pin_file` (the Tauri app's `tofu_pins.json`
+/// format) if given.
+#[derive(Debug)]
+pub struct TofuCertVerifier {
+    pins: Mutex<HashMap<String, Vec<u8>>>,
+    pin_file: Option<PathBuf>,
+}
+
+impl TofuCertVerifier {
+    pub fn new(pin_file: Option<PathBuf>) -> Self {
+        let pins = pin_file.as_ref().map(load_pins).unwrap_or_default();
+        Self {
+            pins: Mutex::new(pins),
+            pin_file,
+        }
+    }
+
+    /// Pin the fingerprint on first sight; reject it if it changed since.
+    fn check(&self, host_key: String, fingerprint: Vec<u8>) -> Result<(), rustls::Error> {
+        let mut pins = self.pins.lock().unwrap_or_else(|p| p.into_inner());
+        match pins.get(&host_key) {
+            Some(pinned) if *pinned != fingerprint => {
+                warn!("TOFU: certificate fingerprint changed for {}! Possible MITM attack.", host_key);
+                Err(rustls::Error::General(format!(
+                    "server certificate fingerprint changed for {}",
+                    host_key
+                )))
+            }
+            Some(_) => {
+                info!("TOFU: certificate fingerprint matches for {}", host_key);
+                Ok(())
+            }
+            None => {
+                info!("TOFU: pinning certificate for {} (first connection)", host_key);
+                pins.insert(host_key, fingerprint);
+                if let Some(path) = &self.pin_file {
+                    save_pins(path, &pins);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Load pins stored as `{ "host": "base64 fingerprint" }`. Empty on any error.
+fn load_pins(path: &PathBuf) -> HashMap<String, Vec<u8>> {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    let Ok(data) = std::fs::read_to_string(path) else {
+        return HashMap::new();
+    };
+    let Ok(map) = serde_json::from_str::<HashMap<String, String>>(&data) else {
+        return HashMap::new();
+    };
+    map.into_iter()
+        .filter_map(|(k, v)| STANDARD.decode(&v).ok().map(|bytes| (k, bytes)))
+        .collect()
+}
+
+fn save_pins(path: &PathBuf, pins: &HashMap<String, Vec<u8>>) {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    let map: HashMap<&str, String> = pins
+        .iter()
+        .map(|(k, v)| (k.as_str(), STANDARD.encode(v)))
+        .collect();
+    match serde_json::to_string_pretty(&map) {
+        Ok(json) => {
+            if let Err(e) = std::fs::write(path, json) {
+                warn!("failed to save TOFU pins: {}", e);
+            }
+        }
+        Err(e) => warn!("failed to serialize TOFU pins: {}", e),
+    }
+}
+
+/// Canonical pin key: lowercase DNS name or standard IP string.
+fn host_key(server_name: &rustls::pki_types::ServerName<'_>) -> String {
+    match server_name {
+        rustls::pki_types::ServerName::DnsName(dns) => dns.as_ref().to_lowercase(),
+        rustls::pki_types::ServerName::IpAddress(ip) => match ip {
+            rustls::pki_types::IpAddr::V4(v4) => std::net::Ipv4Addr::from(*v4.as_ref()).to_string(),
+            rustls::pki_types::IpAddr::V6(v6) => std::net::Ipv6Addr::from(*v6.as_ref()).to_string(),
+        },
+        _ => format!("{:?}", server_name),
+    }
+}
+
+impl rustls::client::danger::ServerCertVerifier for TofuCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        let fingerprint = ring::digest::digest(&ring::digest::SHA256, end_entity.as_ref());
+        self.check(host_key(server_name), fingerprint.as_ref().to_vec())?;
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_host_and_port() {
+        assert_eq!(parse_address("example.com:9987").unwrap(), ("example.com".into(), 9987));
+        assert_eq!(parse_address("[::1]:9987").unwrap(), ("::1".into(), 9987));
+        assert!(parse_address("example.com").is_err());
+        assert!(parse_address(":9987").is_err());
+        assert!(parse_address("example.com:port").is_err());
+        assert!(parse_address("[::1]9987").is_err());
+    }
+
+    #[test]
+    fn tofu_rejects_a_changed_certificate() {
+        let verifier = TofuCertVerifier::new(None);
+        assert!(verifier.check("example.com".into(), vec![1; 32]).is_ok());
+        assert!(verifier.check("example.com".into(), vec![1; 32]).is_ok());
+        assert!(verifier.check("example.com".into(), vec![2; 32]).is_err());
+        assert!(verifier.check("other.example.com".into(), vec![2; 32]).is_ok());
+    }
+}
@@ -42,6 +42,17 @@ pub fn generate_identity_key_pair() -> IdentityKeyPair {
     IdentityKeyPair::generate(&mut OsRng)
 }
 
+/// Sign `message` with the identity private key (XEdDSA).
+///
+/// Used to answer the server's account challenge, proving possession of the
+/// identity key sent in `Authenticate`.
+pub fn sign_with_identity(pair: &IdentityKeyPair, message: &[u8]) -> anyhow::Result<Vec<u8>> {
+    Ok(pair
+        .private_key()
+        .calculate_signature(message, &mut OsRng)?
+        .to_vec())
+}
+
 /// Serialize an IdentityKey's public key to bytes (for protocol messages).
 pub fn identity_key_to_bytes(key: &IdentityKey) -> Vec<u8> {
     key.serialize().to_vec()
@@ -74,4 +85,13 @@ mod tests {
         let restored = identity_key_from_bytes(&bytes).unwrap();
         assert_eq!(pair.public_key().serialize(), restored.serialize());
     }
+
+    #[test]
+    fn identity_signature_verifies() {
+        let pair = generate_identity_key_pair();
+        let sig = sign_with_identity(&pair, b"challenge").unwrap();
+        assert_eq!(sig.len(), 64);
+        assert!(pair.public_key().verify_signature(b"challenge", &sig));
+        assert!(!pair.public_key().verify_signature(b"other", &sig));
+    }
 }
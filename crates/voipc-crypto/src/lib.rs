@@ -17,7 +17,7 @@ pub mod session;
 pub mod stores;
 
 // Re-export key types for convenience
-pub use identity::{generate_identity_key_pair, SerializableIdentityKeyPair};
+pub use identity::{generate_identity_key_pair, sign_with_identity, SerializableIdentityKeyPair};
 pub use media_keys::{MediaKey, build_aad, media_decrypt, media_encrypt, MAX_SEQUENCE_BEFORE_ROTATION};
 pub use prekey::PreKeySet;
 pub use stores::SignalStores;
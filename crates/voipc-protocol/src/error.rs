@@ -11,9 +11,27 @@ pub enum ProtocolError {
     #[error("invalid fragment: index {index} >= count {count}")]
     InvalidFragmentIndex { index: u8, count: u8 },
 
-    #[error("message too large: {0} bytes (max 65536)")]
+    #[error("message too large: {0} bytes")]
     MessageTooLarge(usize),
 
+    #[error("unsupported message envelope version {0}")]
+    UnsupportedEnvelope(u8),
+
+    #[error("malformed message envelope")]
+    InvalidEnvelope,
+
+    /// A message of a type (or with flags) this build doesn't know, e.g.
+    /// from a newer peer. Receivers skip it.
+    #[error("unknown message type {type_id} (flags 0x{flags:02x})")]
+    UnknownMessage { type_id: u16, flags: u8 },
+
+    /// A compressed or chunked message from a peer that didn't negotiate it.
+    #[error("message flags 0x{0:02x} used without negotiating them")]
+    FlagsNotNegotiated(u8),
+
+    #[error("failed to decompress message: {0}")]
+    Decompression(std::io::Error),
+
     #[error("serialization error: {0}")]
     Serialization(#[from] postcard::Error),
 
@@ -21,6 +39,14 @@ pub enum ProtocolError {
     Io(#[from] std::io::Error),
 }
 
+impl ProtocolError {
+    /// Whether the error is a well-formed message this build can't read, which
+    /// should be skipped rather than end the connection.
+    pub fn is_unknown_message(&self) -> bool {
+        matches!(self, Self::UnknownMessage { .. })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,6 +80,15 @@ mod tests {
         assert!(proto_err.to_string().contains("broken"));
     }
 
+    #[test]
+    fn unknown_message_is_skippable() {
+        let e = ProtocolError::UnknownMessage { type_id: 300, flags: 0x80 };
+        assert!(e.is_unknown_message());
+        assert!(e.to_string().contains("300"));
+        assert!(e.to_string().contains("0x80"));
+        assert!(!ProtocolError::InvalidEnvelope.is_unknown_message());
+    }
+
     #[test]
     fn invalid_fragment_index_display() {
         let e = ProtocolError::InvalidFragmentIndex { index: 5, count: 3 };
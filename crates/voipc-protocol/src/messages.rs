@@ -8,9 +8,9 @@ pub enum ClientMessage {
     /// Initial authentication. Sent immediately after TLS handshake.
     Authenticate {
         username: String,
-        /// Protocol version for forward compatibility.
+        /// Highest protocol version the client speaks.
         protocol_version: u32,
-        /// Application version (e.g. "0.1.0"). Must match server version.
+        /// Application version (e.g. "0.1.0"), for logging.
         #[serde(default)]
         app_version: String,
         /// Client's long-term identity public key (32-byte Curve25519).
@@ -19,6 +19,20 @@ pub enum ClientMessage {
         /// Initial pre-key bundle for other users to establish sessions.
         #[serde(default)]
         prekey_bundle: Option<PreKeyBundleData>,
+        /// Virtual server to log in to, when one process hosts several.
+        /// None = chosen by the TLS server name, or the default server.
+        #[serde(default)]
+        virtual_server: Option<String>,
+        /// Token from a previous `Authenticated`, to take back that session
+        /// after the connection dropped. Ignored if the session has expired.
+        #[serde(default)]
+        resume_token: Option<Vec<u8>>,
+        /// Lowest protocol version the client speaks.
+        #[serde(default)]
+        min_protocol_version: u32,
+        /// Optional features the client supports.
+        #[serde(default)]
+        capabilities: Capabilities,
     },
 
     /// Request to join a specific channel (with optional password).
@@ -27,10 +41,15 @@ pub enum ClientMessage {
         password: Option<String>,
     },
 
-    /// Create a new channel.
+    /// Create a new channel, optionally as a sub-channel of `parent_id`.
+    /// A `permanent` channel is saved with the configured channels and never
+    /// auto-deleted (requires the create-permanent permission).
     CreateChannel {
         name: String,
         password: Option<String>,
+        parent_id: Option<ChannelId>,
+        #[serde(default)]
+        permanent: bool,
     },
 
     /// Client is disconnecting gracefully.
@@ -151,6 +170,65 @@ pub enum ClientMessage {
         ciphertext: Vec<u8>,
         message_type: u8,
     },
+
+    // ── Accounts ──────────────────────────────────────────────────────
+
+    /// Answer to `ServerMessage::AuthChallenge`: an XEdDSA signature over
+    /// `codec::auth_challenge_payload(nonce, username)` made with the
+    /// identity key sent in `Authenticate`.
+    AuthChallengeResponse { signature: Vec<u8> },
+
+    /// Register the current username, binding it to the identity key this
+    /// session proved ownership of during authentication.
+    RegisterAccount,
+
+    // ── Bans ──────────────────────────────────────────────────────────
+
+    /// Ban a connected user from the server by username, identity key and IP,
+    /// and disconnect them. Requires the `ban` permission.
+    BanUser {
+        user_id: UserId,
+        reason: String,
+        /// Ban length in seconds. None = permanent.
+        duration_secs: Option<u64>,
+    },
+
+    /// Remove a ban by ID. Requires the `ban` permission.
+    UnbanUser { ban_id: u32 },
+
+    /// Request the active ban list. Requires the `ban` permission.
+    ListBans,
+
+    // ── Whisper ───────────────────────────────────────────────────────
+
+    /// Replace the whisper list: whisper voice packets are sent only to these
+    /// targets. An empty list clears it. The server answers with a fresh
+    /// `WhisperKey` for this session.
+    SetWhisperList { targets: Vec<WhisperTarget> },
+
+    // ── Moderation ────────────────────────────────────────────────────
+
+    /// Server-mute or unmute a user: their voice is dropped by the server
+    /// (requires Kick in the user's channel).
+    SetServerMuted { user_id: UserId, muted: bool },
+
+    /// Make a user a priority speaker, whose voice other clients duck under
+    /// (requires Edit in the user's channel).
+    SetPrioritySpeaker { user_id: UserId, enabled: bool },
+
+    /// Move another user into a channel, bypassing its password (requires
+    /// Move in both the user's channel and the destination).
+    MoveUser { user_id: UserId, channel_id: ChannelId },
+
+    // ── Channel topics ────────────────────────────────────────────────
+
+    /// Replace a channel's description and topic (requires Edit in the
+    /// channel). Answered with `ChannelUpdated` to everyone, or `ChannelError`.
+    SetChannelTopic {
+        channel_id: ChannelId,
+        description: String,
+        topic: String,
+    },
 }
 
 /// Messages sent from server to client over the TCP control channel.
@@ -164,10 +242,30 @@ pub enum ServerMessage {
         udp_port: u16,
         /// Token the client must include in every UDP voice packet.
         udp_token: u64,
+        /// Secret for resuming this session on a new connection if this one
+        /// drops. Replaced on every resume.
+        #[serde(default)]
+        resume_token: Vec<u8>,
+        /// True if an existing session was resumed: the client is still in
+        /// its channel and nobody saw it leave.
+        #[serde(default)]
+        resumed: bool,
+        /// Protocol version used on this connection: the highest both sides
+        /// speak.
+        #[serde(default)]
+        protocol_version: u32,
+        /// Optional features both sides support. Messages of other features
+        /// are neither sent nor accepted.
+        #[serde(default)]
+        capabilities: Capabilities,
     },
 
     /// Authentication failed.
-    AuthError { reason: String },
+    AuthError {
+        reason: String,
+        #[serde(default)]
+        code: AuthErrorCode,
+    },
 
     /// Full channel list (sent on connect and on request).
     ChannelList { channels: Vec<ChannelInfo> },
@@ -199,8 +297,16 @@ pub enum ServerMessage {
     /// Pong response for latency measurement.
     Pong { timestamp: u64 },
 
-    /// Server is shutting down.
-    ServerShutdown { reason: String },
+    /// Server is shutting down. Connections are closed after
+    /// `shutdown_in_secs` at the latest (0 = now); `reconnect_to` names
+    /// another server ("host:port") to move to in the meantime.
+    ServerShutdown {
+        reason: String,
+        #[serde(default)]
+        shutdown_in_secs: u32,
+        #[serde(default)]
+        reconnect_to: Option<String>,
+    },
 
     /// Client was moved to a different channel.
     MovedToChannel { channel_id: ChannelId },
@@ -341,4 +447,129 @@ pub enum ServerMessage {
         ciphertext: Vec<u8>,
         message_type: u8,
     },
+
+    // ── Accounts ──────────────────────────────────────────────────────
+
+    /// Proof-of-possession challenge for the identity key sent in
+    /// `Authenticate`. The client must reply with `AuthChallengeResponse`
+    /// before `Authenticated` is sent.
+    AuthChallenge { nonce: Vec<u8> },
+
+    /// Your username is now registered to your identity key.
+    AccountRegistered { username: String },
+
+    /// Error response for account operations.
+    AccountError { reason: String },
+
+    // ── Permissions ───────────────────────────────────────────────────
+
+    /// Your group or effective permissions changed. Sent after authentication
+    /// and whenever they change. `channels` lists only channels whose
+    /// effective permissions differ from `server`.
+    PermissionsChanged {
+        group: ServerGroup,
+        server: Permissions,
+        channels: Vec<ChannelPermissions>,
+    },
+
+    // ── Bans ──────────────────────────────────────────────────────────
+
+    /// You were banned from the server. The connection is closed afterwards.
+    Banned { reason: String },
+
+    /// Active bans (response to `ListBans`, and after a ban is added or removed).
+    BanList { bans: Vec<BanInfo> },
+
+    /// Error response for ban operations.
+    BanError { reason: String },
+
+    // ── Administration ────────────────────────────────────────────────
+
+    /// You were removed from the server by an operator. The connection is
+    /// closed afterwards.
+    KickedFromServer { reason: String },
+
+    /// Announcement from the server operator, shown to every user.
+    ServerNotice { message: String },
+
+    // ── Whisper ───────────────────────────────────────────────────────
+
+    /// Server-issued key for the whisper packets of `session_id` (sent over
+    /// TLS). Sent to the whispering user after `SetWhisperList`, and to each
+    /// recipient before their first whisper packet from that user.
+    WhisperKey {
+        session_id: SessionId,
+        key_id: u16,
+        key_bytes: Vec<u8>,
+    },
+
+    /// Error response for whisper list operations.
+    WhisperError { reason: String },
+
+    // ── Moderation ────────────────────────────────────────────────────
+
+    /// A user was server-muted or unmuted by a moderator.
+    UserServerMuted { user_id: UserId, muted: bool },
+
+    /// A user became, or stopped being, a priority speaker.
+    UserPrioritySpeaker { user_id: UserId, enabled: bool },
+
+    /// Error response for moderation operations.
+    ModerationError { reason: String },
+
+    // ── Idle ──────────────────────────────────────────────────────────
+
+    /// A user went idle (and was moved to the AFK channel) or came back.
+    UserIdle { user_id: UserId, idle: bool },
+
+    // ── Welcome ───────────────────────────────────────────────────────
+
+    /// The server's message of the day, sent right after `Authenticated`
+    /// when one is configured.
+    Motd { message: String },
+
+    // ── Rate limiting ─────────────────────────────────────────────────
+
+    /// A request or packet was dropped because the client exceeded a rate
+    /// limit. Sent at most once a second per limit, however many are
+    /// dropped.
+    RateLimited {
+        limit: RateLimitKind,
+        /// Time until the limit admits another request.
+        retry_after_ms: u64,
+    },
+}
+
+impl ClientMessage {
+    /// Capabilities that must have been negotiated to send this message.
+    pub fn required_capabilities(&self) -> Capabilities {
+        match self {
+            Self::StartScreenShare { .. }
+            | Self::StopScreenShare
+            | Self::WatchScreenShare { .. }
+            | Self::StopWatchingScreenShare
+            | Self::RequestKeyframe { .. } => Capabilities::SCREEN_SHARE,
+            Self::DistributeMediaKey { .. } => Capabilities::ENCRYPTED_MEDIA,
+            Self::SetWhisperList { .. } => Capabilities::WHISPER,
+            _ => Capabilities::NONE,
+        }
+    }
+}
+
+impl ServerMessage {
+    /// Capabilities a client must have negotiated to be sent this message.
+    pub fn required_capabilities(&self) -> Capabilities {
+        match self {
+            Self::ScreenShareStarted { .. }
+            | Self::ScreenShareStopped { .. }
+            | Self::WatchingScreenShare { .. }
+            | Self::StoppedWatchingScreenShare { .. }
+            | Self::ViewerCountChanged { .. }
+            | Self::KeyframeRequested
+            | Self::ScreenShareError { .. } => Capabilities::SCREEN_SHARE,
+            Self::MediaKeyReceived { .. } | Self::ChannelMediaKey { .. } => Capabilities::ENCRYPTED_MEDIA,
+            Self::WhisperKey { .. } | Self::WhisperError { .. } => Capabilities::WHISPER,
+            _ => Capabilities::NONE,
+        }
+    }
 }
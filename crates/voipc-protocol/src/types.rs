@@ -24,6 +24,15 @@ pub struct UserInfo {
     pub is_deafened: bool,
     #[serde(default)]
     pub is_screen_sharing: bool,
+    /// Muted by a moderator; the server drops this user's voice.
+    #[serde(default)]
+    pub is_server_muted: bool,
+    /// Other clients duck their playback while this user talks.
+    #[serde(default)]
+    pub is_priority_speaker: bool,
+    /// Idle past the server's AFK timeout.
+    #[serde(default)]
+    pub is_idle: bool,
 }
 
 /// Information about a screen capture source (display or window).
@@ -63,7 +72,7 @@ pub struct OneTimePreKey {
 }
 
 /// Information about a channel/room.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ChannelInfo {
     pub channel_id: ChannelId,
     pub name: String,
@@ -76,6 +85,211 @@ pub struct ChannelInfo {
     pub has_password: bool,
     /// User who created this channel (None for the permanent General channel).
     pub created_by: Option<UserId>,
+    /// Parent channel (None = top level).
+    #[serde(default)]
+    pub parent_id: Option<ChannelId>,
+    /// Sort order among siblings (lower first, ties broken by channel_id).
+    #[serde(default)]
+    pub position: u32,
+    /// Short current-topic line, editable live (empty = none).
+    #[serde(default)]
+    pub topic: String,
+}
+
+// ── Permission types ──────────────────────────────────────────────────
+
+/// Server-wide group a user belongs to. Unregistered users are guests;
+/// registered accounts default to members.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerGroup {
+    Admin,
+    Moderator,
+    Member,
+    #[default]
+    Guest,
+}
+
+/// Actions a user may perform, either server-wide or inside one channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Permissions {
+    /// Join the channel.
+    pub join: bool,
+    /// Transmit voice.
+    pub speak: bool,
+    /// Start a screen share.
+    pub screen_share: bool,
+    /// Create new channels (server-wide only).
+    pub create_channel: bool,
+    /// Kick other users out of the channel.
+    pub kick: bool,
+    /// Move other users between channels.
+    pub move_users: bool,
+    /// Edit channel settings (password, invites, topic).
+    pub edit: bool,
+    /// Ban users from the server and manage the ban list (server-wide only).
+    pub ban: bool,
+    /// Create channels that survive restarts (server-wide only).
+    pub create_permanent_channel: bool,
+}
+
+/// A server ban entry, as shown to moderators.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BanInfo {
+    pub ban_id: u32,
+    /// Banned username (matched case-insensitively).
+    pub username: Option<String>,
+    /// Banned identity key, lowercase hex.
+    pub identity_key: Option<String>,
+    /// Banned IP address or CIDR range (e.g. "203.0.113.0/24").
+    pub ip: Option<String>,
+    pub reason: String,
+    /// Username of whoever created the ban.
+    pub banned_by: String,
+    /// Creation time (seconds since UNIX epoch).
+    pub created_at: u64,
+    /// Expiry time (seconds since UNIX epoch). None = permanent.
+    pub expires_at: Option<u64>,
+}
+
+/// A recipient set for whispered voice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WhisperTarget {
+    /// One user.
+    User(UserId),
+    /// Everyone in a channel.
+    Channel(ChannelId),
+    /// Everyone in a channel and all of its sub-channels.
+    ChannelTree(ChannelId),
+}
+
+/// Which rate limit a client ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKind {
+    /// Voice packets (UDP).
+    Voice,
+    /// Screen share video packets (UDP).
+    Video,
+    /// Control messages of any kind.
+    Messages,
+    /// Channel password attempts.
+    PasswordAttempts,
+    /// Channel and direct chat messages.
+    Chat,
+    /// Channel creation.
+    CreateChannel,
+    /// Pre-key uploads.
+    PreKeys,
+}
+
+/// Why the server refused a login, for clients that act on it (for
+/// example to stop reconnecting). `AuthError::reason` is the text to show.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthErrorCode {
+    /// Not given, or not one of the others.
+    #[default]
+    Other,
+    /// No protocol version both sides speak.
+    VersionMismatch,
+    /// Username empty, too long or with invalid characters.
+    InvalidUsername,
+    ServerFull,
+    /// The identity key signature didn't verify.
+    BadSignature,
+    /// The username is registered to another identity key.
+    IdentityMismatch,
+    /// The server only admits registered accounts.
+    GuestsDisabled,
+    Banned,
+    UsernameTaken,
+    /// The requested virtual server doesn't exist.
+    UnknownServer,
+}
+
+/// Effective permissions in a single channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelPermissions {
+    pub channel_id: ChannelId,
+    pub permissions: Permissions,
+}
+
+// ── Capabilities ──────────────────────────────────────────────────────
+
+/// Optional protocol features, as a bit set. Each side announces what it
+/// supports during authentication and only the features both support are
+/// used on the connection. Unknown bits are ignored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    /// AES-256-GCM voice and video with per-channel media keys.
+    pub const ENCRYPTED_MEDIA: Self = Self(1 << 0);
+    /// Whisper lists and whispered voice.
+    pub const WHISPER: Self = Self(1 << 1);
+    /// Screen sharing and watching.
+    pub const SCREEN_SHARE: Self = Self(1 << 2);
+    /// Desktop audio alongside a screen share.
+    pub const SCREEN_SHARE_AUDIO: Self = Self(1 << 3);
+    /// Deflate-compressed TCP message bodies.
+    pub const COMPRESSION: Self = Self(1 << 4);
+    /// TCP messages split over several frames, for ones over 64 KiB.
+    pub const CHUNKED_MESSAGES: Self = Self(1 << 5);
+
+    /// Everything this build supports.
+    pub const ALL: Self = Self(
+        Self::ENCRYPTED_MEDIA.0
+            | Self::WHISPER.0
+            | Self::SCREEN_SHARE.0
+            | Self::SCREEN_SHARE_AUDIO.0
+            | Self::COMPRESSION.0
+            | Self::CHUNKED_MESSAGES.0,
+    );
+
+    const NAMES: [(Self, &'static str); 6] = [
+        (Self::ENCRYPTED_MEDIA, "encrypted_media"),
+        (Self::WHISPER, "whisper"),
+        (Self::SCREEN_SHARE, "screen_share"),
+        (Self::SCREEN_SHARE_AUDIO, "screen_share_audio"),
+        (Self::COMPRESSION, "compression"),
+        (Self::CHUNKED_MESSAGES, "chunked_messages"),
+    ];
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether every feature in `other` is in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The features in both sets.
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Names of the known features in the set, e.g. for logging.
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(cap, _)| self.contains(*cap))
+            .map(|(_, name)| *name)
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -91,6 +305,9 @@ mod tests {
             is_muted: true,
             is_deafened: true,
             is_screen_sharing: false,
+            is_server_muted: false,
+            is_priority_speaker: false,
+            is_idle: false,
         };
         let bytes = postcard::to_allocvec(&info).unwrap();
         let decoded: UserInfo = postcard::from_bytes(&bytes).unwrap();
@@ -112,14 +329,20 @@ mod tests {
             user_count: 3,
             has_password: true,
             created_by: Some(1),
+            parent_id: Some(2),
+            position: 4,
+            topic: "today".into(),
         };
         let bytes = postcard::to_allocvec(&info).unwrap();
         let decoded: ChannelInfo = postcard::from_bytes(&bytes).unwrap();
         assert_eq!(decoded.channel_id, 5);
         assert_eq!(decoded.name, "Test");
+        assert_eq!(decoded.topic, "today");
         assert_eq!(decoded.max_users, 10);
         assert!(decoded.has_password);
         assert_eq!(decoded.created_by, Some(1));
+        assert_eq!(decoded.parent_id, Some(2));
+        assert_eq!(decoded.position, 4);
     }
 
     #[test]
@@ -132,9 +355,62 @@ mod tests {
             is_muted: false,
             is_deafened: false,
             is_screen_sharing: false,
+            is_server_muted: false,
+            is_priority_speaker: false,
+            is_idle: false,
         };
         let bytes = postcard::to_allocvec(&info).unwrap();
         let decoded: UserInfo = postcard::from_bytes(&bytes).unwrap();
         assert!(!decoded.is_screen_sharing);
     }
+
+    #[test]
+    fn permissions_roundtrip() {
+        let perms = Permissions {
+            join: true,
+            speak: true,
+            kick: true,
+            ..Default::default()
+        };
+        let bytes = postcard::to_allocvec(&perms).unwrap();
+        let decoded: Permissions = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, perms);
+        assert!(!decoded.edit);
+    }
+
+    #[test]
+    fn ban_info_roundtrip() {
+        let ban = BanInfo {
+            ban_id: 7,
+            username: Some("mallory".into()),
+            identity_key: None,
+            ip: Some("203.0.113.0/24".into()),
+            reason: "spam".into(),
+            banned_by: "alice".into(),
+            created_at: 1_700_000_000,
+            expires_at: Some(1_700_003_600),
+        };
+        let bytes = postcard::to_allocvec(&ban).unwrap();
+        let decoded: BanInfo = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.ban_id, 7);
+        assert_eq!(decoded.username.as_deref(), Some("mallory"));
+        assert!(decoded.identity_key.is_none());
+        assert_eq!(decoded.ip.as_deref(), Some("203.0.113.0/24"));
+        assert_eq!(decoded.expires_at, Some(1_700_003_600));
+    }
+
+    #[test]
+    fn capabilities_set_operations() {
+        let client = Capabilities::WHISPER.union(Capabilities::SCREEN_SHARE);
+        let negotiated = client.intersection(Capabilities::ALL);
+        assert_eq!(negotiated, client);
+        assert!(negotiated.contains(Capabilities::WHISPER));
+        assert!(negotiated.contains(Capabilities::NONE));
+        assert!(!negotiated.contains(Capabilities::SCREEN_SHARE_AUDIO));
+        assert_eq!(negotiated.names(), vec!["whisper", "screen_share"]);
+
+        // Bits from a newer peer drop out
+        let future = Capabilities::from_bits(1 << 31 | Capabilities::WHISPER.bits());
+        assert_eq!(future.intersection(Capabilities::ALL), Capabilities::WHISPER);
+    }
 }
@@ -14,6 +14,12 @@ pub enum VoicePacketType {
     Pong = 0x04,
     /// AES-256-GCM encrypted Opus voice data.
     EncryptedOpusVoice = 0x05,
+    /// Opus voice encrypted with the sender's whisper key, routed to the
+    /// sender's whisper list instead of their channel.
+    EncryptedWhisperVoice = 0x06,
+    /// `EncryptedOpusVoice` from a priority speaker. Only the server sets this
+    /// type, when forwarding; the payload is decrypted as `EncryptedOpusVoice`.
+    EncryptedPriorityVoice = 0x07,
 }
 
 impl VoicePacketType {
@@ -24,9 +30,19 @@ impl VoicePacketType {
             0x03 => Ok(Self::Ping),
             0x04 => Ok(Self::Pong),
             0x05 => Ok(Self::EncryptedOpusVoice),
+            0x06 => Ok(Self::EncryptedWhisperVoice),
+            0x07 => Ok(Self::EncryptedPriorityVoice),
             other => Err(ProtocolError::UnknownPacketType(other)),
         }
     }
+
+    /// Whether packets of this type carry a key_id and encrypted payload.
+    pub fn is_encrypted(self) -> bool {
+        matches!(
+            self,
+            Self::EncryptedOpusVoice | Self::EncryptedWhisperVoice | Self::EncryptedPriorityVoice
+        )
+    }
 }
 
 /// Header size: 1 (type) + 4 (session_id) + 8 (udp_token) + 4 (sequence) = 17 bytes.
@@ -52,9 +68,9 @@ pub const OPUS_BITRATE: i32 = 48_000; // 48 kbps
 /// [type: u8] [session_id: u32 BE] [udp_token: u64 BE] [sequence: u32 BE] [opus_data: variable]
 /// ```
 ///
-/// Wire format (encrypted, type 0x05):
+/// Wire format (encrypted, type 0x05, whisper 0x06 or priority 0x07):
 /// ```text
-/// [0x05: u8] [session_id: u32 BE] [udp_token: u64 BE] [sequence: u32 BE] [key_id: u16 BE] [encrypted_opus + 16-byte GCM tag]
+/// [0x05|0x06|0x07: u8] [session_id: u32 BE] [udp_token: u64 BE] [sequence: u32 BE] [key_id: u16 BE] [encrypted_opus + 16-byte GCM tag]
 /// ```
 #[derive(Debug, Clone)]
 pub struct VoicePacket {
@@ -98,6 +114,24 @@ impl VoicePacket {
         }
     }
 
+    /// Create an encrypted whisper packet (encrypted with the sender's whisper key).
+    pub fn encrypted_whisper(
+        session_id: u32,
+        udp_token: u64,
+        sequence: u32,
+        key_id: u16,
+        encrypted_data: Vec<u8>,
+    ) -> Self {
+        Self {
+            packet_type: VoicePacketType::EncryptedWhisperVoice,
+            session_id,
+            udp_token,
+            sequence,
+            opus_data: encrypted_data,
+            key_id,
+        }
+    }
+
     /// Create an end-of-transmission packet (PTT released).
     pub fn end_of_transmission(session_id: u32, udp_token: u64, sequence: u32) -> Self {
         Self {
@@ -124,7 +158,7 @@ impl VoicePacket {
 
     /// Serialize to bytes for UDP transmission.
     pub fn to_bytes(&self) -> Vec<u8> {
-        if self.packet_type == VoicePacketType::EncryptedOpusVoice {
+        if self.packet_type.is_encrypted() {
             // Encrypted format: header + key_id(2) + encrypted data
             let mut buf =
                 Vec::with_capacity(ENCRYPTED_VOICE_HEADER_SIZE + self.opus_data.len());
@@ -163,7 +197,7 @@ impl VoicePacket {
         ]);
         let sequence = u32::from_be_bytes([data[13], data[14], data[15], data[16]]);
 
-        if packet_type == VoicePacketType::EncryptedOpusVoice {
+        if packet_type.is_encrypted() {
             if data.len() < ENCRYPTED_VOICE_HEADER_SIZE {
                 return Err(ProtocolError::PacketTooShort {
                     expected: ENCRYPTED_VOICE_HEADER_SIZE,
@@ -224,6 +258,17 @@ mod tests {
         assert!(decoded.opus_data.is_empty());
     }
 
+    #[test]
+    fn roundtrip_whisper_packet() {
+        let original = VoicePacket::encrypted_whisper(3, 0x55, 9, 2, vec![7; 20]);
+        let bytes = original.to_bytes();
+        assert_eq!(bytes.len(), ENCRYPTED_VOICE_HEADER_SIZE + 20);
+        let decoded = VoicePacket::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.packet_type, VoicePacketType::EncryptedWhisperVoice);
+        assert_eq!(decoded.key_id, 2);
+        assert_eq!(decoded.opus_data, vec![7; 20]);
+    }
+
     #[test]
     fn packet_too_short() {
         let result = VoicePacket::from_bytes(&[0x01, 0x00]);
@@ -273,12 +318,14 @@ mod tests {
         assert_eq!(VoicePacketType::from_byte(0x03).unwrap(), VoicePacketType::Ping);
         assert_eq!(VoicePacketType::from_byte(0x04).unwrap(), VoicePacketType::Pong);
         assert_eq!(VoicePacketType::from_byte(0x05).unwrap(), VoicePacketType::EncryptedOpusVoice);
+        assert_eq!(VoicePacketType::from_byte(0x06).unwrap(), VoicePacketType::EncryptedWhisperVoice);
+        assert_eq!(VoicePacketType::from_byte(0x07).unwrap(), VoicePacketType::EncryptedPriorityVoice);
     }
 
     #[test]
     fn voice_packet_type_invalid() {
         assert!(VoicePacketType::from_byte(0x00).is_err());
-        assert!(VoicePacketType::from_byte(0x06).is_err());
+        assert!(VoicePacketType::from_byte(0x08).is_err());
         assert!(VoicePacketType::from_byte(0xFF).is_err());
     }
 
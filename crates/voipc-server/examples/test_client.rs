@@ -8,9 +8,11 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::time::timeout;
 
 use voipc_protocol::codec::{
-    decode_server_msg, encode_client_msg, try_decode_frame, APP_VERSION, PROTOCOL_VERSION,
+    decode_server_msg, encode_client_msg, try_decode_frame, APP_VERSION, MIN_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
 };
 use voipc_protocol::messages::{ClientMessage, ServerMessage};
+use voipc_protocol::types::Capabilities;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -41,6 +43,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         app_version: APP_VERSION.to_string(),
         identity_key: None,
         prekey_bundle: None,
+        virtual_server: None,
+        resume_token: None,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        capabilities: Capabilities::ALL,
     };
     tls.write_all(&encode_client_msg(&auth)?).await?;
     println!("[OK] Sent Authenticate message");
@@ -85,9 +91,12 @@ async fn read_and_print(
             }
             Ok(Ok(_)) => {
                 // Process all complete frames
-                while let Some(payload) = try_decode_frame(buf)? {
-                    let msg = decode_server_msg(&payload)?;
-                    print_message(&msg);
+                while let Some(payload) = try_decode_frame(buf, Capabilities::ALL)? {
+                    match decode_server_msg(&payload) {
+                        Ok(msg) => print_message(&msg),
+                        Err(e) if e.is_unknown_message() => println!("[INFO] Skipped: {}", e),
+                        Err(e) => return Err(e.into()),
+                    }
                 }
             }
             Ok(Err(e)) => {
@@ -113,7 +122,7 @@ fn print_message(msg: &ServerMessage) {
                 user_id, session_id, udp_port
             );
         }
-        ServerMessage::AuthError { reason } => {
+        ServerMessage::AuthError { reason, .. } => {
             println!("[ERR] Auth failed: {}", reason);
         }
         ServerMessage::ChannelList { channels } => {
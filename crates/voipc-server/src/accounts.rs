@@ -0,0 +1,353 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context};
+use curve25519_dalek::edwards::EdwardsPoint;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use subtle::ConstantTimeEq;
+use tracing::info;
+
+use voipc_protocol::types::ServerGroup;
+
+/// Signal's type byte prefixed to serialized Curve25519 public keys.
+const DJB_KEY_TYPE: u8 = 0x05;
+
+/// A registered account as stored in accounts.json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    /// Username as originally registered (case preserved).
+    pub username: String,
+    /// Identity public key the name is bound to, lowercase hex.
+    pub identity_key: String,
+    /// Registration time (seconds since UNIX epoch).
+    #[serde(default)]
+    pub registered_at: u64,
+    /// Server group (edit the file to promote someone to moderator/admin).
+    #[serde(default = "default_account_group")]
+    pub group: ServerGroup,
+}
+
+fn default_account_group() -> ServerGroup {
+    ServerGroup::Member
+}
+
+/// Registered accounts, keyed by lowercase username.
+///
+/// Backed by a JSON file that is rewritten atomically on every registration.
+/// A store without a path (`AccountStore::default()`) is memory-only.
+#[derive(Default)]
+pub struct AccountStore {
+    accounts: DashMap<String, Account>,
+    path: Option<PathBuf>,
+    /// Serializes file rewrites so concurrent registrations can't interleave.
+    write_lock: Mutex<()>,
+}
+
+impl AccountStore {
+    /// Load accounts from a JSON file. A missing file yields an empty store
+    /// that will be created on the first registration.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let store = Self {
+            path: Some(path.to_path_buf()),
+            ..Self::default()
+        };
+        if !path.exists() {
+            return Ok(store);
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read accounts file: {}", path.display()))?;
+        let entries: Vec<Account> = serde_json::from_str(&content)
+            .with_context(|| format!("invalid JSON in accounts file: {}", path.display()))?;
+
+        for (i, account) in entries.into_iter().enumerate() {
+            if account.username.trim().is_empty() {
+                bail!("account entry {} has an empty username", i);
+            }
+            if decode_hex(&account.identity_key).is_none() {
+                bail!("account '{}' has an invalid identity_key", account.username);
+            }
+            let lower = account.username.to_lowercase();
+            if store.accounts.insert(lower, account.clone()).is_some() {
+                bail!("duplicate account: '{}'", account.username);
+            }
+        }
+
+        info!(accounts = store.accounts.len(), "loaded accounts from {}", path.display());
+        Ok(store)
+    }
+
+    /// Number of registered accounts.
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    /// Look up the identity key a username is registered to.
+    pub fn identity_key_for(&self, username: &str) -> Option<Vec<u8>> {
+        self.accounts
+            .get(&username.to_lowercase())
+            .and_then(|a| decode_hex(&a.identity_key))
+    }
+
+    /// Look up the server group of a registered username.
+    pub fn group_for(&self, username: &str) -> Option<ServerGroup> {
+        self.accounts.get(&username.to_lowercase()).map(|a| a.group)
+    }
+
+    /// Register `username` to `identity_key` and persist the store.
+    ///
+    /// Re-registering a name to the same key is a no-op; a name owned by a
+    /// different key is rejected.
+    pub fn register(&self, username: &str, identity_key: &[u8]) -> anyhow::Result<()> {
+        let _guard = self.write_lock.lock().unwrap_or_else(|p| p.into_inner());
+        let lower = username.to_lowercase();
+
+        if let Some(existing) = self.accounts.get(&lower) {
+            let same_key = decode_hex(&existing.identity_key)
+                .is_some_and(|k| bool::from(k.ct_eq(identity_key)));
+            if same_key {
+                return Ok(());
+            }
+            bail!("username is already registered");
+        }
+
+        let account = Account {
+            username: username.to_string(),
+            identity_key: encode_hex(identity_key),
+            registered_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            group: default_account_group(),
+        };
+        self.accounts.insert(lower.clone(), account);
+
+        if let Some(ref path) = self.path {
+            if let Err(e) = self.atomic_rewrite(path) {
+                self.accounts.remove(&lower);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write all accounts to a temp file then atomically rename over the original.
+    fn atomic_rewrite(&self, path: &Path) -> anyhow::Result<()> {
+        let mut entries: Vec<Account> = self.accounts.iter().map(|a| a.value().clone()).collect();
+        entries.sort_by_key(|a| a.username.to_lowercase());
+        let json = serde_json::to_string_pretty(&entries).context("failed to serialize accounts")?;
+
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, json.as_bytes())
+            .with_context(|| format!("failed to write temp file: {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("failed to rename temp file to {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Verify an XEdDSA signature made with a Signal identity key.
+///
+/// `identity_key` is the serialized public key as sent in `Authenticate`:
+/// either 33 bytes (0x05 type prefix + Curve25519 point) or the bare 32 bytes.
+/// Mirrors libsignal's `verify_signature` so clients can sign with
+/// `PrivateKey::calculate_signature`.
+pub fn verify_identity_signature(identity_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let key: [u8; 32] = match identity_key {
+        [DJB_KEY_TYPE, rest @ ..] if rest.len() == 32 => rest.try_into().unwrap(),
+        k if k.len() == 32 => k.try_into().unwrap(),
+        _ => return false,
+    };
+    let signature: [u8; 64] = match signature.try_into() {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+
+    // The Edwards sign bit is smuggled in the top bit of `s`.
+    let sign_bit = (signature[63] & 0x80) >> 7;
+    let ed_public = match MontgomeryPoint(key).to_edwards(sign_bit) {
+        Some(p) => p,
+        None => return false,
+    };
+    let cap_a = ed_public.compress();
+
+    let mut cap_r = [0u8; 32];
+    cap_r.copy_from_slice(&signature[..32]);
+    let mut s = [0u8; 32];
+    s.copy_from_slice(&signature[32..]);
+    s[31] &= 0x7f;
+    if s[31] & 0xe0 != 0 {
+        return false;
+    }
+
+    let mut hash = Sha512::new();
+    hash.update(cap_r);
+    hash.update(cap_a.as_bytes());
+    hash.update(message);
+    let h = Scalar::from_hash(hash);
+
+    let check = EdwardsPoint::vartime_double_scalar_mul_basepoint(
+        &h,
+        &-ed_public,
+        &Scalar::from_bytes_mod_order(s),
+    );
+    bool::from(check.compress().as_bytes().ct_eq(&cap_r))
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngCore;
+
+    /// Minimal XEdDSA signer (libsignal's `calculate_signature`) for tests.
+    fn keypair() -> ([u8; 32], Vec<u8>) {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        secret[0] &= 248;
+        secret[31] &= 127;
+        secret[31] |= 64;
+        let public = EdwardsPoint::mul_base(&Scalar::from_bytes_mod_order(secret)).to_montgomery();
+        let mut serialized = vec![DJB_KEY_TYPE];
+        serialized.extend_from_slice(public.as_bytes());
+        (secret, serialized)
+    }
+
+    fn sign(secret: &[u8; 32], message: &[u8]) -> Vec<u8> {
+        let a = Scalar::from_bytes_mod_order(*secret);
+        let ed_public = EdwardsPoint::mul_base(&a).compress();
+        let sign_bit = ed_public.as_bytes()[31] & 0x80;
+
+        let mut random = [0u8; 64];
+        rand::thread_rng().fill_bytes(&mut random);
+        let mut hash1 = Sha512::new();
+        hash1.update([0xffu8; 32]);
+        hash1.update(secret);
+        hash1.update(message);
+        hash1.update(random);
+        let r = Scalar::from_hash(hash1);
+        let cap_r = EdwardsPoint::mul_base(&r).compress();
+
+        let mut hash = Sha512::new();
+        hash.update(cap_r.as_bytes());
+        hash.update(ed_public.as_bytes());
+        hash.update(message);
+        let h = Scalar::from_hash(hash);
+        let s = h * a + r;
+
+        let mut sig = Vec::with_capacity(64);
+        sig.extend_from_slice(cap_r.as_bytes());
+        sig.extend_from_slice(s.as_bytes());
+        sig[63] = (sig[63] & 0x7f) | sign_bit;
+        sig
+    }
+
+    #[test]
+    fn verify_valid_signature() {
+        let (secret, public) = keypair();
+        let sig = sign(&secret, b"hello");
+        assert!(verify_identity_signature(&public, b"hello", &sig));
+        // Bare 32-byte key form is accepted too
+        assert!(verify_identity_signature(&public[1..], b"hello", &sig));
+    }
+
+    #[test]
+    fn verify_rejects_wrong_message_or_key() {
+        let (secret, public) = keypair();
+        let (_, other_public) = keypair();
+        let sig = sign(&secret, b"hello");
+        assert!(!verify_identity_signature(&public, b"hellO", &sig));
+        assert!(!verify_identity_signature(&other_public, b"hello", &sig));
+    }
+
+    #[test]
+    fn verify_rejects_malformed_input() {
+        let (secret, public) = keypair();
+        let sig = sign(&secret, b"hello");
+        assert!(!verify_identity_signature(&public[..20], b"hello", &sig));
+        assert!(!verify_identity_signature(&public, b"hello", &sig[..63]));
+        let mut tampered = sig.clone();
+        tampered[0] ^= 1;
+        assert!(!verify_identity_signature(&public, b"hello", &tampered));
+    }
+
+    #[test]
+    fn register_and_lookup_is_case_insensitive() {
+        let store = AccountStore::default();
+        store.register("Alice", &[1, 2, 3]).unwrap();
+        assert_eq!(store.identity_key_for("alice"), Some(vec![1, 2, 3]));
+        assert_eq!(store.identity_key_for("ALICE"), Some(vec![1, 2, 3]));
+        assert_eq!(store.identity_key_for("bob"), None);
+        assert_eq!(store.group_for("alice"), Some(ServerGroup::Member));
+        assert_eq!(store.group_for("bob"), None);
+    }
+
+    #[test]
+    fn register_same_key_is_idempotent() {
+        let store = AccountStore::default();
+        store.register("Alice", &[1, 2, 3]).unwrap();
+        assert!(store.register("alice", &[1, 2, 3]).is_ok());
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn register_taken_name_fails() {
+        let store = AccountStore::default();
+        store.register("Alice", &[1, 2, 3]).unwrap();
+        assert!(store.register("alice", &[9, 9, 9]).is_err());
+        assert_eq!(store.identity_key_for("alice"), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn load_persists_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("voipc-accounts-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("accounts.json");
+
+        let store = AccountStore::load(&path).unwrap();
+        assert_eq!(store.len(), 0);
+        store.register("Alice", &[0xde, 0xad]).unwrap();
+
+        let reloaded = AccountStore::load(&path).unwrap();
+        assert_eq!(reloaded.identity_key_for("alice"), Some(vec![0xde, 0xad]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_rejects_bad_hex() {
+        let dir = std::env::temp_dir().join(format!("voipc-accounts-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("accounts.json");
+        std::fs::write(&path, r#"[{"username":"a","identity_key":"zz"}]"#).unwrap();
+
+        assert!(AccountStore::load(&path).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hex_roundtrip() {
+        assert_eq!(decode_hex(&encode_hex(&[0, 15, 255])), Some(vec![0, 15, 255]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex(""), None);
+    }
+}
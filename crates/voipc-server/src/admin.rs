@@ -0,0 +1,594 @@
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{info, warn};
+
+use voipc_protocol::messages::ServerMessage;
+use voipc_protocol::types::{ChannelId, UserId};
+
+use crate::audit::{outcome, AuditAction, AuditEntry, AuditQuery};
+use crate::bans::Ban;
+use crate::channels::ChannelEntry;
+use crate::shutdown::Shutdown;
+use crate::state::ServerState;
+use crate::tcp;
+use crate::virtual_server::VirtualServers;
+
+/// Longest request line accepted on the admin socket.
+const MAX_REQUEST_LEN: u64 = 64 * 1024;
+
+/// Entries returned by an audit query without a `limit`.
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+
+/// Maximum length of a broadcast notice.
+const MAX_NOTICE_LEN: usize = 1024;
+
+/// Name recorded as `banned_by` for bans issued over the admin socket, and
+/// as the actor of its audit entries.
+const ADMIN_NAME: &str = "server admin";
+
+/// A request on the admin socket. Each request is one JSON object on its own
+/// line, with the command in `cmd`, e.g. `{"cmd":"kick","user_id":3}`. An
+/// optional `server` field names the virtual server it applies to.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum AdminRequest {
+    Sessions,
+    Channels,
+    Stats,
+    Kick {
+        user_id: UserId,
+        #[serde(default)]
+        reason: String,
+    },
+    /// Ban a connected user (`user_id`) or explicit criteria.
+    Ban {
+        #[serde(default)]
+        user_id: Option<UserId>,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        identity_key: Option<String>,
+        #[serde(default)]
+        ip: Option<String>,
+        #[serde(default)]
+        reason: String,
+        #[serde(default)]
+        duration_secs: Option<u64>,
+    },
+    Unban {
+        ban_id: u32,
+    },
+    Bans,
+    Move {
+        user_id: UserId,
+        channel_id: ChannelId,
+    },
+    CreateChannel {
+        name: String,
+        #[serde(default)]
+        description: String,
+        #[serde(default)]
+        password: Option<String>,
+        #[serde(default)]
+        max_users: u32,
+        /// Name of a persistent parent channel.
+        #[serde(default)]
+        parent: Option<String>,
+    },
+    DeleteChannel {
+        channel_id: ChannelId,
+    },
+    Broadcast {
+        message: String,
+    },
+    /// Re-read server_settings.json and channels.json.
+    Reload,
+    /// Newest audit log entries, optionally filtered.
+    Audit {
+        #[serde(default)]
+        action: Option<AuditAction>,
+        #[serde(default)]
+        actor: Option<String>,
+        #[serde(default)]
+        since: Option<u64>,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// Stop accepting connections and shut the process down once clients
+    /// have left. Applies to all virtual servers.
+    Shutdown {
+        #[serde(default)]
+        reason: Option<String>,
+        /// Defaults to `shutdown_timeout_secs`.
+        #[serde(default)]
+        timeout_secs: Option<u64>,
+        /// Defaults to `shutdown_reconnect_to`.
+        #[serde(default)]
+        reconnect_to: Option<String>,
+    },
+}
+
+/// Bind the admin socket, replacing a stale socket file left by a previous
+/// run. The socket is only accessible to the server's own user.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            bail!("{} exists and is not a socket", path.display());
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("failed to bind admin socket {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("failed to restrict admin socket {}", path.display()))?;
+    Ok(listener)
+}
+
+/// Accept admin connections until the listener fails.
+pub async fn run_admin_loop(listener: UnixListener, servers: Arc<VirtualServers>, shutdown: Shutdown) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let servers = servers.clone();
+                let shutdown = shutdown.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, &servers, &shutdown).await {
+                        warn!("admin connection error: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                warn!("admin socket accept error: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Answer newline-delimited JSON requests until the client hangs up.
+async fn handle_connection(stream: UnixStream, servers: &VirtualServers, shutdown: &Shutdown) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        let n = (&mut reader).take(MAX_REQUEST_LEN + 1).read_line(&mut line).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        if n as u64 > MAX_REQUEST_LEN {
+            let response = json!({ "ok": false, "error": "request too long" });
+            writer.write_all(format!("{}\n", response).as_bytes()).await?;
+            return Ok(());
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<AdminRequest>(&line) {
+            Ok(request) => {
+                info!(request = %redact_request(&line), "admin request");
+                let result = match request {
+                    AdminRequest::Shutdown {
+                        reason,
+                        timeout_secs,
+                        reconnect_to,
+                    } => request_shutdown(shutdown, reason, timeout_secs, reconnect_to),
+                    request => match target_server(servers, &line) {
+                        Ok(state) => handle_request(state, request).await,
+                        Err(e) => Err(e),
+                    },
+                };
+                match result {
+                    Ok(result) => json!({ "ok": true, "result": result }),
+                    Err(e) => json!({ "ok": false, "error": e.to_string() }),
+                }
+            }
+            Err(e) => json!({ "ok": false, "error": format!("invalid request: {}", e) }),
+        };
+        writer.write_all(format!("{}\n", response).as_bytes()).await?;
+    }
+}
+
+/// A request line for the log, with any password replaced.
+fn redact_request(line: &str) -> String {
+    let Ok(Value::Object(mut fields)) = serde_json::from_str::<Value>(line) else {
+        return line.trim().to_string();
+    };
+    for (key, value) in fields.iter_mut() {
+        if key.contains("password") && !value.is_null() {
+            *value = json!("<redacted>");
+        }
+    }
+    Value::Object(fields).to_string()
+}
+
+/// The virtual server a request line is for: the one named in its `server`
+/// field, or the default server.
+fn target_server<'a>(servers: &'a VirtualServers, line: &str) -> Result<&'a Arc<ServerState>> {
+    #[derive(Deserialize)]
+    struct Target {
+        #[serde(default)]
+        server: Option<String>,
+    }
+    match serde_json::from_str::<Target>(line).ok().and_then(|t| t.server) {
+        Some(id) => servers
+            .by_id(&id)
+            .with_context(|| format!("no virtual server '{}'", id)),
+        None => Ok(servers.default_state()),
+    }
+}
+
+/// Start draining the whole process.
+fn request_shutdown(
+    shutdown: &Shutdown,
+    reason: Option<String>,
+    timeout_secs: Option<u64>,
+    reconnect_to: Option<String>,
+) -> Result<Value> {
+    let reason = reason.unwrap_or_else(|| "server shutting down".into());
+    let Some(request) = shutdown.request(reason, timeout_secs.map(Duration::from_secs), reconnect_to) else {
+        bail!("shutdown already in progress");
+    };
+    info!(
+        timeout_secs = request.timeout.as_secs(),
+        reconnect_to = request.reconnect_to.as_deref().unwrap_or(""),
+        "shutdown requested over admin socket"
+    );
+    Ok(json!({
+        "timeout_secs": request.timeout.as_secs(),
+        "reconnect_to": request.reconnect_to,
+    }))
+}
+
+/// Execute a single admin request.
+pub async fn handle_request(state: &Arc<ServerState>, request: AdminRequest) -> Result<Value> {
+    match request {
+        AdminRequest::Sessions => Ok(list_sessions(state)),
+        AdminRequest::Channels => Ok(list_channels(state).await),
+        AdminRequest::Stats => Ok(stats(state).await),
+        AdminRequest::Kick { user_id, reason } => {
+            let reason = if reason.trim().is_empty() {
+                "kicked by server admin".to_string()
+            } else {
+                reason.trim().to_string()
+            };
+            let target = state.username(user_id).map(|name| format!("{} ({})", name, user_id));
+            let result = tcp::disconnect_user(state, user_id, &reason).await;
+            state.audit.record(AuditEntry::new(AuditAction::Kick, ADMIN_NAME, target, outcome(&result)));
+            result?;
+            info!(user_id, "user kicked via admin socket");
+            Ok(Value::Null)
+        }
+        AdminRequest::Ban {
+            user_id,
+            username,
+            identity_key,
+            ip,
+            reason,
+            duration_secs,
+        } => {
+            let ban = match user_id {
+                Some(user_id) => {
+                    tcp::ban_for_user(state, user_id, None, &reason, ADMIN_NAME.into(), duration_secs)
+                        .with_context(|| format!("user {} is not connected", user_id))?
+                }
+                None => Ban {
+                    id: 0,
+                    username,
+                    identity_key: identity_key.map(|k| k.to_lowercase()),
+                    ip,
+                    reason: reason.trim().to_string(),
+                    banned_by: ADMIN_NAME.into(),
+                    created_at: 0,
+                    expires_at: duration_secs
+                        .map(|d| crate::bans::now_secs().saturating_add(d)),
+                },
+            };
+            let ban = state.bans.add(ban)?;
+            info!(ban_id = ban.id, "ban added via admin socket");
+            tcp::enforce_ban(state, &ban).await;
+            Ok(serde_json::to_value(ban.to_info())?)
+        }
+        AdminRequest::Unban { ban_id } => match state.bans.remove(ban_id)? {
+            Some(_) => {
+                info!(ban_id, "ban removed via admin socket");
+                Ok(Value::Null)
+            }
+            None => bail!("no ban with id {}", ban_id),
+        },
+        AdminRequest::Bans => {
+            let bans: Vec<_> = state.bans.list().iter().map(Ban::to_info).collect();
+            Ok(serde_json::to_value(bans)?)
+        }
+        AdminRequest::Move { user_id, channel_id } => {
+            tcp::move_user(state, user_id, channel_id).await?;
+            info!(user_id, channel_id, "user moved via admin socket");
+            Ok(Value::Null)
+        }
+        AdminRequest::CreateChannel {
+            name,
+            description,
+            password,
+            max_users,
+            parent,
+        } => {
+            let entry = ChannelEntry {
+                name: name.trim().to_string(),
+                description,
+                password: password.filter(|p| !p.is_empty()),
+                max_users,
+                parent: parent.filter(|p| !p.trim().is_empty()),
+                ..Default::default()
+            };
+            let target = entry.name.clone();
+            let result = state.create_persistent_channel(entry).await;
+            state.audit.record(AuditEntry::new(
+                AuditAction::CreateChannel,
+                ADMIN_NAME,
+                Some(match &result {
+                    Ok(info) => format!("{} ({})", info.name, info.channel_id),
+                    Err(_) => target,
+                }),
+                outcome(&result),
+            ));
+            let info = result?;
+            info!(channel_id = info.channel_id, name = %info.name, "persistent channel created via admin socket");
+            tcp::broadcast_to_all(state, &ServerMessage::ChannelCreated { channel: info.clone() }, None).await;
+            Ok(serde_json::to_value(info)?)
+        }
+        AdminRequest::DeleteChannel { channel_id } => {
+            tcp::delete_channel(state, channel_id, ADMIN_NAME).await?;
+            info!(channel_id, "channel deleted via admin socket");
+            Ok(Value::Null)
+        }
+        AdminRequest::Audit {
+            action,
+            actor,
+            since,
+            limit,
+        } => {
+            let query = AuditQuery {
+                action,
+                actor,
+                since,
+                limit: Some(limit.unwrap_or(DEFAULT_AUDIT_LIMIT)),
+            };
+            Ok(serde_json::to_value(state.audit.query(&query)?)?)
+        }
+        AdminRequest::Reload => Ok(serde_json::to_value(crate::reload::reload(state).await?)?),
+        AdminRequest::Shutdown { .. } => bail!("shutdown applies to the whole process, not one server"),
+        AdminRequest::Broadcast { message } => {
+            let message = message.trim();
+            if message.is_empty() {
+                bail!("message is empty");
+            }
+            if message.chars().count() > MAX_NOTICE_LEN {
+                bail!("message exceeds {} characters", MAX_NOTICE_LEN);
+            }
+            tcp::broadcast_to_all(state, &ServerMessage::ServerNotice { message: message.into() }, None).await;
+            Ok(Value::Null)
+        }
+    }
+}
+
+fn list_sessions(state: &ServerState) -> Value {
+    let mut sessions: Vec<Value> = state
+        .sessions
+        .iter()
+        .map(|s| {
+            json!({
+                "user_id": s.user_id,
+                "session_id": s.session_id,
+                "username": s.username,
+                "channel_id": s.channel_id,
+                "group": s.group,
+                "ip": s.tcp_peer_ip.to_canonical().to_string(),
+                "identity_verified": s.identity_verified,
+                "muted": s.is_muted,
+                "deafened": s.is_deafened,
+                "screen_sharing": s.is_screen_sharing,
+            })
+        })
+        .collect();
+    sessions.sort_by_key(|s| s["user_id"].as_u64());
+    Value::Array(sessions)
+}
+
+async fn list_channels(state: &ServerState) -> Value {
+    let channels = state.channels.read().await;
+    let mut list: Vec<Value> = channels
+        .values()
+        .map(|ch| {
+            json!({
+                "channel_id": ch.info.channel_id,
+                "name": ch.info.name,
+                "parent_id": ch.info.parent_id,
+                "description": ch.info.description,
+                "user_count": ch.members.len(),
+                "max_users": ch.info.max_users,
+                "has_password": ch.info.has_password,
+                "persistent": ch.persistent,
+            })
+        })
+        .collect();
+    list.sort_by_key(|ch| ch["channel_id"].as_u64());
+    Value::Array(list)
+}
+
+async fn stats(state: &ServerState) -> Value {
+    let channels = state.channels.read().await.len();
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": state.started_at.elapsed().as_secs(),
+        "users": state.user_count(),
+        "max_users": state.max_users,
+        "channels": channels,
+        "accounts": state.accounts.len(),
+        "bans": state.bans.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::accounts::AccountStore;
+    use crate::bans::BanStore;
+    use crate::config::ServerConfig;
+    use crate::settings::ServerSettings;
+
+    fn make_state() -> Arc<ServerState> {
+        Arc::new(ServerState::new(
+            &ServerConfig::default(),
+            ServerSettings::default(),
+            Vec::new(),
+            AccountStore::default(),
+            BanStore::default(),
+            None,
+            None,
+        ))
+    }
+
+    #[test]
+    fn parses_requests() {
+        let req: AdminRequest = serde_json::from_str(r#"{"cmd":"kick","user_id":3}"#).unwrap();
+        assert!(matches!(req, AdminRequest::Kick { user_id: 3, ref reason } if reason.is_empty()));
+
+        let req: AdminRequest =
+            serde_json::from_str(r#"{"cmd":"create_channel","name":"Music","max_users":5}"#).unwrap();
+        assert!(matches!(req, AdminRequest::CreateChannel { max_users: 5, password: None, .. }));
+
+        assert!(serde_json::from_str::<AdminRequest>(r#"{"cmd":"reboot"}"#).is_err());
+        assert!(serde_json::from_str::<AdminRequest>(r#"{"cmd":"move","user_id":1}"#).is_err());
+    }
+
+    #[test]
+    fn passwords_are_redacted_from_logged_requests() {
+        let logged = redact_request(r#"{"cmd":"create_channel","name":"Staff","password":"hunter2"}"#);
+        assert!(!logged.contains("hunter2"));
+        assert!(logged.contains("create_channel") && logged.contains("Staff"));
+        assert_eq!(redact_request(r#"{"cmd":"stats"}"#), r#"{"cmd":"stats"}"#);
+    }
+
+    #[test]
+    fn shutdown_is_requested_once() {
+        let shutdown = Shutdown::new(&ServerConfig::default());
+        let req: AdminRequest = serde_json::from_str(r#"{"cmd":"shutdown","timeout_secs":5}"#).unwrap();
+        let AdminRequest::Shutdown {
+            reason,
+            timeout_secs,
+            reconnect_to,
+        } = req
+        else {
+            panic!("expected shutdown request");
+        };
+        let result = request_shutdown(&shutdown, reason, timeout_secs, reconnect_to).unwrap();
+        assert_eq!(result["timeout_secs"], 5);
+        assert!(result["reconnect_to"].is_null());
+        assert!(request_shutdown(&shutdown, None, None, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn create_and_delete_channel() {
+        let state = make_state();
+        let created = handle_request(
+            &state,
+            AdminRequest::CreateChannel {
+                name: "Music".into(),
+                description: String::new(),
+                password: Some("secret".into()),
+                max_users: 0,
+                parent: None,
+            },
+        )
+        .await
+        .unwrap();
+        let channel_id = created["channel_id"].as_u64().unwrap() as ChannelId;
+        assert_eq!(created["has_password"], true);
+
+        let channels = handle_request(&state, AdminRequest::Channels).await.unwrap();
+        assert_eq!(channels.as_array().unwrap().len(), 2);
+        assert_eq!(channels[1]["persistent"], true);
+
+        handle_request(&state, AdminRequest::DeleteChannel { channel_id })
+            .await
+            .unwrap();
+        assert!(handle_request(&state, AdminRequest::DeleteChannel { channel_id: 0 })
+            .await
+            .is_err());
+        let stats = handle_request(&state, AdminRequest::Stats).await.unwrap();
+        assert_eq!(stats["channels"], 1);
+    }
+
+    #[tokio::test]
+    async fn audit_records_admin_actions() {
+        let dir = std::env::temp_dir().join(format!("voipc-admin-audit-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = ServerConfig {
+            audit_log: Some(dir.join("audit.jsonl").to_string_lossy().into_owned()),
+            ..ServerConfig::default()
+        };
+        let state = Arc::new(ServerState::new(
+            &config,
+            ServerSettings::default(),
+            Vec::new(),
+            AccountStore::default(),
+            BanStore::default(),
+            None,
+            None,
+        ));
+
+        let request = r#"{"cmd":"create_channel","name":"Music"}"#;
+        handle_request(&state, serde_json::from_str(request).unwrap()).await.unwrap();
+        let channel_id = state.channel_list().await[1].channel_id;
+        handle_request(&state, AdminRequest::DeleteChannel { channel_id }).await.unwrap();
+        assert!(handle_request(&state, AdminRequest::DeleteChannel { channel_id }).await.is_err());
+
+        let request = r#"{"cmd":"audit","action":"delete_channel"}"#;
+        let entries = handle_request(&state, serde_json::from_str(request).unwrap()).await.unwrap();
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["actor"], ADMIN_NAME);
+        assert_eq!(entries[0]["target"], format!("Music ({})", channel_id));
+        assert_eq!(entries[0]["outcome"], "success");
+        assert_eq!(entries[1]["outcome"], "failure");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ban_by_criteria_and_unban() {
+        let state = make_state();
+        let ban = handle_request(
+            &state,
+            AdminRequest::Ban {
+                user_id: None,
+                username: None,
+                identity_key: None,
+                ip: Some("203.0.113.0/24".into()),
+                reason: "spam".into(),
+                duration_secs: None,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(ban["banned_by"], ADMIN_NAME);
+        assert!(handle_request(&state, AdminRequest::Kick { user_id: 9, reason: String::new() })
+            .await
+            .is_err());
+
+        let ban_id = ban["ban_id"].as_u64().unwrap() as u32;
+        handle_request(&state, AdminRequest::Unban { ban_id }).await.unwrap();
+        assert!(handle_request(&state, AdminRequest::Unban { ban_id }).await.is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::bans::now_secs;
+
+/// Number of rotated files kept next to the live log (`audit.jsonl.1` ...).
+const ROTATED_FILES: usize = 3;
+
+/// An administrative action recorded in the audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Kick,
+    SetChannelPassword,
+    CreateChannel,
+    DeleteChannel,
+    SendInvite,
+    AuthSuccess,
+    AuthFailure,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Seconds since UNIX epoch.
+    pub timestamp: u64,
+    pub action: AuditAction,
+    /// Username of the acting user, or "server admin" / "server".
+    pub actor: String,
+    /// The user or channel acted on, e.g. `"bob (3)"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub outcome: Outcome,
+    /// Why the action failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Remote address, for authentication entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(
+        action: AuditAction,
+        actor: impl Into<String>,
+        target: Option<String>,
+        result: Result<(), String>,
+    ) -> Self {
+        let (outcome, reason) = match result {
+            Ok(()) => (Outcome::Success, None),
+            Err(reason) => (Outcome::Failure, Some(reason)),
+        };
+        Self {
+            timestamp: now_secs(),
+            action,
+            actor: actor.into(),
+            target,
+            outcome,
+            reason,
+            peer: None,
+        }
+    }
+}
+
+/// The outcome of an action as recorded by [`AuditEntry::new`].
+pub fn outcome<T>(result: &anyhow::Result<T>) -> Result<(), String> {
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Which entries an audit query returns.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub action: Option<AuditAction>,
+    /// Actor name, matched case-insensitively.
+    #[serde(default)]
+    pub actor: Option<String>,
+    /// Only entries at or after this time (seconds since UNIX epoch).
+    #[serde(default)]
+    pub since: Option<u64>,
+    /// Return at most this many of the newest matches.
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.action.is_none_or(|a| a == entry.action)
+            && self.actor.as_ref().is_none_or(|a| a.eq_ignore_ascii_case(&entry.actor))
+            && self.since.is_none_or(|t| entry.timestamp >= t)
+    }
+}
+
+/// Append-only JSON-lines log of administrative actions.
+///
+/// When a write would take the file past `max_bytes` it is renamed to
+/// `<path>.1` (shifting older files up, the oldest is dropped) and a new file
+/// is started. Write failures are logged and never fail the action itself.
+/// A log without a path (`AuditLog::default()`) records nothing.
+#[derive(Default)]
+pub struct AuditLog {
+    path: Option<PathBuf>,
+    max_bytes: u64,
+    /// Serializes appends and rotation.
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf, max_bytes: u64) -> Self {
+        Self {
+            path: Some(path),
+            max_bytes,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Append an entry to the log.
+    pub fn record(&self, entry: AuditEntry) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mut line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("failed to serialize audit entry: {}", e);
+                return;
+            }
+        };
+        line.push('\n');
+
+        let _guard = self.lock.lock().unwrap_or_else(|p| p.into_inner());
+        if let Err(e) = self.append(path, &line) {
+            warn!("failed to write audit log {}: {}", path.display(), e);
+        }
+    }
+
+    fn append(&self, path: &Path, line: &str) -> std::io::Result<()> {
+        let len = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        if len > 0 && len + line.len() as u64 > self.max_bytes {
+            rotate(path)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(line.as_bytes())
+    }
+
+    /// Matching entries from the live log and its rotated files, oldest first.
+    /// Lines that fail to parse are skipped.
+    pub fn query(&self, query: &AuditQuery) -> anyhow::Result<Vec<AuditEntry>> {
+        let Some(path) = &self.path else {
+            anyhow::bail!("audit log is disabled");
+        };
+
+        let _guard = self.lock.lock().unwrap_or_else(|p| p.into_inner());
+        let mut entries = Vec::new();
+        let files = (1..=ROTATED_FILES).rev().map(|n| rotated_path(path, n));
+        for file in files.chain(std::iter::once(path.clone())) {
+            let Ok(file) = fs::File::open(&file) else {
+                continue;
+            };
+            for line in BufReader::new(file).lines() {
+                if let Ok(entry) = serde_json::from_str::<AuditEntry>(&line?) {
+                    if query.matches(&entry) {
+                        entries.push(entry);
+                    }
+                }
+            }
+        }
+
+        if let Some(limit) = query.limit {
+            let skip = entries.len().saturating_sub(limit);
+            entries.drain(..skip);
+        }
+        Ok(entries)
+    }
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Shift `<path>.N` to `<path>.N+1` and the live log to `<path>.1`.
+fn rotate(path: &Path) -> std::io::Result<()> {
+    let oldest = rotated_path(path, ROTATED_FILES);
+    if oldest.exists() {
+        fs::remove_file(&oldest)?;
+    }
+    for n in (1..ROTATED_FILES).rev() {
+        let from = rotated_path(path, n);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, n + 1))?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log(max_bytes: u64) -> (PathBuf, AuditLog) {
+        let dir = std::env::temp_dir().join(format!("voipc-audit-{}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = AuditLog::new(dir.join("audit.jsonl"), max_bytes);
+        (dir, log)
+    }
+
+    #[test]
+    fn records_and_filters_entries() {
+        let (dir, log) = temp_log(1024 * 1024);
+        log.record(AuditEntry::new(AuditAction::Kick, "alice", Some("bob (2)".into()), Ok(())));
+        log.record(AuditEntry::new(
+            AuditAction::DeleteChannel,
+            "bob",
+            Some("Music (4)".into()),
+            Err("permission denied".into()),
+        ));
+        log.record(AuditEntry::new(AuditAction::Kick, "Alice", Some("carol (3)".into()), Ok(())));
+
+        let all = log.query(&AuditQuery::default()).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[1].outcome, Outcome::Failure);
+        assert_eq!(all[1].reason.as_deref(), Some("permission denied"));
+
+        let query = AuditQuery {
+            action: Some(AuditAction::Kick),
+            actor: Some("ALICE".into()),
+            limit: Some(1),
+            ..Default::default()
+        };
+        let kicks = log.query(&query).unwrap();
+        assert_eq!(kicks.len(), 1);
+        assert_eq!(kicks[0].target.as_deref(), Some("carol (3)"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_by_size_and_reads_rotated_files() {
+        let (dir, log) = temp_log(200);
+        for i in 0..20 {
+            log.record(AuditEntry::new(AuditAction::AuthSuccess, format!("user{}", i), None, Ok(())));
+        }
+
+        let path = dir.join("audit.jsonl");
+        assert!(fs::metadata(&path).unwrap().len() <= 200);
+        assert!(rotated_path(&path, ROTATED_FILES).exists());
+        assert!(!rotated_path(&path, ROTATED_FILES + 1).exists());
+
+        // Oldest entries were dropped with the oldest file; order is kept
+        let entries = log.query(&AuditQuery::default()).unwrap();
+        assert!(entries.len() < 20);
+        assert_eq!(entries.last().unwrap().actor, "user19");
+        let numbers: Vec<u32> = entries.iter().map(|e| e.actor[4..].parse().unwrap()).collect();
+        assert!(numbers.windows(2).all(|w| w[0] + 1 == w[1]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
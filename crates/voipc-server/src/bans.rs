@@ -0,0 +1,416 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tracing::info;
+
+use voipc_protocol::types::BanInfo;
+
+use crate::accounts::decode_hex;
+
+/// A ban entry as stored in bans.json.
+///
+/// An entry keeps a connection out if *any* of its criteria match, so a ban
+/// on a connected user survives a change of name, key or address.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    /// Assigned on load when omitted from a hand-written entry.
+    #[serde(default)]
+    pub id: u32,
+    /// Username, matched case-insensitively.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Identity public key, lowercase hex (as sent in `Authenticate`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_key: Option<String>,
+    /// Single IP address or CIDR range, e.g. `"203.0.113.7"` or `"2001:db8::/32"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub reason: String,
+    #[serde(default)]
+    pub banned_by: String,
+    /// Creation time (seconds since UNIX epoch).
+    #[serde(default)]
+    pub created_at: u64,
+    /// Expiry time (seconds since UNIX epoch). None = permanent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+impl Ban {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|t| t <= now)
+    }
+
+    /// Whether this ban applies to a connection with the given credentials.
+    /// Expiry is not checked here.
+    pub fn matches(&self, username: &str, identity_key: Option<&[u8]>, ip: IpAddr) -> bool {
+        if let Some(ref name) = self.username {
+            if name.to_lowercase() == username.to_lowercase() {
+                return true;
+            }
+        }
+        if let (Some(ref banned), Some(key)) = (&self.identity_key, identity_key) {
+            if decode_hex(banned).is_some_and(|b| bool::from(b.ct_eq(key))) {
+                return true;
+            }
+        }
+        if let Some(range) = self.ip.as_deref().and_then(parse_ip_range) {
+            if ip_in_range(ip, range) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The reason shown to a banned user, including the remaining time.
+    pub fn message(&self, now: u64) -> String {
+        let mut msg = if self.reason.is_empty() {
+            "you are banned from this server".to_string()
+        } else {
+            format!("you are banned from this server: {}", self.reason)
+        };
+        if let Some(expires_at) = self.expires_at {
+            msg.push_str(&format!(
+                " (expires in {})",
+                format_duration(expires_at.saturating_sub(now))
+            ));
+        }
+        msg
+    }
+
+    pub fn to_info(&self) -> BanInfo {
+        BanInfo {
+            ban_id: self.id,
+            username: self.username.clone(),
+            identity_key: self.identity_key.clone(),
+            ip: self.ip.clone(),
+            reason: self.reason.clone(),
+            banned_by: self.banned_by.clone(),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+        }
+    }
+}
+
+/// Server-wide ban list.
+///
+/// Backed by a JSON file that is rewritten atomically whenever a ban is added
+/// or removed; expired entries are dropped on each rewrite. A store without a
+/// path (`BanStore::default()`) is memory-only.
+#[derive(Default)]
+pub struct BanStore {
+    bans: Mutex<Vec<Ban>>,
+    path: Option<PathBuf>,
+}
+
+impl BanStore {
+    /// Load bans from a JSON file. A missing file yields an empty store that
+    /// will be created on the first ban.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let store = Self {
+            path: Some(path.to_path_buf()),
+            ..Self::default()
+        };
+        if !path.exists() {
+            return Ok(store);
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read bans file: {}", path.display()))?;
+        let mut entries: Vec<Ban> = serde_json::from_str(&content)
+            .with_context(|| format!("invalid JSON in bans file: {}", path.display()))?;
+
+        let mut seen_ids = HashSet::new();
+        for ban in entries.iter().filter(|b| b.id != 0) {
+            validate_ban(ban)?;
+            if !seen_ids.insert(ban.id) {
+                bail!("duplicate ban id: {}", ban.id);
+            }
+        }
+        let first_free = seen_ids.iter().max().copied().unwrap_or(0) + 1;
+        for (ban, id) in entries.iter_mut().filter(|b| b.id == 0).zip(first_free..) {
+            ban.id = id;
+            validate_ban(ban)?;
+        }
+
+        info!(bans = entries.len(), "loaded bans from {}", path.display());
+        *store.bans.lock().unwrap_or_else(|p| p.into_inner()) = entries;
+        Ok(store)
+    }
+
+    /// Number of active (unexpired) bans.
+    pub fn len(&self) -> usize {
+        let now = now_secs();
+        self.lock().iter().filter(|b| !b.is_expired(now)).count()
+    }
+
+    /// Find an active ban matching the given credentials.
+    pub fn find(&self, username: &str, identity_key: Option<&[u8]>, ip: IpAddr) -> Option<Ban> {
+        let now = now_secs();
+        self.lock()
+            .iter()
+            .find(|b| !b.is_expired(now) && b.matches(username, identity_key, ip))
+            .cloned()
+    }
+
+    /// All active bans, ordered by ID.
+    pub fn list(&self) -> Vec<Ban> {
+        let now = now_secs();
+        let mut bans: Vec<Ban> = self
+            .lock()
+            .iter()
+            .filter(|b| !b.is_expired(now))
+            .cloned()
+            .collect();
+        bans.sort_by_key(|b| b.id);
+        bans
+    }
+
+    /// Add a ban and persist the store. `id` and `created_at` are assigned
+    /// here; `expires_at` is taken as given.
+    pub fn add(&self, mut ban: Ban) -> anyhow::Result<Ban> {
+        validate_ban(&ban)?;
+        let mut bans = self.lock();
+        ban.id = bans.iter().map(|b| b.id).max().unwrap_or(0) + 1;
+        ban.created_at = now_secs();
+
+        let mut updated = bans.clone();
+        updated.push(ban.clone());
+        self.persist(&mut updated)?;
+        *bans = updated;
+        Ok(ban)
+    }
+
+    /// Remove a ban by ID and persist the store. Returns the removed ban, or
+    /// None if no ban had that ID.
+    pub fn remove(&self, id: u32) -> anyhow::Result<Option<Ban>> {
+        let mut bans = self.lock();
+        let Some(pos) = bans.iter().position(|b| b.id == id) else {
+            return Ok(None);
+        };
+
+        let mut updated = bans.clone();
+        let removed = updated.remove(pos);
+        self.persist(&mut updated)?;
+        *bans = updated;
+        Ok(Some(removed))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Ban>> {
+        self.bans.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Drop expired entries and, if file-backed, atomically rewrite the file.
+    fn persist(&self, bans: &mut Vec<Ban>) -> anyhow::Result<()> {
+        let now = now_secs();
+        bans.retain(|b| !b.is_expired(now));
+        bans.sort_by_key(|b| b.id);
+
+        let Some(ref path) = self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_string_pretty(bans).context("failed to serialize bans")?;
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, json.as_bytes())
+            .with_context(|| format!("failed to write temp file: {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("failed to rename temp file to {}", path.display()))?;
+        Ok(())
+    }
+}
+
+fn validate_ban(ban: &Ban) -> anyhow::Result<()> {
+    if ban.username.is_none() && ban.identity_key.is_none() && ban.ip.is_none() {
+        bail!("ban {} has no username, identity_key or ip", ban.id);
+    }
+    if let Some(ref key) = ban.identity_key {
+        if decode_hex(key).is_none() {
+            bail!("ban {} has an invalid identity_key", ban.id);
+        }
+    }
+    if let Some(ref ip) = ban.ip {
+        if parse_ip_range(ip).is_none() {
+            bail!("ban {} has an invalid ip '{}'", ban.id, ip);
+        }
+    }
+    Ok(())
+}
+
+/// Parse `"addr"` or `"addr/prefix"` into a network address and prefix length.
+pub fn parse_ip_range(s: &str) -> Option<(IpAddr, u32)> {
+    let (addr, prefix) = match s.trim().split_once('/') {
+        Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u32>().ok()?)),
+        None => (s.trim().parse::<IpAddr>().ok()?, None),
+    };
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(max);
+    (prefix <= max).then_some((addr, prefix))
+}
+
+pub fn ip_in_range(ip: IpAddr, (net, prefix): (IpAddr, u32)) -> bool {
+    // Dual-stack listeners report IPv4 peers as ::ffff:a.b.c.d
+    match (ip.to_canonical(), net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs.div_ceil(60)),
+        3600..=86_399 => format!("{}h {}m", secs / 3600, (secs % 3600) / 60),
+        _ => format!("{}d {}h", secs / 86_400, (secs % 86_400) / 3600),
+    }
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn ban(username: Option<&str>, key: Option<&str>, ip: Option<&str>) -> Ban {
+        Ban {
+            id: 0,
+            username: username.map(Into::into),
+            identity_key: key.map(Into::into),
+            ip: ip.map(Into::into),
+            reason: String::new(),
+            banned_by: "admin".into(),
+            created_at: 0,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn cidr_matching() {
+        let range = parse_ip_range("10.1.0.0/16").unwrap();
+        assert!(ip_in_range(ip("10.1.200.3"), range));
+        assert!(!ip_in_range(ip("10.2.0.1"), range));
+        // IPv4-mapped IPv6 peers match IPv4 ranges
+        assert!(ip_in_range(ip("::ffff:10.1.0.9"), range));
+
+        let single = parse_ip_range("192.0.2.1").unwrap();
+        assert!(ip_in_range(ip("192.0.2.1"), single));
+        assert!(!ip_in_range(ip("192.0.2.2"), single));
+
+        let v6 = parse_ip_range("2001:db8::/32").unwrap();
+        assert!(ip_in_range(ip("2001:db8:1::5"), v6));
+        assert!(!ip_in_range(ip("2001:db9::5"), v6));
+
+        assert!(ip_in_range(ip("8.8.8.8"), parse_ip_range("0.0.0.0/0").unwrap()));
+    }
+
+    #[test]
+    fn invalid_ip_ranges_rejected() {
+        assert!(parse_ip_range("10.0.0.0/33").is_none());
+        assert!(parse_ip_range("not-an-ip").is_none());
+        assert!(parse_ip_range("10.0.0.0/").is_none());
+        assert!(parse_ip_range("::/129").is_none());
+    }
+
+    #[test]
+    fn ban_matches_any_criterion() {
+        let b = ban(Some("Mallory"), Some("05aabb"), Some("203.0.113.0/24"));
+        let elsewhere = ip("198.51.100.1");
+        assert!(b.matches("mallory", None, elsewhere));
+        assert!(b.matches("other", Some(&[0x05, 0xaa, 0xbb]), elsewhere));
+        assert!(b.matches("other", None, ip("203.0.113.77")));
+        assert!(!b.matches("other", Some(&[0x05, 0xaa, 0xbc]), elsewhere));
+    }
+
+    #[test]
+    fn store_add_find_remove() {
+        let store = BanStore::default();
+        let added = store.add(ban(Some("mallory"), None, None)).unwrap();
+        assert_eq!(added.id, 1);
+        assert!(added.created_at > 0);
+        assert_eq!(store.add(ban(None, None, Some("10.0.0.1"))).unwrap().id, 2);
+        assert_eq!(store.len(), 2);
+
+        assert!(store.find("MALLORY", None, ip("127.0.0.1")).is_some());
+        assert_eq!(store.find("bob", None, ip("10.0.0.1")).unwrap().id, 2);
+        assert!(store.find("bob", None, ip("127.0.0.1")).is_none());
+
+        assert!(store.remove(1).unwrap().is_some());
+        assert!(store.remove(1).unwrap().is_none());
+        assert!(store.find("mallory", None, ip("127.0.0.1")).is_none());
+    }
+
+    #[test]
+    fn expired_bans_ignored() {
+        let store = BanStore::default();
+        let mut b = ban(Some("mallory"), None, None);
+        b.expires_at = Some(1);
+        store.add(b).unwrap();
+        assert!(store.find("mallory", None, ip("127.0.0.1")).is_none());
+        assert_eq!(store.len(), 0);
+        assert!(store.list().is_empty());
+    }
+
+    #[test]
+    fn ban_without_criteria_rejected() {
+        let store = BanStore::default();
+        assert!(store.add(ban(None, None, None)).is_err());
+        assert!(store.add(ban(None, None, Some("10.0.0.0/99"))).is_err());
+        assert!(store.add(ban(None, Some("xyz"), None)).is_err());
+    }
+
+    #[test]
+    fn ban_message_includes_reason_and_expiry() {
+        let mut b = ban(Some("mallory"), None, None);
+        b.reason = "spam".into();
+        assert_eq!(b.message(0), "you are banned from this server: spam");
+        b.expires_at = Some(1000 + 2 * 3600 + 5 * 60);
+        assert_eq!(
+            b.message(1000),
+            "you are banned from this server: spam (expires in 2h 5m)"
+        );
+    }
+
+    #[test]
+    fn store_persists_to_file() {
+        let dir = std::env::temp_dir().join(format!("voipc-bans-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bans.json");
+        let _ = std::fs::remove_file(&path);
+
+        let store = BanStore::load(&path).unwrap();
+        store.add(ban(Some("mallory"), None, Some("10.0.0.0/8"))).unwrap();
+
+        let reloaded = BanStore::load(&path).unwrap();
+        assert_eq!(reloaded.len(), 1);
+        assert!(reloaded.find("eve", None, ip("10.9.9.9")).is_some());
+
+        // Hand-written entries without an id get one on load
+        std::fs::write(&path, r#"[{"id": 4, "username": "a"}, {"ip": "192.0.2.1"}]"#).unwrap();
+        let ids: Vec<u32> = BanStore::load(&path).unwrap().list().iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![4, 5]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+//! Command-line client for the VoIPC server's local admin socket.
+
+#[cfg(unix)]
+fn main() -> anyhow::Result<()> {
+    unix::run()
+}
+
+#[cfg(not(unix))]
+fn main() -> anyhow::Result<()> {
+    anyhow::bail!("voipc-admin requires a Unix domain socket and is only supported on Unix")
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    use anyhow::{bail, Context, Result};
+    use clap::{Parser, Subcommand};
+    use serde_json::{json, Value};
+
+    #[derive(Parser)]
+    #[command(name = "voipc-admin", about = "Administer a running VoIPC server")]
+    struct Args {
+        /// Path of the server's admin socket
+        #[arg(short, long, default_value = "voipc-admin.sock")]
+        socket: String,
+
+        /// Print the raw JSON result instead of a table
+        #[arg(long)]
+        json: bool,
+
+        /// Virtual server to administer (default: the top-level server)
+        #[arg(long)]
+        server: Option<String>,
+
+        #[command(subcommand)]
+        command: Command,
+    }
+
+    #[derive(Subcommand)]
+    enum Command {
+        /// List connected users
+        Sessions,
+        /// List channels
+        Channels,
+        /// Show server statistics
+        Stats,
+        /// Disconnect a user
+        Kick {
+            user_id: u32,
+            #[arg(short, long, default_value = "")]
+            reason: String,
+        },
+        /// Ban a connected user, or a username / identity key / IP range
+        Ban {
+            /// Connected user to ban (by name, identity key and IP)
+            user_id: Option<u32>,
+            #[arg(long, conflicts_with = "user_id")]
+            username: Option<String>,
+            #[arg(long, conflicts_with = "user_id")]
+            identity_key: Option<String>,
+            /// IP address or CIDR range
+            #[arg(long, conflicts_with = "user_id")]
+            ip: Option<String>,
+            #[arg(short, long, default_value = "")]
+            reason: String,
+            /// Ban duration in seconds (permanent if omitted)
+            #[arg(short, long)]
+            duration: Option<u64>,
+        },
+        /// Remove a ban
+        Unban { ban_id: u32 },
+        /// List active bans
+        Bans,
+        /// Move a user into another channel
+        Move { user_id: u32, channel_id: u32 },
+        /// Create a persistent channel
+        CreateChannel {
+            name: String,
+            #[arg(long, default_value = "")]
+            description: String,
+            #[arg(long)]
+            password: Option<String>,
+            /// Maximum users (0 = unlimited)
+            #[arg(long, default_value_t = 0)]
+            max_users: u32,
+            /// Name of a persistent channel to nest this one under
+            #[arg(long)]
+            parent: Option<String>,
+        },
+        /// Delete a channel, moving its users to General
+        DeleteChannel { channel_id: u32 },
+        /// Send a notice to every connected user
+        Broadcast {
+            #[arg(required = true)]
+            message: Vec<String>,
+        },
+        /// Re-read server settings and persistent channels
+        Reload,
+        /// Show the newest audit log entries
+        Audit {
+            /// Only this action, e.g. kick or delete_channel
+            #[arg(long)]
+            action: Option<String>,
+            /// Only actions by this user
+            #[arg(long)]
+            actor: Option<String>,
+            /// Only entries at or after this UNIX time
+            #[arg(long)]
+            since: Option<u64>,
+            /// Number of entries to show
+            #[arg(short, long, default_value_t = 100)]
+            limit: usize,
+        },
+        /// Stop accepting connections and shut the server down once clients
+        /// have left (applies to all virtual servers)
+        Shutdown {
+            /// Seconds to wait for clients (default: shutdown_timeout_secs)
+            #[arg(short, long)]
+            timeout: Option<u64>,
+            /// Server clients should move to, as host:port
+            #[arg(long)]
+            reconnect_to: Option<String>,
+            #[arg(short, long)]
+            reason: Option<String>,
+        },
+    }
+
+    pub fn run() -> Result<()> {
+        let args = Args::parse();
+
+        let mut request = match &args.command {
+            Command::Sessions => json!({ "cmd": "sessions" }),
+            Command::Channels => json!({ "cmd": "channels" }),
+            Command::Stats => json!({ "cmd": "stats" }),
+            Command::Kick { user_id, reason } => {
+                json!({ "cmd": "kick", "user_id": user_id, "reason": reason })
+            }
+            Command::Ban {
+                user_id,
+                username,
+                identity_key,
+                ip,
+                reason,
+                duration,
+            } => json!({
+                "cmd": "ban",
+                "user_id": user_id,
+                "username": username,
+                "identity_key": identity_key,
+                "ip": ip,
+                "reason": reason,
+                "duration_secs": duration,
+            }),
+            Command::Unban { ban_id } => json!({ "cmd": "unban", "ban_id": ban_id }),
+            Command::Bans => json!({ "cmd": "bans" }),
+            Command::Move { user_id, channel_id } => {
+                json!({ "cmd": "move", "user_id": user_id, "channel_id": channel_id })
+            }
+            Command::CreateChannel {
+                name,
+                description,
+                password,
+                max_users,
+                parent,
+            } => json!({
+                "cmd": "create_channel",
+                "name": name,
+                "description": description,
+                "password": password,
+                "max_users": max_users,
+                "parent": parent,
+            }),
+            Command::DeleteChannel { channel_id } => {
+                json!({ "cmd": "delete_channel", "channel_id": channel_id })
+            }
+            Command::Broadcast { message } => {
+                json!({ "cmd": "broadcast", "message": message.join(" ") })
+            }
+            Command::Reload => json!({ "cmd": "reload" }),
+            Command::Audit {
+                action,
+                actor,
+                since,
+                limit,
+            } => json!({
+                "cmd": "audit",
+                "action": action,
+                "actor": actor,
+                "since": since,
+                "limit": limit,
+            }),
+            Command::Shutdown {
+                timeout,
+                reconnect_to,
+                reason,
+            } => json!({
+                "cmd": "shutdown",
+                "timeout_secs": timeout,
+                "reconnect_to": reconnect_to,
+                "reason": reason,
+            }),
+        };
+
+        if let Some(server) = &args.server {
+            request["server"] = json!(server);
+        }
+
+        let result = send_request(&args.socket, &request)?;
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&result)?);
+            return Ok(());
+        }
+
+        match args.command {
+            Command::Sessions => print_table(
+                &result,
+                &["user_id", "username", "channel_id", "group", "ip", "identity_verified"],
+            ),
+            Command::Channels => print_table(
+                &result,
+                &["channel_id", "name", "parent_id", "user_count", "max_users", "has_password", "persistent"],
+            ),
+            Command::Bans => print_table(
+                &result,
+                &["ban_id", "username", "ip", "reason", "banned_by", "expires_at"],
+            ),
+            Command::Audit { .. } => print_table(
+                &result,
+                &["timestamp", "action", "actor", "target", "outcome", "reason", "peer"],
+            ),
+            Command::Stats => {
+                if let Some(stats) = result.as_object() {
+                    for (key, value) in stats {
+                        println!("{:<12} {}", key, cell(value));
+                    }
+                }
+            }
+            _ if result.is_null() => println!("ok"),
+            _ => println!("{}", serde_json::to_string_pretty(&result)?),
+        }
+        Ok(())
+    }
+
+    /// Send one request and return its result, or the server's error.
+    fn send_request(socket: &str, request: &Value) -> Result<Value> {
+        let mut stream = UnixStream::connect(socket)
+            .with_context(|| format!("failed to connect to admin socket {}", socket))?;
+        stream.write_all(format!("{}\n", request).as_bytes())?;
+
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let response: Value = serde_json::from_str(&line).context("invalid response from server")?;
+        if response["ok"] == true {
+            Ok(response["result"].clone())
+        } else {
+            bail!("{}", response["error"].as_str().unwrap_or("unknown error"))
+        }
+    }
+
+    fn cell(value: &Value) -> String {
+        match value {
+            Value::Null => "-".into(),
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        }
+    }
+
+    fn print_table(rows: &Value, columns: &[&str]) {
+        let rows = rows.as_array().map(Vec::as_slice).unwrap_or_default();
+        let cells: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| columns.iter().map(|c| cell(&row[*c])).collect())
+            .collect();
+        let widths: Vec<usize> = columns
+            .iter()
+            .enumerate()
+            .map(|(i, c)| cells.iter().map(|r| r[i].chars().count()).fold(c.len(), usize::max))
+            .collect();
+
+        let print_row = |values: Vec<&str>| {
+            let line: Vec<String> = values
+                .iter()
+                .zip(&widths)
+                .map(|(v, w)| format!("{:<width$}", v, width = w))
+                .collect();
+            println!("{}", line.join("  ").trim_end());
+        };
+        print_row(columns.to_vec());
+        for row in &cells {
+            print_row(row.iter().map(String::as_str).collect());
+        }
+    }
+}
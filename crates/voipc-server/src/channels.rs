@@ -1,46 +1,135 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
 use anyhow::{bail, Context};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use tracing::info;
+use subtle::ConstantTimeEq;
+use tracing::{info, warn};
 
+use voipc_protocol::types::ServerGroup;
+
+use crate::permissions::PermissionOverride;
+
+/// Legacy unsalted hash, still accepted: `"sha256:<64 hex chars>"`.
 const SHA256_PREFIX: &str = "sha256:";
+/// Argon2id PHC string of the password.
+const ARGON2_PREFIX: &str = "argon2:";
+/// Argon2id PHC string of a legacy SHA-256 hex digest, which is what
+/// `sha256:` entries are upgraded to on load.
+const ARGON2_SHA256_PREFIX: &str = "argon2-sha256:";
+
+/// Deepest allowed channel nesting (a top-level channel has depth 1).
+pub const MAX_CHANNEL_DEPTH: usize = 8;
+
+/// Longest channel description, in bytes.
+pub const MAX_DESCRIPTION_LEN: usize = 1024;
+
+/// Longest channel topic, in bytes.
+pub const MAX_TOPIC_LEN: usize = 256;
 
 /// A single channel entry as read from channels.json.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct ChannelEntry {
     pub name: String,
 
     #[serde(default)]
     pub description: String,
 
+    /// Current topic, shown with the channel (editable live).
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub topic: String,
+
     /// Plaintext password — hashed to `password_hash` on first load and removed.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub password: Option<String>,
 
-    /// SHA-256 hash of the password: `"sha256:<64 hex chars>"`.
+    /// Hash of the password: `"argon2:<PHC string>"`. Legacy
+    /// `"sha256:<64 hex chars>"` hashes are upgraded on load.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub password_hash: Option<String>,
 
     /// Maximum users (0 = unlimited).
     #[serde(default)]
     pub max_users: u32,
+
+    /// Per-group permission overrides for this channel.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub permissions: HashMap<ServerGroup, PermissionOverride>,
+
+    /// Name of the parent channel, which must be another entry in the file
+    /// (channel IDs are assigned at load time). None = top level.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+
+    /// Sort order among siblings (lower first).
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub position: u32,
+
+    /// Without a password of its own, require the parent's password.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub inherit_password: bool,
+
+    /// Apply the parent's permission overrides before this channel's own.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub inherit_permissions: bool,
+}
+
+fn is_zero(v: &u32) -> bool {
+    *v == 0
 }
 
-/// Hash a plaintext password to `"sha256:<64 hex chars>"`.
+fn is_false(v: &bool) -> bool {
+    !*v
+}
+
+/// Hash a plaintext password to `"argon2:<PHC string>"` (Argon2id with a
+/// random salt).
 pub fn hash_password(plaintext: &str) -> String {
-    let digest = Sha256::digest(plaintext.as_bytes());
-    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
-    format!("{}{}", SHA256_PREFIX, hex)
+    format!("{}{}", ARGON2_PREFIX, argon2_phc(plaintext.as_bytes()))
+}
+
+/// Check a plaintext password against a stored hash in any supported format.
+pub fn verify_password(plaintext: &str, stored: &str) -> bool {
+    if let Some(phc) = stored.strip_prefix(ARGON2_PREFIX) {
+        argon2_verify(plaintext.as_bytes(), phc)
+    } else if let Some(phc) = stored.strip_prefix(ARGON2_SHA256_PREFIX) {
+        argon2_verify(sha256_hex(plaintext).as_bytes(), phc)
+    } else if let Some(hex) = stored.strip_prefix(SHA256_PREFIX) {
+        sha256_hex(plaintext).as_bytes().ct_eq(hex.as_bytes()).into()
+    } else {
+        false
+    }
+}
+
+fn sha256_hex(plaintext: &str) -> String {
+    Sha256::digest(plaintext.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn argon2_phc(secret: &[u8]) -> String {
+    let salt_bytes: [u8; 16] = rand::random();
+    let salt = SaltString::encode_b64(&salt_bytes).expect("16 bytes is a valid salt");
+    Argon2::default()
+        .hash_password(secret, &salt)
+        .expect("default Argon2 parameters are valid")
+        .to_string()
+}
+
+fn argon2_verify(secret: &[u8], phc: &str) -> bool {
+    PasswordHash::new(phc).is_ok_and(|hash| Argon2::default().verify_password(secret, &hash).is_ok())
 }
 
 /// Load, validate, and prepare persistent channel entries from a JSON file.
 ///
-/// If any plaintext passwords are found, they are hashed and the file is atomically
-/// rewritten with `password_hash` fields. If the JSON is invalid or validation fails,
-/// an error is returned and the file is **never** modified.
+/// If any plaintext passwords or legacy `sha256:` hashes are found, they are
+/// hashed with Argon2 and the file is atomically rewritten with `password_hash`
+/// fields. If the JSON is invalid or validation fails, an error is returned and
+/// the file is **never** modified.
 pub fn load_and_prepare_channels(path: &Path) -> anyhow::Result<Vec<ChannelEntry>> {
     let content = std::fs::read_to_string(path)
         .with_context(|| format!("failed to read channels file: {}", path.display()))?;
@@ -49,13 +138,22 @@ pub fn load_and_prepare_channels(path: &Path) -> anyhow::Result<Vec<ChannelEntry
         .with_context(|| format!("invalid JSON in channels file: {}", path.display()))?;
 
     validate_entries(&entries)?;
+    for entry in entries.iter_mut() {
+        if sanitize_topic(entry) {
+            warn!(
+                "channel '{}': description or topic was too long or had control characters, trimmed",
+                entry.name.trim()
+            );
+        }
+    }
 
-    let needs_rewrite = hash_plaintext_passwords(&mut entries);
+    let hashed = hash_plaintext_passwords(&mut entries);
+    let upgraded = upgrade_legacy_hashes(&mut entries);
 
-    if needs_rewrite {
+    if hashed || upgraded {
         atomic_rewrite(path, &entries)
             .with_context(|| format!("failed to rewrite channels file: {}", path.display()))?;
-        info!("hashed plaintext passwords in {}", path.display());
+        info!("hashed channel passwords in {}", path.display());
     }
 
     Ok(entries)
@@ -65,49 +163,151 @@ fn validate_entries(entries: &[ChannelEntry]) -> anyhow::Result<()> {
     let mut seen_names: HashSet<String> = HashSet::new();
 
     for (i, entry) in entries.iter().enumerate() {
+        validate_entry(entry).with_context(|| format!("invalid channel entry {}", i))?;
         let name = entry.name.trim();
-        if name.is_empty() {
-            bail!("channel entry {} has an empty name", i);
-        }
-        if name.to_lowercase() == "general" {
-            bail!("channel entry {} uses reserved name 'General'", i);
-        }
-        if name.chars().any(|c| c.is_control()) {
-            bail!("channel '{}' contains control characters", name);
-        }
-        if name.len() > 64 {
-            bail!("channel '{}' name exceeds 64 characters", name);
-        }
         let lower = name.to_lowercase();
         if !seen_names.insert(lower) {
             bail!("duplicate channel name: '{}'", name);
         }
-        if entry.password.is_some() && entry.password_hash.is_some() {
-            bail!(
-                "channel '{}' has both 'password' and 'password_hash' — use only one",
-                name
-            );
-        }
-        if let Some(ref hash) = entry.password_hash {
-            if !hash.starts_with(SHA256_PREFIX) {
+    }
+
+    // Every parent must be another entry, without cycles or excessive depth
+    let parents: HashMap<String, Option<String>> = entries
+        .iter()
+        .map(|e| {
+            let parent = e.parent.as_ref().map(|p| p.trim().to_lowercase());
+            (e.name.trim().to_lowercase(), parent)
+        })
+        .collect();
+    for entry in entries {
+        let mut depth = 1;
+        let mut current = entry.name.trim().to_lowercase();
+        while let Some(Some(parent)) = parents.get(&current) {
+            if !parents.contains_key(parent) {
+                bail!(
+                    "channel '{}' has unknown parent '{}'",
+                    entry.name.trim(),
+                    parent
+                );
+            }
+            depth += 1;
+            if depth > MAX_CHANNEL_DEPTH {
                 bail!(
-                    "channel '{}' password_hash must start with '{}'",
-                    name,
-                    SHA256_PREFIX
+                    "channel '{}' is nested deeper than {} levels (or its parents form a cycle)",
+                    entry.name.trim(),
+                    MAX_CHANNEL_DEPTH
                 );
             }
-            let hex_part = &hash[SHA256_PREFIX.len()..];
+            current = parent.clone();
+        }
+    }
+
+    Ok(())
+}
+
+/// Validate a single entry (name and password fields). Name uniqueness is
+/// checked by the caller.
+pub fn validate_entry(entry: &ChannelEntry) -> anyhow::Result<()> {
+    let name = entry.name.trim();
+    if name.is_empty() {
+        bail!("channel name is empty");
+    }
+    if name.to_lowercase() == "general" {
+        bail!("'General' is a reserved channel name");
+    }
+    if name.chars().any(|c| c.is_control()) {
+        bail!("channel '{}' contains control characters", name);
+    }
+    if name.len() > 64 {
+        bail!("channel '{}' name exceeds 64 characters", name);
+    }
+    if entry
+        .parent
+        .as_ref()
+        .is_some_and(|p| p.trim().to_lowercase() == name.to_lowercase())
+    {
+        bail!("channel '{}' cannot be its own parent", name);
+    }
+    if entry.password.is_some() && entry.password_hash.is_some() {
+        bail!(
+            "channel '{}' has both 'password' and 'password_hash' — use only one",
+            name
+        );
+    }
+    if let Some(ref hash) = entry.password_hash {
+        if let Some(hex_part) = hash.strip_prefix(SHA256_PREFIX) {
             if hex_part.len() != 64 || !hex_part.chars().all(|c| c.is_ascii_hexdigit()) {
                 bail!("channel '{}' password_hash has invalid SHA-256 hex", name);
             }
+        } else if let Some(phc) = hash
+            .strip_prefix(ARGON2_PREFIX)
+            .or_else(|| hash.strip_prefix(ARGON2_SHA256_PREFIX))
+        {
+            if PasswordHash::new(phc).is_err() {
+                bail!("channel '{}' password_hash is not a valid Argon2 hash", name);
+            }
+        } else {
+            bail!(
+                "channel '{}' password_hash must start with '{}' (or legacy '{}')",
+                name,
+                ARGON2_PREFIX,
+                SHA256_PREFIX
+            );
         }
     }
+    Ok(())
+}
 
+/// Check a channel description and topic. The topic is a single line; the
+/// description may span several.
+pub fn validate_topic(description: &str, topic: &str) -> anyhow::Result<()> {
+    if description.len() > MAX_DESCRIPTION_LEN {
+        bail!("description exceeds {} bytes", MAX_DESCRIPTION_LEN);
+    }
+    if description.chars().any(|c| c.is_control() && c != '\n') {
+        bail!("description contains control characters");
+    }
+    if topic.len() > MAX_TOPIC_LEN {
+        bail!("topic exceeds {} bytes", MAX_TOPIC_LEN);
+    }
+    if topic.chars().any(|c| c.is_control()) {
+        bail!("topic contains control characters");
+    }
     Ok(())
 }
 
+/// Bring a loaded description and topic within the limits that
+/// [`validate_topic`] holds new ones to, so files written before those
+/// limits still load. Returns `true` if anything changed.
+pub fn sanitize_topic(entry: &mut ChannelEntry) -> bool {
+    let description = clean_text(&entry.description, MAX_DESCRIPTION_LEN, true);
+    let topic = clean_text(&entry.topic, MAX_TOPIC_LEN, false);
+    let changed = description != entry.description || topic != entry.topic;
+    entry.description = description;
+    entry.topic = topic;
+    changed
+}
+
+/// Control characters become spaces (newlines kept if `multiline`), and the
+/// text is cut to `max_len` bytes on a character boundary.
+fn clean_text(text: &str, max_len: usize, multiline: bool) -> String {
+    let mut cleaned: String = text
+        .chars()
+        .filter(|&c| c != '\r')
+        .map(|c| if c.is_control() && !(multiline && c == '\n') { ' ' } else { c })
+        .collect();
+    if cleaned.len() > max_len {
+        let mut end = max_len;
+        while !cleaned.is_char_boundary(end) {
+            end -= 1;
+        }
+        cleaned.truncate(end);
+    }
+    cleaned
+}
+
 /// Convert any plaintext passwords to hashed form. Returns `true` if any were converted.
-fn hash_plaintext_passwords(entries: &mut [ChannelEntry]) -> bool {
+pub fn hash_plaintext_passwords(entries: &mut [ChannelEntry]) -> bool {
     let mut changed = false;
     for entry in entries.iter_mut() {
         if let Some(plaintext) = entry.password.take() {
@@ -118,8 +318,23 @@ fn hash_plaintext_passwords(entries: &mut [ChannelEntry]) -> bool {
     changed
 }
 
+/// Wrap legacy `sha256:` hashes in Argon2 so the stored value is salted and
+/// slow to brute-force. Returns `true` if any were upgraded.
+pub fn upgrade_legacy_hashes(entries: &mut [ChannelEntry]) -> bool {
+    let mut changed = false;
+    for entry in entries.iter_mut() {
+        let Some(hex) = entry.password_hash.as_deref().and_then(|h| h.strip_prefix(SHA256_PREFIX)) else {
+            continue;
+        };
+        let upgraded = format!("{}{}", ARGON2_SHA256_PREFIX, argon2_phc(hex.to_ascii_lowercase().as_bytes()));
+        entry.password_hash = Some(upgraded);
+        changed = true;
+    }
+    changed
+}
+
 /// Write entries to a temp file then atomically rename over the original.
-fn atomic_rewrite(path: &Path, entries: &[ChannelEntry]) -> anyhow::Result<()> {
+pub fn atomic_rewrite(path: &Path, entries: &[ChannelEntry]) -> anyhow::Result<()> {
     let json = serde_json::to_string_pretty(entries).context("failed to serialize channels")?;
 
     let tmp_path = path.with_extension("json.tmp");
@@ -138,13 +353,34 @@ mod tests {
     #[test]
     fn hash_password_produces_correct_format() {
         let hash = hash_password("secretpass");
-        assert!(hash.starts_with("sha256:"));
-        assert_eq!(hash.len(), 7 + 64); // "sha256:" + 64 hex chars
+        assert!(hash.starts_with("argon2:$argon2id$"));
+        assert!(verify_password("secretpass", &hash));
+        assert!(!verify_password("wrongpass", &hash));
     }
 
     #[test]
-    fn hash_password_is_deterministic() {
-        assert_eq!(hash_password("test"), hash_password("test"));
+    fn hash_password_is_salted() {
+        assert_ne!(hash_password("test"), hash_password("test"));
+    }
+
+    #[test]
+    fn legacy_sha256_hashes_verify_and_upgrade() {
+        let legacy = format!("sha256:{}", sha256_hex("test"));
+        assert!(verify_password("test", &legacy));
+        assert!(!verify_password("nope", &legacy));
+
+        let mut entries = vec![ChannelEntry {
+            name: "Old".into(),
+            password_hash: Some(legacy),
+            ..Default::default()
+        }];
+        assert!(upgrade_legacy_hashes(&mut entries));
+        let upgraded = entries[0].password_hash.clone().unwrap();
+        assert!(upgraded.starts_with("argon2-sha256:"));
+        assert!(validate_entries(&entries).is_ok());
+        assert!(verify_password("test", &upgraded));
+        assert!(!verify_password("nope", &upgraded));
+        assert!(!upgrade_legacy_hashes(&mut entries));
     }
 
     #[test]
@@ -155,6 +391,8 @@ mod tests {
             password: None,
             password_hash: None,
             max_users: 0,
+            permissions: HashMap::new(),
+            ..Default::default()
         }];
         assert!(validate_entries(&entries).is_err());
     }
@@ -167,6 +405,8 @@ mod tests {
             password: None,
             password_hash: None,
             max_users: 0,
+            permissions: HashMap::new(),
+            ..Default::default()
         }];
         assert!(validate_entries(&entries).is_err());
     }
@@ -180,6 +420,8 @@ mod tests {
                 password: None,
                 password_hash: None,
                 max_users: 0,
+                permissions: HashMap::new(),
+                ..Default::default()
             },
             ChannelEntry {
                 name: "music".into(),
@@ -187,6 +429,8 @@ mod tests {
                 password: None,
                 password_hash: None,
                 max_users: 0,
+                permissions: HashMap::new(),
+                ..Default::default()
             },
         ];
         assert!(validate_entries(&entries).is_err());
@@ -200,6 +444,8 @@ mod tests {
             password: Some("plain".into()),
             password_hash: Some("sha256:abc".into()),
             max_users: 0,
+            permissions: HashMap::new(),
+            ..Default::default()
         }];
         assert!(validate_entries(&entries).is_err());
     }
@@ -213,6 +459,8 @@ mod tests {
                 password: None,
                 password_hash: None,
                 max_users: 10,
+                permissions: HashMap::new(),
+                ..Default::default()
             },
             ChannelEntry {
                 name: "AFK".into(),
@@ -220,6 +468,8 @@ mod tests {
                 password: None,
                 password_hash: Some(hash_password("test")),
                 max_users: 0,
+                permissions: HashMap::new(),
+                ..Default::default()
             },
         ];
         assert!(validate_entries(&entries).is_ok());
@@ -233,6 +483,8 @@ mod tests {
             password: Some("secret".into()),
             password_hash: None,
             max_users: 0,
+            permissions: HashMap::new(),
+            ..Default::default()
         }];
         let changed = hash_plaintext_passwords(&mut entries);
         assert!(changed);
@@ -241,7 +493,7 @@ mod tests {
             .password_hash
             .as_ref()
             .unwrap()
-            .starts_with("sha256:"));
+            .starts_with("argon2:"));
     }
 
     #[test]
@@ -253,6 +505,8 @@ mod tests {
             password: None,
             password_hash: Some(hash.clone()),
             max_users: 0,
+            permissions: HashMap::new(),
+            ..Default::default()
         }];
         let changed = hash_plaintext_passwords(&mut entries);
         assert!(!changed);
@@ -267,6 +521,8 @@ mod tests {
             password: None,
             password_hash: Some("md5:abcdef".into()),
             max_users: 0,
+            permissions: HashMap::new(),
+            ..Default::default()
         }];
         assert!(validate_entries(&entries).is_err());
     }
@@ -275,4 +531,75 @@ mod tests {
     fn validate_empty_array_succeeds() {
         assert!(validate_entries(&[]).is_ok());
     }
+
+    fn child(name: &str, parent: &str) -> ChannelEntry {
+        ChannelEntry {
+            name: name.into(),
+            parent: Some(parent.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_channel_tree() {
+        let root = ChannelEntry {
+            name: "Teams".into(),
+            ..Default::default()
+        };
+        let tree = vec![root.clone(), child("Backend", "teams"), child("Standup", "Backend")];
+        assert!(validate_entries(&tree).is_ok());
+
+        assert!(validate_entries(&[root.clone(), child("Backend", "Frontend")]).is_err());
+        assert!(validate_entries(&[child("A", "B"), child("B", "A")]).is_err());
+        assert!(validate_entries(&[child("A", "a")]).is_err());
+    }
+
+    #[test]
+    fn validate_depth_limit() {
+        let mut entries = vec![ChannelEntry {
+            name: "L1".into(),
+            ..Default::default()
+        }];
+        for i in 2..=MAX_CHANNEL_DEPTH {
+            entries.push(child(&format!("L{}", i), &format!("L{}", i - 1)));
+        }
+        assert!(validate_entries(&entries).is_ok());
+        entries.push(child("Deep", &format!("L{}", MAX_CHANNEL_DEPTH)));
+        assert!(validate_entries(&entries).is_err());
+    }
+
+    #[test]
+    fn sanitize_trims_loaded_topics() {
+        let mut entry = ChannelEntry {
+            name: "Old".into(),
+            description: format!("Rules:\r\n\tbe nice{}", "é".repeat(MAX_DESCRIPTION_LEN)),
+            topic: "Tonight\tat 8".into(),
+            ..Default::default()
+        };
+        assert!(validate_entry(&entry).is_ok());
+        assert!(sanitize_topic(&mut entry));
+        assert!(entry.description.starts_with("Rules:\n be nice"));
+        assert!(entry.description.len() <= MAX_DESCRIPTION_LEN);
+        assert_eq!(entry.topic, "Tonight at 8");
+        assert!(validate_topic(&entry.description, &entry.topic).is_ok());
+        assert!(!sanitize_topic(&mut entry));
+    }
+
+    #[test]
+    fn validate_topic_limits() {
+        assert!(validate_topic("Line one\nline two", "Release night").is_ok());
+        assert!(validate_topic("", "two\nlines").is_err());
+        assert!(validate_topic("", &"x".repeat(MAX_TOPIC_LEN + 1)).is_err());
+        assert!(validate_topic(&"x".repeat(MAX_DESCRIPTION_LEN + 1), "").is_err());
+    }
+
+    #[test]
+    fn entry_parses_permission_overrides() {
+        let json = r#"[{"name": "Staff", "permissions": {"guest": {"join": false}}}]"#;
+        let entries: Vec<ChannelEntry> = serde_json::from_str(json).unwrap();
+        let ov = &entries[0].permissions[&ServerGroup::Guest];
+        assert_eq!(ov.join, Some(false));
+        assert_eq!(ov.speak, None);
+        assert!(validate_entries(&entries).is_ok());
+    }
 }
@@ -1,7 +1,15 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+
 use serde::Deserialize;
 
+use crate::bans::{ip_in_range, parse_ip_range};
+
+/// Id of the server formed by the top-level configuration.
+pub const DEFAULT_SERVER_ID: &str = "default";
+
 /// Server configuration, loaded from a TOML file.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct ServerConfig {
     /// IP address to bind on (default "0.0.0.0").
     /// Set this to the public/VPN IP that clients connect to so that UDP
@@ -21,11 +29,89 @@ pub struct ServerConfig {
     #[serde(default = "default_max_users")]
     pub max_users: u32,
 
+    /// Maximum concurrent connections from one IP address, including ones
+    /// still authenticating.
+    #[serde(default = "default_max_connections_per_ip")]
+    pub max_connections_per_ip: u32,
+
+    /// Maximum concurrent connections in total.
+    #[serde(default = "default_max_total_connections")]
+    pub max_total_connections: u32,
+
+    /// Per-IP connection limits for specific address ranges, e.g. an office
+    /// behind NAT. The first matching range applies.
+    #[serde(default)]
+    pub connection_limit_overrides: Vec<ConnectionLimitOverride>,
+
     /// Path to TLS certificate file (PEM).
     pub cert_path: String,
 
     /// Path to TLS private key file (PEM).
     pub key_path: String,
+
+    /// Path of the local admin socket (Unix only). Disabled when unset.
+    #[serde(default)]
+    pub admin_socket: Option<String>,
+
+    /// Address of the Prometheus metrics HTTP listener, e.g.
+    /// "127.0.0.1:9100". Disabled when unset.
+    #[serde(default)]
+    pub metrics_addr: Option<String>,
+
+    /// Path of the JSON-lines audit log of administrative actions.
+    /// Disabled when unset.
+    #[serde(default)]
+    pub audit_log: Option<String>,
+
+    /// Size at which the audit log is rotated, in bytes.
+    #[serde(default = "default_audit_log_max_bytes")]
+    pub audit_log_max_bytes: u64,
+
+    /// How long a shutdown waits for clients to disconnect before closing
+    /// their connections, in seconds.
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+
+    /// Server ("host:port") clients are told to move to when this one shuts
+    /// down on a signal, e.g. a standby instance.
+    #[serde(default)]
+    pub shutdown_reconnect_to: Option<String>,
+
+    /// Further servers hosted by this process, each with its own channels,
+    /// settings, accounts and bans. The top-level settings form the
+    /// "default" server.
+    #[serde(default)]
+    pub virtual_servers: Vec<VirtualServerConfig>,
+}
+
+/// A virtual server sharing this process's listeners.
+#[derive(Debug, Clone, Deserialize)]
+pub struct VirtualServerConfig {
+    /// Name clients give in `Authenticate` to select this server.
+    pub id: String,
+    /// TLS server names (SNI) that select this server.
+    #[serde(default)]
+    pub hostnames: Vec<String>,
+    /// Directory holding this server's server_settings.json, channels.json,
+    /// accounts.json, bans.json and (when audit logging is on) audit.jsonl.
+    pub data_dir: String,
+    /// Maximum concurrent users (defaults to the top-level `max_users`).
+    #[serde(default)]
+    pub max_users: Option<u32>,
+    /// Certificate presented for `hostnames` (defaults to the top-level one).
+    #[serde(default)]
+    pub cert_path: Option<String>,
+    #[serde(default)]
+    pub key_path: Option<String>,
+}
+
+/// A per-IP connection limit for an address range.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConnectionLimitOverride {
+    /// IP address or CIDR range, e.g. "203.0.113.0/24".
+    pub cidr: String,
+    /// Connections allowed from each address in the range (0 = unlimited).
+    pub max_connections_per_ip: u32,
 }
 
 fn default_host() -> String {
@@ -44,6 +130,22 @@ fn default_max_users() -> u32 {
     64
 }
 
+fn default_max_connections_per_ip() -> u32 {
+    5
+}
+
+fn default_max_total_connections() -> u32 {
+    256
+}
+
+fn default_audit_log_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
@@ -51,9 +153,59 @@ impl Default for ServerConfig {
             tcp_port: default_tcp_port(),
             udp_port: default_udp_port(),
             max_users: default_max_users(),
+            max_connections_per_ip: default_max_connections_per_ip(),
+            max_total_connections: default_max_total_connections(),
+            connection_limit_overrides: Vec::new(),
             cert_path: "certs/server.crt".into(),
             key_path: "certs/server.key".into(),
+            admin_socket: None,
+            metrics_addr: None,
+            audit_log: None,
+            audit_log_max_bytes: default_audit_log_max_bytes(),
+            shutdown_timeout_secs: default_shutdown_timeout_secs(),
+            shutdown_reconnect_to: None,
+            virtual_servers: Vec::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for entry in &self.connection_limit_overrides {
+            if parse_ip_range(&entry.cidr).is_none() {
+                anyhow::bail!("invalid connection limit override range '{}'", entry.cidr);
+            }
+        }
+
+        let mut ids = HashSet::from([DEFAULT_SERVER_ID.to_string()]);
+        let mut hostnames = HashSet::new();
+        for vs in &self.virtual_servers {
+            if vs.id.trim().is_empty() || vs.id.chars().any(|c| c.is_control()) {
+                anyhow::bail!("virtual server id '{}' is invalid", vs.id);
+            }
+            if !ids.insert(vs.id.to_lowercase()) {
+                anyhow::bail!("duplicate virtual server id '{}'", vs.id);
+            }
+            for host in &vs.hostnames {
+                if !hostnames.insert(host.to_lowercase()) {
+                    anyhow::bail!("hostname '{}' is used by two virtual servers", host);
+                }
+            }
+            if vs.cert_path.is_some() != vs.key_path.is_some() {
+                anyhow::bail!("virtual server '{}' needs both cert_path and key_path", vs.id);
+            }
         }
+        Ok(())
+    }
+
+    /// Connections allowed from one address (None = unlimited).
+    pub fn connection_limit_for(&self, ip: IpAddr) -> Option<u32> {
+        let limit = self
+            .connection_limit_overrides
+            .iter()
+            .find(|o| parse_ip_range(&o.cidr).is_some_and(|range| ip_in_range(ip, range)))
+            .map_or(self.max_connections_per_ip, |o| o.max_connections_per_ip);
+        (limit > 0).then_some(limit)
     }
 }
 
@@ -67,6 +219,9 @@ mod tests {
         assert_eq!(config.tcp_port, 9987);
         assert_eq!(config.udp_port, 9987);
         assert_eq!(config.max_users, 64);
+        assert_eq!(config.audit_log, None);
+        assert_eq!(config.audit_log_max_bytes, 10 * 1024 * 1024);
+        assert_eq!(config.shutdown_timeout_secs, 30);
     }
 
     #[test]
@@ -83,5 +238,68 @@ mod tests {
         assert_eq!(config.udp_port, 5678);
         assert_eq!(config.max_users, 128);
         assert_eq!(config.cert_path, "test.crt");
+        assert_eq!(config.admin_socket, None);
+        assert_eq!(config.max_connections_per_ip, 5);
+        assert_eq!(config.max_total_connections, 256);
+    }
+
+    #[test]
+    fn connection_limit_overrides_by_range() {
+        let toml = r#"
+            cert_path = "test.crt"
+            key_path = "test.key"
+            max_connections_per_ip = 3
+
+            [[connection_limit_overrides]]
+            cidr = "203.0.113.0/24"
+            max_connections_per_ip = 50
+
+            [[connection_limit_overrides]]
+            cidr = "10.0.0.1"
+            max_connections_per_ip = 0
+        "#;
+        let config: ServerConfig = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(config.connection_limit_for(ip("203.0.113.9")), Some(50));
+        assert_eq!(config.connection_limit_for(ip("::ffff:203.0.113.9")), Some(50));
+        assert_eq!(config.connection_limit_for(ip("10.0.0.1")), None);
+        assert_eq!(config.connection_limit_for(ip("198.51.100.1")), Some(3));
+
+        let bad = ServerConfig {
+            connection_limit_overrides: vec![ConnectionLimitOverride {
+                cidr: "10.0.0.0/40".into(),
+                max_connections_per_ip: 10,
+            }],
+            ..ServerConfig::default()
+        };
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn virtual_servers_are_validated() {
+        let toml = r#"
+            cert_path = "test.crt"
+            key_path = "test.key"
+
+            [[virtual_servers]]
+            id = "gaming"
+            hostnames = ["gaming.example.com"]
+            data_dir = "vs/gaming"
+            max_users = 16
+        "#;
+        let mut config: ServerConfig = toml::from_str(toml).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.virtual_servers[0].max_users, Some(16));
+
+        let mut other = config.virtual_servers[0].clone();
+        other.id = "Default".into();
+        other.hostnames.clear();
+        config.virtual_servers.push(other);
+        assert!(config.validate().is_err());
+
+        config.virtual_servers[1].id = "music".into();
+        config.virtual_servers[1].hostnames = vec!["GAMING.example.com".into()];
+        assert!(config.validate().is_err());
     }
 }
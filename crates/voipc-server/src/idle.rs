@@ -0,0 +1,83 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use voipc_protocol::messages::{ClientMessage, ServerMessage};
+
+use crate::state::ServerState;
+use crate::tcp;
+
+/// How often sessions are checked for idleness.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Whether a control message shows the user doing something, as opposed to
+/// keepalives and key exchange the client performs on its own (including
+/// after being moved to the AFK channel).
+pub fn is_user_activity(msg: &ClientMessage) -> bool {
+    !matches!(
+        msg,
+        ClientMessage::Ping { .. }
+            | ClientMessage::RequestChannelList
+            | ClientMessage::RequestPreKeyBundle { .. }
+            | ClientMessage::UploadPreKeys { .. }
+            | ClientMessage::DistributeSenderKey { .. }
+            | ClientMessage::DistributeMediaKey { .. }
+            | ClientMessage::RequestKeyframe { .. }
+            | ClientMessage::StopScreenShare
+            | ClientMessage::StopWatchingScreenShare
+    )
+}
+
+/// Periodically move idle users to the AFK channel and bring back those who
+/// return. Does nothing while no AFK channel is configured.
+pub async fn run_idle_loop(state: Arc<ServerState>) {
+    let mut timer = tokio::time::interval(CHECK_INTERVAL);
+    timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        timer.tick().await;
+        apply_idle(&state).await;
+    }
+}
+
+async fn apply_idle(state: &Arc<ServerState>) {
+    let changes = state.update_idle(Instant::now()).await;
+
+    if let Some(afk_channel) = changes.afk_channel {
+        for &user_id in &changes.went_idle {
+            info!(user_id, afk_channel, "user idle, moving to AFK channel");
+            if let Err(e) = tcp::move_user(state, user_id, afk_channel).await {
+                warn!(user_id, "failed to move idle user: {}", e);
+            }
+            let msg = ServerMessage::UserIdle { user_id, idle: true };
+            tcp::broadcast_to_all(state, &msg, None).await;
+        }
+    }
+
+    for (user_id, back_to) in changes.returned {
+        if let Some(channel_id) = back_to {
+            info!(user_id, channel_id, "user back from idle, returning to channel");
+            if let Err(e) = tcp::move_user(state, user_id, channel_id).await {
+                warn!(user_id, "failed to return user from AFK channel: {}", e);
+            }
+        }
+        let msg = ServerMessage::UserIdle { user_id, idle: false };
+        tcp::broadcast_to_all(state, &msg, None).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keepalives_and_key_exchange_are_not_activity() {
+        assert!(!is_user_activity(&ClientMessage::Ping { timestamp: 0 }));
+        assert!(!is_user_activity(&ClientMessage::RequestPreKeyBundle { target_user_id: 1 }));
+        assert!(is_user_activity(&ClientMessage::SetMuted { muted: true }));
+        assert!(is_user_activity(&ClientMessage::JoinChannel {
+            channel_id: 1,
+            password: None,
+        }));
+    }
+}
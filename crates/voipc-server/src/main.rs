@@ -12,16 +12,29 @@ use tokio::net::{TcpListener, UdpSocket};
 use tokio_rustls::TlsAcceptor;
 use tracing::{error, info, warn};
 
+mod accounts;
+#[cfg(unix)]
+mod admin;
+mod audit;
+mod bans;
 mod channels;
 mod config;
+mod idle;
+mod metrics;
+mod permissions;
+mod reload;
 mod settings;
+mod shutdown;
 mod state;
 mod tcp;
 mod udp;
+mod virtual_server;
 
 use config::ServerConfig;
+use rustls::sign::CertifiedKey;
+use shutdown::Shutdown;
 use state::ServerState;
-use voipc_protocol::messages::ServerMessage;
+use virtual_server::{SniCertResolver, VirtualServers};
 
 #[derive(Parser)]
 #[command(name = "voipc-server", about = "VoIPC voice communication server")]
@@ -57,6 +70,26 @@ struct Args {
     /// Path to persistent channels file (JSON)
     #[arg(long)]
     channels: Option<String>,
+
+    /// Path to registered accounts file (JSON), created on first registration
+    #[arg(long, default_value = "accounts.json")]
+    accounts: String,
+
+    /// Path to ban list file (JSON), defaults to bans.json next to the channels file
+    #[arg(long)]
+    bans: Option<String>,
+
+    /// Path of the local admin socket used by voipc-admin, overrides config
+    #[arg(long)]
+    admin_socket: Option<String>,
+
+    /// Address for the Prometheus metrics listener (e.g. 127.0.0.1:9100), overrides config
+    #[arg(long)]
+    metrics_addr: Option<String>,
+
+    /// Path of the audit log (JSON lines), overrides config
+    #[arg(long)]
+    audit_log: Option<String>,
 }
 
 #[tokio::main]
@@ -101,29 +134,48 @@ async fn main() -> Result<()> {
     if let Some(host) = args.host {
         config.host = host;
     }
+    if let Some(path) = args.admin_socket {
+        config.admin_socket = Some(path);
+    }
+    if let Some(addr) = args.metrics_addr {
+        config.metrics_addr = Some(addr);
+    }
+    if let Some(path) = args.audit_log {
+        config.audit_log = Some(path);
+    }
+    config.validate()?;
 
-    // Load server settings (JSON)
-    let server_settings = if let Some(settings_path) = &args.settings {
-        settings::ServerSettings::load_from_file(std::path::Path::new(settings_path))
-            .with_context(|| format!("failed to load settings: {}", settings_path))?
-    } else if std::path::Path::new("server_settings.json").exists() {
-        settings::ServerSettings::load_from_file(std::path::Path::new("server_settings.json"))
-            .context("failed to load server_settings.json")?
+    // Load server settings (JSON). The same path is re-read on reload.
+    let settings_path = std::path::PathBuf::from(args.settings.as_deref().unwrap_or("server_settings.json"));
+    let server_settings = if args.settings.is_some() || settings_path.exists() {
+        settings::ServerSettings::load_from_file(&settings_path)
+            .with_context(|| format!("failed to load settings: {}", settings_path.display()))?
     } else {
         settings::ServerSettings::default()
     };
 
-    // Load persistent channels (JSON)
-    let persistent_channels = if let Some(channels_path) = &args.channels {
-        channels::load_and_prepare_channels(std::path::Path::new(channels_path))
-            .with_context(|| format!("failed to load channels: {}", channels_path))?
-    } else if std::path::Path::new("channels.json").exists() {
-        channels::load_and_prepare_channels(std::path::Path::new("channels.json"))
-            .context("failed to load channels.json")?
+    // Load persistent channels (JSON). Channels created at runtime are saved
+    // to the same file, so it is created on demand.
+    let channels_path = std::path::PathBuf::from(args.channels.as_deref().unwrap_or("channels.json"));
+    let persistent_channels = if args.channels.is_some() || channels_path.exists() {
+        channels::load_and_prepare_channels(&channels_path)
+            .with_context(|| format!("failed to load channels: {}", channels_path.display()))?
     } else {
         Vec::new()
     };
 
+    // Load registered accounts (JSON)
+    let accounts = accounts::AccountStore::load(std::path::Path::new(&args.accounts))
+        .with_context(|| format!("failed to load accounts: {}", args.accounts))?;
+
+    // Load bans (JSON), kept next to the channels file unless overridden
+    let bans_path = match &args.bans {
+        Some(path) => std::path::PathBuf::from(path),
+        None => channels_path.with_file_name("bans.json"),
+    };
+    let bans = bans::BanStore::load(&bans_path)
+        .with_context(|| format!("failed to load bans: {}", bans_path.display()))?;
+
     info!("VoIPC Server starting");
     info!(
         host = %config.host,
@@ -132,21 +184,46 @@ async fn main() -> Result<()> {
         max_users = config.max_users,
         empty_channel_timeout = server_settings.empty_channel_timeout_secs,
         persistent_channels = persistent_channels.len(),
+        accounts = accounts.len(),
+        bans = bans.len(),
+        allow_guests = server_settings.allow_guests,
+        virtual_servers = config.virtual_servers.len(),
     );
 
-    // Load TLS certificate and key
-    let certs = load_certs(&config.cert_path)?;
-    let key = load_key(&config.key_path)?;
+    // Load TLS certificates and keys; virtual servers with their own
+    // certificate get it for their hostnames
+    let mut cert_resolver = SniCertResolver::new(certified_key(&config.cert_path, &config.key_path)?);
+    for vs in &config.virtual_servers {
+        if let (Some(cert_path), Some(key_path)) = (&vs.cert_path, &vs.key_path) {
+            let key = certified_key(cert_path, key_path)?;
+            for hostname in &vs.hostnames {
+                cert_resolver.add(hostname, key.clone());
+            }
+        }
+    }
 
     let tls_config = rustls::ServerConfig::builder()
         .with_no_client_auth()
-        .with_single_cert(certs, key)
-        .context("invalid TLS configuration")?;
+        .with_cert_resolver(Arc::new(cert_resolver));
 
     let tls_acceptor = TlsAcceptor::from(Arc::new(tls_config));
 
-    // Create shared state
-    let state = Arc::new(ServerState::new(&config, server_settings, persistent_channels));
+    // Create shared state: the default server plus any virtual servers
+    let mut servers = VirtualServers::new(ServerState::new(
+        &config,
+        server_settings,
+        persistent_channels,
+        accounts,
+        bans,
+        Some(channels_path),
+        Some(settings_path),
+    ));
+    for vs in &config.virtual_servers {
+        let vs_state = virtual_server::load_virtual_server(&config, vs)
+            .with_context(|| format!("failed to load virtual server '{}'", vs.id))?;
+        servers.add(vs, vs_state);
+    }
+    let servers = Arc::new(servers);
 
     // Bind TCP listener
     let tcp_listener = TcpListener::bind(format!("{}:{}", config.host, config.tcp_port))
@@ -195,30 +272,85 @@ async fn main() -> Result<()> {
     info!("UDP socket bound on {}:{}", config.host, config.udp_port);
 
     // Spawn UDP voice loop
-    let udp_state = state.clone();
+    let udp_servers = servers.clone();
     let udp_sock = udp_socket.clone();
     tokio::spawn(async move {
-        udp::run_udp_loop(udp_sock, udp_state).await;
+        udp::run_udp_loop(udp_sock, udp_servers).await;
     });
 
+    // Move idle users to the AFK channel (when one is configured)
+    for vs in servers.iter() {
+        let idle_state = vs.state.clone();
+        tokio::spawn(async move {
+            idle::run_idle_loop(idle_state).await;
+        });
+    }
+
+    // Reload settings and persistent channels on SIGHUP
+    #[cfg(unix)]
+    {
+        let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .context("failed to install SIGHUP handler")?;
+        let reload_servers = servers.clone();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("SIGHUP received, reloading settings and channels");
+                for vs in reload_servers.iter() {
+                    if let Err(e) = reload::reload(&vs.state).await {
+                        error!(server = %vs.id, "reload failed, keeping current configuration: {:#}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    // Prometheus metrics listener (opt-in)
+    if let Some(addr) = &config.metrics_addr {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("failed to bind metrics listener on {}", addr))?;
+        info!("metrics available at http://{}/metrics", addr);
+        let metrics_servers = servers.clone();
+        tokio::spawn(async move {
+            metrics::run_metrics_listener(listener, metrics_servers).await;
+        });
+    }
+
+    // Drain connections on SIGINT / SIGTERM or an admin request
+    let shutdown = Shutdown::new(&config);
+    shutdown::spawn_signal_handler(shutdown.clone())?;
+
+    // Local admin socket (opt-in)
+    #[cfg(unix)]
+    if let Some(path) = &config.admin_socket {
+        let listener = admin::bind(std::path::Path::new(path))?;
+        info!("admin socket listening on {}", path);
+        let admin_servers = servers.clone();
+        let admin_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            admin::run_admin_loop(listener, admin_servers, admin_shutdown).await;
+        });
+    }
+    #[cfg(not(unix))]
+    if config.admin_socket.is_some() {
+        warn!("admin_socket is only supported on Unix, ignoring");
+    }
+
     // TCP accept loop with connection limits
     info!("server ready, accepting connections");
 
-    const MAX_CONNECTIONS_PER_IP: u32 = 5;
-    const MAX_TOTAL_CONNECTIONS: u32 = 256;
-
     let active_connections = Arc::new(AtomicU32::new(0));
     let per_ip_connections: Arc<DashMap<IpAddr, u32>> = Arc::new(DashMap::new());
 
-    let shutdown = tokio::signal::ctrl_c();
-    tokio::pin!(shutdown);
+    let requested = shutdown.requested();
+    tokio::pin!(requested);
 
-    loop {
+    let drain_request = loop {
         let accept_result = tokio::select! {
             result = tcp_listener.accept() => result,
-            _ = &mut shutdown => {
-                info!("shutdown signal received, stopping accept loop");
-                break;
+            request = &mut requested => {
+                info!("shutdown requested, stopping accept loop");
+                break request;
             }
         };
 
@@ -233,7 +365,7 @@ async fn main() -> Result<()> {
         let peer_ip = peer_addr.ip();
 
         // Global connection limit
-        if active_connections.load(Ordering::Relaxed) >= MAX_TOTAL_CONNECTIONS {
+        if active_connections.load(Ordering::Relaxed) >= config.max_total_connections {
             warn!(peer = %peer_addr, "rejecting connection: global limit reached");
             drop(tcp_stream);
             continue;
@@ -241,8 +373,9 @@ async fn main() -> Result<()> {
 
         // Per-IP connection limit
         {
+            let limit = config.connection_limit_for(peer_ip);
             let mut count = per_ip_connections.entry(peer_ip).or_insert(0);
-            if *count >= MAX_CONNECTIONS_PER_IP {
+            if limit.is_some_and(|limit| *count >= limit) {
                 warn!(peer = %peer_addr, "rejecting connection: per-IP limit reached");
                 drop(tcp_stream);
                 continue;
@@ -265,16 +398,17 @@ async fn main() -> Result<()> {
         }
 
         let tls_acceptor = tls_acceptor.clone();
-        let state = state.clone();
+        let servers = servers.clone();
         let conn_count = active_connections.clone();
         let ip_conns = per_ip_connections.clone();
 
         tokio::spawn(async move {
             match tls_acceptor.accept(tcp_stream).await {
                 Ok(tls_stream) => {
-                    tcp::handle_connection(tls_stream, state).await;
+                    tcp::handle_connection(tls_stream, servers).await;
                 }
                 Err(e) => {
+                    servers.default_state().metrics.tls_handshake_failed();
                     error!(peer = %peer_addr, "TLS handshake failed: {}", e);
                 }
             }
@@ -289,21 +423,32 @@ async fn main() -> Result<()> {
                 }
             }
         });
-    }
-
-    // Graceful shutdown: notify all connected clients
-    info!("broadcasting shutdown to all connected clients");
-    let shutdown_msg = ServerMessage::ServerShutdown {
-        reason: "server shutting down".into(),
     };
-    if let Ok(data) = voipc_protocol::codec::encode_server_msg(&shutdown_msg) {
-        state.broadcast_raw_to_all(&data).await;
+
+    // Graceful shutdown: refuse new connections and let clients leave
+    drop(tcp_listener);
+    shutdown::drain(&servers, &drain_request, &shutdown).await;
+    #[cfg(unix)]
+    if let Some(path) = &config.admin_socket {
+        let _ = fs::remove_file(path);
     }
-    tokio::time::sleep(Duration::from_millis(500)).await;
     info!("server shut down");
     Ok(())
 }
 
+/// Load a certificate chain and its private key for the certificate resolver.
+fn certified_key(cert_path: &str, key_path: &str) -> Result<Arc<CertifiedKey>> {
+    let certs = load_certs(cert_path)?;
+    let key = load_key(key_path)?;
+    let signing_key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .with_context(|| format!("unsupported private key in {}", key_path))?;
+    let certified = CertifiedKey::new(certs, signing_key);
+    certified
+        .keys_match()
+        .with_context(|| format!("{} does not match {}", key_path, cert_path))?;
+    Ok(Arc::new(certified))
+}
+
 fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
     let cert_data = fs::read(path).with_context(|| format!("failed to read cert: {}", path))?;
     let mut reader = std::io::BufReader::new(cert_data.as_slice());
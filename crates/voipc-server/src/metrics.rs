@@ -0,0 +1,403 @@
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, warn};
+
+use voipc_protocol::types::RateLimitKind;
+
+use crate::virtual_server::VirtualServers;
+
+/// Largest HTTP request head accepted by the metrics listener.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// A per-session rate limiter, used to label dropped packets and messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limiter {
+    UdpVoice,
+    UdpVideo,
+    Tcp,
+    PasswordAttempt,
+    Chat,
+    CreateChannel,
+    Prekey,
+}
+
+impl Limiter {
+    pub const ALL: [Limiter; 7] = [
+        Limiter::UdpVoice,
+        Limiter::UdpVideo,
+        Limiter::Tcp,
+        Limiter::PasswordAttempt,
+        Limiter::Chat,
+        Limiter::CreateChannel,
+        Limiter::Prekey,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Limiter::UdpVoice => "udp_voice",
+            Limiter::UdpVideo => "udp_video",
+            Limiter::Tcp => "tcp",
+            Limiter::PasswordAttempt => "password_attempt",
+            Limiter::Chat => "chat",
+            Limiter::CreateChannel => "create_channel",
+            Limiter::Prekey => "prekey",
+        }
+    }
+
+    /// The limit as reported to clients.
+    pub fn kind(self) -> RateLimitKind {
+        match self {
+            Limiter::UdpVoice => RateLimitKind::Voice,
+            Limiter::UdpVideo => RateLimitKind::Video,
+            Limiter::Tcp => RateLimitKind::Messages,
+            Limiter::PasswordAttempt => RateLimitKind::PasswordAttempts,
+            Limiter::Chat => RateLimitKind::Chat,
+            Limiter::CreateChannel => RateLimitKind::CreateChannel,
+            Limiter::Prekey => RateLimitKind::PreKeys,
+        }
+    }
+}
+
+/// Why an authentication attempt was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFailure {
+    VersionMismatch,
+    InvalidUsername,
+    ServerFull,
+    BadSignature,
+    IdentityMismatch,
+    GuestsDisabled,
+    Banned,
+    UsernameTaken,
+    Timeout,
+}
+
+impl AuthFailure {
+    const ALL: [AuthFailure; 9] = [
+        AuthFailure::VersionMismatch,
+        AuthFailure::InvalidUsername,
+        AuthFailure::ServerFull,
+        AuthFailure::BadSignature,
+        AuthFailure::IdentityMismatch,
+        AuthFailure::GuestsDisabled,
+        AuthFailure::Banned,
+        AuthFailure::UsernameTaken,
+        AuthFailure::Timeout,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            AuthFailure::VersionMismatch => "version_mismatch",
+            AuthFailure::InvalidUsername => "invalid_username",
+            AuthFailure::ServerFull => "server_full",
+            AuthFailure::BadSignature => "bad_signature",
+            AuthFailure::IdentityMismatch => "identity_mismatch",
+            AuthFailure::GuestsDisabled => "guests_disabled",
+            AuthFailure::Banned => "banned",
+            AuthFailure::UsernameTaken => "username_taken",
+            AuthFailure::Timeout => "timeout",
+        }
+    }
+}
+
+/// Server-wide counters, exported in the Prometheus text format. Gauges such
+/// as session and channel member counts are read from the state at scrape time.
+#[derive(Default)]
+pub struct Metrics {
+    voice_packets: AtomicU64,
+    voice_bytes: AtomicU64,
+    video_packets: AtomicU64,
+    video_bytes: AtomicU64,
+    tls_handshake_failures: AtomicU64,
+    rate_limited: [AtomicU64; Limiter::ALL.len()],
+    auth_failures: [AtomicU64; AuthFailure::ALL.len()],
+}
+
+impl Metrics {
+    /// Count one voice packet forwarded to a recipient.
+    pub fn voice_forwarded(&self, bytes: usize) {
+        self.voice_packets.fetch_add(1, Ordering::Relaxed);
+        self.voice_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Count one video (or screen-share audio) packet forwarded to a viewer.
+    pub fn video_forwarded(&self, bytes: usize) {
+        self.video_packets.fetch_add(1, Ordering::Relaxed);
+        self.video_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Count a packet or message dropped by a rate limiter.
+    pub fn rate_limited(&self, limiter: Limiter) {
+        self.rate_limited[limiter as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn tls_handshake_failed(&self) {
+        self.tls_handshake_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn auth_failed(&self, reason: AuthFailure) {
+        self.auth_failures[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Render all metrics in the Prometheus text exposition format. Every series
+/// carries a `server` label naming its virtual server, except TLS handshake
+/// failures, which happen before a connection picks a server.
+pub async fn render(servers: &VirtualServers) -> String {
+    let mut snapshots = Vec::new();
+    for vs in servers.iter() {
+        let channels = vs.state.channels.read().await;
+        let mut members: Vec<_> = channels
+            .values()
+            .map(|ch| (ch.info.channel_id, ch.info.name.clone(), ch.members.len()))
+            .collect();
+        members.sort_by_key(|(id, _, _)| *id);
+        let shares: usize = channels.values().map(|ch| ch.screen_shares.len()).sum();
+        snapshots.push((escape_label(&vs.id), &vs.state, members, shares));
+    }
+    let load = |c: &AtomicU64| c.load(Ordering::Relaxed);
+    let mut out = String::new();
+
+    header(&mut out, "voipc_sessions", "gauge", "Connected sessions.");
+    for (server, state, _, _) in &snapshots {
+        let _ = writeln!(out, "voipc_sessions{{server=\"{}\"}} {}", server, state.user_count());
+    }
+
+    header(&mut out, "voipc_channel_members", "gauge", "Members per channel.");
+    for (server, _, members, _) in &snapshots {
+        for (id, name, count) in members {
+            let _ = writeln!(
+                out,
+                "voipc_channel_members{{server=\"{}\",channel_id=\"{}\",channel=\"{}\"}} {}",
+                server,
+                id,
+                escape_label(name),
+                count
+            );
+        }
+    }
+
+    header(&mut out, "voipc_screen_shares", "gauge", "Active screen shares.");
+    for (server, _, _, shares) in &snapshots {
+        let _ = writeln!(out, "voipc_screen_shares{{server=\"{}\"}} {}", server, shares);
+    }
+
+    header(
+        &mut out,
+        "voipc_udp_packets_forwarded_total",
+        "counter",
+        "UDP packets forwarded to recipients.",
+    );
+    for (server, state, _, _) in &snapshots {
+        let m = &state.metrics;
+        let _ = writeln!(out, "voipc_udp_packets_forwarded_total{{server=\"{}\",kind=\"voice\"}} {}", server, load(&m.voice_packets));
+        let _ = writeln!(out, "voipc_udp_packets_forwarded_total{{server=\"{}\",kind=\"video\"}} {}", server, load(&m.video_packets));
+    }
+    header(
+        &mut out,
+        "voipc_udp_bytes_forwarded_total",
+        "counter",
+        "UDP bytes forwarded to recipients.",
+    );
+    for (server, state, _, _) in &snapshots {
+        let m = &state.metrics;
+        let _ = writeln!(out, "voipc_udp_bytes_forwarded_total{{server=\"{}\",kind=\"voice\"}} {}", server, load(&m.voice_bytes));
+        let _ = writeln!(out, "voipc_udp_bytes_forwarded_total{{server=\"{}\",kind=\"video\"}} {}", server, load(&m.video_bytes));
+    }
+
+    header(
+        &mut out,
+        "voipc_rate_limited_total",
+        "counter",
+        "Packets and messages dropped by rate limiters.",
+    );
+    for (server, state, _, _) in &snapshots {
+        for limiter in Limiter::ALL {
+            let _ = writeln!(
+                out,
+                "voipc_rate_limited_total{{server=\"{}\",limiter=\"{}\"}} {}",
+                server,
+                limiter.label(),
+                load(&state.metrics.rate_limited[limiter as usize])
+            );
+        }
+    }
+
+    // Counted on the default server: the handshake fails before SNI or
+    // `Authenticate` can select another one
+    header(&mut out, "voipc_tls_handshake_failures_total", "counter", "Failed TLS handshakes.");
+    let tls_failures = load(&servers.default_state().metrics.tls_handshake_failures);
+    let _ = writeln!(out, "voipc_tls_handshake_failures_total {}", tls_failures);
+
+    header(&mut out, "voipc_auth_failures_total", "counter", "Rejected authentication attempts.");
+    for (server, state, _, _) in &snapshots {
+        for reason in AuthFailure::ALL {
+            let _ = writeln!(
+                out,
+                "voipc_auth_failures_total{{server=\"{}\",reason=\"{}\"}} {}",
+                server,
+                reason.label(),
+                load(&state.metrics.auth_failures[reason as usize])
+            );
+        }
+    }
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escape a label value (backslash, double quote and newline).
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve `GET /metrics` over plain HTTP until the listener fails.
+pub async fn run_metrics_listener(listener: TcpListener, servers: Arc<VirtualServers>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let servers = servers.clone();
+                tokio::spawn(async move {
+                    let result =
+                        tokio::time::timeout(Duration::from_secs(5), serve(stream, &servers)).await;
+                    if !matches!(result, Ok(Ok(()))) {
+                        debug!(%peer, "metrics request failed");
+                    }
+                });
+            }
+            Err(e) => {
+                warn!("metrics accept error: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+async fn serve(mut stream: TcpStream, servers: &VirtualServers) -> std::io::Result<()> {
+    let mut head = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 || head.len() + n > MAX_REQUEST_HEAD {
+            return Ok(());
+        }
+        head.extend_from_slice(&chunk[..n]);
+    }
+
+    let request_line = String::from_utf8_lossy(&head);
+    let (status, content_type, body) = if is_metrics_request(&request_line) {
+        ("200 OK", "text/plain; version=0.0.4", render(servers).await)
+    } else {
+        ("404 Not Found", "text/plain", "not found\n".to_string())
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Whether the request line is `GET /metrics` (query string allowed).
+fn is_metrics_request(head: &str) -> bool {
+    let mut parts = head.lines().next().unwrap_or("").split_whitespace();
+    let (Some("GET"), Some(target)) = (parts.next(), parts.next()) else {
+        return false;
+    };
+    target.split('?').next() == Some("/metrics")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::accounts::AccountStore;
+    use crate::bans::BanStore;
+    use crate::config::{ServerConfig, VirtualServerConfig};
+    use crate::settings::ServerSettings;
+    use crate::state::ServerState;
+
+    fn empty_state() -> ServerState {
+        ServerState::new(
+            &ServerConfig::default(),
+            ServerSettings::default(),
+            Vec::new(),
+            AccountStore::default(),
+            BanStore::default(),
+            None,
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn render_includes_counters_and_channels() {
+        let servers = VirtualServers::new(empty_state());
+        let state = servers.default_state();
+        state.create_channel("Say \"hi\"".into(), None, 1, None).await.unwrap();
+        state.metrics.voice_forwarded(100);
+        state.metrics.voice_forwarded(50);
+        state.metrics.rate_limited(Limiter::Chat);
+        state.metrics.auth_failed(AuthFailure::ServerFull);
+        state.metrics.tls_handshake_failed();
+
+        let out = render(&servers).await;
+        assert!(out.contains("voipc_sessions{server=\"default\"} 0\n"));
+        assert!(out.contains("voipc_channel_members{server=\"default\",channel_id=\"0\",channel=\"General\"} 0\n"));
+        assert!(out.contains("channel=\"Say \\\"hi\\\"\"} 0\n"));
+        assert!(out.contains("voipc_udp_packets_forwarded_total{server=\"default\",kind=\"voice\"} 2\n"));
+        assert!(out.contains("voipc_udp_bytes_forwarded_total{server=\"default\",kind=\"voice\"} 150\n"));
+        assert!(out.contains("voipc_rate_limited_total{server=\"default\",limiter=\"chat\"} 1\n"));
+        assert!(out.contains("voipc_rate_limited_total{server=\"default\",limiter=\"tcp\"} 0\n"));
+        assert!(out.contains("voipc_tls_handshake_failures_total 1\n"));
+        assert!(out.contains("voipc_auth_failures_total{server=\"default\",reason=\"server_full\"} 1\n"));
+    }
+
+    #[tokio::test]
+    async fn render_labels_every_virtual_server() {
+        let mut servers = VirtualServers::new(empty_state());
+        let config = VirtualServerConfig {
+            id: "gaming".into(),
+            hostnames: Vec::new(),
+            data_dir: String::new(),
+            max_users: None,
+            cert_path: None,
+            key_path: None,
+        };
+        servers.add(&config, empty_state());
+        let gaming = servers.by_id("gaming").unwrap();
+        gaming.metrics.video_forwarded(10);
+        gaming.metrics.auth_failed(AuthFailure::Banned);
+
+        let out = render(&servers).await;
+        assert!(out.contains("voipc_sessions{server=\"gaming\"} 0\n"));
+        assert!(out.contains("voipc_channel_members{server=\"gaming\",channel_id=\"0\",channel=\"General\"} 0\n"));
+        assert!(out.contains("voipc_udp_packets_forwarded_total{server=\"gaming\",kind=\"video\"} 1\n"));
+        assert!(out.contains("voipc_udp_packets_forwarded_total{server=\"default\",kind=\"video\"} 0\n"));
+        assert!(out.contains("voipc_auth_failures_total{server=\"gaming\",reason=\"banned\"} 1\n"));
+        assert!(out.contains("voipc_auth_failures_total{server=\"default\",reason=\"banned\"} 0\n"));
+        assert_eq!(out.matches("# TYPE voipc_sessions gauge").count(), 1);
+    }
+
+    #[test]
+    fn metrics_request_matching() {
+        assert!(is_metrics_request("GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n"));
+        assert!(is_metrics_request("GET /metrics?x=1 HTTP/1.0\r\n\r\n"));
+        assert!(!is_metrics_request("POST /metrics HTTP/1.1\r\n\r\n"));
+        assert!(!is_metrics_request("GET / HTTP/1.1\r\n\r\n"));
+    }
+}
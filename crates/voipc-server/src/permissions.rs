@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+
+use voipc_protocol::types::{Permissions, ServerGroup};
+
+/// A single permission, used to query a `Permissions` set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Join,
+    Speak,
+    ScreenShare,
+    CreateChannel,
+    Kick,
+    Move,
+    Edit,
+    Ban,
+    CreatePermanentChannel,
+}
+
+impl Permission {
+    /// Whether `perms` grants this permission.
+    pub fn allowed_by(self, perms: &Permissions) -> bool {
+        match self {
+            Permission::Join => perms.join,
+            Permission::Speak => perms.speak,
+            Permission::ScreenShare => perms.screen_share,
+            Permission::CreateChannel => perms.create_channel,
+            Permission::Kick => perms.kick,
+            Permission::Move => perms.move_users,
+            Permission::Edit => perms.edit,
+            Permission::Ban => perms.ban,
+            Permission::CreatePermanentChannel => perms.create_permanent_channel,
+        }
+    }
+}
+
+/// Per-channel adjustment of a group's permissions. `None` inherits the
+/// server-wide value, `Some` replaces it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PermissionOverride {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub join: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speak: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen_share: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kick: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub move_users: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edit: Option<bool>,
+}
+
+impl PermissionOverride {
+    /// Apply this override on top of `perms`.
+    pub fn apply(&self, perms: &mut Permissions) {
+        if let Some(v) = self.join {
+            perms.join = v;
+        }
+        if let Some(v) = self.speak {
+            perms.speak = v;
+        }
+        if let Some(v) = self.screen_share {
+            perms.screen_share = v;
+        }
+        if let Some(v) = self.kick {
+            perms.kick = v;
+        }
+        if let Some(v) = self.move_users {
+            perms.move_users = v;
+        }
+        if let Some(v) = self.edit {
+            perms.edit = v;
+        }
+    }
+}
+
+/// Built-in server-wide permissions for a group, used when the settings file
+/// doesn't configure that group.
+///
+/// Guests and members keep the pre-permission behaviour (anyone may join,
+/// talk, share and create channels); moderators and admins can additionally
+/// kick, move, edit and ban everywhere, and create permanent channels.
+pub fn default_group_permissions(group: ServerGroup) -> Permissions {
+    let everyone = Permissions {
+        join: true,
+        speak: true,
+        screen_share: true,
+        create_channel: true,
+        ..Default::default()
+    };
+    match group {
+        ServerGroup::Admin | ServerGroup::Moderator => Permissions {
+            kick: true,
+            move_users: true,
+            edit: true,
+            ban: true,
+            create_permanent_channel: true,
+            ..everyone
+        },
+        ServerGroup::Member | ServerGroup::Guest => everyone,
+    }
+}
+
+/// Extra rights the creator of a user-created channel holds inside it.
+pub fn apply_creator_grants(perms: &mut Permissions) {
+    perms.join = true;
+    perms.kick = true;
+    perms.edit = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_preserve_open_server() {
+        let guest = default_group_permissions(ServerGroup::Guest);
+        assert!(guest.join && guest.speak && guest.screen_share && guest.create_channel);
+        assert!(!guest.kick && !guest.move_users && !guest.edit && !guest.ban);
+        assert!(!guest.create_permanent_channel);
+        assert_eq!(guest, default_group_permissions(ServerGroup::Member));
+    }
+
+    #[test]
+    fn moderators_can_manage() {
+        let moderator = default_group_permissions(ServerGroup::Moderator);
+        assert!(moderator.kick && moderator.move_users && moderator.edit && moderator.ban);
+        assert!(moderator.create_permanent_channel);
+    }
+
+    #[test]
+    fn override_replaces_only_set_fields() {
+        let mut perms = default_group_permissions(ServerGroup::Guest);
+        let ov = PermissionOverride {
+            speak: Some(false),
+            kick: Some(true),
+            ..Default::default()
+        };
+        ov.apply(&mut perms);
+        assert!(!perms.speak);
+        assert!(perms.kick);
+        assert!(perms.join);
+        assert!(perms.screen_share);
+    }
+
+    #[test]
+    fn override_json_omits_unset_fields() {
+        let ov: PermissionOverride = serde_json::from_str(r#"{"speak": false}"#).unwrap();
+        assert_eq!(ov.speak, Some(false));
+        assert_eq!(ov.join, None);
+        assert_eq!(serde_json::to_string(&ov).unwrap(), r#"{"speak":false}"#);
+    }
+
+    #[test]
+    fn permission_allowed_by() {
+        let perms = Permissions {
+            edit: true,
+            ..Default::default()
+        };
+        assert!(Permission::Edit.allowed_by(&perms));
+        assert!(!Permission::Kick.allowed_by(&perms));
+    }
+}
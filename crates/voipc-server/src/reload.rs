@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use voipc_protocol::messages::ServerMessage;
+
+use crate::channels::load_and_prepare_channels;
+use crate::settings::ServerSettings;
+use crate::state::ServerState;
+use crate::tcp;
+
+/// Serializes reloads triggered by SIGHUP and the admin socket.
+static RELOAD_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// What a reload changed.
+#[derive(Debug, Default, Serialize)]
+pub struct ReloadSummary {
+    pub channels_created: usize,
+    pub channels_updated: usize,
+    pub channels_retired: usize,
+    /// Channel entries not applied because a user-created channel has the name.
+    pub channels_skipped: Vec<String>,
+}
+
+/// Re-read the settings and persistent channel files and apply them to the
+/// running server. Both files are loaded and validated before anything is
+/// changed; a missing file means defaults / no persistent channels, as at
+/// startup.
+pub async fn reload(state: &Arc<ServerState>) -> Result<ReloadSummary> {
+    let _guard = RELOAD_LOCK.lock().await;
+
+    let settings = match &state.settings_path {
+        Some(path) if path.exists() => ServerSettings::load_from_file(path)
+            .with_context(|| format!("failed to load settings: {}", path.display()))?,
+        _ => ServerSettings::default(),
+    };
+    let entries = match &state.channels_path {
+        Some(path) if path.exists() => load_and_prepare_channels(path)
+            .with_context(|| format!("failed to load channels: {}", path.display()))?,
+        _ => Vec::new(),
+    };
+
+    state.set_settings(settings);
+    let changes = state.reload_persistent_channels(&entries).await;
+
+    for channel in &changes.created {
+        tcp::broadcast_to_all(state, &ServerMessage::ChannelCreated { channel: channel.clone() }, None).await;
+    }
+    for channel in &changes.updated {
+        tcp::broadcast_to_all(state, &ServerMessage::ChannelUpdated { channel: channel.clone() }, None).await;
+    }
+    for &channel_id in &changes.retired {
+        if let Err(e) = tcp::delete_channel(state, channel_id, "reload").await {
+            warn!(channel_id, "failed to retire channel: {}", e);
+        }
+    }
+    for name in &changes.skipped {
+        warn!(name = %name, "persistent channel not applied: a user-created channel has that name");
+    }
+
+    // Group permissions and channel overrides may have changed
+    state.refresh_permissions().await;
+    tcp::send_permissions_to_all(state).await;
+
+    let summary = ReloadSummary {
+        channels_created: changes.created.len(),
+        channels_updated: changes.updated.len(),
+        channels_retired: changes.retired.len(),
+        channels_skipped: changes.skipped,
+    };
+    info!(
+        created = summary.channels_created,
+        updated = summary.channels_updated,
+        retired = summary.channels_retired,
+        skipped = summary.channels_skipped.len(),
+        "reloaded settings and persistent channels"
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::accounts::AccountStore;
+    use crate::bans::BanStore;
+    use crate::channels::{verify_password, ChannelEntry};
+    use crate::config::ServerConfig;
+
+    fn entry(name: &str, max_users: u32) -> ChannelEntry {
+        ChannelEntry {
+            name: name.into(),
+            description: String::new(),
+            password: None,
+            password_hash: None,
+            max_users,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn reload_adds_updates_and_retires_channels() {
+        let dir = std::env::temp_dir().join(format!("voipc-reload-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let channels_path = dir.join("channels.json");
+        let settings_path = dir.join("server_settings.json");
+
+        let state = Arc::new(ServerState::new(
+            &ServerConfig::default(),
+            ServerSettings::default(),
+            vec![entry("Music", 0), entry("Games", 0)],
+            AccountStore::default(),
+            BanStore::default(),
+            Some(channels_path.clone()),
+            Some(settings_path.clone()),
+        ));
+        state.create_channel("Mine".into(), None, 1, None).await.unwrap();
+
+        std::fs::write(
+            &channels_path,
+            r#"[{"name": "Music", "max_users": 5, "password": "pw"},
+                {"name": "Lounge"},
+                {"name": "mine"}]"#,
+        )
+        .unwrap();
+        std::fs::write(&settings_path, r#"{"max_channels": 7}"#).unwrap();
+
+        let summary = reload(&state).await.unwrap();
+        assert_eq!(summary.channels_created, 1);
+        assert_eq!(summary.channels_updated, 1);
+        assert_eq!(summary.channels_retired, 1);
+        assert_eq!(summary.channels_skipped, vec!["mine".to_string()]);
+        assert_eq!(state.settings().max_channels, 7);
+
+        let channels = state.channel_list().await;
+        let names: Vec<&str> = channels.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["General", "Music", "Mine", "Lounge"]);
+        let music = &channels[1];
+        assert_eq!(music.channel_id, 1);
+        assert_eq!(music.max_users, 5);
+        assert!(music.has_password);
+        {
+            let live = state.channels.read().await;
+            let hash = live[&1].password.as_ref().unwrap();
+            assert!(verify_password("pw", hash));
+        }
+
+        // A second reload of the same files changes nothing
+        let summary = reload(&state).await.unwrap();
+        assert_eq!(summary.channels_created + summary.channels_updated + summary.channels_retired, 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
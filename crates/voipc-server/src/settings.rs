@@ -1,6 +1,13 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::Path;
 
+use voipc_protocol::types::{Permissions, ServerGroup};
+
+use crate::metrics::Limiter;
+use crate::permissions::default_group_permissions;
+use crate::state::RateLimiter;
+
 /// Runtime server settings, loaded from a JSON file.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerSettings {
@@ -15,6 +22,107 @@ pub struct ServerSettings {
     /// Maximum channel name length.
     #[serde(default = "default_max_channel_name_len")]
     pub max_channel_name_len: usize,
+
+    /// Allow users whose name is not a registered account to connect.
+    /// When false, only registered accounts (verified by identity key) may log in.
+    #[serde(default = "default_allow_guests")]
+    pub allow_guests: bool,
+
+    /// Server-wide permissions per group. Groups not listed here use the
+    /// built-in defaults; a listed group replaces them entirely.
+    #[serde(default)]
+    pub group_permissions: HashMap<ServerGroup, Permissions>,
+
+    /// Message of the day, sent to every user when they connect (empty = none).
+    #[serde(default)]
+    pub motd: String,
+
+    /// Name of the channel idle users are moved to (None = AFK detection off).
+    #[serde(default)]
+    pub afk_channel: Option<String>,
+
+    /// Seconds without voice or user activity before a user counts as idle.
+    #[serde(default = "default_afk_timeout")]
+    pub afk_timeout_secs: u64,
+
+    /// Idle users who come back within this many seconds of being moved are
+    /// returned to the channel they were in.
+    #[serde(default = "default_afk_return_grace")]
+    pub afk_return_grace_secs: u64,
+
+    /// Seconds a session is kept after its connection drops, so the client
+    /// can resume it without leaving its channel (0 = no resumption).
+    #[serde(default = "default_resume_grace")]
+    pub resume_grace_secs: u64,
+
+    /// Per-session rate limits, applied to sessions that connect afterwards.
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+}
+
+/// A token bucket: up to `burst` at once, refilled at `per_sec`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub burst: f64,
+    pub per_sec: f64,
+}
+
+impl RateLimit {
+    const fn new(burst: f64, per_sec: f64) -> Self {
+        Self { burst, per_sec }
+    }
+
+    pub fn limiter(&self) -> RateLimiter {
+        RateLimiter::new(self.burst, self.per_sec)
+    }
+}
+
+/// Rate limits for each session. Omitted entries keep their defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimits {
+    /// UDP voice packets (50 fps + margin).
+    pub udp_voice: RateLimit,
+    /// UDP video packets (60 fps × 2 fragments on average).
+    pub udp_video: RateLimit,
+    /// All TCP control messages.
+    pub tcp: RateLimit,
+    /// Channel password attempts.
+    pub password_attempt: RateLimit,
+    /// Channel and direct chat messages.
+    pub chat: RateLimit,
+    /// Channel creation.
+    pub create_channel: RateLimit,
+    /// Pre-key uploads.
+    pub prekey: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            udp_voice: RateLimit::new(55.0, 55.0),
+            udp_video: RateLimit::new(120.0, 120.0),
+            tcp: RateLimit::new(50.0, 50.0),
+            password_attempt: RateLimit::new(3.0, 1.0),
+            chat: RateLimit::new(5.0, 5.0),
+            create_channel: RateLimit::new(1.0, 0.2),
+            prekey: RateLimit::new(1.0, 0.2),
+        }
+    }
+}
+
+impl RateLimits {
+    pub fn get(&self, limiter: Limiter) -> RateLimit {
+        match limiter {
+            Limiter::UdpVoice => self.udp_voice,
+            Limiter::UdpVideo => self.udp_video,
+            Limiter::Tcp => self.tcp,
+            Limiter::PasswordAttempt => self.password_attempt,
+            Limiter::Chat => self.chat,
+            Limiter::CreateChannel => self.create_channel,
+            Limiter::Prekey => self.prekey,
+        }
+    }
 }
 
 fn default_empty_channel_timeout() -> u64 {
@@ -26,6 +134,18 @@ fn default_max_channels() -> u32 {
 fn default_max_channel_name_len() -> usize {
     32
 }
+fn default_allow_guests() -> bool {
+    true
+}
+fn default_afk_timeout() -> u64 {
+    1800
+}
+fn default_afk_return_grace() -> u64 {
+    600
+}
+fn default_resume_grace() -> u64 {
+    60
+}
 
 impl Default for ServerSettings {
     fn default() -> Self {
@@ -33,6 +153,14 @@ impl Default for ServerSettings {
             empty_channel_timeout_secs: default_empty_channel_timeout(),
             max_channels: default_max_channels(),
             max_channel_name_len: default_max_channel_name_len(),
+            allow_guests: default_allow_guests(),
+            group_permissions: HashMap::new(),
+            motd: String::new(),
+            afk_channel: None,
+            afk_timeout_secs: default_afk_timeout(),
+            afk_return_grace_secs: default_afk_return_grace(),
+            resume_grace_secs: default_resume_grace(),
+            rate_limits: RateLimits::default(),
         }
     }
 }
@@ -40,7 +168,31 @@ impl Default for ServerSettings {
 impl ServerSettings {
     pub fn load_from_file(path: &Path) -> anyhow::Result<Self> {
         let content = std::fs::read_to_string(path)?;
-        Ok(serde_json::from_str(&content)?)
+        let settings: Self = serde_json::from_str(&content)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for limiter in Limiter::ALL {
+            let limit = self.rate_limits.get(limiter);
+            // A bucket smaller than one token would never admit anything
+            if !(limit.burst >= 1.0 && limit.burst.is_finite()) {
+                anyhow::bail!("rate limit {}: burst must be at least 1", limiter.label());
+            }
+            if !(limit.per_sec > 0.0 && limit.per_sec.is_finite()) {
+                anyhow::bail!("rate limit {}: per_sec must be positive", limiter.label());
+            }
+        }
+        Ok(())
+    }
+
+    /// Server-wide permissions for a group.
+    pub fn permissions_for_group(&self, group: ServerGroup) -> Permissions {
+        self.group_permissions
+            .get(&group)
+            .copied()
+            .unwrap_or_else(|| default_group_permissions(group))
     }
 }
 
@@ -54,6 +206,29 @@ mod tests {
         assert_eq!(settings.empty_channel_timeout_secs, 300);
         assert_eq!(settings.max_channels, 50);
         assert_eq!(settings.max_channel_name_len, 32);
+        assert!(settings.allow_guests);
+        assert!(settings.motd.is_empty());
+        assert!(settings.afk_channel.is_none());
+        assert_eq!(settings.afk_timeout_secs, 1800);
+        assert_eq!(settings.afk_return_grace_secs, 600);
+        assert_eq!(settings.resume_grace_secs, 60);
+        assert_eq!(settings.rate_limits.password_attempt, RateLimit::new(3.0, 1.0));
+    }
+
+    #[test]
+    fn rate_limits_are_partially_overridable_and_validated() {
+        let json = r#"{ "rate_limits": { "chat": { "burst": 10, "per_sec": 2 } } }"#;
+        let settings: ServerSettings = serde_json::from_str(json).unwrap();
+        assert_eq!(settings.rate_limits.chat, RateLimit::new(10.0, 2.0));
+        assert_eq!(settings.rate_limits.tcp, RateLimit::new(50.0, 50.0));
+        assert!(settings.validate().is_ok());
+
+        let json = r#"{ "rate_limits": { "tcp": { "burst": 0.5, "per_sec": 2 } } }"#;
+        let settings: ServerSettings = serde_json::from_str(json).unwrap();
+        assert!(settings.validate().is_err());
+        let json = r#"{ "rate_limits": { "prekey": { "burst": 1, "per_sec": 0 } } }"#;
+        let settings: ServerSettings = serde_json::from_str(json).unwrap();
+        assert!(settings.validate().is_err());
     }
 
     #[test]
@@ -67,5 +242,29 @@ mod tests {
         assert_eq!(settings.empty_channel_timeout_secs, 600);
         assert_eq!(settings.max_channels, 100);
         assert_eq!(settings.max_channel_name_len, 64);
+        assert!(settings.allow_guests);
+    }
+
+    #[test]
+    fn settings_guests_can_be_disabled() {
+        let json = r#"{ "allow_guests": false }"#;
+        let settings: ServerSettings = serde_json::from_str(json).unwrap();
+        assert!(!settings.allow_guests);
+        assert_eq!(settings.max_channels, 50);
+    }
+
+    #[test]
+    fn group_permissions_override_defaults() {
+        let json = r#"{ "group_permissions": { "guest": { "join": true } } }"#;
+        let settings: ServerSettings = serde_json::from_str(json).unwrap();
+        let guest = settings.permissions_for_group(ServerGroup::Guest);
+        assert!(guest.join);
+        assert!(!guest.speak);
+        assert!(!guest.create_channel);
+        // Unlisted groups keep the built-in defaults
+        assert_eq!(
+            settings.permissions_for_group(ServerGroup::Admin),
+            default_group_permissions(ServerGroup::Admin)
+        );
     }
 }
@@ -0,0 +1,233 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::sync::{watch, Notify};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use voipc_protocol::messages::ServerMessage;
+
+use crate::config::ServerConfig;
+use crate::virtual_server::VirtualServers;
+
+/// How often a drain checks whether the clients have left.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Time the writer tasks get to send `ServerShutdown` to clients that are
+/// still connected when the drain ends.
+const FLUSH_DELAY: Duration = Duration::from_millis(500);
+
+/// A request to shut the server down.
+#[derive(Debug, Clone, Default)]
+pub struct DrainRequest {
+    pub reason: String,
+    /// How long clients get to disconnect on their own.
+    pub timeout: Duration,
+    /// Server ("host:port") clients should move to.
+    pub reconnect_to: Option<String>,
+}
+
+/// Shutdown trigger shared by the signal handler, the admin socket and the
+/// accept loop. Only the first request counts; a forced shutdown cuts a
+/// running drain short.
+#[derive(Clone)]
+pub struct Shutdown {
+    request: Arc<watch::Sender<Option<DrainRequest>>>,
+    force: Arc<Notify>,
+    /// Used when a request doesn't give its own.
+    timeout: Duration,
+    reconnect_to: Option<String>,
+}
+
+impl Shutdown {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            request: Arc::new(watch::Sender::new(None)),
+            force: Arc::new(Notify::new()),
+            timeout: Duration::from_secs(config.shutdown_timeout_secs),
+            reconnect_to: config.shutdown_reconnect_to.clone(),
+        }
+    }
+
+    /// Start a shutdown, with the configured timeout and reconnect hint
+    /// unless given. Returns the request, or None if a shutdown is already
+    /// under way.
+    pub fn request(
+        &self,
+        reason: String,
+        timeout: Option<Duration>,
+        reconnect_to: Option<String>,
+    ) -> Option<DrainRequest> {
+        let request = DrainRequest {
+            reason,
+            timeout: timeout.unwrap_or(self.timeout),
+            reconnect_to: reconnect_to.or_else(|| self.reconnect_to.clone()),
+        };
+        let started = self.request.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(request.clone());
+            true
+        });
+        started.then_some(request)
+    }
+
+    /// Wait until a shutdown is requested.
+    pub async fn requested(&self) -> DrainRequest {
+        let mut rx = self.request.subscribe();
+        // Cannot fail while `self` holds the sender
+        let request = rx.wait_for(Option::is_some).await.map(|r| r.clone());
+        request.ok().flatten().unwrap_or_default()
+    }
+
+    /// Stop draining and close the remaining connections now.
+    pub fn force(&self) {
+        self.force.notify_one();
+    }
+
+    async fn forced(&self) {
+        self.force.notified().await;
+    }
+}
+
+/// Turn SIGINT (Ctrl+C) and, on Unix, SIGTERM into a shutdown request. A
+/// second signal forces the shutdown.
+pub fn spawn_signal_handler(shutdown: Shutdown) -> Result<()> {
+    #[cfg(unix)]
+    let mut terminate = {
+        use anyhow::Context;
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .context("failed to install SIGTERM handler")?
+    };
+
+    tokio::spawn(async move {
+        loop {
+            #[cfg(unix)]
+            let signal = tokio::select! {
+                Ok(()) = tokio::signal::ctrl_c() => "SIGINT",
+                _ = terminate.recv() => "SIGTERM",
+            };
+            #[cfg(not(unix))]
+            let signal = match tokio::signal::ctrl_c().await {
+                Ok(()) => "Ctrl+C",
+                Err(_) => return,
+            };
+
+            if shutdown.request("server shutting down".into(), None, None).is_some() {
+                info!("{} received, shutting down", signal);
+            } else {
+                warn!("{} received again, closing remaining connections", signal);
+                shutdown.force();
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Tell every client the server is going away, then wait until they have
+/// all disconnected, the request's timeout passes or the shutdown is forced.
+/// Clients still connected at the end get a moment to receive the notice.
+/// New connections must no longer be accepted.
+pub async fn drain(servers: &VirtualServers, request: &DrainRequest, shutdown: &Shutdown) {
+    let msg = ServerMessage::ServerShutdown {
+        reason: request.reason.clone(),
+        shutdown_in_secs: request.timeout.as_secs().try_into().unwrap_or(u32::MAX),
+        reconnect_to: request.reconnect_to.clone(),
+    };
+    if let Ok(data) = voipc_protocol::codec::encode_server_msg(&msg) {
+        for vs in servers.iter() {
+            vs.state.broadcast_raw_to_all(&data).await;
+        }
+    }
+
+    let connected = wait_for_clients(servers, request.timeout, shutdown).await;
+    if connected > 0 {
+        tokio::time::sleep(FLUSH_DELAY).await;
+    }
+}
+
+/// Wait until every client has disconnected, `timeout` passes or the
+/// shutdown is forced. Returns how many are still connected.
+async fn wait_for_clients(servers: &VirtualServers, timeout: Duration, shutdown: &Shutdown) -> usize {
+    let deadline = Instant::now() + timeout;
+    info!(timeout_secs = timeout.as_secs(), "waiting for clients to disconnect");
+    loop {
+        // Suspended sessions have already lost their connection
+        let connected: usize = servers.iter().map(|vs| vs.state.connected_count()).sum();
+        if connected == 0 {
+            info!("all clients disconnected");
+            return 0;
+        }
+        if Instant::now() >= deadline {
+            info!(connected, "shutdown timeout reached, closing remaining connections");
+            return connected;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            _ = shutdown.forced() => {
+                info!(connected, "shutdown forced, closing remaining connections");
+                return connected;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::accounts::AccountStore;
+    use crate::bans::BanStore;
+    use crate::settings::ServerSettings;
+    use crate::state::ServerState;
+
+    fn make_servers() -> VirtualServers {
+        VirtualServers::new(ServerState::new(
+            &ServerConfig::default(),
+            ServerSettings::default(),
+            Vec::new(),
+            AccountStore::default(),
+            BanStore::default(),
+            None,
+            None,
+        ))
+    }
+
+    #[tokio::test]
+    async fn first_request_wins() {
+        let config = ServerConfig {
+            shutdown_reconnect_to: Some("backup.example.com:9987".into()),
+            ..ServerConfig::default()
+        };
+        let shutdown = Shutdown::new(&config);
+        let waiter = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.requested().await }
+        });
+
+        let started = shutdown.request("maintenance".into(), Some(Duration::from_secs(10)), None);
+        assert!(started.is_some());
+        assert!(shutdown.request("again".into(), None, None).is_none());
+
+        let request = waiter.await.unwrap();
+        assert_eq!(request.reason, "maintenance");
+        assert_eq!(request.timeout, Duration::from_secs(10));
+        assert_eq!(request.reconnect_to.as_deref(), Some("backup.example.com:9987"));
+        // Later callers see the same request
+        assert_eq!(shutdown.requested().await.reason, "maintenance");
+    }
+
+    #[tokio::test]
+    async fn drain_ends_once_nobody_is_connected() {
+        let servers = make_servers();
+        let request = DrainRequest {
+            timeout: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        drain(&servers, &request, &Shutdown::new(&ServerConfig::default())).await;
+        assert!(started.elapsed() < POLL_INTERVAL);
+    }
+}
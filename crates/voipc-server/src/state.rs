@@ -1,19 +1,34 @@
 use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use dashmap::DashMap;
 use subtle::ConstantTimeEq;
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
+use tokio::task::JoinHandle;
 use zeroize::Zeroizing;
 
+use voipc_protocol::codec::encode_server_msg;
+use voipc_protocol::messages::ServerMessage;
 use voipc_protocol::types::*;
 
-use crate::channels::ChannelEntry;
+use crate::accounts::AccountStore;
+use crate::audit::AuditLog;
+use crate::bans::{Ban, BanStore};
+use crate::channels::{ChannelEntry, MAX_CHANNEL_DEPTH};
+use crate::permissions::{apply_creator_grants, Permission, PermissionOverride};
 use crate::config::ServerConfig;
+use crate::metrics::{Limiter, Metrics};
 use crate::settings::ServerSettings;
 
+/// Minimum time between `RateLimited` notices to one session for one limiter.
+const RATE_LIMIT_NOTICE_INTERVAL: Duration = Duration::from_secs(1);
+/// Length of a session resume token, in bytes.
+pub const RESUME_TOKEN_LEN: usize = 32;
+
 /// Simple token-bucket rate limiter.
 pub struct RateLimiter {
     tokens: f64,
@@ -46,6 +61,25 @@ impl RateLimiter {
             false
         }
     }
+
+    /// Time until the next token is available.
+    pub fn retry_after(&self) -> Duration {
+        let missing = (1.0 - self.tokens).max(0.0);
+        Duration::from_secs_f64(missing / self.refill_rate)
+    }
+}
+
+/// Where a session is between login and removal.
+#[derive(Debug, Default)]
+pub enum SessionState {
+    /// Attached to an open connection.
+    #[default]
+    Live,
+    /// The connection was lost. The timer ends the session when the resume
+    /// grace period runs out.
+    Suspended(JoinHandle<()>),
+    /// Being removed. It can no longer be resumed.
+    CleaningUp,
 }
 
 /// Per-user session state held by the server.
@@ -57,21 +91,38 @@ pub struct UserSession {
     pub channel_id: ChannelId,
     pub is_muted: bool,
     pub is_deafened: bool,
+    /// Server group (from the account store; guests if unregistered).
+    pub group: ServerGroup,
+    /// Effective permissions in the current channel (recomputed on join).
+    pub permissions: Permissions,
     /// Sender for pushing TCP control messages to this user's writer task.
     pub tcp_tx: tokio::sync::mpsc::Sender<Vec<u8>>,
+    /// Signalled to make the connection task close this session (e.g. on ban).
+    pub disconnect: Arc<Notify>,
+    /// Secret the client presents to resume this session on a new connection.
+    pub resume_token: [u8; RESUME_TOKEN_LEN],
+    /// Whether the session is live, waiting to be resumed, or on its way
+    /// out.
+    pub state: SessionState,
+    /// Counts the connections this session has been resumed on, so a
+    /// connection that was taken over knows not to end the session.
+    pub connection: u64,
+    /// Optional features negotiated with the client on its current
+    /// connection.
+    pub capabilities: Capabilities,
     /// The user's UDP source address (learned from their first UDP packet).
     pub udp_addr: Option<SocketAddr>,
     /// Random token for authenticating UDP voice packets.
     pub udp_token: u64,
     /// IP address from TCP authentication (for UDP source verification).
     pub tcp_peer_ip: IpAddr,
-    /// Rate limiter for UDP voice packets (55 pkt/s — 50fps + margin).
+    /// Rate limiter for UDP voice packets.
     pub udp_voice_rate: RateLimiter,
-    /// Rate limiter for UDP video packets (120 pkt/s — 60fps × 2 fragments avg).
+    /// Rate limiter for UDP video packets.
     pub udp_video_rate: RateLimiter,
-    /// Global rate limiter for all TCP messages (50 msg/s burst).
+    /// Global rate limiter for all TCP messages.
     pub global_rate: RateLimiter,
-    /// Rate limiter for channel password attempts (3 burst, 1/s refill).
+    /// Rate limiter for channel password attempts.
     pub password_attempt_rate: RateLimiter,
     /// Rate limiter for chat messages (channel + DM).
     pub chat_rate: RateLimiter,
@@ -79,14 +130,31 @@ pub struct UserSession {
     pub create_channel_rate: RateLimiter,
     /// Rate limiter for pre-key uploads.
     pub prekey_rate: RateLimiter,
+    /// When this session was last sent `RateLimited`, per `Limiter`.
+    pub last_rate_limit_notice: [Option<Instant>; Limiter::ALL.len()],
     /// Whether this user is currently screen sharing.
     pub is_screen_sharing: bool,
     /// The user_id of the screenshare this user is currently watching (if any).
     pub watching_screenshare: Option<UserId>,
+    /// Whisper targets and key (None = no whisper list set).
+    pub whisper: Option<WhisperList>,
+    /// Muted by a moderator — the UDP loop drops this user's voice.
+    pub is_server_muted: bool,
+    /// Voice is forwarded marked as priority, so receivers duck other speakers.
+    pub is_priority_speaker: bool,
+    /// When this user last sent voice.
+    pub last_voice: Instant,
+    /// When this user last sent a control message (keepalives and automatic
+    /// key exchange don't count).
+    pub last_activity: Instant,
+    /// Set while the user is idle.
+    pub idle: Option<IdleState>,
 
     // ── E2E Encryption fields ─────────────────────────────────────────
-    /// Client's long-term identity public key (Curve25519). Opaque to server.
+    /// Client's long-term identity public key (Curve25519).
     pub identity_key: Option<Vec<u8>>,
+    /// Whether the client proved possession of `identity_key` during auth.
+    pub identity_verified: bool,
     /// Available one-time pre-keys (consumed when another user requests a bundle).
     pub prekeys: Vec<OneTimePreKey>,
     /// Current signed pre-key data.
@@ -99,6 +167,23 @@ pub struct UserSession {
     pub device_id: u32,
 }
 
+impl UserSession {
+    /// Public view of this user, as sent in user lists.
+    pub fn user_info(&self) -> UserInfo {
+        UserInfo {
+            user_id: self.user_id,
+            username: self.username.clone(),
+            channel_id: self.channel_id,
+            is_muted: self.is_muted,
+            is_deafened: self.is_deafened,
+            is_screen_sharing: self.is_screen_sharing,
+            is_server_muted: self.is_server_muted,
+            is_priority_speaker: self.is_priority_speaker,
+            is_idle: self.idle.is_some(),
+        }
+    }
+}
+
 /// Tracks an active screen share session within a channel.
 #[allow(dead_code)]
 pub struct ScreenShareSession {
@@ -110,13 +195,43 @@ pub struct ScreenShareSession {
     pub resolution: u16,
 }
 
+/// Maximum number of entries in a whisper list.
+pub const MAX_WHISPER_TARGETS: usize = 32;
+
+/// A user's whisper list and the key their whisper packets are encrypted with.
+pub struct WhisperList {
+    pub targets: Vec<WhisperTarget>,
+    pub key_id: u16,
+    pub key_bytes: Zeroizing<[u8; 32]>,
+    /// Recipients that have been sent the current key.
+    pub key_holders: HashSet<SessionId>,
+}
+
+/// A user who went idle, and where they were at the time.
+pub struct IdleState {
+    pub since: Instant,
+    pub return_channel: ChannelId,
+}
+
+/// Result of [`ServerState::update_idle`]; the caller does the moves.
+#[derive(Debug, Default)]
+pub struct IdleChanges {
+    /// The configured AFK channel, if it exists.
+    pub afk_channel: Option<ChannelId>,
+    /// Users who just went idle.
+    pub went_idle: Vec<UserId>,
+    /// Users who came back, with the channel to return them to (if any).
+    pub returned: Vec<(UserId, Option<ChannelId>)>,
+}
+
 /// A channel/room on the server.
 #[allow(dead_code)]
 pub struct Channel {
     pub info: ChannelInfo,
     /// Set of user_ids currently in this channel.
     pub members: HashSet<UserId>,
-    /// Channel password (None = no password required). Zeroized on drop.
+    /// Hash of the channel password, in any format `channels::verify_password`
+    /// accepts (None = no password required). Zeroized on drop.
     pub password: Option<Zeroizing<String>>,
     /// Handle to the auto-delete timer task (cancelled when a user joins).
     pub delete_timer: Option<tokio::task::JoinHandle<()>>,
@@ -133,6 +248,166 @@ pub struct Channel {
     pub media_key_id: u16,
     /// Whether this channel was loaded from channels.json and cannot be auto-deleted.
     pub persistent: bool,
+    /// Per-group permission overrides for this channel.
+    pub permission_overrides: HashMap<ServerGroup, PermissionOverride>,
+    /// Without a password of its own, require the parent's password.
+    pub inherit_password: bool,
+    /// Apply the parent's permission overrides before this channel's own.
+    pub inherit_permissions: bool,
+}
+
+impl Channel {
+    /// Build a persistent channel from a channels.json entry (password
+    /// already hashed). `has_password` is corrected later if the password
+    /// is inherited.
+    fn persistent(channel_id: ChannelId, entry: &ChannelEntry, parent_id: Option<ChannelId>) -> Self {
+        // Generate a random AES-256 media key for this channel
+        let mut key_bytes = [0u8; 32];
+        rand::Rng::fill(&mut rand::thread_rng(), &mut key_bytes);
+
+        Channel {
+            info: ChannelInfo {
+                channel_id,
+                name: entry.name.trim().to_string(),
+                description: entry.description.clone(),
+                max_users: entry.max_users,
+                user_count: 0,
+                has_password: entry.password_hash.is_some(),
+                created_by: None,
+                parent_id,
+                position: entry.position,
+                topic: entry.topic.clone(),
+            },
+            members: HashSet::new(),
+            password: entry.password_hash.clone().map(Zeroizing::new),
+            delete_timer: None,
+            created_by: None,
+            invited_users: HashSet::new(),
+            screen_shares: HashMap::new(),
+            media_key_bytes: Some(Zeroizing::new(key_bytes)),
+            media_key_id: 0,
+            persistent: true,
+            permission_overrides: entry.permissions.clone(),
+            inherit_password: entry.inherit_password,
+            inherit_permissions: entry.inherit_permissions,
+        }
+    }
+
+    /// The channels.json entry for a persistent channel.
+    fn to_entry(&self, channels: &HashMap<ChannelId, Channel>) -> ChannelEntry {
+        ChannelEntry {
+            name: self.info.name.clone(),
+            description: self.info.description.clone(),
+            topic: self.info.topic.clone(),
+            password: None,
+            password_hash: self.password.as_ref().map(|p| p.to_string()),
+            max_users: self.info.max_users,
+            permissions: self.permission_overrides.clone(),
+            parent: self
+                .info
+                .parent_id
+                .and_then(|id| channels.get(&id))
+                .map(|p| p.info.name.clone()),
+            position: self.info.position,
+            inherit_password: self.inherit_password,
+            inherit_permissions: self.inherit_permissions,
+        }
+    }
+}
+
+/// The channel and its ancestors, nearest first. Bounded by the maximum
+/// depth so a corrupted tree cannot loop.
+fn lineage<'a>(channels: &'a HashMap<ChannelId, Channel>, channel: &'a Channel) -> Vec<&'a Channel> {
+    let mut chain = vec![channel];
+    let mut parent = channel.info.parent_id;
+    while let Some(ch) = parent.and_then(|id| channels.get(&id)) {
+        if chain.len() >= MAX_CHANNEL_DEPTH {
+            break;
+        }
+        chain.push(ch);
+        parent = ch.info.parent_id;
+    }
+    chain
+}
+
+/// The channel whose password guards `channel`: the channel itself if it has
+/// one, otherwise the nearest ancestor reached through `inherit_password`.
+fn password_holder<'a>(channels: &'a HashMap<ChannelId, Channel>, channel: &'a Channel) -> Option<&'a Channel> {
+    for ch in lineage(channels, channel) {
+        if ch.password.is_some() {
+            return Some(ch);
+        }
+        if !ch.inherit_password {
+            return None;
+        }
+    }
+    None
+}
+
+/// The password hash that guards `channel`, if any.
+fn password_hash_of<'a>(channels: &'a HashMap<ChannelId, Channel>, channel: &'a Channel) -> Option<&'a str> {
+    password_holder(channels, channel)?.password.as_ref().map(|pw| pw.as_str())
+}
+
+/// Recompute `has_password` (which reflects inherited passwords) for every
+/// channel. Returns the infos that changed.
+fn sync_password_flags(channels: &mut HashMap<ChannelId, Channel>) -> Vec<ChannelInfo> {
+    let flags: Vec<(ChannelId, bool)> = channels
+        .values()
+        .map(|ch| (ch.info.channel_id, password_holder(channels, ch).is_some()))
+        .collect();
+    let mut changed = Vec::new();
+    for (id, has_password) in flags {
+        if let Some(ch) = channels.get_mut(&id) {
+            if ch.info.has_password != has_password {
+                ch.info.has_password = has_password;
+                changed.push(ch.info.clone());
+            }
+        }
+    }
+    changed.sort_by_key(|ch| ch.channel_id);
+    changed
+}
+
+/// Changes made by `ServerState::reload_persistent_channels`.
+#[derive(Debug, Default)]
+pub struct ChannelReload {
+    pub created: Vec<ChannelInfo>,
+    pub updated: Vec<ChannelInfo>,
+    /// Channels no longer in the file. They are no longer persistent and
+    /// must be deleted by the caller once their members have been moved.
+    pub retired: Vec<ChannelId>,
+    /// Entries whose name is taken by a user-created channel.
+    pub skipped: Vec<String>,
+}
+
+/// A channel taken out by `ServerState::remove_channel`.
+#[derive(Debug, Default)]
+pub struct RemovedChannel {
+    /// Users still inside, to be moved elsewhere by the caller.
+    pub members: Vec<UserId>,
+    /// Sub-channels that moved up a level (and any channel whose inherited
+    /// password changed as a result).
+    pub updated: Vec<ChannelInfo>,
+}
+
+/// A join that passed `ServerState::validate_join`. It carries the channel
+/// password hash the user's password was verified against, so the join
+/// itself only has to check that the hash is still the same.
+#[derive(Debug, Clone, Default)]
+pub struct JoinPass {
+    verified_hash: Option<Zeroizing<String>>,
+}
+
+/// A session taken over by a new connection, see
+/// `ServerState::resume_session`.
+#[derive(Debug)]
+pub struct ResumedSession {
+    pub user_id: UserId,
+    pub session_id: SessionId,
+    pub udp_token: u64,
+    pub resume_token: [u8; RESUME_TOKEN_LEN],
+    pub connection: u64,
 }
 
 /// The shared server state, designed for concurrent access.
@@ -151,12 +426,27 @@ pub struct ServerState {
     pub max_users: u32,
     /// UDP port (sent to clients during authentication).
     pub udp_port: u16,
-    /// Runtime settings.
-    pub settings: ServerSettings,
+    /// Runtime settings (replaced on reload).
+    settings: std::sync::RwLock<Arc<ServerSettings>>,
+    /// Where runtime settings are re-read from on reload.
+    pub settings_path: Option<PathBuf>,
+    /// Registered accounts (username -> identity key).
+    pub accounts: AccountStore,
+    /// Server-wide bans.
+    pub bans: BanStore,
+    /// Where persistent channels are saved when changed at runtime
+    /// (None = never written).
+    pub channels_path: Option<PathBuf>,
+    /// When the server started (for uptime).
+    pub started_at: Instant,
+    /// Counters exported by the metrics listener.
+    pub metrics: Metrics,
+    /// Record of administrative actions.
+    pub audit: AuditLog,
     /// Next user_id counter.
     next_user_id: AtomicU32,
-    /// Next session_id counter.
-    next_session_id: AtomicU32,
+    /// Next session_id counter, shared by all virtual servers in the process.
+    next_session_id: Arc<AtomicU32>,
     /// Next channel_id counter (0 is reserved for General).
     next_channel_id: AtomicU32,
 }
@@ -167,6 +457,10 @@ impl ServerState {
         config: &ServerConfig,
         settings: ServerSettings,
         persistent_channels: Vec<ChannelEntry>,
+        accounts: AccountStore,
+        bans: BanStore,
+        channels_path: Option<PathBuf>,
+        settings_path: Option<PathBuf>,
     ) -> Self {
         let mut channels = HashMap::new();
         channels.insert(
@@ -180,6 +474,9 @@ impl ServerState {
                     user_count: 0,
                     has_password: false,
                     created_by: None,
+                    parent_id: None,
+                    position: 0,
+                    topic: String::new(),
                 },
                 members: HashSet::new(),
                 password: None,
@@ -190,46 +487,29 @@ impl ServerState {
                 media_key_bytes: None,
                 media_key_id: 0,
                 persistent: false,
+                permission_overrides: HashMap::new(),
+                inherit_password: false,
+                inherit_permissions: false,
             },
         );
 
-        // Insert persistent channels from channels.json with IDs starting at 1
+        // Insert persistent channels from channels.json with IDs starting at 1,
+        // resolving parent names once every entry has an ID
+        let ids: HashMap<String, ChannelId> = persistent_channels
+            .iter()
+            .zip(1..)
+            .map(|(entry, id)| (entry.name.trim().to_lowercase(), id))
+            .collect();
         let mut next_id: u32 = 1;
         for entry in &persistent_channels {
-            let channel_id = next_id;
+            let parent_id = entry
+                .parent
+                .as_ref()
+                .and_then(|p| ids.get(&p.trim().to_lowercase()).copied());
+            channels.insert(next_id, Channel::persistent(next_id, entry, parent_id));
             next_id += 1;
-
-            let has_password = entry.password_hash.is_some();
-            let password = entry.password_hash.clone().map(Zeroizing::new);
-
-            // Generate a random AES-256 media key for this channel
-            let mut key_bytes = [0u8; 32];
-            rand::Rng::fill(&mut rand::thread_rng(), &mut key_bytes);
-
-            channels.insert(
-                channel_id,
-                Channel {
-                    info: ChannelInfo {
-                        channel_id,
-                        name: entry.name.clone(),
-                        description: entry.description.clone(),
-                        max_users: entry.max_users,
-                        user_count: 0,
-                        has_password,
-                        created_by: None,
-                    },
-                    members: HashSet::new(),
-                    password,
-                    delete_timer: None,
-                    created_by: None,
-                    invited_users: HashSet::new(),
-                    screen_shares: HashMap::new(),
-                    media_key_bytes: Some(Zeroizing::new(key_bytes)),
-                    media_key_id: 0,
-                    persistent: true,
-                },
-            );
         }
+        sync_password_flags(&mut channels);
 
         Self {
             sessions: DashMap::new(),
@@ -239,13 +519,34 @@ impl ServerState {
             channels: RwLock::new(channels),
             max_users: config.max_users,
             udp_port: config.udp_port,
-            settings,
+            settings: std::sync::RwLock::new(Arc::new(settings)),
+            settings_path,
+            accounts,
+            bans,
+            channels_path,
+            started_at: Instant::now(),
+            metrics: Metrics::default(),
+            audit: match &config.audit_log {
+                Some(path) => AuditLog::new(PathBuf::from(path), config.audit_log_max_bytes),
+                None => AuditLog::default(),
+            },
             next_user_id: AtomicU32::new(1),
-            next_session_id: AtomicU32::new(1),
+            next_session_id: Arc::new(AtomicU32::new(1)),
             next_channel_id: AtomicU32::new(next_id),
         }
     }
 
+    /// The current runtime settings.
+    pub fn settings(&self) -> Arc<ServerSettings> {
+        self.settings.read().unwrap_or_else(|p| p.into_inner()).clone()
+    }
+
+    /// Replace the runtime settings. Cached session permissions are not
+    /// updated; call `refresh_permissions` afterwards.
+    pub fn set_settings(&self, settings: ServerSettings) {
+        *self.settings.write().unwrap_or_else(|p| p.into_inner()) = Arc::new(settings);
+    }
+
     /// Allocate a new unique user ID.
     pub fn next_user_id(&self) -> UserId {
         self.next_user_id.fetch_add(1, Ordering::Relaxed)
@@ -256,16 +557,97 @@ impl ServerState {
         self.next_session_id.fetch_add(1, Ordering::Relaxed)
     }
 
+    /// Allocate session IDs from the same counter as `other`, keeping them
+    /// unique across virtual servers that share a UDP socket.
+    pub fn share_session_ids(&mut self, other: &ServerState) {
+        self.next_session_id = other.next_session_id.clone();
+    }
+
     /// Allocate a new unique channel ID.
     pub fn next_channel_id(&self) -> ChannelId {
         self.next_channel_id.fetch_add(1, Ordering::Relaxed)
     }
 
+    /// Username of a connected user.
+    pub fn username(&self, user_id: UserId) -> Option<String> {
+        let session_id = *self.user_to_session.get(&user_id)?;
+        self.sessions.get(&session_id).map(|s| s.username.clone())
+    }
+
+    /// Name of a channel.
+    pub async fn channel_name(&self, channel_id: ChannelId) -> Option<String> {
+        self.channels.read().await.get(&channel_id).map(|ch| ch.info.name.clone())
+    }
+
     /// Get the total number of connected users.
     pub fn user_count(&self) -> usize {
         self.sessions.len()
     }
 
+    /// Whether a session negotiated all of `capabilities`.
+    pub fn has_capabilities(&self, session_id: SessionId, capabilities: Capabilities) -> bool {
+        self.sessions
+            .get(&session_id)
+            .is_some_and(|s| s.capabilities.contains(capabilities))
+    }
+
+    /// Number of sessions with a live connection, i.e. not suspended
+    /// awaiting resumption.
+    pub fn connected_count(&self) -> usize {
+        self.sessions
+            .iter()
+            .filter(|s| matches!(s.state, SessionState::Live))
+            .count()
+    }
+
+    /// Take one token from a session's rate limiter. Drops are counted in the
+    /// metrics and reported to the client with `RateLimited` (at most once a
+    /// second per limiter, so dropped voice can't hide a refused request); a
+    /// missing session counts as rate-limited.
+    pub fn try_consume_rate(&self, session_id: SessionId, limiter: Limiter) -> bool {
+        let mut notice = None;
+        let allowed = self
+            .sessions
+            .get_mut(&session_id)
+            .map(|mut s| {
+                let rate = match limiter {
+                    Limiter::UdpVoice => &mut s.udp_voice_rate,
+                    Limiter::UdpVideo => &mut s.udp_video_rate,
+                    Limiter::Tcp => &mut s.global_rate,
+                    Limiter::PasswordAttempt => &mut s.password_attempt_rate,
+                    Limiter::Chat => &mut s.chat_rate,
+                    Limiter::CreateChannel => &mut s.create_channel_rate,
+                    Limiter::Prekey => &mut s.prekey_rate,
+                };
+                if rate.try_consume() {
+                    return true;
+                }
+                let retry_after = rate.retry_after();
+                let now = Instant::now();
+                let last_notice = &mut s.last_rate_limit_notice[limiter as usize];
+                if last_notice.is_none_or(|t| now.duration_since(t) >= RATE_LIMIT_NOTICE_INTERVAL) {
+                    *last_notice = Some(now);
+                    notice = Some((s.tcp_tx.clone(), retry_after));
+                }
+                false
+            })
+            .unwrap_or(false);
+        if !allowed {
+            self.metrics.rate_limited(limiter);
+        }
+        if let Some((tx, retry_after)) = notice {
+            let msg = ServerMessage::RateLimited {
+                limit: limiter.kind(),
+                retry_after_ms: retry_after.as_millis() as u64,
+            };
+            // Never wait here: this runs on the UDP path too
+            if let Ok(data) = encode_server_msg(&msg) {
+                let _ = tx.try_send(data);
+            }
+        }
+        allowed
+    }
+
     /// Broadcast a raw serialized message to all connected sessions.
     pub async fn broadcast_raw_to_all(&self, data: &[u8]) {
         for entry in self.sessions.iter() {
@@ -294,62 +676,60 @@ impl ServerState {
             .filter_map(|&uid| {
                 let sid = self.user_to_session.get(&uid)?;
                 let session = self.sessions.get(&*sid)?;
-                Some(UserInfo {
-                    user_id: session.user_id,
-                    username: session.username.clone(),
-                    channel_id: session.channel_id,
-                    is_muted: session.is_muted,
-                    is_deafened: session.is_deafened,
-                    is_screen_sharing: session.is_screen_sharing,
-                })
+                Some(session.user_info())
             })
             .collect()
     }
 
     /// Check if a join would succeed (password, capacity) without modifying state.
-    /// Invited users bypass the password check.
+    /// Invited users bypass the password check. The password is verified
+    /// on a blocking thread, outside the channels lock.
     pub async fn validate_join(
         &self,
         channel_id: ChannelId,
         password: Option<&str>,
         user_id: UserId,
-    ) -> anyhow::Result<()> {
-        let channels = self.channels.read().await;
-        let channel = channels
-            .get(&channel_id)
-            .ok_or_else(|| anyhow::anyhow!("channel {} does not exist", channel_id))?;
+    ) -> anyhow::Result<JoinPass> {
+        let stored = {
+            let channels = self.channels.read().await;
+            let channel = channels
+                .get(&channel_id)
+                .ok_or_else(|| anyhow::anyhow!("channel {} does not exist", channel_id))?;
+
+            if channel_id != 0 && !self.user_has(&channels, user_id, channel, Permission::Join) {
+                anyhow::bail!("you do not have permission to join this channel");
+            }
 
-        let is_invited = channel.invited_users.contains(&user_id);
+            if channel.info.max_users > 0 && channel.members.len() >= channel.info.max_users as usize {
+                anyhow::bail!("channel is full");
+            }
 
-        if !is_invited {
-            if let Some(ref channel_pw) = channel.password {
-                let matches = match password {
-                    Some(pw) if channel.persistent => {
-                        // Persistent channels store a SHA-256 hash — hash the attempt first
-                        let attempt_hash = crate::channels::hash_password(pw);
-                        attempt_hash.as_bytes().ct_eq(channel_pw.as_bytes()).into()
-                    }
-                    Some(pw) => {
-                        // User-created channels store plaintext passwords
-                        pw.as_bytes().ct_eq(channel_pw.as_bytes()).into()
-                    }
-                    None => false,
-                };
-                if !matches {
-                    anyhow::bail!("incorrect channel password");
-                }
+            if channel.invited_users.contains(&user_id) {
+                return Ok(JoinPass::default());
             }
-        }
+            match password_hash_of(&channels, channel) {
+                Some(hash) => Zeroizing::new(hash.to_string()),
+                None => return Ok(JoinPass::default()),
+            }
+        };
 
-        if channel.info.max_users > 0 && channel.members.len() >= channel.info.max_users as usize {
-            anyhow::bail!("channel is full");
+        let Some(password) = password else {
+            anyhow::bail!("incorrect channel password");
+        };
+        let password = Zeroizing::new(password.to_string());
+        let hash = stored.clone();
+        let matches = tokio::task::spawn_blocking(move || crate::channels::verify_password(&password, &hash))
+            .await
+            .unwrap_or(false);
+        if !matches {
+            anyhow::bail!("incorrect channel password");
         }
-
-        Ok(())
+        Ok(JoinPass {
+            verified_hash: Some(stored),
+        })
     }
 
-    /// Add a user to a channel with optional password.
-    /// Invited users bypass the password check automatically.
+    /// Validate and join in one go; see `validate_join` and `join_validated`.
     /// Returns the list of other members' session_ids for notification.
     pub async fn join_channel(
         &self,
@@ -358,35 +738,80 @@ impl ServerState {
         channel_id: ChannelId,
         password: Option<&str>,
     ) -> anyhow::Result<Vec<SessionId>> {
+        let pass = self.validate_join(channel_id, password, user_id).await?;
+        self.join_validated(user_id, session_id, channel_id, &pass).await
+    }
+
+    /// Add a user to a channel after `validate_join` let them in. Permission
+    /// and capacity are checked again, and the channel password must still
+    /// be the one that was verified. Invited users bypass the password.
+    /// Returns the list of other members' session_ids for notification.
+    pub async fn join_validated(
+        &self,
+        user_id: UserId,
+        session_id: SessionId,
+        channel_id: ChannelId,
+        pass: &JoinPass,
+    ) -> anyhow::Result<Vec<SessionId>> {
+        let group = self.group_of(user_id);
         let mut channels = self.channels.write().await;
 
         let channel = channels
-            .get_mut(&channel_id)
+            .get(&channel_id)
             .ok_or_else(|| anyhow::anyhow!("channel {} does not exist", channel_id))?;
 
-        // Check if the user was invited (bypass password if so)
-        let was_invited = channel.invited_users.remove(&user_id);
+        let permissions = self.channel_permissions(&channels, group, user_id, channel);
+        if channel_id != 0 && !Permission::Join.allowed_by(&permissions) {
+            anyhow::bail!("you do not have permission to join this channel");
+        }
 
-        if !was_invited {
-            if let Some(ref channel_pw) = channel.password {
-                let matches = match password {
-                    Some(pw) if channel.persistent => {
-                        let attempt_hash = crate::channels::hash_password(pw);
-                        attempt_hash.as_bytes().ct_eq(channel_pw.as_bytes()).into()
-                    }
-                    Some(pw) => pw.as_bytes().ct_eq(channel_pw.as_bytes()).into(),
-                    None => false,
-                };
-                if !matches {
+        // Invited users bypass the password
+        let is_invited = channel.invited_users.contains(&user_id);
+        if !is_invited {
+            if let Some(hash) = password_hash_of(&channels, channel) {
+                if pass.verified_hash.as_ref().map(|h| h.as_str()) != Some(hash) {
                     anyhow::bail!("incorrect channel password");
                 }
             }
         }
 
+        let channel = channels.get_mut(&channel_id).expect("channel checked above");
+        channel.invited_users.remove(&user_id);
+
         if channel.info.max_users > 0 && channel.members.len() >= channel.info.max_users as usize {
             anyhow::bail!("channel is full");
         }
 
+        Ok(self.add_member(channel, user_id, session_id, permissions))
+    }
+
+    /// Put a user into a channel regardless of password, capacity and join
+    /// permission (server-initiated moves).
+    pub async fn force_join_channel(
+        &self,
+        user_id: UserId,
+        session_id: SessionId,
+        channel_id: ChannelId,
+    ) -> anyhow::Result<Vec<SessionId>> {
+        let group = self.group_of(user_id);
+        let mut channels = self.channels.write().await;
+        let channel = channels
+            .get(&channel_id)
+            .ok_or_else(|| anyhow::anyhow!("channel {} does not exist", channel_id))?;
+        let permissions = self.channel_permissions(&channels, group, user_id, channel);
+        let channel = channels.get_mut(&channel_id).expect("channel checked above");
+        Ok(self.add_member(channel, user_id, session_id, permissions))
+    }
+
+    /// Add a user to an already-validated channel.
+    /// Returns the other members' session_ids for notification.
+    fn add_member(
+        &self,
+        channel: &mut Channel,
+        user_id: UserId,
+        session_id: SessionId,
+        permissions: Permissions,
+    ) -> Vec<SessionId> {
         // Cancel any pending delete timer
         if let Some(timer) = channel.delete_timer.take() {
             timer.abort();
@@ -402,12 +827,13 @@ impl ServerState {
         channel.members.insert(user_id);
         channel.info.user_count = channel.members.len() as u32;
 
-        // Update the session's channel_id
+        // Update the session's channel_id and cached permissions
         if let Some(mut session) = self.sessions.get_mut(&session_id) {
-            session.channel_id = channel_id;
+            session.channel_id = channel.info.channel_id;
+            session.permissions = permissions;
         }
 
-        Ok(others)
+        others
     }
 
     /// Remove a user from their current channel.
@@ -462,21 +888,156 @@ impl ServerState {
         Some(session)
     }
 
-    /// Create a new user-created channel.
+    /// Hand a session over to a new connection from `ip` if `token` is its
+    /// resume token. A suspended session's grace timer is stopped; a session
+    /// whose old connection is still open (the client noticed the drop
+    /// first) has that connection closed. The token is replaced and the UDP
+    /// address forgotten, so the client's new socket can bind. The new
+    /// connection's `capabilities` replace the old ones. A session that is
+    /// already being cleaned up can't be resumed.
+    pub fn resume_session(
+        &self,
+        username: &str,
+        token: &[u8],
+        ip: IpAddr,
+        capabilities: Capabilities,
+    ) -> Option<ResumedSession> {
+        let session_id = *self.username_to_session.get(&username.to_lowercase())?;
+        let mut session = self.sessions.get_mut(&session_id)?;
+        if !bool::from(session.resume_token.as_slice().ct_eq(token)) {
+            return None;
+        }
+
+        match std::mem::take(&mut session.state) {
+            SessionState::Suspended(timer) => timer.abort(),
+            SessionState::Live => {
+                session.disconnect.notify_one();
+                session.disconnect = Arc::new(Notify::new());
+            }
+            SessionState::CleaningUp => {
+                session.state = SessionState::CleaningUp;
+                return None;
+            }
+        }
+        session.connection += 1;
+        session.resume_token = rand::random();
+        session.tcp_peer_ip = ip;
+        session.capabilities = capabilities;
+        let old_addr = session.udp_addr.take();
+        let resumed = ResumedSession {
+            user_id: session.user_id,
+            session_id,
+            udp_token: session.udp_token,
+            resume_token: session.resume_token,
+            connection: session.connection,
+        };
+        drop(session);
+
+        if let Some(addr) = old_addr {
+            self.addr_to_session.remove(&addr);
+        }
+        Some(resumed)
+    }
+
+    /// Keep a session whose connection dropped until `timer` ends it. Does
+    /// nothing (and stops the timer) if the session is gone, is being
+    /// cleaned up, or has been resumed on a newer connection.
+    pub fn suspend_session(&self, session_id: SessionId, connection: u64, timer: JoinHandle<()>) -> bool {
+        match self.sessions.get_mut(&session_id) {
+            Some(mut session)
+                if session.connection == connection && matches!(session.state, SessionState::Live) =>
+            {
+                session.state = SessionState::Suspended(timer);
+                true
+            }
+            _ => {
+                timer.abort();
+                false
+            }
+        }
+    }
+
+    /// End a session's suspension for good. True if it was suspended, in
+    /// which case it is now cleaning up and the caller must remove it.
+    pub fn take_suspended(&self, session_id: SessionId) -> bool {
+        self.sessions.get_mut(&session_id).is_some_and(|mut session| {
+            if !matches!(session.state, SessionState::Suspended(_)) {
+                return false;
+            }
+            session.state = SessionState::CleaningUp;
+            true
+        })
+    }
+
+    /// Start removing a session whose connection `connection` is closing.
+    /// False if it was resumed on a newer connection or is already being
+    /// cleaned up; otherwise the caller must remove it.
+    pub fn begin_cleanup(&self, session_id: SessionId, connection: u64) -> bool {
+        self.sessions.get_mut(&session_id).is_some_and(|mut session| {
+            if session.connection != connection {
+                return false;
+            }
+            match std::mem::replace(&mut session.state, SessionState::CleaningUp) {
+                SessionState::Live => true,
+                SessionState::Suspended(timer) => {
+                    timer.abort();
+                    true
+                }
+                SessionState::CleaningUp => false,
+            }
+        })
+    }
+
+    /// Whether a session has been resumed on a connection newer than
+    /// `connection`.
+    pub fn is_superseded(&self, session_id: SessionId, connection: u64) -> bool {
+        self.sessions
+            .get(&session_id)
+            .is_some_and(|session| session.connection != connection)
+    }
+
+    /// Create a new user-created channel, optionally nested under `parent_id`
+    /// (which requires Join permission in the parent). Sub-channels inherit
+    /// the parent's permission overrides but not its password.
     pub async fn create_channel(
         &self,
         name: String,
         password: Option<String>,
         created_by: UserId,
+        parent_id: Option<ChannelId>,
     ) -> anyhow::Result<ChannelInfo> {
+        // Hash before taking the lock and off the async workers; Argon2 is
+        // deliberately slow
+        let password = match password {
+            Some(pw) => Some(
+                tokio::task::spawn_blocking(move || crate::channels::hash_password(&Zeroizing::new(pw)))
+                    .await?,
+            ),
+            None => None,
+        };
         let mut channels = self.channels.write().await;
 
+        if let Some(parent_id) = parent_id {
+            if parent_id == 0 {
+                anyhow::bail!("cannot create sub-channels of the General channel");
+            }
+            let parent = channels
+                .get(&parent_id)
+                .ok_or_else(|| anyhow::anyhow!("parent channel does not exist"))?;
+            if !self.user_has(&channels, created_by, parent, Permission::Join) {
+                anyhow::bail!("you do not have permission to create sub-channels here");
+            }
+            if lineage(&channels, parent).len() >= MAX_CHANNEL_DEPTH {
+                anyhow::bail!("channels cannot be nested more than {} levels deep", MAX_CHANNEL_DEPTH);
+            }
+        }
+
         // Count only user-created channels (exclude General and persistent channels)
         let user_channels = channels
             .values()
             .filter(|ch| !ch.persistent && ch.info.channel_id != 0)
             .count();
-        if user_channels >= self.settings.max_channels as usize {
+        if user_channels >= self.settings().max_channels as usize {
             anyhow::bail!("maximum number of channels reached");
         }
 
@@ -496,6 +1057,9 @@ impl ServerState {
             user_count: 0,
             has_password,
             created_by: Some(created_by),
+            parent_id,
+            position: 0,
+            topic: String::new(),
         };
 
         // Generate a random AES-256 media key for this channel
@@ -515,14 +1079,19 @@ impl ServerState {
                 media_key_bytes: Some(Zeroizing::new(key_bytes)),
                 media_key_id: 0,
                 persistent: false,
+                permission_overrides: HashMap::new(),
+                inherit_password: false,
+                inherit_permissions: parent_id.is_some(),
             },
         );
 
         Ok(info)
     }
 
-    /// Delete an empty, non-General, non-persistent channel.
-    pub async fn delete_channel(&self, channel_id: ChannelId) -> anyhow::Result<()> {
+    /// Delete an empty, non-General, non-persistent channel without
+    /// sub-channels. Returns the channel's parent, which may now be
+    /// deletable itself.
+    pub async fn delete_channel(&self, channel_id: ChannelId) -> anyhow::Result<Option<ChannelId>> {
         if channel_id == 0 {
             anyhow::bail!("cannot delete the General channel");
         }
@@ -540,42 +1109,374 @@ impl ServerState {
             anyhow::bail!("channel is not empty");
         }
 
+        if channels.values().any(|ch| ch.info.parent_id == Some(channel_id)) {
+            anyhow::bail!("channel has sub-channels");
+        }
+
+        let parent_id = channel.info.parent_id;
         if let Some(ch) = channels.remove(&channel_id) {
             if let Some(timer) = ch.delete_timer {
                 timer.abort();
             }
         }
 
-        Ok(())
+        Ok(parent_id)
+    }
+
+    /// Create a persistent channel at runtime and save it to the channels file.
+    pub async fn create_persistent_channel(&self, entry: ChannelEntry) -> anyhow::Result<ChannelInfo> {
+        self.insert_persistent_channel(entry, None).await
+    }
+
+    /// Create a user's channel as persistent: it is appended to the channels
+    /// file (password hashed) and never auto-deleted. The creator manages it
+    /// like any channel they created until they disconnect. A parent must be
+    /// persistent as well.
+    pub async fn create_permanent_channel(
+        &self,
+        name: String,
+        password: Option<String>,
+        created_by: UserId,
+        parent_id: Option<ChannelId>,
+    ) -> anyhow::Result<ChannelInfo> {
+        let parent = match parent_id {
+            Some(parent_id) => {
+                let channels = self.channels.read().await;
+                let parent = channels
+                    .get(&parent_id)
+                    .ok_or_else(|| anyhow::anyhow!("parent channel does not exist"))?;
+                if !parent.persistent {
+                    anyhow::bail!("permanent channels can only be nested under permanent channels");
+                }
+                if !self.user_has(&channels, created_by, parent, Permission::Join) {
+                    anyhow::bail!("you do not have permission to create sub-channels here");
+                }
+                Some(parent.info.name.clone())
+            }
+            None => None,
+        };
+        let entry = ChannelEntry {
+            name,
+            password,
+            parent,
+            ..Default::default()
+        };
+        self.insert_persistent_channel(entry, Some(created_by)).await
     }
 
-    /// Change a channel's password (creator only). Returns the updated ChannelInfo.
+    async fn insert_persistent_channel(
+        &self,
+        mut entry: ChannelEntry,
+        created_by: Option<UserId>,
+    ) -> anyhow::Result<ChannelInfo> {
+        crate::channels::validate_entry(&entry)?;
+        crate::channels::validate_topic(&entry.description, &entry.topic)?;
+        // Hash before taking the lock and off the async workers; Argon2 is
+        // deliberately slow
+        if entry.password.is_some() {
+            entry = tokio::task::spawn_blocking(move || {
+                crate::channels::hash_plaintext_passwords(std::slice::from_mut(&mut entry));
+                entry
+            })
+            .await?;
+        }
+
+        let mut channels = self.channels.write().await;
+        let lower = entry.name.trim().to_lowercase();
+        if channels.values().any(|ch| ch.info.name.to_lowercase() == lower) {
+            anyhow::bail!("a channel with that name already exists");
+        }
+
+        // The parent must itself be persistent so the file stays self-contained
+        let parent_id = match &entry.parent {
+            Some(parent) => {
+                let parent_lower = parent.trim().to_lowercase();
+                let parent = channels
+                    .values()
+                    .find(|ch| ch.persistent && ch.info.name.to_lowercase() == parent_lower)
+                    .ok_or_else(|| anyhow::anyhow!("no persistent channel named '{}'", parent.trim()))?;
+                if lineage(&channels, parent).len() >= MAX_CHANNEL_DEPTH {
+                    anyhow::bail!("channels cannot be nested more than {} levels deep", MAX_CHANNEL_DEPTH);
+                }
+                Some(parent.info.channel_id)
+            }
+            None => None,
+        };
+
+        let channel_id = self.next_channel_id();
+        let mut channel = Channel::persistent(channel_id, &entry, parent_id);
+        channel.info.has_password = password_holder(&channels, &channel).is_some();
+        channel.info.created_by = created_by;
+        channel.created_by = created_by;
+        let info = channel.info.clone();
+        channels.insert(channel_id, channel);
+
+        if let Err(e) = self.save_persistent_channels(&channels) {
+            channels.remove(&channel_id);
+            return Err(e);
+        }
+        Ok(info)
+    }
+
+    /// Remove any channel except General, persistent or not, updating the
+    /// channels file if needed. Sub-channels move up to the removed channel's
+    /// parent. The users still inside must be moved elsewhere by the caller.
+    pub async fn remove_channel(&self, channel_id: ChannelId) -> anyhow::Result<RemovedChannel> {
+        if channel_id == 0 {
+            anyhow::bail!("cannot delete the General channel");
+        }
+
+        let mut channels = self.channels.write().await;
+        let channel = channels
+            .remove(&channel_id)
+            .ok_or_else(|| anyhow::anyhow!("channel does not exist"))?;
+
+        let children: Vec<ChannelId> = channels
+            .values()
+            .filter(|ch| ch.info.parent_id == Some(channel_id))
+            .map(|ch| ch.info.channel_id)
+            .collect();
+        for id in &children {
+            if let Some(ch) = channels.get_mut(id) {
+                ch.info.parent_id = channel.info.parent_id;
+            }
+        }
+
+        if channel.persistent {
+            if let Err(e) = self.save_persistent_channels(&channels) {
+                for id in &children {
+                    if let Some(ch) = channels.get_mut(id) {
+                        ch.info.parent_id = Some(channel_id);
+                    }
+                }
+                channels.insert(channel_id, channel);
+                return Err(e);
+            }
+        }
+        if let Some(timer) = channel.delete_timer {
+            timer.abort();
+        }
+
+        // Children (and their descendants) may have lost an inherited password
+        let mut updated = sync_password_flags(&mut channels);
+        for id in children {
+            if !updated.iter().any(|info| info.channel_id == id) {
+                if let Some(ch) = channels.get(&id) {
+                    updated.push(ch.info.clone());
+                }
+            }
+        }
+        updated.sort_by_key(|info| info.channel_id);
+
+        Ok(RemovedChannel {
+            members: channel.members.into_iter().collect(),
+            updated,
+        })
+    }
+
+    /// Replace a channel's description and topic (requires Edit in the
+    /// channel). Persistent channels are saved; nothing changes if that fails.
+    pub async fn set_channel_topic(
+        &self,
+        channel_id: ChannelId,
+        user_id: UserId,
+        description: String,
+        topic: String,
+    ) -> anyhow::Result<ChannelInfo> {
+        let description = description.trim_end().to_string();
+        let topic = topic.trim().to_string();
+        crate::channels::validate_topic(&description, &topic)?;
+
+        let mut channels = self.channels.write().await;
+        let channel = channels
+            .get(&channel_id)
+            .ok_or_else(|| anyhow::anyhow!("channel does not exist"))?;
+        if !self.user_has(&channels, user_id, channel, Permission::Edit) {
+            anyhow::bail!("you do not have permission to edit this channel");
+        }
+
+        let channel = channels.get_mut(&channel_id).expect("channel checked above");
+        let old_description = std::mem::replace(&mut channel.info.description, description);
+        let old_topic = std::mem::replace(&mut channel.info.topic, topic);
+        if channel.persistent {
+            if let Err(e) = self.save_persistent_channels(&channels) {
+                let channel = channels.get_mut(&channel_id).expect("channel checked above");
+                channel.info.description = old_description;
+                channel.info.topic = old_topic;
+                return Err(e);
+            }
+        }
+        Ok(channels[&channel_id].info.clone())
+    }
+
+    /// Rewrite the channels file from the current persistent channels.
+    fn save_persistent_channels(&self, channels: &HashMap<ChannelId, Channel>) -> anyhow::Result<()> {
+        let Some(ref path) = self.channels_path else {
+            return Ok(());
+        };
+        let mut persistent: Vec<&Channel> = channels.values().filter(|ch| ch.persistent).collect();
+        persistent.sort_by_key(|ch| ch.info.channel_id);
+        let entries: Vec<ChannelEntry> = persistent.iter().map(|ch| ch.to_entry(channels)).collect();
+        crate::channels::atomic_rewrite(path, &entries)
+    }
+
+    /// Bring the live persistent channels in line with freshly loaded
+    /// channels.json entries (validated and hashed). Channels are matched by
+    /// name, case-insensitively; members of updated channels stay connected.
+    /// A parent whose entry was skipped leaves its children at the top level.
+    pub async fn reload_persistent_channels(&self, entries: &[ChannelEntry]) -> ChannelReload {
+        let mut result = ChannelReload::default();
+        let mut channels = self.channels.write().await;
+        let before: HashMap<ChannelId, ChannelEntry> = channels
+            .values()
+            .filter(|ch| ch.persistent)
+            .map(|ch| (ch.info.channel_id, ch.to_entry(&channels)))
+            .collect();
+        let mut ids: HashMap<String, ChannelId> = HashMap::new();
+        let mut created: Vec<ChannelId> = Vec::new();
+
+        for entry in entries {
+            let lower = entry.name.trim().to_lowercase();
+            let existing = channels
+                .values_mut()
+                .find(|ch| ch.info.channel_id != 0 && ch.info.name.to_lowercase() == lower);
+            match existing {
+                Some(ch) if ch.persistent => {
+                    ch.info.name = entry.name.trim().to_string();
+                    ch.info.description = entry.description.clone();
+                    ch.info.topic = entry.topic.clone();
+                    ch.info.max_users = entry.max_users;
+                    ch.info.position = entry.position;
+                    ch.password = entry.password_hash.clone().map(Zeroizing::new);
+                    ch.permission_overrides = entry.permissions.clone();
+                    ch.inherit_password = entry.inherit_password;
+                    ch.inherit_permissions = entry.inherit_permissions;
+                    ids.insert(lower, ch.info.channel_id);
+                }
+                Some(_) => result.skipped.push(entry.name.trim().to_string()),
+                None => {
+                    let channel_id = self.next_channel_id();
+                    channels.insert(channel_id, Channel::persistent(channel_id, entry, None));
+                    created.push(channel_id);
+                    ids.insert(lower, channel_id);
+                }
+            }
+        }
+
+        // Resolve parents once every entry has a channel
+        for entry in entries {
+            let Some(id) = ids.get(&entry.name.trim().to_lowercase()) else {
+                continue;
+            };
+            let parent_id = entry
+                .parent
+                .as_ref()
+                .and_then(|p| ids.get(&p.trim().to_lowercase()).copied());
+            if let Some(ch) = channels.get_mut(id) {
+                ch.info.parent_id = parent_id;
+            }
+        }
+        let flag_changes = sync_password_flags(&mut channels);
+
+        for ch in channels.values_mut() {
+            if ch.persistent && !ids.values().any(|&id| id == ch.info.channel_id) {
+                ch.persistent = false;
+                result.retired.push(ch.info.channel_id);
+            }
+        }
+        result.retired.sort();
+
+        result.created = created.iter().map(|id| channels[id].info.clone()).collect();
+        for (id, old) in &before {
+            let Some(ch) = channels.get(id).filter(|ch| ch.persistent) else {
+                continue;
+            };
+            if ch.to_entry(&channels) != *old {
+                result.updated.push(ch.info.clone());
+            }
+        }
+        for info in flag_changes {
+            let listed = created.contains(&info.channel_id)
+                || result.updated.iter().any(|u| u.channel_id == info.channel_id);
+            if !listed {
+                result.updated.push(info);
+            }
+        }
+        result.updated.sort_by_key(|info| info.channel_id);
+        result
+    }
+
+    /// Recompute every session's cached channel permissions (after settings
+    /// or channel overrides change).
+    pub async fn refresh_permissions(&self) {
+        let channels = self.channels.read().await;
+        for mut session in self.sessions.iter_mut() {
+            if let Some(ch) = channels.get(&session.channel_id) {
+                session.permissions = self.channel_permissions(&channels, session.group, session.user_id, ch);
+            }
+        }
+    }
+
+    /// Change a channel's password (requires Edit). Returns the updated ChannelInfo.
     pub async fn set_channel_password(
         &self,
         channel_id: ChannelId,
         user_id: UserId,
         password: Option<String>,
-    ) -> anyhow::Result<ChannelInfo> {
+    ) -> anyhow::Result<Vec<ChannelInfo>> {
         if channel_id == 0 {
             anyhow::bail!("cannot modify the General channel");
         }
 
+        // Check permission before spending Argon2 time on the new hash
+        {
+            let channels = self.channels.read().await;
+            let channel = channels
+                .get(&channel_id)
+                .ok_or_else(|| anyhow::anyhow!("channel does not exist"))?;
+            if !self.user_has(&channels, user_id, channel, Permission::Edit) {
+                anyhow::bail!("you do not have permission to change this channel's password");
+            }
+        }
+
+        let password = match password {
+            Some(pw) => Some(
+                tokio::task::spawn_blocking(move || crate::channels::hash_password(&Zeroizing::new(pw)))
+                    .await?,
+            ),
+            None => None,
+        };
         let mut channels = self.channels.write().await;
         let channel = channels
-            .get_mut(&channel_id)
+            .get(&channel_id)
             .ok_or_else(|| anyhow::anyhow!("channel does not exist"))?;
 
-        if channel.created_by != Some(user_id) {
-            anyhow::bail!("only the channel creator can change the password");
+        // Permissions may have changed while hashing
+        if !self.user_has(&channels, user_id, channel, Permission::Edit) {
+            anyhow::bail!("you do not have permission to change this channel's password");
+        }
+        let channel = channels.get_mut(&channel_id).expect("channel checked above");
+        let persistent = channel.persistent;
+        let old_password = std::mem::replace(&mut channel.password, password.map(Zeroizing::new));
+
+        // Sub-channels inheriting the password change with it
+        let mut changed = sync_password_flags(&mut channels);
+        if !changed.iter().any(|info| info.channel_id == channel_id) {
+            changed.insert(0, channels[&channel_id].info.clone());
         }
 
-        channel.info.has_password = password.is_some();
-        channel.password = password.map(Zeroizing::new);
-
-        Ok(channel.info.clone())
+        if persistent {
+            if let Err(e) = self.save_persistent_channels(&channels) {
+                let channel = channels.get_mut(&channel_id).expect("channel checked above");
+                channel.password = old_password;
+                sync_password_flags(&mut channels);
+                return Err(e);
+            }
+        }
+        Ok(changed)
     }
 
-    /// Remove a user from a channel (creator kicks them).
+    /// Remove a user from a channel (requires Kick).
     /// Returns the kicked user's session_id and the channel's remaining member count.
     pub async fn kick_user(
         &self,
@@ -593,12 +1494,13 @@ impl ServerState {
 
         let mut channels = self.channels.write().await;
         let channel = channels
-            .get_mut(&channel_id)
+            .get(&channel_id)
             .ok_or_else(|| anyhow::anyhow!("channel does not exist"))?;
 
-        if channel.created_by != Some(requester_id) {
-            anyhow::bail!("only the channel creator can kick users");
+        if !self.user_has(&channels, requester_id, channel, Permission::Kick) {
+            anyhow::bail!("you do not have permission to kick users from this channel");
         }
+        let channel = channels.get_mut(&channel_id).expect("channel checked above");
 
         if !channel.members.remove(&target_id) {
             anyhow::bail!("user is not in this channel");
@@ -630,7 +1532,7 @@ impl ServerState {
         }
     }
 
-    /// Add a user to a channel's invite list (creator only).
+    /// Add a user to a channel's invite list (requires Edit).
     /// Returns (channel_name, inviter_username) for the notification.
     pub async fn add_invite(
         &self,
@@ -644,12 +1546,13 @@ impl ServerState {
 
         let mut channels = self.channels.write().await;
         let channel = channels
-            .get_mut(&channel_id)
+            .get(&channel_id)
             .ok_or_else(|| anyhow::anyhow!("channel does not exist"))?;
 
-        if channel.created_by != Some(requester_id) {
-            anyhow::bail!("only the channel creator can invite users");
+        if !self.user_has(&channels, requester_id, channel, Permission::Edit) {
+            anyhow::bail!("you do not have permission to invite users to this channel");
         }
+        let channel = channels.get_mut(&channel_id).expect("channel checked above");
 
         if channel.members.contains(&target_id) {
             anyhow::bail!("user is already in this channel");
@@ -659,49 +1562,465 @@ impl ServerState {
             anyhow::bail!("invite list is full (max 50)");
         }
 
-        let channel_name = channel.info.name.clone();
-        channel.invited_users.insert(target_id);
-
-        // Look up inviter's username
-        let inviter_name = self
-            .user_to_session
-            .get(&requester_id)
-            .and_then(|sid| self.sessions.get(&*sid).map(|s| s.username.clone()))
-            .unwrap_or_else(|| "Unknown".into());
-
-        Ok((channel_name, inviter_name))
-    }
+        let channel_name = channel.info.name.clone();
+        channel.invited_users.insert(target_id);
+
+        // Look up inviter's username
+        let inviter_name = self
+            .user_to_session
+            .get(&requester_id)
+            .and_then(|sid| self.sessions.get(&*sid).map(|s| s.username.clone()))
+            .unwrap_or_else(|| "Unknown".into());
+
+        Ok((channel_name, inviter_name))
+    }
+
+    /// Remove a user from a channel's invite list.
+    pub async fn remove_invite(&self, channel_id: ChannelId, user_id: UserId) {
+        let mut channels = self.channels.write().await;
+        if let Some(channel) = channels.get_mut(&channel_id) {
+            channel.invited_users.remove(&user_id);
+        }
+    }
+
+    /// Check if a user is a member of a channel or the channel is public (no password).
+    pub async fn is_channel_public_or_member(
+        &self,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> bool {
+        let channels = self.channels.read().await;
+        match channels.get(&channel_id) {
+            Some(channel) => {
+                password_holder(&channels, channel).is_none() || channel.members.contains(&user_id)
+            }
+            None => false,
+        }
+    }
+
+    // ── Permission methods ─────────────────────────────────────────────
+
+    /// The server group of a connected user (guest if unknown).
+    pub fn group_of(&self, user_id: UserId) -> ServerGroup {
+        self.user_to_session
+            .get(&user_id)
+            .and_then(|sid| self.sessions.get(&*sid).map(|s| s.group))
+            .unwrap_or_default()
+    }
+
+    /// Effective permissions of a user in a channel: the group's server-wide
+    /// permissions, then the group's overrides from each ancestor the channel
+    /// inherits from (outermost first) and the channel itself, then the
+    /// creator's grants if the user created any of those channels.
+    fn channel_permissions(
+        &self,
+        channels: &HashMap<ChannelId, Channel>,
+        group: ServerGroup,
+        user_id: UserId,
+        channel: &Channel,
+    ) -> Permissions {
+        let mut chain = Vec::new();
+        for ch in lineage(channels, channel) {
+            chain.push(ch);
+            if !ch.inherit_permissions {
+                break;
+            }
+        }
+
+        let mut perms = self.settings().permissions_for_group(group);
+        for ch in chain.iter().rev() {
+            if let Some(ov) = ch.permission_overrides.get(&group) {
+                ov.apply(&mut perms);
+            }
+        }
+        if chain.iter().any(|ch| ch.created_by == Some(user_id)) {
+            apply_creator_grants(&mut perms);
+        }
+        perms
+    }
+
+    fn user_has(
+        &self,
+        channels: &HashMap<ChannelId, Channel>,
+        user_id: UserId,
+        channel: &Channel,
+        permission: Permission,
+    ) -> bool {
+        permission.allowed_by(&self.channel_permissions(channels, self.group_of(user_id), user_id, channel))
+    }
+
+    /// Whether a user holds a server-wide permission (e.g. CreateChannel).
+    pub fn has_server_permission(&self, user_id: UserId, permission: Permission) -> bool {
+        permission.allowed_by(&self.settings().permissions_for_group(self.group_of(user_id)))
+    }
+
+    /// Everything a client needs to render its permissions: its group, its
+    /// server-wide permissions, and every channel where they differ.
+    pub async fn permission_summary(
+        &self,
+        session_id: SessionId,
+    ) -> Option<(ServerGroup, Permissions, Vec<ChannelPermissions>)> {
+        let (user_id, group) = {
+            let session = self.sessions.get(&session_id)?;
+            (session.user_id, session.group)
+        };
+        let server = self.settings().permissions_for_group(group);
+
+        let channels = self.channels.read().await;
+        let mut differing: Vec<ChannelPermissions> = channels
+            .values()
+            .filter_map(|ch| {
+                let perms = self.channel_permissions(&channels, group, user_id, ch);
+                (perms != server).then_some(ChannelPermissions {
+                    channel_id: ch.info.channel_id,
+                    permissions: perms,
+                })
+            })
+            .collect();
+        differing.sort_by_key(|c| c.channel_id);
+        Some((group, server, differing))
+    }
+
+    /// Change a session's group and recompute its cached channel permissions.
+    pub async fn set_group(&self, session_id: SessionId, group: ServerGroup) {
+        let channels = self.channels.read().await;
+        if let Some(mut session) = self.sessions.get_mut(&session_id) {
+            session.group = group;
+            if let Some(ch) = channels.get(&session.channel_id) {
+                session.permissions = self.channel_permissions(&channels, group, session.user_id, ch);
+            }
+        }
+    }
+
+    /// Sessions currently connected that the given ban applies to.
+    pub fn sessions_matching_ban(&self, ban: &Ban) -> Vec<SessionId> {
+        self.sessions
+            .iter()
+            .filter(|s| {
+                let key = if s.identity_verified { s.identity_key.as_deref() } else { None };
+                ban.matches(&s.username, key, s.tcp_peer_ip)
+            })
+            .map(|s| s.session_id)
+            .collect()
+    }
+
+    /// Get the current media key for a channel (if any).
+    /// Returns (key_id, key_bytes) for non-General channels.
+    pub async fn get_channel_media_key(&self, channel_id: ChannelId) -> Option<(u16, [u8; 32])> {
+        let channels = self.channels.read().await;
+        let channel = channels.get(&channel_id)?;
+        let key_bytes = channel.media_key_bytes.as_ref()?;
+        Some((channel.media_key_id, **key_bytes))
+    }
+
+    // ── Moderation methods ─────────────────────────────────────────────
+
+    /// Server-mute or unmute `target_id` (requires Kick in the target's
+    /// channel). Returns whether the state changed.
+    pub async fn set_server_muted(
+        &self,
+        requester_id: UserId,
+        target_id: UserId,
+        muted: bool,
+    ) -> anyhow::Result<bool> {
+        if requester_id == target_id {
+            anyhow::bail!("you cannot server-mute yourself");
+        }
+        let target_sid = self.check_moderation(requester_id, target_id, Permission::Kick).await?;
+        let mut session = self
+            .sessions
+            .get_mut(&target_sid)
+            .ok_or_else(|| anyhow::anyhow!("user not found"))?;
+        Ok(std::mem::replace(&mut session.is_server_muted, muted) != muted)
+    }
+
+    /// Grant or revoke priority speaker for `target_id` (requires Edit in the
+    /// target's channel). Returns whether the state changed.
+    pub async fn set_priority_speaker(
+        &self,
+        requester_id: UserId,
+        target_id: UserId,
+        enabled: bool,
+    ) -> anyhow::Result<bool> {
+        let target_sid = self.check_moderation(requester_id, target_id, Permission::Edit).await?;
+        let mut session = self
+            .sessions
+            .get_mut(&target_sid)
+            .ok_or_else(|| anyhow::anyhow!("user not found"))?;
+        Ok(std::mem::replace(&mut session.is_priority_speaker, enabled) != enabled)
+    }
+
+    /// Check that `requester_id` may move `target_id` into `channel_id`:
+    /// Move is required in both the target's current channel and the
+    /// destination. Passwords and user limits don't apply to moves.
+    pub async fn check_move(
+        &self,
+        requester_id: UserId,
+        target_id: UserId,
+        channel_id: ChannelId,
+    ) -> anyhow::Result<()> {
+        if requester_id == target_id {
+            anyhow::bail!("use join to switch your own channel");
+        }
+        let target_sid = self.check_moderation(requester_id, target_id, Permission::Move).await?;
+        if self.sessions.get(&target_sid).map(|s| s.channel_id) == Some(channel_id) {
+            anyhow::bail!("user is already in that channel");
+        }
+
+        let channels = self.channels.read().await;
+        let channel = channels
+            .get(&channel_id)
+            .ok_or_else(|| anyhow::anyhow!("channel does not exist"))?;
+        if !self.user_has(&channels, requester_id, channel, Permission::Move) {
+            anyhow::bail!("you do not have permission to move users into that channel");
+        }
+        Ok(())
+    }
+
+    /// Check that `requester_id` holds `perm` in the channel `target_id` is
+    /// in, and that a non-admin isn't acting on an admin. Returns the
+    /// target's session.
+    async fn check_moderation(
+        &self,
+        requester_id: UserId,
+        target_id: UserId,
+        perm: Permission,
+    ) -> anyhow::Result<SessionId> {
+        let (target_sid, target_channel) = self
+            .user_to_session
+            .get(&target_id)
+            .and_then(|sid| self.sessions.get(&*sid).map(|s| (s.session_id, s.channel_id)))
+            .ok_or_else(|| anyhow::anyhow!("user not found"))?;
+
+        if requester_id != target_id
+            && self.group_of(target_id) == ServerGroup::Admin
+            && self.group_of(requester_id) != ServerGroup::Admin
+        {
+            anyhow::bail!("you cannot moderate an admin");
+        }
+
+        let channels = self.channels.read().await;
+        let channel = channels
+            .get(&target_channel)
+            .ok_or_else(|| anyhow::anyhow!("channel does not exist"))?;
+        if !self.user_has(&channels, requester_id, channel, perm) {
+            anyhow::bail!("you do not have permission to do that in this user's channel");
+        }
+        Ok(target_sid)
+    }
+
+    // ── Idle methods ───────────────────────────────────────────────────
+
+    /// Note that a session sent voice.
+    pub fn record_voice(&self, session_id: SessionId) {
+        if let Some(mut session) = self.sessions.get_mut(&session_id) {
+            session.last_voice = Instant::now();
+        }
+    }
+
+    /// Note that a session's user did something over the control channel.
+    pub fn record_activity(&self, session_id: SessionId) {
+        if let Some(mut session) = self.sessions.get_mut(&session_id) {
+            session.last_activity = Instant::now();
+        }
+    }
+
+    /// Apply the AFK settings as of `now`. Users outside General and the AFK
+    /// channel who have been inactive for the timeout are marked idle; idle
+    /// users who have been active since are unmarked, and returned to their
+    /// old channel if they are still in the AFK channel within the grace
+    /// window. Nobody is moved here.
+    pub async fn update_idle(&self, now: Instant) -> IdleChanges {
+        let settings = self.settings();
+        let channels = self.channels.read().await;
+        let afk_channel = settings.afk_channel.as_ref().and_then(|name| {
+            let lower = name.trim().to_lowercase();
+            channels
+                .values()
+                .find(|ch| ch.info.name.to_lowercase() == lower)
+                .map(|ch| ch.info.channel_id)
+        });
+        let timeout = std::time::Duration::from_secs(settings.afk_timeout_secs);
+        let grace = std::time::Duration::from_secs(settings.afk_return_grace_secs);
+
+        let mut changes = IdleChanges {
+            afk_channel,
+            ..Default::default()
+        };
+        for mut session in self.sessions.iter_mut() {
+            let last_seen = session.last_voice.max(session.last_activity);
+            match &session.idle {
+                Some(idle) if last_seen > idle.since => {
+                    let back_to = (Some(session.channel_id) == afk_channel
+                        && now.saturating_duration_since(idle.since) <= grace
+                        && channels.contains_key(&idle.return_channel))
+                        .then_some(idle.return_channel);
+                    changes.returned.push((session.user_id, back_to));
+                    session.idle = None;
+                }
+                Some(_) => {}
+                None => {
+                    let Some(afk) = afk_channel else { continue };
+                    if session.channel_id != 0
+                        && session.channel_id != afk
+                        && now.saturating_duration_since(last_seen) >= timeout
+                    {
+                        session.idle = Some(IdleState {
+                            since: now,
+                            return_channel: session.channel_id,
+                        });
+                        changes.went_idle.push(session.user_id);
+                    }
+                }
+            }
+        }
+        changes
+    }
+
+    // ── Whisper methods ────────────────────────────────────────────────
+
+    /// Replace a session's whisper list, issuing a new whisper key so earlier
+    /// recipients cannot decrypt further whispers. An empty list clears it.
+    /// Returns the new (key_id, key) for the whispering user.
+    pub async fn set_whisper_list(
+        &self,
+        session_id: SessionId,
+        targets: Vec<WhisperTarget>,
+    ) -> anyhow::Result<Option<(u16, [u8; 32])>> {
+        let mut targets = targets;
+        let mut seen = HashSet::new();
+        targets.retain(|t| seen.insert(*t));
+        if targets.len() > MAX_WHISPER_TARGETS {
+            anyhow::bail!("whisper list is too long (max {})", MAX_WHISPER_TARGETS);
+        }
+
+        let user_id = self
+            .sessions
+            .get(&session_id)
+            .map(|s| s.user_id)
+            .ok_or_else(|| anyhow::anyhow!("session not found"))?;
+        {
+            let channels = self.channels.read().await;
+            for target in &targets {
+                match *target {
+                    WhisperTarget::User(uid) if uid == user_id => {
+                        anyhow::bail!("you cannot whisper to yourself")
+                    }
+                    WhisperTarget::User(uid) => {
+                        if !self.user_to_session.contains_key(&uid) {
+                            anyhow::bail!("user {} is not connected", uid);
+                        }
+                    }
+                    WhisperTarget::Channel(cid) | WhisperTarget::ChannelTree(cid) => {
+                        if cid == 0 {
+                            anyhow::bail!("voice is disabled in the General channel");
+                        }
+                        let Some(channel) = channels.get(&cid) else {
+                            anyhow::bail!("channel {} does not exist", cid);
+                        };
+                        if !self.may_whisper_into(&channels, user_id, channel) {
+                            anyhow::bail!("you are not allowed to whisper into channel {}", cid);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut session = self
+            .sessions
+            .get_mut(&session_id)
+            .ok_or_else(|| anyhow::anyhow!("session not found"))?;
+        if targets.is_empty() {
+            session.whisper = None;
+            return Ok(None);
+        }
 
-    /// Remove a user from a channel's invite list.
-    pub async fn remove_invite(&self, channel_id: ChannelId, user_id: UserId) {
-        let mut channels = self.channels.write().await;
-        if let Some(channel) = channels.get_mut(&channel_id) {
-            channel.invited_users.remove(&user_id);
+        let mut key_bytes = [0u8; 32];
+        rand::Rng::fill(&mut rand::thread_rng(), &mut key_bytes);
+        let key_id = session.whisper.as_ref().map_or(0, |w| w.key_id.wrapping_add(1));
+        session.whisper = Some(WhisperList {
+            targets,
+            key_id,
+            key_bytes: Zeroizing::new(key_bytes),
+            key_holders: HashSet::new(),
+        });
+        Ok(Some((key_id, key_bytes)))
+    }
+
+    /// Whether a user may whisper into a channel: they are in it, or they
+    /// could join it without a password (it has none, or they are invited).
+    fn may_whisper_into(&self, channels: &HashMap<ChannelId, Channel>, user_id: UserId, channel: &Channel) -> bool {
+        if channel.members.contains(&user_id) {
+            return true;
         }
+        self.user_has(channels, user_id, channel, Permission::Join)
+            && (password_holder(channels, channel).is_none() || channel.invited_users.contains(&user_id))
     }
 
-    /// Check if a user is a member of a channel or the channel is public (no password).
-    pub async fn is_channel_public_or_member(
-        &self,
-        channel_id: ChannelId,
-        user_id: UserId,
-    ) -> bool {
+    /// Sessions that should receive a session's whisper packets, resolved
+    /// against current channel membership. The sender and users in General
+    /// are left out.
+    pub async fn whisper_recipients(&self, session_id: SessionId) -> Vec<SessionId> {
+        let Some((user_id, targets)) = self
+            .sessions
+            .get(&session_id)
+            .and_then(|s| s.whisper.as_ref().map(|w| (s.user_id, w.targets.clone())))
+        else {
+            return Vec::new();
+        };
+
         let channels = self.channels.read().await;
-        match channels.get(&channel_id) {
-            Some(channel) => {
-                channel.password.is_none() || channel.members.contains(&user_id)
+        let mut user_ids: HashSet<UserId> = HashSet::new();
+        for target in targets {
+            match target {
+                WhisperTarget::User(uid) => {
+                    user_ids.insert(uid);
+                }
+                WhisperTarget::Channel(cid) => {
+                    if let Some(ch) = channels.get(&cid) {
+                        if self.may_whisper_into(&channels, user_id, ch) {
+                            user_ids.extend(&ch.members);
+                        }
+                    }
+                }
+                WhisperTarget::ChannelTree(cid) => {
+                    // Sub-channels count only where the whisperer could go
+                    for ch in channels.values() {
+                        if lineage(&channels, ch).iter().any(|c| c.info.channel_id == cid)
+                            && self.may_whisper_into(&channels, user_id, ch)
+                        {
+                            user_ids.extend(&ch.members);
+                        }
+                    }
+                }
             }
-            None => false,
         }
+
+        let mut recipients: Vec<SessionId> = user_ids
+            .into_iter()
+            .filter_map(|uid| self.user_to_session.get(&uid).map(|s| *s))
+            .filter(|&sid| sid != session_id)
+            .filter(|sid| self.sessions.get(sid).is_some_and(|s| s.channel_id != 0))
+            .collect();
+        recipients.sort_unstable();
+        recipients
     }
 
-    /// Get the current media key for a channel (if any).
-    /// Returns (key_id, key_bytes) for non-General channels.
-    pub async fn get_channel_media_key(&self, channel_id: ChannelId) -> Option<(u16, [u8; 32])> {
-        let channels = self.channels.read().await;
-        let channel = channels.get(&channel_id)?;
-        let key_bytes = channel.media_key_bytes.as_ref()?;
-        Some((channel.media_key_id, **key_bytes))
+    /// Record that `recipients` are being sent the current whisper key of
+    /// `session_id`. Returns the key and the recipients that did not have it.
+    pub fn whisper_key_for(
+        &self,
+        session_id: SessionId,
+        recipients: &[SessionId],
+    ) -> Option<(u16, [u8; 32], Vec<SessionId>)> {
+        let mut session = self.sessions.get_mut(&session_id)?;
+        let whisper = session.whisper.as_mut()?;
+        let missing: Vec<SessionId> = recipients
+            .iter()
+            .copied()
+            .filter(|sid| whisper.key_holders.insert(*sid))
+            .collect();
+        Some((whisper.key_id, *whisper.key_bytes, missing))
     }
 
     // ── Screen share methods ───────────────────────────────────────────
@@ -897,12 +2216,14 @@ impl ServerState {
         Ok((sharer_user_id, share.sharer_session_id, old_count, new_count))
     }
 
-    /// Get the UDP addresses of all viewers of a given sharer.
+    /// Get the UDP addresses of all viewers of a given sharer that
+    /// negotiated `capabilities`.
     /// Called from UDP routing to forward video packets only to viewers.
     pub async fn get_screen_share_viewer_addrs(
         &self,
         sharer_user_id: UserId,
         channel_id: ChannelId,
+        capabilities: Capabilities,
     ) -> Vec<SocketAddr> {
         let channels = self.channels.read().await;
         let Some(channel) = channels.get(&channel_id) else {
@@ -918,7 +2239,7 @@ impl ServerState {
             .filter_map(|&vid| {
                 let sid = *self.user_to_session.get(&vid)?;
                 let session = self.sessions.get(&sid)?;
-                session.udp_addr
+                session.capabilities.contains(capabilities).then_some(session.udp_addr?)
             })
             .collect()
     }
@@ -1009,7 +2330,15 @@ mod tests {
     use crate::settings::ServerSettings;
 
     fn make_state() -> ServerState {
-        ServerState::new(&ServerConfig::default(), ServerSettings::default(), Vec::new())
+        ServerState::new(
+            &ServerConfig::default(),
+            ServerSettings::default(),
+            Vec::new(),
+            AccountStore::default(),
+            BanStore::default(),
+            None,
+            None,
+        )
     }
 
     fn add_user(state: &ServerState, username: &str) -> (UserId, SessionId) {
@@ -1023,7 +2352,14 @@ mod tests {
             channel_id: 0,
             is_muted: false,
             is_deafened: false,
+            group: ServerGroup::Guest,
+            permissions: Permissions::default(),
             tcp_tx: tx,
+            disconnect: Arc::new(Notify::new()),
+            resume_token: [user_id as u8; RESUME_TOKEN_LEN],
+            state: SessionState::Live,
+            connection: 0,
+            capabilities: Capabilities::ALL,
             udp_addr: None,
             udp_token: user_id as u64 * 1000,
             tcp_peer_ip: std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST),
@@ -1034,9 +2370,17 @@ mod tests {
             chat_rate: RateLimiter::new(5.0, 5.0),
             create_channel_rate: RateLimiter::new(1.0, 0.2),
             prekey_rate: RateLimiter::new(1.0, 0.2),
+            last_rate_limit_notice: Default::default(),
             is_screen_sharing: false,
             watching_screenshare: None,
+            whisper: None,
+            is_server_muted: false,
+            is_priority_speaker: false,
+            last_voice: Instant::now(),
+            last_activity: Instant::now(),
+            idle: None,
             identity_key: None,
+            identity_verified: false,
             prekeys: Vec::new(),
             signed_prekey_id: None,
             signed_prekey: None,
@@ -1091,6 +2435,48 @@ mod tests {
         assert!(!rl.try_consume());
     }
 
+    #[test]
+    fn rate_limited_requests_are_reported_once() {
+        let state = make_state();
+        let (_, sid) = add_user(&state, "alice");
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        state.sessions.get_mut(&sid).unwrap().tcp_tx = tx;
+
+        assert!(state.try_consume_rate(sid, Limiter::CreateChannel));
+        assert!(!state.try_consume_rate(sid, Limiter::CreateChannel));
+        assert!(!state.try_consume_rate(sid, Limiter::CreateChannel));
+
+        let data = rx.try_recv().unwrap();
+        match voipc_protocol::codec::decode_server_msg(&data[4..]).unwrap() {
+            ServerMessage::RateLimited { limit, retry_after_ms } => {
+                assert_eq!(limit, RateLimitKind::CreateChannel);
+                assert!(retry_after_ms > 4000 && retry_after_ms <= 5000);
+            }
+            other => panic!("expected RateLimited, got {:?}", other),
+        }
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn rate_limit_notices_are_throttled_per_limiter() {
+        let state = make_state();
+        let (_, sid) = add_user(&state, "alice");
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        state.sessions.get_mut(&sid).unwrap().tcp_tx = tx;
+
+        while state.try_consume_rate(sid, Limiter::UdpVoice) {}
+        assert!(state.try_consume_rate(sid, Limiter::CreateChannel));
+        assert!(!state.try_consume_rate(sid, Limiter::CreateChannel));
+
+        let kinds: Vec<RateLimitKind> = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|data| match voipc_protocol::codec::decode_server_msg(&data[4..]).unwrap() {
+                ServerMessage::RateLimited { limit, .. } => limit,
+                other => panic!("expected RateLimited, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(kinds, vec![Limiter::UdpVoice.kind(), RateLimitKind::CreateChannel]);
+    }
+
     // ── ServerState basics ─────────────────────────────────────────────
 
     #[test]
@@ -1137,7 +2523,7 @@ mod tests {
     async fn validate_join_open_channel() {
         let state = make_state();
         let (uid, _) = add_user(&state, "alice");
-        let ch = state.create_channel("Open".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Open".into(), None, uid, None).await.unwrap();
         assert!(state.validate_join(ch.channel_id, None, uid).await.is_ok());
     }
 
@@ -1145,7 +2531,7 @@ mod tests {
     async fn validate_join_wrong_password() {
         let state = make_state();
         let (uid, _) = add_user(&state, "alice");
-        let ch = state.create_channel("Priv".into(), Some("secret".into()), uid).await.unwrap();
+        let ch = state.create_channel("Priv".into(), Some("secret".into()), uid, None).await.unwrap();
         let (uid2, _) = add_user(&state, "bob");
         let err = state.validate_join(ch.channel_id, Some("wrong"), uid2).await;
         assert!(err.unwrap_err().to_string().contains("incorrect"));
@@ -1155,7 +2541,7 @@ mod tests {
     async fn validate_join_correct_password() {
         let state = make_state();
         let (uid, _) = add_user(&state, "alice");
-        let ch = state.create_channel("Priv".into(), Some("secret".into()), uid).await.unwrap();
+        let ch = state.create_channel("Priv".into(), Some("secret".into()), uid, None).await.unwrap();
         let (uid2, _) = add_user(&state, "bob");
         assert!(state.validate_join(ch.channel_id, Some("secret"), uid2).await.is_ok());
     }
@@ -1164,7 +2550,7 @@ mod tests {
     async fn validate_join_full_channel() {
         let state = make_state();
         let (uid, sid) = add_user(&state, "alice");
-        let ch = state.create_channel("Small".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Small".into(), None, uid, None).await.unwrap();
         {
             let mut channels = state.channels.write().await;
             channels.get_mut(&ch.channel_id).unwrap().info.max_users = 1;
@@ -1179,7 +2565,7 @@ mod tests {
     async fn validate_join_invited_bypasses_password() {
         let state = make_state();
         let (uid, _) = add_user(&state, "alice");
-        let ch = state.create_channel("Inv".into(), Some("secret".into()), uid).await.unwrap();
+        let ch = state.create_channel("Inv".into(), Some("secret".into()), uid, None).await.unwrap();
         let (uid2, _) = add_user(&state, "bob");
         {
             let mut channels = state.channels.write().await;
@@ -1188,11 +2574,27 @@ mod tests {
         assert!(state.validate_join(ch.channel_id, None, uid2).await.is_ok());
     }
 
+    #[tokio::test]
+    async fn join_is_refused_if_password_changed_after_validation() {
+        let state = make_state();
+        let (uid, _) = add_user(&state, "alice");
+        let ch = state.create_channel("Priv".into(), Some("secret".into()), uid, None).await.unwrap();
+        let (uid2, sid2) = add_user(&state, "bob");
+        let pass = state.validate_join(ch.channel_id, Some("secret"), uid2).await.unwrap();
+
+        state.set_channel_password(ch.channel_id, uid, Some("other".into())).await.unwrap();
+        let err = state.join_validated(uid2, sid2, ch.channel_id, &pass).await.unwrap_err();
+        assert!(err.to_string().contains("incorrect"));
+
+        let pass = state.validate_join(ch.channel_id, Some("other"), uid2).await.unwrap();
+        state.join_validated(uid2, sid2, ch.channel_id, &pass).await.unwrap();
+    }
+
     #[tokio::test]
     async fn join_channel_adds_member() {
         let state = make_state();
         let (uid, sid) = add_user(&state, "alice");
-        let ch = state.create_channel("Test".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Test".into(), None, uid, None).await.unwrap();
         let others = state.join_channel(uid, sid, ch.channel_id, None).await.unwrap();
         assert!(others.is_empty());
         let channels = state.channels.read().await;
@@ -1205,7 +2607,7 @@ mod tests {
     async fn join_channel_clears_invite() {
         let state = make_state();
         let (uid, _) = add_user(&state, "alice");
-        let ch = state.create_channel("Test".into(), Some("pw".into()), uid).await.unwrap();
+        let ch = state.create_channel("Test".into(), Some("pw".into()), uid, None).await.unwrap();
         let (uid2, sid2) = add_user(&state, "bob");
         {
             let mut channels = state.channels.write().await;
@@ -1220,7 +2622,7 @@ mod tests {
     async fn leave_channel_removes_member() {
         let state = make_state();
         let (uid, sid) = add_user(&state, "alice");
-        let ch = state.create_channel("Test".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Test".into(), None, uid, None).await.unwrap();
         state.join_channel(uid, sid, ch.channel_id, None).await.unwrap();
         let (left_ch, remaining, count) = state.leave_current_channel(uid, sid).await.unwrap();
         assert_eq!(left_ch, ch.channel_id);
@@ -1232,7 +2634,7 @@ mod tests {
     async fn create_channel_succeeds() {
         let state = make_state();
         let (uid, _) = add_user(&state, "alice");
-        let ch = state.create_channel("MyRoom".into(), Some("pw".into()), uid).await.unwrap();
+        let ch = state.create_channel("MyRoom".into(), Some("pw".into()), uid, None).await.unwrap();
         assert_eq!(ch.name, "MyRoom");
         assert!(ch.has_password);
         assert_eq!(ch.created_by, Some(uid));
@@ -1243,8 +2645,8 @@ mod tests {
     async fn create_channel_duplicate_name_fails() {
         let state = make_state();
         let (uid, _) = add_user(&state, "alice");
-        state.create_channel("Dup".into(), None, uid).await.unwrap();
-        let err = state.create_channel("Dup".into(), None, uid).await;
+        state.create_channel("Dup".into(), None, uid, None).await.unwrap();
+        let err = state.create_channel("Dup".into(), None, uid, None).await;
         assert!(err.unwrap_err().to_string().contains("already exists"));
     }
 
@@ -1259,7 +2661,7 @@ mod tests {
     async fn delete_channel_empty_succeeds() {
         let state = make_state();
         let (uid, _) = add_user(&state, "alice");
-        let ch = state.create_channel("ToDelete".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("ToDelete".into(), None, uid, None).await.unwrap();
         assert!(state.delete_channel(ch.channel_id).await.is_ok());
         let channels = state.channels.read().await;
         assert!(!channels.contains_key(&ch.channel_id));
@@ -1271,19 +2673,19 @@ mod tests {
     async fn set_password_by_creator() {
         let state = make_state();
         let (uid, _) = add_user(&state, "alice");
-        let ch = state.create_channel("Room".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Room".into(), None, uid, None).await.unwrap();
         let updated = state.set_channel_password(ch.channel_id, uid, Some("pw".into())).await.unwrap();
-        assert!(updated.has_password);
+        assert!(updated[0].has_password);
     }
 
     #[tokio::test]
     async fn set_password_non_creator_fails() {
         let state = make_state();
         let (uid, _) = add_user(&state, "alice");
-        let ch = state.create_channel("Room".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Room".into(), None, uid, None).await.unwrap();
         let (uid2, _) = add_user(&state, "bob");
         let err = state.set_channel_password(ch.channel_id, uid2, Some("hack".into())).await;
-        assert!(err.unwrap_err().to_string().contains("creator"));
+        assert!(err.unwrap_err().to_string().contains("permission"));
     }
 
     #[tokio::test]
@@ -1298,7 +2700,7 @@ mod tests {
     async fn kick_user_by_creator() {
         let state = make_state();
         let (uid, sid) = add_user(&state, "alice");
-        let ch = state.create_channel("Room".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Room".into(), None, uid, None).await.unwrap();
         state.join_channel(uid, sid, ch.channel_id, None).await.unwrap();
         let (uid2, sid2) = add_user(&state, "bob");
         state.join_channel(uid2, sid2, ch.channel_id, None).await.unwrap();
@@ -1311,7 +2713,7 @@ mod tests {
     async fn kick_self_fails() {
         let state = make_state();
         let (uid, sid) = add_user(&state, "alice");
-        let ch = state.create_channel("Room".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Room".into(), None, uid, None).await.unwrap();
         state.join_channel(uid, sid, ch.channel_id, None).await.unwrap();
         let err = state.kick_user(ch.channel_id, uid, uid).await;
         assert!(err.unwrap_err().to_string().contains("yourself"));
@@ -1321,19 +2723,19 @@ mod tests {
     async fn kick_non_creator_fails() {
         let state = make_state();
         let (uid, sid) = add_user(&state, "alice");
-        let ch = state.create_channel("Room".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Room".into(), None, uid, None).await.unwrap();
         state.join_channel(uid, sid, ch.channel_id, None).await.unwrap();
         let (uid2, sid2) = add_user(&state, "bob");
         state.join_channel(uid2, sid2, ch.channel_id, None).await.unwrap();
         let err = state.kick_user(ch.channel_id, uid2, uid).await;
-        assert!(err.unwrap_err().to_string().contains("creator"));
+        assert!(err.unwrap_err().to_string().contains("permission"));
     }
 
     #[tokio::test]
     async fn add_invite_succeeds() {
         let state = make_state();
         let (uid, _) = add_user(&state, "alice");
-        let ch = state.create_channel("Room".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Room".into(), None, uid, None).await.unwrap();
         let (uid2, _) = add_user(&state, "bob");
         let (ch_name, inviter) = state.add_invite(ch.channel_id, uid, uid2).await.unwrap();
         assert_eq!(ch_name, "Room");
@@ -1346,7 +2748,7 @@ mod tests {
     async fn add_invite_limit() {
         let state = make_state();
         let (uid, _) = add_user(&state, "alice");
-        let ch = state.create_channel("Room".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Room".into(), None, uid, None).await.unwrap();
         for i in 0..50 {
             let (target, _) = add_user(&state, &format!("user{i}"));
             state.add_invite(ch.channel_id, uid, target).await.unwrap();
@@ -1362,7 +2764,7 @@ mod tests {
     async fn start_screen_share() {
         let state = make_state();
         let (uid, sid) = add_user(&state, "alice");
-        let ch = state.create_channel("Room".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Room".into(), None, uid, None).await.unwrap();
         state.join_channel(uid, sid, ch.channel_id, None).await.unwrap();
         let others = state.start_screen_share(uid, sid, ch.channel_id, 720).await.unwrap();
         assert!(others.is_empty());
@@ -1383,7 +2785,7 @@ mod tests {
     async fn stop_screen_share_clears_state() {
         let state = make_state();
         let (uid, sid) = add_user(&state, "alice");
-        let ch = state.create_channel("Room".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Room".into(), None, uid, None).await.unwrap();
         state.join_channel(uid, sid, ch.channel_id, None).await.unwrap();
         state.start_screen_share(uid, sid, ch.channel_id, 720).await.unwrap();
         state.stop_screen_share(uid, sid, ch.channel_id).await.unwrap();
@@ -1396,7 +2798,7 @@ mod tests {
     async fn watch_screen_share_adds_viewer() {
         let state = make_state();
         let (uid, sid) = add_user(&state, "alice");
-        let ch = state.create_channel("Room".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Room".into(), None, uid, None).await.unwrap();
         state.join_channel(uid, sid, ch.channel_id, None).await.unwrap();
         state.start_screen_share(uid, sid, ch.channel_id, 720).await.unwrap();
         let (uid2, sid2) = add_user(&state, "bob");
@@ -1413,7 +2815,7 @@ mod tests {
     async fn cleanup_screen_shares_for_user() {
         let state = make_state();
         let (uid, sid) = add_user(&state, "alice");
-        let ch = state.create_channel("Room".into(), None, uid).await.unwrap();
+        let ch = state.create_channel("Room".into(), None, uid, None).await.unwrap();
         state.join_channel(uid, sid, ch.channel_id, None).await.unwrap();
         state.start_screen_share(uid, sid, ch.channel_id, 720).await.unwrap();
         let (uid2, sid2) = add_user(&state, "bob");
@@ -1424,4 +2826,669 @@ mod tests {
         assert_eq!(cleanup.stopped_sharer_user_id, Some(uid));
         assert!(cleanup.viewers_to_notify_stopped.contains(&sid2));
     }
+
+    // ── Permissions ───────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn moderator_can_kick_in_any_channel() {
+        let state = make_state();
+        let (uid, sid) = add_user(&state, "alice");
+        let ch = state.create_channel("Room".into(), None, uid, None).await.unwrap();
+        state.join_channel(uid, sid, ch.channel_id, None).await.unwrap();
+        let (mod_uid, mod_sid) = add_user(&state, "mod");
+        state.set_group(mod_sid, ServerGroup::Moderator).await;
+        assert!(state.kick_user(ch.channel_id, mod_uid, uid).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn channel_override_denies_join() {
+        let state = make_state();
+        let ch = state.create_channel("Staff".into(), None, 999, None).await.unwrap();
+        {
+            let mut channels = state.channels.write().await;
+            let channel = channels.get_mut(&ch.channel_id).unwrap();
+            channel.permission_overrides.insert(
+                ServerGroup::Guest,
+                PermissionOverride {
+                    join: Some(false),
+                    ..Default::default()
+                },
+            );
+        }
+        let (uid, sid) = add_user(&state, "alice");
+        let err = state.validate_join(ch.channel_id, None, uid).await;
+        assert!(err.unwrap_err().to_string().contains("permission"));
+        assert!(state.join_channel(uid, sid, ch.channel_id, None).await.is_err());
+
+        // Members are unaffected by the guest override
+        state.set_group(sid, ServerGroup::Member).await;
+        assert!(state.join_channel(uid, sid, ch.channel_id, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn join_caches_channel_permissions() {
+        let state = make_state();
+        let (uid, sid) = add_user(&state, "alice");
+        let ch = state.create_channel("Room".into(), None, 999, None).await.unwrap();
+        {
+            let mut channels = state.channels.write().await;
+            channels.get_mut(&ch.channel_id).unwrap().permission_overrides.insert(
+                ServerGroup::Guest,
+                PermissionOverride {
+                    speak: Some(false),
+                    ..Default::default()
+                },
+            );
+        }
+        state.join_channel(uid, sid, ch.channel_id, None).await.unwrap();
+        let perms = state.sessions.get(&sid).unwrap().permissions;
+        assert!(perms.join);
+        assert!(!perms.speak);
+    }
+
+    #[tokio::test]
+    async fn permission_summary_lists_creator_channels() {
+        let state = make_state();
+        let (uid, sid) = add_user(&state, "alice");
+        let ch = state.create_channel("Mine".into(), None, uid, None).await.unwrap();
+        state.create_channel("Theirs".into(), None, 999, None).await.unwrap();
+
+        let (group, server, channels) = state.permission_summary(sid).await.unwrap();
+        assert_eq!(group, ServerGroup::Guest);
+        assert!(!server.kick);
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].channel_id, ch.channel_id);
+        assert!(channels[0].permissions.kick && channels[0].permissions.edit);
+    }
+
+    #[test]
+    fn sessions_matching_ban_by_name_and_ip() {
+        let state = make_state();
+        let (_, alice) = add_user(&state, "alice");
+        let (_, bob) = add_user(&state, "bob");
+
+        let by_name = Ban {
+            id: 1,
+            username: Some("ALICE".into()),
+            identity_key: None,
+            ip: None,
+            reason: String::new(),
+            banned_by: "admin".into(),
+            created_at: 0,
+            expires_at: None,
+        };
+        assert_eq!(state.sessions_matching_ban(&by_name), vec![alice]);
+
+        // Both test users connect from localhost
+        let by_ip = Ban {
+            username: None,
+            ip: Some("127.0.0.0/8".into()),
+            ..by_name
+        };
+        let mut matched = state.sessions_matching_ban(&by_ip);
+        matched.sort();
+        assert_eq!(matched, vec![alice, bob]);
+    }
+
+    #[tokio::test]
+    async fn force_join_bypasses_password_and_permissions() {
+        let state = make_state();
+        let (uid, sid) = add_user(&state, "alice");
+        let ch = state
+            .create_channel("Locked".into(), Some("secret".into()), 999, None)
+            .await
+            .unwrap();
+        assert!(state.join_channel(uid, sid, ch.channel_id, None).await.is_err());
+        state.force_join_channel(uid, sid, ch.channel_id).await.unwrap();
+        assert_eq!(state.sessions.get(&sid).unwrap().channel_id, ch.channel_id);
+    }
+
+    #[tokio::test]
+    async fn persistent_channels_are_saved_and_removed() {
+        let dir = std::env::temp_dir().join(format!("voipc-channels-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("channels.json");
+        let state = ServerState::new(
+            &ServerConfig::default(),
+            ServerSettings::default(),
+            Vec::new(),
+            AccountStore::default(),
+            BanStore::default(),
+            Some(path.clone()),
+            None,
+        );
+
+        let entry = ChannelEntry {
+            name: "Music".into(),
+            description: String::new(),
+            password: Some("secret".into()),
+            password_hash: None,
+            max_users: 0,
+            permissions: HashMap::new(),
+            ..Default::default()
+        };
+        let info = state.create_persistent_channel(entry.clone()).await.unwrap();
+        assert!(info.has_password);
+        assert!(state.create_persistent_channel(entry).await.is_err());
+
+        let saved = crate::channels::load_and_prepare_channels(&path).unwrap();
+        assert_eq!(saved.len(), 1);
+        assert!(saved[0].password.is_none());
+        assert!(saved[0].password_hash.is_some());
+
+        let (uid, sid) = add_user(&state, "alice");
+        state.join_channel(uid, sid, info.channel_id, Some("secret")).await.unwrap();
+        assert_eq!(state.remove_channel(info.channel_id).await.unwrap().members, vec![uid]);
+        assert!(crate::channels::load_and_prepare_channels(&path).unwrap().is_empty());
+        assert!(state.remove_channel(0).await.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn permanent_channels_are_saved_with_hashed_password() {
+        let dir = std::env::temp_dir().join(format!("voipc-permanent-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("channels.json");
+        let state = ServerState::new(
+            &ServerConfig::default(),
+            ServerSettings::default(),
+            Vec::new(),
+            AccountStore::default(),
+            BanStore::default(),
+            Some(path.clone()),
+            None,
+        );
+        let (alice, alice_sid) = add_user(&state, "alice");
+        let temp = state.create_channel("Temp".into(), None, alice, None).await.unwrap();
+
+        let info = state
+            .create_permanent_channel("Lounge".into(), Some("pw".into()), alice, None)
+            .await
+            .unwrap();
+        assert_eq!(info.created_by, Some(alice));
+        assert!(info.has_password);
+        assert!(state
+            .create_permanent_channel("Nested".into(), None, alice, Some(temp.channel_id))
+            .await
+            .is_err());
+        state
+            .create_permanent_channel("Corner".into(), None, alice, Some(info.channel_id))
+            .await
+            .unwrap();
+
+        let saved = crate::channels::load_and_prepare_channels(&path).unwrap();
+        let names: Vec<&str> = saved.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["Lounge", "Corner"]);
+        assert!(crate::channels::verify_password("pw", saved[0].password_hash.as_deref().unwrap()));
+        assert_eq!(saved[1].parent.as_deref(), Some("Lounge"));
+
+        // Persistent, so never auto-deleted, and the password still works
+        assert!(state.delete_channel(info.channel_id).await.is_err());
+        state.join_channel(alice, alice_sid, info.channel_id, Some("pw")).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn channel_topic_edits_require_edit_and_are_saved() {
+        let dir = std::env::temp_dir().join(format!("voipc-topic-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("channels.json");
+        let state = ServerState::new(
+            &ServerConfig::default(),
+            ServerSettings::default(),
+            Vec::new(),
+            AccountStore::default(),
+            BanStore::default(),
+            Some(path.clone()),
+            None,
+        );
+        let info = state
+            .create_persistent_channel(ChannelEntry {
+                name: "Music".into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let (alice, alice_sid) = add_user(&state, "alice");
+
+        assert!(state
+            .set_channel_topic(info.channel_id, alice, "Tunes".into(), "Album night".into())
+            .await
+            .is_err());
+        state.set_group(alice_sid, ServerGroup::Moderator).await;
+        assert!(state
+            .set_channel_topic(info.channel_id, alice, String::new(), "a\tb".into())
+            .await
+            .is_err());
+        let updated = state
+            .set_channel_topic(info.channel_id, alice, "Tunes".into(), " Album night ".into())
+            .await
+            .unwrap();
+        assert_eq!(updated.topic, "Album night");
+
+        let saved = crate::channels::load_and_prepare_channels(&path).unwrap();
+        assert_eq!(saved[0].description, "Tunes");
+        assert_eq!(saved[0].topic, "Album night");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn password_change_is_undone_if_it_cannot_be_saved() {
+        let dir = std::env::temp_dir().join(format!("voipc-password-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state = ServerState::new(
+            &ServerConfig::default(),
+            ServerSettings::default(),
+            Vec::new(),
+            AccountStore::default(),
+            BanStore::default(),
+            Some(dir.join("channels.json")),
+            None,
+        );
+        let parent = state.create_persistent_channel(tree_entry("Music", None)).await.unwrap();
+        let child = state
+            .create_persistent_channel(tree_entry("Jazz", Some("Music")))
+            .await
+            .unwrap();
+        let (alice, alice_sid) = add_user(&state, "alice");
+        state.set_group(alice_sid, ServerGroup::Moderator).await;
+
+        // The channels file can no longer be written
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(state
+            .set_channel_password(parent.channel_id, alice, Some("secret".into()))
+            .await
+            .is_err());
+        let channels = state.channels.read().await;
+        assert!(channels[&parent.channel_id].password.is_none());
+        assert!(!channels[&parent.channel_id].info.has_password);
+        assert!(!channels[&child.channel_id].info.has_password);
+    }
+
+    // ── Sub-channels ───────────────────────────────────────────────────
+
+    fn tree_entry(name: &str, parent: Option<&str>) -> ChannelEntry {
+        ChannelEntry {
+            name: name.into(),
+            parent: parent.map(Into::into),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn sub_channel_inherits_parent_overrides() {
+        let state = make_state();
+        let parent = state.create_channel("Staff".into(), None, 999, None).await.unwrap();
+        let child = state
+            .create_channel("Standup".into(), None, 999, Some(parent.channel_id))
+            .await
+            .unwrap();
+        assert_eq!(child.parent_id, Some(parent.channel_id));
+        {
+            let mut channels = state.channels.write().await;
+            channels.get_mut(&parent.channel_id).unwrap().permission_overrides.insert(
+                ServerGroup::Guest,
+                PermissionOverride {
+                    join: Some(false),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let (uid, sid) = add_user(&state, "alice");
+        assert!(state.join_channel(uid, sid, child.channel_id, None).await.is_err());
+
+        // Without inheritance only the child's own overrides apply
+        state.channels.write().await.get_mut(&child.channel_id).unwrap().inherit_permissions = false;
+        assert!(state.join_channel(uid, sid, child.channel_id, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn sub_channel_inherits_parent_password() {
+        let mut parent = tree_entry("Staff", None);
+        parent.password_hash = Some(crate::channels::hash_password("secret"));
+        let mut inheriting = tree_entry("Standup", Some("Staff"));
+        inheriting.inherit_password = true;
+        let state = ServerState::new(
+            &ServerConfig::default(),
+            ServerSettings::default(),
+            vec![parent, inheriting, tree_entry("Open", Some("Staff"))],
+            AccountStore::default(),
+            BanStore::default(),
+            None,
+            None,
+        );
+        let list = state.channel_list().await;
+        assert_eq!(list[2].parent_id, Some(1));
+        assert!(list[2].has_password);
+        assert!(!list[3].has_password);
+
+        let (uid, sid) = add_user(&state, "alice");
+        assert!(state.validate_join(2, None, uid).await.is_err());
+        assert!(state.join_channel(uid, sid, 2, Some("secret")).await.is_ok());
+        assert!(state.join_channel(uid, sid, 3, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn parent_with_sub_channels_is_not_auto_deleted() {
+        let state = make_state();
+        let (uid, _) = add_user(&state, "alice");
+        let parent = state.create_channel("Parent".into(), None, uid, None).await.unwrap();
+        let child = state
+            .create_channel("Child".into(), None, uid, Some(parent.channel_id))
+            .await
+            .unwrap();
+
+        assert!(state.delete_channel(parent.channel_id).await.is_err());
+        assert_eq!(state.delete_channel(child.channel_id).await.unwrap(), Some(parent.channel_id));
+        assert_eq!(state.delete_channel(parent.channel_id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn sub_channel_depth_is_limited() {
+        let state = make_state();
+        let mut parent_id = None;
+        for i in 0..MAX_CHANNEL_DEPTH {
+            let info = state.create_channel(format!("L{}", i), None, 999, parent_id).await.unwrap();
+            parent_id = Some(info.channel_id);
+        }
+        assert!(state.create_channel("Deep".into(), None, 999, parent_id).await.is_err());
+        assert!(state.create_channel("Lobby".into(), None, 999, Some(0)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn removing_a_parent_reparents_children() {
+        let state = make_state();
+        let top = state.create_channel("Top".into(), None, 999, None).await.unwrap();
+        let mid = state.create_channel("Mid".into(), None, 999, Some(top.channel_id)).await.unwrap();
+        let leaf = state.create_channel("Leaf".into(), None, 999, Some(mid.channel_id)).await.unwrap();
+
+        let removed = state.remove_channel(mid.channel_id).await.unwrap();
+        assert_eq!(removed.updated.len(), 1);
+        assert_eq!(removed.updated[0].channel_id, leaf.channel_id);
+        assert_eq!(removed.updated[0].parent_id, Some(top.channel_id));
+    }
+
+    // ── Moderation ─────────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn server_mute_requires_kick_and_spares_admins() {
+        let state = make_state();
+        let ch = state.create_channel("Room".into(), None, 999, None).await.unwrap();
+        let (alice, alice_sid) = add_user(&state, "alice");
+        let (bob, bob_sid) = add_user(&state, "bob");
+        let (admin, admin_sid) = add_user(&state, "root");
+        state.join_channel(bob, bob_sid, ch.channel_id, None).await.unwrap();
+        state.set_group(admin_sid, ServerGroup::Admin).await;
+
+        assert!(state.set_server_muted(alice, bob, true).await.is_err());
+        state.set_group(alice_sid, ServerGroup::Moderator).await;
+        assert!(state.set_server_muted(alice, bob, true).await.unwrap());
+        assert!(!state.set_server_muted(alice, bob, true).await.unwrap());
+        assert!(state.sessions.get(&bob_sid).unwrap().user_info().is_server_muted);
+
+        assert!(state.set_server_muted(alice, alice, true).await.is_err());
+        assert!(state.set_server_muted(alice, admin, true).await.is_err());
+        assert!(state.set_server_muted(admin, alice, true).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn channel_creator_can_grant_priority_speaker() {
+        let state = make_state();
+        let (alice, alice_sid) = add_user(&state, "alice");
+        let (bob, bob_sid) = add_user(&state, "bob");
+        let ch = state.create_channel("Stage".into(), None, alice, None).await.unwrap();
+        state.join_channel(alice, alice_sid, ch.channel_id, None).await.unwrap();
+
+        // Bob is still in General, where Alice has no Edit
+        assert!(state.set_priority_speaker(alice, bob, true).await.is_err());
+        state.join_channel(bob, bob_sid, ch.channel_id, None).await.unwrap();
+        assert!(state.set_priority_speaker(alice, bob, true).await.unwrap());
+        assert!(state.set_priority_speaker(alice, alice, true).await.unwrap());
+        assert!(state.set_priority_speaker(bob, alice, false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn move_requires_move_in_both_channels() {
+        let state = make_state();
+        let (alice, alice_sid) = add_user(&state, "alice");
+        let (bob, bob_sid) = add_user(&state, "bob");
+        let (admin, admin_sid) = add_user(&state, "root");
+        let mine = state.create_channel("Mine".into(), None, alice, None).await.unwrap();
+        let other = state.create_channel("Other".into(), None, 999, None).await.unwrap();
+        state.set_group(admin_sid, ServerGroup::Admin).await;
+
+        // Alice has no Move in General, where Bob is
+        assert!(state.check_move(alice, bob, mine.channel_id).await.is_err());
+        state.set_group(alice_sid, ServerGroup::Moderator).await;
+        state.check_move(alice, bob, mine.channel_id).await.unwrap();
+        state.check_move(alice, bob, other.channel_id).await.unwrap();
+
+        state.join_channel(bob, bob_sid, other.channel_id, None).await.unwrap();
+        assert!(state.check_move(alice, bob, other.channel_id).await.is_err());
+        assert!(state.check_move(alice, bob, 9999).await.is_err());
+        assert!(state.check_move(alice, alice, other.channel_id).await.is_err());
+        assert!(state.check_move(alice, admin, other.channel_id).await.is_err());
+        state.check_move(admin, alice, other.channel_id).await.unwrap();
+    }
+
+    // ── Idle ───────────────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn idle_users_are_marked_and_returned_within_grace() {
+        let state = make_state();
+        let afk = state.create_channel("AFK".into(), None, 999, None).await.unwrap();
+        let room = state.create_channel("Room".into(), None, 999, None).await.unwrap();
+        let (alice, alice_sid) = add_user(&state, "alice");
+        let (_bob, _) = add_user(&state, "bob");
+        state.join_channel(alice, alice_sid, room.channel_id, None).await.unwrap();
+
+        let start = Instant::now();
+        let later = start + std::time::Duration::from_secs(1800);
+        // Without an AFK channel nobody goes idle
+        assert!(state.update_idle(later).await.went_idle.is_empty());
+
+        state.set_settings(ServerSettings {
+            afk_channel: Some("afk".into()),
+            ..Default::default()
+        });
+        assert!(state.update_idle(start).await.went_idle.is_empty());
+        // Bob is in General, which is exempt
+        let changes = state.update_idle(later).await;
+        assert_eq!(changes.afk_channel, Some(afk.channel_id));
+        assert_eq!(changes.went_idle, vec![alice]);
+        assert!(state.sessions.get(&alice_sid).unwrap().user_info().is_idle);
+        assert!(state.update_idle(later).await.went_idle.is_empty());
+
+        // Active again inside the grace window, while still in the AFK channel
+        state.leave_current_channel(alice, alice_sid).await;
+        state.force_join_channel(alice, alice_sid, afk.channel_id).await.unwrap();
+        state.sessions.get_mut(&alice_sid).unwrap().last_activity = later + std::time::Duration::from_secs(1);
+        let changes = state.update_idle(later + std::time::Duration::from_secs(60)).await;
+        assert_eq!(changes.returned, vec![(alice, Some(room.channel_id))]);
+        assert!(!state.sessions.get(&alice_sid).unwrap().user_info().is_idle);
+
+        // Past the grace window they stay where they are
+        state.sessions.get_mut(&alice_sid).unwrap().idle = Some(IdleState {
+            since: later,
+            return_channel: room.channel_id,
+        });
+        let changes = state.update_idle(later + std::time::Duration::from_secs(3600)).await;
+        assert_eq!(changes.returned, vec![(alice, None)]);
+    }
+
+    // ── Whisper ────────────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn whisper_recipients_resolve_users_channels_and_subtrees() {
+        let state = make_state();
+        let top = state.create_channel("Top".into(), None, 999, None).await.unwrap();
+        let sub = state.create_channel("Sub".into(), None, 999, Some(top.channel_id)).await.unwrap();
+        let other = state.create_channel("Other".into(), None, 999, None).await.unwrap();
+
+        let (alice, alice_sid) = add_user(&state, "alice");
+        let (bob, bob_sid) = add_user(&state, "bob");
+        let (carol, carol_sid) = add_user(&state, "carol");
+        let (dave, dave_sid) = add_user(&state, "dave");
+        let (_, erin_sid) = add_user(&state, "erin");
+        state.join_channel(alice, alice_sid, top.channel_id, None).await.unwrap();
+        state.join_channel(bob, bob_sid, top.channel_id, None).await.unwrap();
+        state.join_channel(carol, carol_sid, sub.channel_id, None).await.unwrap();
+        state.join_channel(dave, dave_sid, other.channel_id, None).await.unwrap();
+
+        state
+            .set_whisper_list(alice_sid, vec![WhisperTarget::Channel(top.channel_id)])
+            .await
+            .unwrap();
+        assert_eq!(state.whisper_recipients(alice_sid).await, vec![bob_sid]);
+
+        state
+            .set_whisper_list(alice_sid, vec![WhisperTarget::ChannelTree(top.channel_id)])
+            .await
+            .unwrap();
+        assert_eq!(state.whisper_recipients(alice_sid).await, vec![bob_sid, carol_sid]);
+
+        // Users in General are never whispered to
+        let erin = state.sessions.get(&erin_sid).unwrap().user_id;
+        state
+            .set_whisper_list(alice_sid, vec![WhisperTarget::User(dave), WhisperTarget::User(erin)])
+            .await
+            .unwrap();
+        assert_eq!(state.whisper_recipients(alice_sid).await, vec![dave_sid]);
+    }
+
+    #[tokio::test]
+    async fn whisper_list_is_validated() {
+        let state = make_state();
+        let (alice, alice_sid) = add_user(&state, "alice");
+        assert!(state.set_whisper_list(alice_sid, vec![WhisperTarget::User(alice)]).await.is_err());
+        assert!(state.set_whisper_list(alice_sid, vec![WhisperTarget::User(4242)]).await.is_err());
+        assert!(state.set_whisper_list(alice_sid, vec![WhisperTarget::Channel(0)]).await.is_err());
+        assert!(state.set_whisper_list(alice_sid, vec![WhisperTarget::ChannelTree(77)]).await.is_err());
+
+        let too_many = (1..=MAX_WHISPER_TARGETS as u32 + 1).map(WhisperTarget::Channel).collect();
+        assert!(state.set_whisper_list(alice_sid, too_many).await.is_err());
+        assert!(state.set_whisper_list(alice_sid, Vec::new()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn whisper_into_locked_channel_is_refused() {
+        let state = make_state();
+        let locked = state.create_channel("Locked".into(), Some("secret".into()), 999, None).await.unwrap();
+        let inner = state.create_channel("Inner".into(), None, 999, Some(locked.channel_id)).await.unwrap();
+        {
+            let mut channels = state.channels.write().await;
+            channels.get_mut(&inner.channel_id).unwrap().inherit_password = true;
+        }
+        let (alice, alice_sid) = add_user(&state, "alice");
+        let (bob, bob_sid) = add_user(&state, "bob");
+        state.join_channel(bob, bob_sid, locked.channel_id, Some("secret")).await.unwrap();
+
+        for target in [WhisperTarget::Channel(locked.channel_id), WhisperTarget::ChannelTree(locked.channel_id)] {
+            let err = state.set_whisper_list(alice_sid, vec![target]).await.unwrap_err();
+            assert!(err.to_string().contains("not allowed"));
+        }
+
+        // Members and invited users may
+        state
+            .set_whisper_list(bob_sid, vec![WhisperTarget::Channel(locked.channel_id)])
+            .await
+            .unwrap();
+        {
+            let mut channels = state.channels.write().await;
+            channels.get_mut(&locked.channel_id).unwrap().invited_users.insert(alice);
+        }
+        state
+            .set_whisper_list(alice_sid, vec![WhisperTarget::Channel(locked.channel_id)])
+            .await
+            .unwrap();
+        assert_eq!(state.whisper_recipients(alice_sid).await, vec![bob_sid]);
+    }
+
+    #[tokio::test]
+    async fn whisper_key_rotates_and_tracks_holders() {
+        let state = make_state();
+        let ch = state.create_channel("Room".into(), None, 999, None).await.unwrap();
+        let (_, alice_sid) = add_user(&state, "alice");
+        let (bob, bob_sid) = add_user(&state, "bob");
+        state.join_channel(bob, bob_sid, ch.channel_id, None).await.unwrap();
+
+        let target = vec![WhisperTarget::User(bob)];
+        let (id0, key0) = state.set_whisper_list(alice_sid, target.clone()).await.unwrap().unwrap();
+        let (_, _, missing) = state.whisper_key_for(alice_sid, &[bob_sid]).unwrap();
+        assert_eq!(missing, vec![bob_sid]);
+        let (_, _, missing) = state.whisper_key_for(alice_sid, &[bob_sid]).unwrap();
+        assert!(missing.is_empty());
+
+        let (id1, key1) = state.set_whisper_list(alice_sid, target).await.unwrap().unwrap();
+        assert_eq!(id1, id0 + 1);
+        assert_ne!(key0, key1);
+        let (_, key, missing) = state.whisper_key_for(alice_sid, &[bob_sid]).unwrap();
+        assert_eq!(missing, vec![bob_sid]);
+        assert_eq!(key, key1);
+    }
+
+    #[tokio::test]
+    async fn suspended_session_is_resumed_with_its_token() {
+        let state = make_state();
+        let (alice, sid) = add_user(&state, "Alice");
+        let addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        state.sessions.get_mut(&sid).unwrap().udp_addr = Some(addr);
+        state.addr_to_session.insert(addr, sid);
+        let token = state.sessions.get(&sid).unwrap().resume_token;
+        let timer = tokio::spawn(std::future::pending());
+        assert!(state.suspend_session(sid, 0, timer));
+
+        let ip: IpAddr = "10.0.0.2".parse().unwrap();
+        assert!(state.resume_session("alice", &[0; RESUME_TOKEN_LEN], ip, Capabilities::ALL).is_none());
+        assert!(state.resume_session("alice", &token[..16], ip, Capabilities::ALL).is_none());
+
+        let resumed = state.resume_session("alice", &token, ip, Capabilities::WHISPER).unwrap();
+        assert_eq!((resumed.user_id, resumed.session_id), (alice, sid));
+        assert_eq!(resumed.udp_token, alice as u64 * 1000);
+        assert_eq!(resumed.connection, 1);
+        assert_ne!(resumed.resume_token, token);
+        {
+            let session = state.sessions.get(&sid).unwrap();
+            assert!(matches!(session.state, SessionState::Live));
+            assert!(session.udp_addr.is_none());
+            assert_eq!(session.tcp_peer_ip, ip);
+        }
+        // The new connection's capabilities apply
+        assert!(state.has_capabilities(sid, Capabilities::WHISPER));
+        assert!(!state.has_capabilities(sid, Capabilities::SCREEN_SHARE));
+        assert!(!state.addr_to_session.contains_key(&addr));
+        assert!(!state.take_suspended(sid));
+
+        // The old connection must not suspend or end the session
+        assert!(state.is_superseded(sid, 0));
+        assert!(!state.is_superseded(sid, 1));
+        assert!(!state.suspend_session(sid, 0, tokio::spawn(std::future::pending())));
+        assert!(state.resume_session("alice", &token, ip, Capabilities::ALL).is_none());
+    }
+
+    #[tokio::test]
+    async fn session_being_cleaned_up_is_not_resumed() {
+        let state = make_state();
+        let (_alice, sid) = add_user(&state, "Alice");
+        let token = state.sessions.get(&sid).unwrap().resume_token;
+        let ip: IpAddr = "10.0.0.2".parse().unwrap();
+
+        // The grace timer fires and starts cleaning up just as the client
+        // comes back
+        assert!(state.suspend_session(sid, 0, tokio::spawn(std::future::pending())));
+        assert!(state.take_suspended(sid));
+        assert!(state.resume_session("alice", &token, ip, Capabilities::ALL).is_none());
+        assert_eq!(state.sessions.get(&sid).unwrap().connection, 0);
+        assert!(!state.take_suspended(sid));
+        assert!(!state.begin_cleanup(sid, 0));
+
+        // Same when the old connection closes for good while still open
+        let (_bob, sid) = add_user(&state, "Bob");
+        let token = state.sessions.get(&sid).unwrap().resume_token;
+        assert!(state.begin_cleanup(sid, 0));
+        assert!(state.resume_session("bob", &token, ip, Capabilities::ALL).is_none());
+        assert!(!state.suspend_session(sid, 0, tokio::spawn(std::future::pending())));
+        assert_eq!(state.connected_count(), 0);
+    }
 }
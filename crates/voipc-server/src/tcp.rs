@@ -11,17 +11,50 @@ use tokio_rustls::server::TlsStream;
 use tracing::{debug, error, info, warn};
 
 use voipc_protocol::codec::{
-    decode_client_msg, encode_server_msg, try_decode_frame, APP_VERSION, PROTOCOL_VERSION,
+    auth_challenge_payload, decode_client_msg, encode_server_msg, negotiate_version, pack_frame,
+    try_decode_frame, AUTH_CHALLENGE_LEN, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
 };
 use voipc_protocol::messages::{ClientMessage, ServerMessage};
 use voipc_protocol::types::*;
 
-use crate::state::ServerState;
+use crate::accounts::{encode_hex, verify_identity_signature};
+use crate::audit::{outcome, AuditAction, AuditEntry};
+use crate::bans::{now_secs, Ban};
+use crate::idle;
+use crate::metrics::{AuthFailure, Limiter};
+use crate::permissions::Permission;
+use crate::state::{JoinPass, ServerState, SessionState, UserSession, RESUME_TOKEN_LEN};
+use crate::virtual_server::VirtualServers;
+
+/// Longest ban reason kept, in characters.
+const MAX_BAN_REASON_LEN: usize = 128;
+/// Longest unverified username written to the audit log, in characters.
+const MAX_AUDIT_NAME_LEN: usize = 64;
+/// Most bans sent in one `BanList` (newest first kept), so the message stays
+/// under `MAX_MSG_SIZE` even with long reasons.
+const MAX_BAN_LIST_LEN: usize = 50;
+
+/// A session a connection logged in to.
+struct Login {
+    user_id: UserId,
+    session_id: SessionId,
+    /// Which of the session's connections this is (see `UserSession::connection`).
+    connection: u64,
+    /// True if an existing session was resumed.
+    resumed: bool,
+}
+
+/// What a connection agreed on with the client during authentication.
+#[derive(Clone, Copy)]
+struct Negotiated {
+    protocol_version: u32,
+    capabilities: Capabilities,
+}
 
 /// Handle a single TCP client connection (already TLS-wrapped).
 pub async fn handle_connection(
     mut tls_stream: TlsStream<TcpStream>,
-    state: Arc<ServerState>,
+    servers: Arc<VirtualServers>,
 ) {
     let peer_socket_addr = match tls_stream.get_ref().0.peer_addr() {
         Ok(addr) => addr,
@@ -37,34 +70,72 @@ pub async fn handle_connection(
 
     // --- Authentication phase (with timeout) ---
     let mut buf = BytesMut::with_capacity(4096);
-    let auth_result = tokio::time::timeout(
-        Duration::from_secs(5),
-        authenticate(&mut tls_stream, &mut buf, &state, &peer_addr, tcp_peer_ip),
-    )
+    let mut claimed_username = None;
+    // Failures before a server is chosen are counted against the default one
+    let mut state = servers.default_state().clone();
+    let auth_result = tokio::time::timeout(Duration::from_secs(5), async {
+        let msg = read_auth_msg(&mut tls_stream, &mut buf).await?;
+        state = select_server(&servers, &msg, &mut tls_stream).await?;
+        authenticate(
+            &mut tls_stream,
+            &mut buf,
+            &state,
+            msg,
+            &peer_addr,
+            tcp_peer_ip,
+            &mut claimed_username,
+        )
+        .await
+    })
     .await;
-    let (user_id, session_id) = match auth_result {
-        Ok(Ok(ids)) => ids,
+    let auth_outcome = match &auth_result {
+        Ok(result) => outcome(result),
+        Err(_) => Err("timed out".into()),
+    };
+    let action = if auth_outcome.is_ok() {
+        AuditAction::AuthSuccess
+    } else {
+        AuditAction::AuthFailure
+    };
+    state.audit.record(AuditEntry {
+        peer: Some(peer_addr.clone()),
+        ..AuditEntry::new(
+            action,
+            claimed_username.unwrap_or_else(|| "unknown".into()),
+            None,
+            auth_outcome,
+        )
+    });
+    let login = match auth_result {
+        Ok(Ok(login)) => login,
         Ok(Err(e)) => {
             warn!(peer = %peer_addr, "authentication failed: {}", e);
             return;
         }
         Err(_) => {
+            state.metrics.auth_failed(AuthFailure::Timeout);
             warn!(peer = %peer_addr, "authentication timed out");
             return;
         }
     };
 
-    info!(peer = %peer_addr, user_id, session_id, "user authenticated");
+    let Login { user_id, session_id, connection, resumed } = login;
+    info!(peer = %peer_addr, user_id, session_id, resumed, "user authenticated");
 
     // --- Split into reader/writer ---
     let (read_half, mut write_half) = tokio::io::split(tls_stream);
 
-    // Writer task: receives serialized messages from a channel and writes to TCP
+    // Writer task: receives serialized messages from a channel and writes to TCP,
+    // compressed and chunked as negotiated
     let (tx, mut rx) = mpsc::channel::<Vec<u8>>(64);
+    let capabilities = state
+        .sessions
+        .get(&session_id)
+        .map_or(Capabilities::NONE, |s| s.capabilities);
 
     let writer_handle = tokio::spawn(async move {
         while let Some(data) = rx.recv().await {
-            if let Err(e) = write_half.write_all(&data).await {
+            if let Err(e) = write_half.write_all(&pack_frame(data, capabilities)).await {
                 error!("TCP write error: {}", e);
                 break;
             }
@@ -72,18 +143,41 @@ pub async fn handle_connection(
     });
 
     // Store the sender in the session
-    if let Some(mut session) = state.sessions.get_mut(&session_id) {
-        session.tcp_tx = tx.clone();
+    let disconnect = match state.sessions.get_mut(&session_id) {
+        Some(mut session) => {
+            session.tcp_tx = tx.clone();
+            session.disconnect.clone()
+        }
+        None => Arc::new(tokio::sync::Notify::new()),
+    };
+    let mut forced_disconnect = false;
+    let mut said_goodbye = false;
+
+    if resumed {
+        // Still in its channel; bring the client up to date with what it
+        // missed while away
+        let channel_list = state.channel_list().await;
+        let _ = send_msg(&tx, &ServerMessage::ChannelList { channels: channel_list }).await;
+        send_channel_state(&state, session_id, &tx).await;
+    } else {
+        // Welcome message, ahead of everything else
+        let motd = state.settings().motd.trim().to_string();
+        if !motd.is_empty() {
+            let _ = send_msg(&tx, &ServerMessage::Motd { message: motd }).await;
+        }
+
+        // Send channel list
+        let channel_list = state.channel_list().await;
+        let _ = send_msg(&tx, &ServerMessage::ChannelList { channels: channel_list }).await;
+
+        // Auto-join General (channel 0)
+        if let Err(e) = handle_join_channel(&state, user_id, session_id, 0, None, &tx).await {
+            error!("failed to auto-join General: {}", e);
+        }
     }
 
-    // Send channel list
-    let channel_list = state.channel_list().await;
-    let _ = send_msg(&tx, &ServerMessage::ChannelList { channels: channel_list }).await;
-
-    // Auto-join General (channel 0)
-    if let Err(e) = handle_join_channel(&state, user_id, session_id, 0, None, &tx).await {
-        error!("failed to auto-join General: {}", e);
-    }
+    // Tell the client what it is allowed to do
+    send_permissions(&state, session_id, &tx).await;
 
     // --- Message loop with keepalive ---
     let idle_timeout = Duration::from_secs(300); // 5 min idle disconnect
@@ -95,7 +189,7 @@ pub async fn handle_connection(
     keepalive_timer.tick().await;
 
     let mut read_half = read_half;
-    loop {
+    'read: loop {
         let got_data = tokio::select! {
             result = read_half.read_buf(&mut buf) => {
                 match result {
@@ -128,6 +222,11 @@ pub async fn handle_connection(
                 debug!(user_id, "sent keepalive ping");
                 false
             }
+            _ = disconnect.notified() => {
+                info!(user_id, "session closed by server");
+                forced_disconnect = true;
+                break;
+            }
         };
 
         if !got_data {
@@ -142,27 +241,32 @@ pub async fn handle_connection(
                 tokio::task::yield_now().await;
                 msgs_this_read = 0;
             }
-            match try_decode_frame(&mut buf) {
+            match try_decode_frame(&mut buf, capabilities) {
                 Ok(Some(payload)) => {
                     msgs_this_read += 1;
                     match decode_client_msg(&payload) {
                         Ok(msg) => {
                             // Global per-session rate limiter
-                            let allowed = state
-                                .sessions
-                                .get_mut(&session_id)
-                                .map(|mut s| s.global_rate.try_consume())
-                                .unwrap_or(false);
+                            let allowed = state.try_consume_rate(session_id, Limiter::Tcp);
                             if !allowed {
                                 warn!(user_id, "global rate limit exceeded, dropping message");
                                 continue;
                             }
+                            if idle::is_user_activity(&msg) {
+                                state.record_activity(session_id);
+                            }
+                            if matches!(msg, ClientMessage::Disconnect) {
+                                said_goodbye = true;
+                            }
                             if let Err(e) =
                                 handle_message(msg, &state, user_id, session_id, &tx).await
                             {
                                 error!(user_id, "error handling message: {}", e);
                             }
                         }
+                        Err(e) if e.is_unknown_message() => {
+                            debug!(user_id, "skipping message from a newer client: {}", e);
+                        }
                         Err(e) => {
                             warn!(user_id, "failed to decode client message: {}", e);
                         }
@@ -170,191 +274,431 @@ pub async fn handle_connection(
                 }
                 Ok(None) => break, // need more data
                 Err(e) => {
-                    error!(user_id, "frame decode error: {}", e);
-                    break;
+                    // The stream can't be resynchronised past a bad frame
+                    error!(user_id, "frame decode error, closing connection: {}", e);
+                    break 'read;
                 }
             }
         }
     }
 
     // --- Cleanup ---
-    cleanup_session(&state, user_id, session_id).await;
-    writer_handle.abort();
+    let grace = Duration::from_secs(state.settings().resume_grace_secs);
+    if state.is_superseded(session_id, connection) {
+        info!(user_id, session_id, "session resumed on a new connection");
+        writer_handle.abort();
+        return;
+    }
+    if forced_disconnect || said_goodbye || grace.is_zero() {
+        if state.begin_cleanup(session_id, connection) {
+            cleanup_session(&state, user_id, session_id).await;
+        }
+    } else {
+        suspend_session(&state, user_id, session_id, connection, grace);
+    }
+    if forced_disconnect {
+        // Let the writer flush the final message (e.g. Banned) before closing
+        drop(tx);
+        let mut writer_handle = writer_handle;
+        let _ = tokio::time::timeout(Duration::from_secs(1), &mut writer_handle).await;
+        writer_handle.abort();
+    } else {
+        writer_handle.abort();
+    }
 }
 
-/// Perform the authentication handshake.
+/// Pick the virtual server a connection logs in to, from the server named in
+/// `Authenticate` or the TLS server name.
+async fn select_server(
+    servers: &VirtualServers,
+    msg: &ClientMessage,
+    stream: &mut TlsStream<TcpStream>,
+) -> Result<Arc<ServerState>> {
+    let requested = match msg {
+        ClientMessage::Authenticate { virtual_server, .. } => virtual_server.as_deref(),
+        _ => None,
+    };
+    let server_name = stream.get_ref().1.server_name();
+    if let Some(state) = servers.select(requested, server_name) {
+        return Ok(state.clone());
+    }
+    let err_msg = ServerMessage::AuthError {
+        reason: "no such virtual server on this host".into(),
+        code: AuthErrorCode::UnknownServer,
+    };
+    stream.write_all(&encode_server_msg(&err_msg)?).await?;
+    anyhow::bail!("unknown virtual server requested");
+}
+
+/// Perform the authentication handshake, starting from the client's first
+/// message.
+#[allow(clippy::too_many_arguments)]
 async fn authenticate(
     stream: &mut TlsStream<TcpStream>,
     buf: &mut BytesMut,
     state: &ServerState,
+    msg: ClientMessage,
     peer_addr: &str,
     tcp_peer_ip: std::net::IpAddr,
-) -> Result<(UserId, SessionId)> {
-    // Read until we get a complete message
-    loop {
-        let n = stream.read_buf(buf).await?;
-        if n == 0 {
-            anyhow::bail!("client disconnected during authentication");
-        }
+    claimed_username: &mut Option<String>,
+) -> Result<Login> {
+    match msg {
+        ClientMessage::Authenticate {
+            username,
+            protocol_version,
+            app_version,
+            identity_key,
+            prekey_bundle,
+            virtual_server: _,
+            resume_token,
+            min_protocol_version,
+            capabilities,
+        } => {
+            *claimed_username = Some(username.chars().take(MAX_AUDIT_NAME_LEN).collect());
+
+            let Some(protocol_version) = negotiate_version(min_protocol_version, protocol_version) else {
+                state.metrics.auth_failed(AuthFailure::VersionMismatch);
+                let err_msg = ServerMessage::AuthError {
+                    reason: format!(
+                        "protocol version mismatch: client speaks {}-{}, server speaks {}-{}",
+                        min_protocol_version, protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+                    ),
+                    code: AuthErrorCode::VersionMismatch,
+                };
+                let data = encode_server_msg(&err_msg)?;
+                stream.write_all(&data).await?;
+                anyhow::bail!("protocol version mismatch");
+            };
+            let negotiated = Negotiated {
+                protocol_version,
+                capabilities: capabilities.intersection(Capabilities::ALL),
+            };
 
-        if let Some(payload) = try_decode_frame(buf)? {
-            let msg = decode_client_msg(&payload)?;
-
-            match msg {
-                ClientMessage::Authenticate {
-                    username,
-                    protocol_version,
-                    app_version,
-                    identity_key,
-                    prekey_bundle,
-                } => {
-                    if protocol_version != PROTOCOL_VERSION {
-                        let err_msg = ServerMessage::AuthError {
-                            reason: format!(
-                                "protocol version mismatch: client={}, server={}",
-                                protocol_version, PROTOCOL_VERSION
-                            ),
-                        };
-                        let data = encode_server_msg(&err_msg)?;
-                        stream.write_all(&data).await?;
-                        anyhow::bail!("protocol version mismatch");
-                    }
+            let username = username.trim().to_string();
+            if username.is_empty() || username.len() > 32 {
+                state.metrics.auth_failed(AuthFailure::InvalidUsername);
+                let err_msg = ServerMessage::AuthError {
+                    reason: "username must be 1-32 characters".into(),
+                    code: AuthErrorCode::InvalidUsername,
+                };
+                let data = encode_server_msg(&err_msg)?;
+                stream.write_all(&data).await?;
+                anyhow::bail!("invalid username");
+            }
 
-                    if app_version != APP_VERSION {
-                        let err_msg = ServerMessage::AuthError {
-                            reason: format!(
-                                "version mismatch: client={}, server={}",
-                                app_version, APP_VERSION
-                            ),
-                        };
-                        let data = encode_server_msg(&err_msg)?;
-                        stream.write_all(&data).await?;
-                        anyhow::bail!("app version mismatch");
-                    }
+            if username.chars().any(|c| c.is_control()) {
+                state.metrics.auth_failed(AuthFailure::InvalidUsername);
+                let err_msg = ServerMessage::AuthError {
+                    reason: "username contains invalid characters".into(),
+                    code: AuthErrorCode::InvalidUsername,
+                };
+                let data = encode_server_msg(&err_msg)?;
+                stream.write_all(&data).await?;
+                anyhow::bail!("invalid username characters");
+            }
 
-                    let username = username.trim().to_string();
-                    if username.is_empty() || username.len() > 32 {
-                        let err_msg = ServerMessage::AuthError {
-                            reason: "username must be 1-32 characters".into(),
-                        };
-                        let data = encode_server_msg(&err_msg)?;
-                        stream.write_all(&data).await?;
-                        anyhow::bail!("invalid username");
-                    }
+            // An unknown or expired token falls through to a normal login
+            if let Some(token) = resume_token {
+                if let Some(login) =
+                    resume(stream, state, &username, &token, identity_key.as_deref(), negotiated, peer_addr, tcp_peer_ip)
+                        .await?
+                {
+                    return Ok(login);
+                }
+            }
 
-                    if username.chars().any(|c| c.is_control()) {
-                        let err_msg = ServerMessage::AuthError {
-                            reason: "username contains invalid characters".into(),
-                        };
-                        let data = encode_server_msg(&err_msg)?;
-                        stream.write_all(&data).await?;
-                        anyhow::bail!("invalid username characters");
-                    }
+            if state.user_count() >= state.max_users as usize {
+                state.metrics.auth_failed(AuthFailure::ServerFull);
+                let err_msg = ServerMessage::AuthError {
+                    reason: "server is full".into(),
+                    code: AuthErrorCode::ServerFull,
+                };
+                let data = encode_server_msg(&err_msg)?;
+                stream.write_all(&data).await?;
+                anyhow::bail!("server full");
+            }
 
-                    if state.user_count() >= state.max_users as usize {
-                        let err_msg = ServerMessage::AuthError {
-                            reason: "server is full".into(),
-                        };
-                        let data = encode_server_msg(&err_msg)?;
-                        stream.write_all(&data).await?;
-                        anyhow::bail!("server full");
-                    }
+            // A client presenting an identity key must prove it holds the
+            // private half before that key can unlock a registered name.
+            let identity_verified = if let Some(ref key) = identity_key {
+                let nonce: [u8; AUTH_CHALLENGE_LEN] = rand::thread_rng().gen();
+                let challenge = ServerMessage::AuthChallenge {
+                    nonce: nonce.to_vec(),
+                };
+                let data = encode_server_msg(&challenge)?;
+                stream.write_all(&data).await?;
+
+                let signature = match read_auth_msg(stream, buf).await? {
+                    ClientMessage::AuthChallengeResponse { signature } => signature,
+                    _ => anyhow::bail!(
+                        "expected AuthChallengeResponse, got unexpected message type"
+                    ),
+                };
+                let payload = auth_challenge_payload(&nonce, &username);
+                if !verify_identity_signature(key, &payload, &signature) {
+                    state.metrics.auth_failed(AuthFailure::BadSignature);
+                    let err_msg = ServerMessage::AuthError {
+                        reason: "identity key verification failed".into(),
+                        code: AuthErrorCode::BadSignature,
+                    };
+                    let data = encode_server_msg(&err_msg)?;
+                    stream.write_all(&data).await?;
+                    anyhow::bail!("invalid identity challenge signature");
+                }
+                true
+            } else {
+                false
+            };
 
-                    let user_id = state.next_user_id();
-                    let session_id = state.next_session_id();
-
-                    // Atomic username reservation — prevents race between two
-                    // simultaneous registrations with the same name
-                    let username_lower = username.to_lowercase();
-                    match state.username_to_session.entry(username_lower) {
-                        dashmap::mapref::entry::Entry::Occupied(_) => {
-                            let err_msg = ServerMessage::AuthError {
-                                reason: "username already taken".into(),
-                            };
-                            let data = encode_server_msg(&err_msg)?;
-                            stream.write_all(&data).await?;
-                            anyhow::bail!("username taken");
-                        }
-                        dashmap::mapref::entry::Entry::Vacant(entry) => {
-                            entry.insert(session_id);
-                        }
-                    }
-                    let udp_token: u64 = rand::thread_rng().gen();
-
-                    // Extract E2E encryption fields from the pre-key bundle
-                    let (prekeys, signed_prekey_id, signed_prekey, signed_prekey_signature, registration_id, device_id) =
-                        if let Some(ref bundle) = prekey_bundle {
-                            (
-                                bundle.prekeys.clone(),
-                                Some(bundle.signed_prekey_id),
-                                Some(bundle.signed_prekey.clone()),
-                                Some(bundle.signed_prekey_signature.clone()),
-                                bundle.registration_id,
-                                bundle.device_id,
-                            )
-                        } else {
-                            (Vec::new(), None, None, None, 0, 1)
-                        };
-
-                    // Create a placeholder sender (will be replaced after split)
-                    let (placeholder_tx, _) = mpsc::channel(1);
-
-                    let session = crate::state::UserSession {
-                        user_id,
-                        session_id,
-                        username: username.clone(),
-                        channel_id: 0,
-                        is_muted: false,
-                        is_deafened: false,
-                        tcp_tx: placeholder_tx,
-                        udp_addr: None,
-                        udp_token,
-                        tcp_peer_ip,
-                        udp_voice_rate: crate::state::RateLimiter::new(55.0, 55.0),
-                        udp_video_rate: crate::state::RateLimiter::new(120.0, 120.0),
-                        global_rate: crate::state::RateLimiter::new(50.0, 50.0),
-                        password_attempt_rate: crate::state::RateLimiter::new(3.0, 1.0),
-                        chat_rate: crate::state::RateLimiter::new(5.0, 5.0),
-                        create_channel_rate: crate::state::RateLimiter::new(1.0, 0.2),
-                        prekey_rate: crate::state::RateLimiter::new(1.0, 0.2),
-                        is_screen_sharing: false,
-                        watching_screenshare: None,
-                        identity_key,
-                        prekeys,
-                        signed_prekey_id,
-                        signed_prekey,
-                        signed_prekey_signature,
-                        registration_id,
-                        device_id,
+            match state.accounts.identity_key_for(&username) {
+                Some(ref registered_key)
+                    if !identity_verified
+                        || identity_key.as_ref() != Some(registered_key) =>
+                {
+                    state.metrics.auth_failed(AuthFailure::IdentityMismatch);
+                    let err_msg = ServerMessage::AuthError {
+                        reason: "username is registered to another identity".into(),
+                        code: AuthErrorCode::IdentityMismatch,
+                    };
+                    let data = encode_server_msg(&err_msg)?;
+                    stream.write_all(&data).await?;
+                    anyhow::bail!("registered username, identity mismatch");
+                }
+                None if !state.settings().allow_guests => {
+                    state.metrics.auth_failed(AuthFailure::GuestsDisabled);
+                    let err_msg = ServerMessage::AuthError {
+                        reason: "this server only admits registered accounts".into(),
+                        code: AuthErrorCode::GuestsDisabled,
                     };
+                    let data = encode_server_msg(&err_msg)?;
+                    stream.write_all(&data).await?;
+                    anyhow::bail!("guest login disabled");
+                }
+                _ => {}
+            }
 
-                    state.sessions.insert(session_id, session);
-                    state.user_to_session.insert(user_id, session_id);
+            if let Some(ban) = state.bans.find(&username, identity_key.as_deref(), tcp_peer_ip) {
+                state.metrics.auth_failed(AuthFailure::Banned);
+                let err_msg = ServerMessage::AuthError {
+                    reason: ban.message(now_secs()),
+                    code: AuthErrorCode::Banned,
+                };
+                let data = encode_server_msg(&err_msg)?;
+                stream.write_all(&data).await?;
+                anyhow::bail!("banned (ban {})", ban.id);
+            }
 
-                    let auth_msg = ServerMessage::Authenticated {
-                        user_id,
-                        session_id,
-                        udp_port: state.udp_port,
-                        udp_token,
+            // Reaching here with a registered name means the key matched
+            let group = state
+                .accounts
+                .group_for(&username)
+                .unwrap_or(ServerGroup::Guest);
+
+            let user_id = state.next_user_id();
+            let session_id = state.next_session_id();
+
+            // Atomic username reservation — prevents race between two
+            // simultaneous registrations with the same name
+            let username_lower = username.to_lowercase();
+            match state.username_to_session.entry(username_lower) {
+                dashmap::mapref::entry::Entry::Occupied(_) => {
+                    state.metrics.auth_failed(AuthFailure::UsernameTaken);
+                    let err_msg = ServerMessage::AuthError {
+                        reason: "username already taken".into(),
+                        code: AuthErrorCode::UsernameTaken,
                     };
-                    let data = encode_server_msg(&auth_msg)?;
+                    let data = encode_server_msg(&err_msg)?;
                     stream.write_all(&data).await?;
+                    anyhow::bail!("username taken");
+                }
+                dashmap::mapref::entry::Entry::Vacant(entry) => {
+                    entry.insert(session_id);
+                }
+            }
+            let udp_token: u64 = rand::thread_rng().gen();
+            let resume_token: [u8; RESUME_TOKEN_LEN] = rand::thread_rng().gen();
+
+            // Extract E2E encryption fields from the pre-key bundle
+            let (prekeys, signed_prekey_id, signed_prekey, signed_prekey_signature, registration_id, device_id) =
+                if let Some(ref bundle) = prekey_bundle {
+                    (
+                        bundle.prekeys.clone(),
+                        Some(bundle.signed_prekey_id),
+                        Some(bundle.signed_prekey.clone()),
+                        Some(bundle.signed_prekey_signature.clone()),
+                        bundle.registration_id,
+                        bundle.device_id,
+                    )
+                } else {
+                    (Vec::new(), None, None, None, 0, 1)
+                };
 
-                    info!(
-                        peer = %peer_addr,
-                        username = %username,
-                        user_id,
-                        session_id,
-                        "authenticated"
-                    );
+            // Create a placeholder sender (will be replaced after split)
+            let (placeholder_tx, _) = mpsc::channel(1);
+            let limits = state.settings().rate_limits.clone();
 
-                    return Ok((user_id, session_id));
-                }
-                _ => {
-                    anyhow::bail!("expected Authenticate message, got unexpected message type");
+            let session = crate::state::UserSession {
+                user_id,
+                session_id,
+                username: username.clone(),
+                channel_id: 0,
+                is_muted: false,
+                is_deafened: false,
+                group,
+                permissions: Permissions::default(),
+                tcp_tx: placeholder_tx,
+                disconnect: Arc::new(tokio::sync::Notify::new()),
+                resume_token,
+                state: SessionState::Live,
+                connection: 0,
+                capabilities: negotiated.capabilities,
+                udp_addr: None,
+                udp_token,
+                tcp_peer_ip,
+                udp_voice_rate: limits.udp_voice.limiter(),
+                udp_video_rate: limits.udp_video.limiter(),
+                global_rate: limits.tcp.limiter(),
+                password_attempt_rate: limits.password_attempt.limiter(),
+                chat_rate: limits.chat.limiter(),
+                create_channel_rate: limits.create_channel.limiter(),
+                prekey_rate: limits.prekey.limiter(),
+                last_rate_limit_notice: Default::default(),
+                is_screen_sharing: false,
+                watching_screenshare: None,
+                whisper: None,
+                is_server_muted: false,
+                is_priority_speaker: false,
+                last_voice: Instant::now(),
+                last_activity: Instant::now(),
+                idle: None,
+                identity_key,
+                identity_verified,
+                prekeys,
+                signed_prekey_id,
+                signed_prekey,
+                signed_prekey_signature,
+                registration_id,
+                device_id,
+            };
+
+            state.sessions.insert(session_id, session);
+            state.user_to_session.insert(user_id, session_id);
+
+            let auth_msg = ServerMessage::Authenticated {
+                user_id,
+                session_id,
+                udp_port: state.udp_port,
+                udp_token,
+                resume_token: resume_token.to_vec(),
+                resumed: false,
+                protocol_version: negotiated.protocol_version,
+                capabilities: negotiated.capabilities,
+            };
+            let data = encode_server_msg(&auth_msg)?;
+            stream.write_all(&data).await?;
+
+            info!(
+                peer = %peer_addr,
+                username = %username,
+                user_id,
+                session_id,
+                app_version = %app_version,
+                protocol_version = negotiated.protocol_version,
+                capabilities = ?negotiated.capabilities.names(),
+                "authenticated"
+            );
+
+            Ok(Login {
+                user_id,
+                session_id,
+                connection: 0,
+                resumed: false,
+            })
+        }
+        _ => {
+            anyhow::bail!("expected Authenticate message, got unexpected message type");
+        }
+    }
+}
+
+/// Take back a session with its resume token. None if the token does not
+/// match a session of that user.
+#[allow(clippy::too_many_arguments)]
+async fn resume(
+    stream: &mut TlsStream<TcpStream>,
+    state: &ServerState,
+    username: &str,
+    token: &[u8],
+    identity_key: Option<&[u8]>,
+    negotiated: Negotiated,
+    peer_addr: &str,
+    tcp_peer_ip: std::net::IpAddr,
+) -> Result<Option<Login>> {
+    // The session's own bans were enforced when they were made, but the
+    // client may be coming back from a banned address
+    if let Some(ban) = state.bans.find(username, identity_key, tcp_peer_ip) {
+        state.metrics.auth_failed(AuthFailure::Banned);
+        let err_msg = ServerMessage::AuthError {
+            reason: ban.message(now_secs()),
+            code: AuthErrorCode::Banned,
+        };
+        stream.write_all(&encode_server_msg(&err_msg)?).await?;
+        anyhow::bail!("banned (ban {})", ban.id);
+    }
+
+    let Some(resumed) = state.resume_session(username, token, tcp_peer_ip, negotiated.capabilities) else {
+        debug!(peer = %peer_addr, "resume token not accepted, logging in afresh");
+        return Ok(None);
+    };
+    let auth_msg = ServerMessage::Authenticated {
+        user_id: resumed.user_id,
+        session_id: resumed.session_id,
+        udp_port: state.udp_port,
+        udp_token: resumed.udp_token,
+        resume_token: resumed.resume_token.to_vec(),
+        resumed: true,
+        protocol_version: negotiated.protocol_version,
+        capabilities: negotiated.capabilities,
+    };
+    stream.write_all(&encode_server_msg(&auth_msg)?).await?;
+
+    info!(
+        peer = %peer_addr,
+        username = %username,
+        user_id = resumed.user_id,
+        session_id = resumed.session_id,
+        "resumed session"
+    );
+    Ok(Some(Login {
+        user_id: resumed.user_id,
+        session_id: resumed.session_id,
+        connection: resumed.connection,
+        resumed: true,
+    }))
+}
+
+/// Read the next complete client message during the authentication phase.
+/// Nothing is negotiated yet, so each message must fit in a single plain
+/// frame.
+async fn read_auth_msg(
+    stream: &mut TlsStream<TcpStream>,
+    buf: &mut BytesMut,
+) -> Result<ClientMessage> {
+    loop {
+        if let Some(payload) = try_decode_frame(buf, Capabilities::NONE)? {
+            match decode_client_msg(&payload) {
+                Ok(msg) => return Ok(msg),
+                Err(e) if e.is_unknown_message() => {
+                    debug!("skipping message from a newer client during authentication: {}", e);
+                    continue;
                 }
+                Err(e) => return Err(e.into()),
             }
         }
+        let n = stream.read_buf(buf).await?;
+        if n == 0 {
+            anyhow::bail!("client disconnected during authentication");
+        }
     }
 }
 
@@ -366,6 +710,13 @@ async fn handle_message(
     session_id: SessionId,
     tx: &mpsc::Sender<Vec<u8>>,
 ) -> Result<()> {
+    // Optional features only work if they were negotiated at login
+    let required = msg.required_capabilities();
+    if !state.has_capabilities(session_id, required) {
+        warn!(user_id, capabilities = ?required.names(), "message needs a capability that was not negotiated, ignoring");
+        return Ok(());
+    }
+
     match msg {
         ClientMessage::JoinChannel {
             channel_id,
@@ -374,18 +725,30 @@ async fn handle_message(
             handle_join_channel(state, user_id, session_id, channel_id, password.as_deref(), tx)
                 .await?;
         }
-        ClientMessage::CreateChannel { name, password } => {
-            let allowed = state
-                .sessions
-                .get_mut(&session_id)
-                .map(|mut s| s.create_channel_rate.try_consume())
-                .unwrap_or(false);
+        ClientMessage::CreateChannel {
+            name,
+            password,
+            parent_id,
+            permanent,
+        } => {
+            // A rate-limited request is answered with RateLimited
+            let allowed = state.try_consume_rate(session_id, Limiter::CreateChannel);
             if !allowed {
+                return Ok(());
+            }
+            if !state.has_server_permission(user_id, Permission::CreateChannel) {
                 let _ = send_msg(tx, &ServerMessage::ChannelError {
-                    reason: "rate limit exceeded, try again later".into(),
+                    reason: "you do not have permission to create channels".into(),
+                }).await;
+            } else if permanent
+                && !state.has_server_permission(user_id, Permission::CreatePermanentChannel)
+            {
+                let _ = send_msg(tx, &ServerMessage::ChannelError {
+                    reason: "you do not have permission to create permanent channels".into(),
                 }).await;
             } else {
-                handle_create_channel(state, user_id, session_id, name, password, tx).await?;
+                handle_create_channel(state, user_id, session_id, name, password, parent_id, permanent, tx)
+                    .await?;
             }
         }
         ClientMessage::Disconnect => {
@@ -435,6 +798,19 @@ async fn handle_message(
         } => {
             handle_set_channel_password(state, user_id, channel_id, password, tx).await?;
         }
+        ClientMessage::SetChannelTopic {
+            channel_id,
+            description,
+            topic,
+        } => match state.set_channel_topic(channel_id, user_id, description, topic).await {
+            Ok(channel) => {
+                info!(user_id, channel_id, "channel topic changed");
+                broadcast_to_all(state, &ServerMessage::ChannelUpdated { channel }, None).await;
+            }
+            Err(e) => {
+                let _ = send_msg(tx, &ServerMessage::ChannelError { reason: e.to_string() }).await;
+            }
+        },
         ClientMessage::KickUser {
             channel_id,
             user_id: target_id,
@@ -514,11 +890,7 @@ async fn handle_message(
             handle_request_prekey_bundle(state, target_user_id, tx).await?;
         }
         ClientMessage::UploadPreKeys { prekeys } => {
-            let allowed = state
-                .sessions
-                .get_mut(&session_id)
-                .map(|mut s| s.prekey_rate.try_consume())
-                .unwrap_or(false);
+            let allowed = state.try_consume_rate(session_id, Limiter::Prekey);
             if allowed {
                 handle_upload_prekeys(state, session_id, prekeys).await;
             }
@@ -528,32 +900,16 @@ async fn handle_message(
             ciphertext,
             message_type,
         } => {
-            let allowed = state
-                .sessions
-                .get_mut(&session_id)
-                .map(|mut s| s.chat_rate.try_consume())
-                .unwrap_or(false);
-            if !allowed {
-                let _ = send_msg(tx, &ServerMessage::ChannelError {
-                    reason: "sending too fast, slow down".into(),
-                }).await;
-            } else {
+            let allowed = state.try_consume_rate(session_id, Limiter::Chat);
+            if allowed {
                 handle_encrypted_direct_message(
                     state, user_id, session_id, target_user_id, ciphertext, message_type, tx,
                 ).await?;
             }
         }
         ClientMessage::SendEncryptedChannelMessage { ciphertext } => {
-            let allowed = state
-                .sessions
-                .get_mut(&session_id)
-                .map(|mut s| s.chat_rate.try_consume())
-                .unwrap_or(false);
-            if !allowed {
-                let _ = send_msg(tx, &ServerMessage::ChannelError {
-                    reason: "sending too fast, slow down".into(),
-                }).await;
-            } else {
+            let allowed = state.try_consume_rate(session_id, Limiter::Chat);
+            if allowed {
                 handle_encrypted_channel_message(
                     state, user_id, session_id, ciphertext, tx,
                 ).await?;
@@ -578,6 +934,96 @@ async fn handle_message(
                 state, user_id, channel_id, target_user_id, encrypted_media_key,
             ).await?;
         }
+
+        // ── Accounts ─────────────────────────────────────────────────────
+        ClientMessage::RegisterAccount => {
+            handle_register_account(state, user_id, session_id, tx).await?;
+        }
+        ClientMessage::AuthChallengeResponse { .. } => {
+            warn!(user_id, "received AuthChallengeResponse outside authentication, ignoring");
+        }
+
+        // ── Bans ─────────────────────────────────────────────────────────
+        ClientMessage::BanUser {
+            user_id: target_id,
+            reason,
+            duration_secs,
+        } => {
+            handle_ban_user(state, user_id, session_id, target_id, reason, duration_secs, tx).await?;
+        }
+        ClientMessage::UnbanUser { ban_id } => {
+            handle_unban_user(state, user_id, ban_id, tx).await?;
+        }
+        ClientMessage::ListBans => {
+            if state.has_server_permission(user_id, Permission::Ban) {
+                send_ban_list(state, tx).await;
+            } else {
+                let _ = send_msg(tx, &ServerMessage::BanError {
+                    reason: "you do not have permission to view bans".into(),
+                }).await;
+            }
+        }
+
+        // ── Whisper ──────────────────────────────────────────────────────
+        ClientMessage::SetWhisperList { targets } => {
+            let reply = match state.set_whisper_list(session_id, targets).await {
+                Ok(Some((key_id, key_bytes))) => ServerMessage::WhisperKey {
+                    session_id,
+                    key_id,
+                    key_bytes: key_bytes.to_vec(),
+                },
+                Ok(None) => return Ok(()),
+                Err(e) => ServerMessage::WhisperError {
+                    reason: e.to_string(),
+                },
+            };
+            let _ = send_msg(tx, &reply).await;
+        }
+
+        // ── Moderation ───────────────────────────────────────────────────
+        ClientMessage::SetServerMuted {
+            user_id: target_id,
+            muted,
+        } => match state.set_server_muted(user_id, target_id, muted).await {
+            Ok(true) => {
+                info!(user_id, target_id, muted, "server mute changed");
+                let msg = ServerMessage::UserServerMuted { user_id: target_id, muted };
+                broadcast_to_all(state, &msg, None).await;
+            }
+            Ok(false) => {}
+            Err(e) => {
+                let _ = send_msg(tx, &ServerMessage::ModerationError { reason: e.to_string() }).await;
+            }
+        },
+        ClientMessage::SetPrioritySpeaker {
+            user_id: target_id,
+            enabled,
+        } => match state.set_priority_speaker(user_id, target_id, enabled).await {
+            Ok(true) => {
+                info!(user_id, target_id, enabled, "priority speaker changed");
+                let msg = ServerMessage::UserPrioritySpeaker { user_id: target_id, enabled };
+                broadcast_to_all(state, &msg, None).await;
+            }
+            Ok(false) => {}
+            Err(e) => {
+                let _ = send_msg(tx, &ServerMessage::ModerationError { reason: e.to_string() }).await;
+            }
+        },
+        ClientMessage::MoveUser {
+            user_id: target_id,
+            channel_id,
+        } => {
+            let result = match state.check_move(user_id, target_id, channel_id).await {
+                Ok(()) => move_user(state, target_id, channel_id).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => info!(user_id, target_id, channel_id, "user moved"),
+                Err(e) => {
+                    let _ = send_msg(tx, &ServerMessage::ModerationError { reason: e.to_string() }).await;
+                }
+            }
+        }
     }
     Ok(())
 }
@@ -593,37 +1039,45 @@ async fn handle_join_channel(
 ) -> Result<()> {
     // Rate-limit password attempts to prevent brute force
     if password.is_some() {
-        let allowed = state
-            .sessions
-            .get_mut(&session_id)
-            .map(|mut s| s.password_attempt_rate.try_consume())
-            .unwrap_or(false);
+        let allowed = state.try_consume_rate(session_id, Limiter::PasswordAttempt);
         if !allowed {
+            return Ok(());
+        }
+    }
+
+    // Validate the join BEFORE leaving the current channel.
+    // This way, if the password is wrong or the channel is full,
+    // the user stays where they are instead of being dumped into General.
+    let pass = match state.validate_join(channel_id, password, user_id).await {
+        Ok(pass) => pass,
+        Err(e) => {
             let _ = send_msg(
                 tx,
                 &ServerMessage::ChannelError {
-                    reason: "too many password attempts, slow down".into(),
+                    reason: e.to_string(),
                 },
             )
             .await;
             return Ok(());
         }
-    }
+    };
 
-    // Validate the join BEFORE leaving the current channel.
-    // This way, if the password is wrong or the channel is full,
-    // the user stays where they are instead of being dumped into General.
-    if let Err(e) = state.validate_join(channel_id, password, user_id).await {
-        let _ = send_msg(
-            tx,
-            &ServerMessage::ChannelError {
-                reason: e.to_string(),
-            },
-        )
-        .await;
-        return Ok(());
-    }
+    switch_channel(state, user_id, session_id, channel_id, &pass, false, tx).await
+}
 
+/// Move a session from its current channel into `channel_id` and notify
+/// everyone. With `force`, password, capacity and join permission are not
+/// checked (server-initiated moves); otherwise `pass` is the result of
+/// validating the join.
+async fn switch_channel(
+    state: &Arc<ServerState>,
+    user_id: UserId,
+    session_id: SessionId,
+    channel_id: ChannelId,
+    pass: &JoinPass,
+    force: bool,
+    tx: &mpsc::Sender<Vec<u8>>,
+) -> Result<()> {
     // Capture the old channel BEFORE leaving so we can clean up screenshare state
     let old_channel_id = state
         .sessions
@@ -692,10 +1146,12 @@ async fn handle_join_channel(
     }
 
     // Join the new channel (should succeed since we validated above)
-    if let Err(e) = state
-        .join_channel(user_id, session_id, channel_id, password)
-        .await
-    {
+    let joined = if force {
+        state.force_join_channel(user_id, session_id, channel_id).await
+    } else {
+        state.join_validated(user_id, session_id, channel_id, pass).await
+    };
+    if let Err(e) = joined {
         // Shouldn't happen, but handle gracefully
         warn!(user_id, "join failed after validation: {}", e);
         let _ = state.join_channel(user_id, session_id, 0, None).await;
@@ -723,7 +1179,12 @@ async fn handle_join_channel(
     .await;
 
     // Send the channel's media encryption key (for voice/video AES-256-GCM)
-    if let Some((key_id, key_bytes)) = state.get_channel_media_key(channel_id).await {
+    let key = if state.has_capabilities(session_id, Capabilities::ENCRYPTED_MEDIA) {
+        state.get_channel_media_key(channel_id).await
+    } else {
+        None
+    };
+    if let Some((key_id, key_bytes)) = key {
         let _ = send_msg(
             tx,
             &ServerMessage::ChannelMediaKey {
@@ -736,25 +1197,8 @@ async fn handle_join_channel(
     }
 
     // Build user info for the join notification
-    let user_info = UserInfo {
-        user_id,
-        username: state
-            .sessions
-            .get(&session_id)
-            .map(|s| s.username.clone())
-            .unwrap_or_default(),
-        channel_id,
-        is_muted: state
-            .sessions
-            .get(&session_id)
-            .map(|s| s.is_muted)
-            .unwrap_or(false),
-        is_deafened: state
-            .sessions
-            .get(&session_id)
-            .map(|s| s.is_deafened)
-            .unwrap_or(false),
-        is_screen_sharing: false,
+    let Some(user_info) = state.sessions.get(&session_id).map(|s| s.user_info()) else {
+        return Ok(());
     };
 
     let join_msg = ServerMessage::UserJoined { user: user_info };
@@ -764,23 +1208,26 @@ async fn handle_join_channel(
 }
 
 /// Handle a create channel request.
+#[allow(clippy::too_many_arguments)]
 async fn handle_create_channel(
     state: &Arc<ServerState>,
     user_id: UserId,
     session_id: SessionId,
     name: String,
     password: Option<String>,
+    parent_id: Option<ChannelId>,
+    permanent: bool,
     tx: &mpsc::Sender<Vec<u8>>,
 ) -> Result<()> {
     // Validate and sanitize name
     let name = name.trim().to_string();
-    if name.is_empty() || name.len() > state.settings.max_channel_name_len {
+    if name.is_empty() || name.len() > state.settings().max_channel_name_len {
         let _ = send_msg(
             tx,
             &ServerMessage::ChannelError {
                 reason: format!(
                     "channel name must be 1-{} characters",
-                    state.settings.max_channel_name_len
+                    state.settings().max_channel_name_len
                 ),
             },
         )
@@ -802,13 +1249,32 @@ async fn handle_create_channel(
     // Store password for the join call (create_channel takes ownership)
     let join_password = password.clone();
 
-    match state.create_channel(name, password, user_id).await {
+    let target = name.clone();
+    let created = if permanent {
+        state.create_permanent_channel(name, password, user_id, parent_id).await
+    } else {
+        state.create_channel(name, password, user_id, parent_id).await
+    };
+    let target = match &created {
+        Ok(info) => format!("{} ({})", info.name, info.channel_id),
+        Err(_) => target,
+    };
+    state.audit.record(AuditEntry::new(
+        AuditAction::CreateChannel,
+        audit_actor(state, user_id),
+        Some(target),
+        outcome(&created),
+    ));
+    match created {
         Ok(info) => {
             let channel_id = info.channel_id;
             // Broadcast ChannelCreated to all users
             let msg = ServerMessage::ChannelCreated { channel: info };
             broadcast_to_all(state, &msg, None).await;
 
+            // The creator gains management rights in the new channel
+            send_permissions(state, session_id, tx).await;
+
             // Auto-join the creator into the new channel
             handle_join_channel(
                 state,
@@ -834,7 +1300,7 @@ async fn handle_create_channel(
     Ok(())
 }
 
-/// Handle a password change request from the channel creator.
+/// Handle a password change request (requires Edit in the channel).
 async fn handle_set_channel_password(
     state: &Arc<ServerState>,
     user_id: UserId,
@@ -842,12 +1308,20 @@ async fn handle_set_channel_password(
     password: Option<String>,
     tx: &mpsc::Sender<Vec<u8>>,
 ) -> Result<()> {
-    match state.set_channel_password(channel_id, user_id, password).await {
-        Ok(updated_info) => {
-            let msg = ServerMessage::ChannelUpdated {
-                channel: updated_info,
-            };
-            broadcast_to_all(state, &msg, None).await;
+    let result = state.set_channel_password(channel_id, user_id, password).await;
+    state.audit.record(AuditEntry::new(
+        AuditAction::SetChannelPassword,
+        audit_actor(state, user_id),
+        Some(audit_channel(state, channel_id).await),
+        outcome(&result),
+    ));
+    match result {
+        Ok(updated) => {
+            // The channel itself, plus sub-channels inheriting its password
+            for channel in updated {
+                let msg = ServerMessage::ChannelUpdated { channel };
+                broadcast_to_all(state, &msg, None).await;
+            }
         }
         Err(e) => {
             let _ = send_msg(
@@ -862,7 +1336,7 @@ async fn handle_set_channel_password(
     Ok(())
 }
 
-/// Handle a kick request from the channel creator.
+/// Handle a kick request (requires Kick in the channel).
 async fn handle_kick_user(
     state: &Arc<ServerState>,
     requester_id: UserId,
@@ -871,7 +1345,19 @@ async fn handle_kick_user(
     target_id: UserId,
     tx: &mpsc::Sender<Vec<u8>>,
 ) -> Result<()> {
-    match state.kick_user(channel_id, requester_id, target_id).await {
+    let target = format!(
+        "{} from {}",
+        audit_user(state, target_id),
+        audit_channel(state, channel_id).await
+    );
+    let result = state.kick_user(channel_id, requester_id, target_id).await;
+    state.audit.record(AuditEntry::new(
+        AuditAction::Kick,
+        audit_actor(state, requester_id),
+        Some(target),
+        outcome(&result),
+    ));
+    match result {
         Ok((target_session_id, remaining_count)) => {
             // Notify the kicked user
             if let Some(session) = state.sessions.get(&target_session_id) {
@@ -879,7 +1365,7 @@ async fn handle_kick_user(
                     &session.tcp_tx,
                     &ServerMessage::Kicked {
                         channel_id,
-                        reason: "You were kicked by the channel creator".into(),
+                        reason: "You were kicked from the channel".into(),
                     },
                 )
                 .await;
@@ -908,28 +1394,10 @@ async fn handle_kick_user(
             }
 
             // Broadcast UserJoined (to General) to everyone
-            let user_info = UserInfo {
-                user_id: target_id,
-                username: state
-                    .sessions
-                    .get(&target_session_id)
-                    .map(|s| s.username.clone())
-                    .unwrap_or_default(),
-                channel_id: 0,
-                is_muted: state
-                    .sessions
-                    .get(&target_session_id)
-                    .map(|s| s.is_muted)
-                    .unwrap_or(false),
-                is_deafened: state
-                    .sessions
-                    .get(&target_session_id)
-                    .map(|s| s.is_deafened)
-                    .unwrap_or(false),
-                is_screen_sharing: false,
-            };
-            let join_msg = ServerMessage::UserJoined { user: user_info };
-            broadcast_to_all(state, &join_msg, Some(target_id)).await;
+            if let Some(user_info) = state.sessions.get(&target_session_id).map(|s| s.user_info()) {
+                let join_msg = ServerMessage::UserJoined { user: user_info };
+                broadcast_to_all(state, &join_msg, Some(target_id)).await;
+            }
 
             // Start auto-delete timer if the channel is now empty
             if remaining_count == 0 {
@@ -957,7 +1425,18 @@ async fn handle_send_invite(
     target_user_id: UserId,
     tx: &mpsc::Sender<Vec<u8>>,
 ) -> Result<()> {
-    match state.add_invite(channel_id, requester_id, target_user_id).await {
+    let result = state.add_invite(channel_id, requester_id, target_user_id).await;
+    state.audit.record(AuditEntry::new(
+        AuditAction::SendInvite,
+        audit_actor(state, requester_id),
+        Some(format!(
+            "{} to {}",
+            audit_user(state, target_user_id),
+            audit_channel(state, channel_id).await
+        )),
+        outcome(&result),
+    ));
+    match result {
         Ok((channel_name, invited_by)) => {
             // Send InviteReceived to the target user
             if let Some(target_sid) = state.user_to_session.get(&target_user_id) {
@@ -1073,11 +1552,22 @@ async fn handle_start_screen_share(
     resolution: u16,
     tx: &mpsc::Sender<Vec<u8>>,
 ) -> Result<()> {
-    let channel_id = state
+    let (channel_id, can_share) = state
         .sessions
         .get(&session_id)
-        .map(|s| s.channel_id)
-        .unwrap_or(0);
+        .map(|s| (s.channel_id, Permission::ScreenShare.allowed_by(&s.permissions)))
+        .unwrap_or((0, false));
+
+    if !can_share {
+        let _ = send_msg(
+            tx,
+            &ServerMessage::ScreenShareError {
+                reason: "you do not have permission to share your screen here".into(),
+            },
+        )
+        .await;
+        return Ok(());
+    }
 
     match state
         .start_screen_share(user_id, session_id, channel_id, resolution)
@@ -1567,8 +2057,8 @@ async fn handle_distribute_media_key(
 
     if let Some(target_sid) = state.user_to_session.get(&target_user_id) {
         if let Some(session) = state.sessions.get(&*target_sid) {
-            let _ = send_msg(
-                &session.tcp_tx,
+            let _ = send_to_session(
+                &session,
                 &ServerMessage::MediaKeyReceived {
                     channel_id,
                     from_user_id,
@@ -1580,6 +2070,320 @@ async fn handle_distribute_media_key(
     Ok(())
 }
 
+/// Register the session's username to its verified identity key.
+async fn handle_register_account(
+    state: &Arc<ServerState>,
+    user_id: UserId,
+    session_id: SessionId,
+    tx: &mpsc::Sender<Vec<u8>>,
+) -> Result<()> {
+    let (username, identity_key) = match state.sessions.get(&session_id) {
+        Some(s) if s.identity_verified => (s.username.clone(), s.identity_key.clone()),
+        Some(_) => (String::new(), None),
+        None => return Ok(()),
+    };
+    let Some(identity_key) = identity_key else {
+        let _ = send_msg(tx, &ServerMessage::AccountError {
+            reason: "registration requires a verified identity key".into(),
+        }).await;
+        return Ok(());
+    };
+
+    match state.accounts.register(&username, &identity_key) {
+        Ok(()) => {
+            info!(user_id, username = %username, "account registered");
+            let _ = send_msg(tx, &ServerMessage::AccountRegistered { username }).await;
+            if state.group_of(user_id) == ServerGroup::Guest {
+                state.set_group(session_id, ServerGroup::Member).await;
+                send_permissions(state, session_id, tx).await;
+            }
+        }
+        Err(e) => {
+            warn!(user_id, "account registration failed: {:#}", e);
+            let reason = if state.accounts.identity_key_for(&username).is_some() {
+                "username is already registered"
+            } else {
+                "failed to save account"
+            };
+            let _ = send_msg(tx, &ServerMessage::AccountError {
+                reason: reason.into(),
+            }).await;
+        }
+    }
+    Ok(())
+}
+
+/// Ban a connected user by username, identity key and IP, then disconnect
+/// every session the ban applies to.
+async fn handle_ban_user(
+    state: &Arc<ServerState>,
+    user_id: UserId,
+    session_id: SessionId,
+    target_id: UserId,
+    reason: String,
+    duration_secs: Option<u64>,
+    tx: &mpsc::Sender<Vec<u8>>,
+) -> Result<()> {
+    let error = |reason: &str| ServerMessage::BanError {
+        reason: reason.into(),
+    };
+
+    if !state.has_server_permission(user_id, Permission::Ban) {
+        let _ = send_msg(tx, &error("you do not have permission to ban users")).await;
+        return Ok(());
+    }
+    if target_id == user_id {
+        let _ = send_msg(tx, &error("you cannot ban yourself")).await;
+        return Ok(());
+    }
+
+    let Some((banned_by, requester_ip)) = state
+        .sessions
+        .get(&session_id)
+        .map(|s| (s.username.clone(), s.tcp_peer_ip))
+    else {
+        return Ok(());
+    };
+    if state.group_of(target_id) == ServerGroup::Admin
+        && state.group_of(user_id) != ServerGroup::Admin
+    {
+        let _ = send_msg(tx, &error("you cannot ban an admin")).await;
+        return Ok(());
+    }
+    // Users behind the same NAT as the requester are banned by name and key
+    // only, so the requester doesn't lock themselves out.
+    let Some(ban) = ban_for_user(state, target_id, Some(requester_ip), &reason, banned_by, duration_secs)
+    else {
+        let _ = send_msg(tx, &error("user not found")).await;
+        return Ok(());
+    };
+
+    match state.bans.add(ban) {
+        Ok(ban) => {
+            info!(user_id, target_id, ban_id = ban.id, "user banned");
+            enforce_ban(state, &ban).await;
+            send_ban_list(state, tx).await;
+        }
+        Err(e) => {
+            error!(user_id, "failed to add ban: {:#}", e);
+            let _ = send_msg(tx, &error("failed to save ban")).await;
+        }
+    }
+    Ok(())
+}
+
+/// Remove a ban by ID.
+async fn handle_unban_user(
+    state: &Arc<ServerState>,
+    user_id: UserId,
+    ban_id: u32,
+    tx: &mpsc::Sender<Vec<u8>>,
+) -> Result<()> {
+    if !state.has_server_permission(user_id, Permission::Ban) {
+        let _ = send_msg(tx, &ServerMessage::BanError {
+            reason: "you do not have permission to unban users".into(),
+        }).await;
+        return Ok(());
+    }
+
+    match state.bans.remove(ban_id) {
+        Ok(Some(_)) => {
+            info!(user_id, ban_id, "ban removed");
+            send_ban_list(state, tx).await;
+        }
+        Ok(None) => {
+            let _ = send_msg(tx, &ServerMessage::BanError {
+                reason: "ban not found".into(),
+            }).await;
+        }
+        Err(e) => {
+            error!(user_id, "failed to remove ban: {:#}", e);
+            let _ = send_msg(tx, &ServerMessage::BanError {
+                reason: "failed to save ban list".into(),
+            }).await;
+        }
+    }
+    Ok(())
+}
+
+/// Build a ban covering a connected user's name, verified identity key and
+/// IP. The IP is left out when it equals `exempt_ip`. Returns None if the
+/// user is not connected.
+pub fn ban_for_user(
+    state: &ServerState,
+    target_id: UserId,
+    exempt_ip: Option<std::net::IpAddr>,
+    reason: &str,
+    banned_by: String,
+    duration_secs: Option<u64>,
+) -> Option<Ban> {
+    let sid = *state.user_to_session.get(&target_id)?;
+    let session = state.sessions.get(&sid)?;
+    let ip = session.tcp_peer_ip.to_canonical();
+    let identity_key = if session.identity_verified {
+        session.identity_key.as_deref().map(encode_hex)
+    } else {
+        None
+    };
+    Some(Ban {
+        id: 0,
+        username: Some(session.username.clone()),
+        identity_key,
+        ip: (Some(ip) != exempt_ip.map(|e| e.to_canonical())).then(|| ip.to_string()),
+        reason: reason.trim().chars().take(MAX_BAN_REASON_LEN).collect(),
+        banned_by,
+        created_at: 0,
+        expires_at: duration_secs.map(|d| now_secs().saturating_add(d)),
+    })
+}
+
+/// Notify and disconnect every connected session a ban applies to.
+pub async fn enforce_ban(state: &ServerState, ban: &Ban) {
+    let reason = ban.message(now_secs());
+    for sid in state.sessions_matching_ban(ban) {
+        if let Some(session) = state.sessions.get(&sid) {
+            let _ = send_msg(&session.tcp_tx, &ServerMessage::Banned {
+                reason: reason.clone(),
+            }).await;
+            session.disconnect.notify_one();
+        }
+    }
+}
+
+// ── Server-initiated actions (admin interface) ───────────────────────────
+
+/// Move a connected user into another channel, bypassing password, capacity
+/// and permission checks.
+pub async fn move_user(state: &Arc<ServerState>, target_id: UserId, channel_id: ChannelId) -> Result<()> {
+    let Some((session_id, current, tx)) = state.user_to_session.get(&target_id).and_then(|sid| {
+        state
+            .sessions
+            .get(&*sid)
+            .map(|s| (s.session_id, s.channel_id, s.tcp_tx.clone()))
+    }) else {
+        anyhow::bail!("user {} is not connected", target_id);
+    };
+    if !state.channels.read().await.contains_key(&channel_id) {
+        anyhow::bail!("channel {} does not exist", channel_id);
+    }
+    if current == channel_id {
+        anyhow::bail!("user {} is already in channel {}", target_id, channel_id);
+    }
+
+    let _ = send_msg(&tx, &ServerMessage::MovedToChannel { channel_id }).await;
+    switch_channel(state, target_id, session_id, channel_id, &JoinPass::default(), true, &tx).await
+}
+
+/// Disconnect a user from the server, telling them why.
+pub async fn disconnect_user(state: &ServerState, target_id: UserId, reason: &str) -> Result<()> {
+    let Some(sid) = state.user_to_session.get(&target_id).map(|s| *s) else {
+        anyhow::bail!("user {} is not connected", target_id);
+    };
+    if let Some(session) = state.sessions.get(&sid) {
+        let _ = send_msg(&session.tcp_tx, &ServerMessage::KickedFromServer {
+            reason: reason.into(),
+        }).await;
+        session.disconnect.notify_one();
+    }
+    Ok(())
+}
+
+/// Delete any channel except General, moving everyone inside to General.
+/// `actor` is who asked for it, as recorded in the audit log.
+pub async fn delete_channel(state: &Arc<ServerState>, channel_id: ChannelId, actor: &str) -> Result<()> {
+    let target = audit_channel(state, channel_id).await;
+    let result = remove_channel_and_members(state, channel_id).await;
+    state.audit.record(AuditEntry::new(
+        AuditAction::DeleteChannel,
+        actor,
+        Some(target),
+        outcome(&result),
+    ));
+    result
+}
+
+async fn remove_channel_and_members(state: &Arc<ServerState>, channel_id: ChannelId) -> Result<()> {
+    if channel_id == 0 {
+        anyhow::bail!("cannot delete the General channel");
+    }
+    let members: Vec<UserId> = {
+        let channels = state.channels.read().await;
+        let channel = channels
+            .get(&channel_id)
+            .ok_or_else(|| anyhow::anyhow!("channel {} does not exist", channel_id))?;
+        channel.members.iter().copied().collect()
+    };
+    for uid in members {
+        if let Err(e) = move_user(state, uid, 0).await {
+            warn!(user_id = uid, "failed to move user out of deleted channel: {}", e);
+        }
+    }
+
+    // Anyone who slipped in meanwhile is moved after the channel is gone
+    let removed = state.remove_channel(channel_id).await?;
+    broadcast_to_all(state, &ServerMessage::ChannelDeleted { channel_id }, None).await;
+    for channel in removed.updated {
+        broadcast_to_all(state, &ServerMessage::ChannelUpdated { channel }, None).await;
+    }
+    for uid in removed.members {
+        let _ = move_user(state, uid, 0).await;
+    }
+    Ok(())
+}
+
+/// The acting user as named in audit entries.
+fn audit_actor(state: &ServerState, user_id: UserId) -> String {
+    state.username(user_id).unwrap_or_else(|| format!("user {}", user_id))
+}
+
+/// A user as the target of an audit entry, e.g. `"bob (3)"`.
+fn audit_user(state: &ServerState, user_id: UserId) -> String {
+    format!("{} ({})", audit_actor(state, user_id), user_id)
+}
+
+/// A channel as the target of an audit entry, e.g. `"Music (4)"`.
+async fn audit_channel(state: &ServerState, channel_id: ChannelId) -> String {
+    match state.channel_name(channel_id).await {
+        Some(name) => format!("{} ({})", name, channel_id),
+        None => format!("channel {}", channel_id),
+    }
+}
+
+/// Send the most recent active bans.
+async fn send_ban_list(state: &ServerState, tx: &mpsc::Sender<Vec<u8>>) {
+    let bans = state.bans.list();
+    let skip = bans.len().saturating_sub(MAX_BAN_LIST_LEN);
+    let bans = bans[skip..].iter().map(Ban::to_info).collect();
+    let _ = send_msg(tx, &ServerMessage::BanList { bans }).await;
+}
+
+/// Send the session its current group and effective permissions.
+async fn send_permissions(state: &ServerState, session_id: SessionId, tx: &mpsc::Sender<Vec<u8>>) {
+    if let Some((group, server, channels)) = state.permission_summary(session_id).await {
+        let _ = send_msg(
+            tx,
+            &ServerMessage::PermissionsChanged {
+                group,
+                server,
+                channels,
+            },
+        )
+        .await;
+    }
+}
+
+/// Resend permissions to every connected session (after a reload).
+pub async fn send_permissions_to_all(state: &ServerState) {
+    let sessions: Vec<(SessionId, mpsc::Sender<Vec<u8>>)> = state
+        .sessions
+        .iter()
+        .map(|s| (s.session_id, s.tcp_tx.clone()))
+        .collect();
+    for (session_id, tx) in sessions {
+        send_permissions(state, session_id, &tx).await;
+    }
+}
+
 /// Clean up when a user disconnects.
 async fn cleanup_session(state: &Arc<ServerState>, user_id: UserId, session_id: SessionId) {
     // Clean up screen share state before leaving the channel
@@ -1653,6 +2457,59 @@ async fn cleanup_session(state: &Arc<ServerState>, user_id: UserId, session_id:
     info!(user_id, session_id, "session cleaned up");
 }
 
+/// Keep a session whose connection dropped for the resume grace period, then
+/// clean it up unless it was resumed. A kick or ban in the meantime ends it
+/// straight away. Peers see nothing until then.
+fn suspend_session(
+    state: &Arc<ServerState>,
+    user_id: UserId,
+    session_id: SessionId,
+    connection: u64,
+    grace: Duration,
+) {
+    let Some(disconnect) = state.sessions.get(&session_id).map(|s| s.disconnect.clone()) else {
+        return;
+    };
+    let state_for_task = state.clone();
+    let timer = tokio::spawn(async move {
+        tokio::select! {
+            _ = tokio::time::sleep(grace) => {}
+            _ = disconnect.notified() => {}
+        }
+        if state_for_task.take_suspended(session_id) {
+            cleanup_session(&state_for_task, user_id, session_id).await;
+        }
+    });
+    if state.suspend_session(session_id, connection, timer) {
+        info!(user_id, session_id, grace_secs = grace.as_secs(), "connection lost, session suspended");
+    }
+}
+
+/// Send a resumed session the user list and media key of its channel.
+async fn send_channel_state(state: &ServerState, session_id: SessionId, tx: &mpsc::Sender<Vec<u8>>) {
+    let Some(channel_id) = state.sessions.get(&session_id).map(|s| s.channel_id) else {
+        return;
+    };
+    let users = state.users_in_channel(channel_id).await;
+    let _ = send_msg(tx, &ServerMessage::UserList { channel_id, users }).await;
+    let key = if state.has_capabilities(session_id, Capabilities::ENCRYPTED_MEDIA) {
+        state.get_channel_media_key(channel_id).await
+    } else {
+        None
+    };
+    if let Some((key_id, key_bytes)) = key {
+        let _ = send_msg(
+            tx,
+            &ServerMessage::ChannelMediaKey {
+                channel_id,
+                key_id,
+                key_bytes: key_bytes.to_vec(),
+            },
+        )
+        .await;
+    }
+}
+
 /// Start an auto-delete timer for an empty channel.
 /// Persistent channels (from channels.json) are never auto-deleted.
 async fn start_channel_delete_timer(state: &Arc<ServerState>, channel_id: ChannelId) {
@@ -1667,19 +2524,37 @@ async fn start_channel_delete_timer(state: &Arc<ServerState>, channel_id: Channe
     }
 
     let state_for_task = state.clone();
-    let timeout_secs = state.settings.empty_channel_timeout_secs;
+    let timeout_secs = state.settings().empty_channel_timeout_secs;
 
     let handle = tokio::spawn(async move {
-        tokio::time::sleep(std::time::Duration::from_secs(timeout_secs)).await;
-
-        match state_for_task.delete_channel(channel_id).await {
-            Ok(()) => {
-                info!(channel_id, "auto-deleted empty channel after timeout");
-                let msg = ServerMessage::ChannelDeleted { channel_id };
-                broadcast_to_all(&state_for_task, &msg, None).await;
-            }
-            Err(_) => {
-                // Channel not empty or already deleted — no action needed
+        // A parent is kept while it has sub-channels, so once the last one is
+        // deleted the parent gets its own timeout and the walk continues up
+        let mut channel_id = channel_id;
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(timeout_secs)).await;
+
+            let target = audit_channel(&state_for_task, channel_id).await;
+            match state_for_task.delete_channel(channel_id).await {
+                Ok(parent_id) => {
+                    info!(channel_id, "auto-deleted empty channel after timeout");
+                    state_for_task.audit.record(AuditEntry::new(
+                        AuditAction::DeleteChannel,
+                        "server",
+                        Some(target),
+                        Ok(()),
+                    ));
+                    let msg = ServerMessage::ChannelDeleted { channel_id };
+                    broadcast_to_all(&state_for_task, &msg, None).await;
+                    match parent_id {
+                        Some(parent_id) if parent_id != 0 => channel_id = parent_id,
+                        _ => break,
+                    }
+                }
+                Err(_) => {
+                    // Channel not empty, has sub-channels, is persistent or
+                    // already deleted — no action needed
+                    break;
+                }
             }
         }
     });
@@ -1688,7 +2563,7 @@ async fn start_channel_delete_timer(state: &Arc<ServerState>, channel_id: Channe
 }
 
 /// Broadcast a message to ALL connected users, optionally excluding one.
-async fn broadcast_to_all(
+pub async fn broadcast_to_all(
     state: &ServerState,
     msg: &ServerMessage,
     exclude_user: Option<UserId>,
@@ -1698,7 +2573,7 @@ async fn broadcast_to_all(
         if Some(session.user_id) == exclude_user {
             continue;
         }
-        let _ = send_msg(&session.tcp_tx, msg).await;
+        let _ = send_to_session(session, msg).await;
     }
 }
 
@@ -1717,13 +2592,22 @@ async fn broadcast_to_channel(
             }
             if let Some(sid) = state.user_to_session.get(&uid) {
                 if let Some(session) = state.sessions.get(&*sid) {
-                    let _ = send_msg(&session.tcp_tx, msg).await;
+                    let _ = send_to_session(&session, msg).await;
                 }
             }
         }
     }
 }
 
+/// Send a server message to a session, unless it belongs to a feature the
+/// client did not negotiate.
+async fn send_to_session(session: &UserSession, msg: &ServerMessage) -> Result<()> {
+    if !session.capabilities.contains(msg.required_capabilities()) {
+        return Ok(());
+    }
+    send_msg(&session.tcp_tx, msg).await
+}
+
 /// Send a server message to a client via their TCP sender.
 async fn send_msg(tx: &mpsc::Sender<Vec<u8>>, msg: &ServerMessage) -> Result<()> {
     let data = encode_server_msg(msg)?;
@@ -3,16 +3,23 @@ use std::sync::Arc;
 use tokio::net::UdpSocket;
 use tracing::{debug, error, trace, warn};
 
+use voipc_protocol::codec::encode_server_msg;
+use voipc_protocol::messages::ServerMessage;
+use voipc_protocol::types::Capabilities;
+use voipc_protocol::video::VideoPacketType;
 use voipc_protocol::voice::{VoicePacket, VoicePacketType, VOICE_HEADER_SIZE};
 
+use crate::metrics::Limiter;
+use crate::permissions::Permission;
 use crate::state::ServerState;
+use crate::virtual_server::VirtualServers;
 
 /// Maximum buffer size for incoming UDP packets.
 /// Video fragments can be up to ~1400 bytes, voice up to 512.
 const MAX_UDP_PACKET_SIZE: usize = 1500;
 
 /// Run the UDP voice+video packet receive/forward loop.
-pub async fn run_udp_loop(socket: Arc<UdpSocket>, state: Arc<ServerState>) {
+pub async fn run_udp_loop(socket: Arc<UdpSocket>, servers: Arc<VirtualServers>) {
     let mut buf = vec![0u8; MAX_UDP_PACKET_SIZE];
     loop {
         let (len, src_addr) = match socket.recv_from(&mut buf).await {
@@ -29,16 +36,28 @@ pub async fn run_udp_loop(socket: Arc<UdpSocket>, state: Arc<ServerState>) {
             continue;
         }
 
+        // Every packet type carries the sender's session ID at bytes 1-4,
+        // which identifies the virtual server it belongs to
+        if data.len() < 5 {
+            continue;
+        }
+        let session_id = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
+        let Some(state) = servers.by_session(session_id) else {
+            trace!(src = %src_addr, session_id, "UDP packet for unknown session");
+            continue;
+        };
+
         let packet_type_byte = data[0];
 
         match packet_type_byte {
-            // Voice packets: 0x01-0x05 (includes encrypted voice 0x05)
-            0x01..=0x05 => {
-                handle_voice_packet(data, src_addr, &socket, &state).await;
+            // Voice packets: 0x01-0x06 (includes encrypted voice 0x05 and whisper 0x06;
+            // priority 0x07 is only ever set by the server)
+            0x01..=0x06 => {
+                handle_voice_packet(data, src_addr, &socket, state).await;
             }
             // Video / screen-share audio packets: 0x10-0x15 (includes encrypted 0x13-0x15)
             0x10..=0x15 => {
-                handle_video_packet(data, src_addr, &socket, &state).await;
+                handle_video_packet(data, src_addr, &socket, state).await;
             }
             _ => {
                 debug!(src = %src_addr, "unknown UDP packet type: 0x{:02x}", packet_type_byte);
@@ -69,11 +88,7 @@ async fn handle_voice_packet(
     };
 
     // UDP voice rate limiting
-    let allowed = state
-        .sessions
-        .get_mut(&session_id)
-        .map(|mut s| s.udp_voice_rate.try_consume())
-        .unwrap_or(false);
+    let allowed = state.try_consume_rate(session_id, Limiter::UdpVoice);
     if !allowed {
         trace!(session_id, "UDP voice rate limit exceeded, dropping packet");
         return;
@@ -95,27 +110,79 @@ async fn handle_voice_packet(
         return;
     }
 
+    state.record_voice(session_id);
+
     // Forward voice packet to all other members in the same channel
-    let channel_id = match state.sessions.get(&session_id) {
-        Some(session) => session.channel_id,
+    let (channel_id, can_speak, priority) = match state.sessions.get(&session_id) {
+        Some(session) => (
+            session.channel_id,
+            Permission::Speak.allowed_by(&session.permissions) && !session.is_server_muted,
+            session.is_priority_speaker,
+        ),
         None => {
             warn!(session_id, "voice forward: session not found in state");
             return;
         }
     };
 
+    if !can_speak {
+        trace!(session_id, channel_id, "voice forward: dropping (no speak permission or server-muted)");
+        return;
+    }
+
     // Voice is disabled in the General channel (channel 0)
     if channel_id == 0 {
         debug!(session_id, "voice forward: dropping (General channel)");
         return;
     }
 
+    // Whisper packets go only to the sender's whisper recipients
+    if packet.packet_type == VoicePacketType::EncryptedWhisperVoice {
+        forward_whisper(data, session_id, socket, state).await;
+        return;
+    }
+
     let channels = state.channels.read().await;
     let Some(channel) = channels.get(&channel_id) else {
         warn!(session_id, channel_id, "voice forward: channel not found");
         return;
     };
 
+    // End of transmission also reaches whisper recipients outside the channel
+    if packet.packet_type == VoicePacketType::EndOfTransmission {
+        for sid in state.whisper_recipients(session_id).await {
+            let Some(session) = state.sessions.get(&sid) else {
+                continue;
+            };
+            if channel.members.contains(&session.user_id)
+                || !session.capabilities.contains(Capabilities::WHISPER)
+            {
+                continue;
+            }
+            if let Some(addr) = session.udp_addr {
+                let _ = socket.send_to(data, addr).await;
+            }
+        }
+    }
+
+    // Mark a priority speaker's voice so receivers duck other speakers
+    let marked;
+    let data = if priority && packet.packet_type == VoicePacketType::EncryptedOpusVoice {
+        let mut copy = data.to_vec();
+        copy[0] = VoicePacketType::EncryptedPriorityVoice as u8;
+        marked = copy;
+        &marked[..]
+    } else {
+        data
+    };
+
+    // Members without media keys couldn't decrypt encrypted voice
+    let required = if packet.packet_type.is_encrypted() {
+        Capabilities::ENCRYPTED_MEDIA
+    } else {
+        Capabilities::NONE
+    };
+
     for &member_uid in &channel.members {
         let Some(member_sid) = state.user_to_session.get(&member_uid) else {
             continue;
@@ -127,20 +194,71 @@ async fn handle_voice_packet(
         let Some(member_session) = state.sessions.get(&*member_sid) else {
             continue;
         };
+        if !member_session.capabilities.contains(required) {
+            continue;
+        }
 
         if let Some(member_addr) = member_session.udp_addr {
-            if let Err(e) = socket.send_to(data, member_addr).await {
-                warn!(
+            match socket.send_to(data, member_addr).await {
+                Ok(_) => state.metrics.voice_forwarded(data.len()),
+                Err(e) => warn!(
                     target_user = member_uid,
                     %member_addr,
                     "failed to forward voice packet: {}",
                     e
-                );
+                ),
             }
         }
     }
 }
 
+/// Forward a whisper packet to the sender's whisper recipients, first sending
+/// the sender's whisper key over TCP to any recipient that does not have it.
+async fn forward_whisper(
+    data: &[u8],
+    session_id: u32,
+    socket: &UdpSocket,
+    state: &ServerState,
+) {
+    let mut recipients = state.whisper_recipients(session_id).await;
+    recipients.retain(|&sid| state.has_capabilities(sid, Capabilities::WHISPER));
+    if recipients.is_empty() {
+        trace!(session_id, "whisper forward: no recipients");
+        return;
+    }
+
+    let Some((key_id, key_bytes, missing)) = state.whisper_key_for(session_id, &recipients) else {
+        return;
+    };
+    if !missing.is_empty() {
+        let msg = ServerMessage::WhisperKey {
+            session_id,
+            key_id,
+            key_bytes: key_bytes.to_vec(),
+        };
+        match encode_server_msg(&msg) {
+            Ok(encoded) => {
+                for sid in &missing {
+                    if let Some(session) = state.sessions.get(sid) {
+                        let _ = session.tcp_tx.try_send(encoded.clone());
+                    }
+                }
+            }
+            Err(e) => warn!(session_id, "failed to encode whisper key: {}", e),
+        }
+    }
+
+    for sid in recipients {
+        let Some(addr) = state.sessions.get(&sid).and_then(|s| s.udp_addr) else {
+            continue;
+        };
+        match socket.send_to(data, addr).await {
+            Ok(_) => state.metrics.voice_forwarded(data.len()),
+            Err(e) => warn!(target_session = sid, %addr, "failed to forward whisper packet: {}", e),
+        }
+    }
+}
+
 /// Handle a video packet — forward ONLY to viewers of this sharer (not all channel members).
 async fn handle_video_packet(
     data: &[u8],
@@ -167,11 +285,7 @@ async fn handle_video_packet(
     };
 
     // UDP video rate limiting
-    let allowed = state
-        .sessions
-        .get_mut(&resolved_session_id)
-        .map(|mut s| s.udp_video_rate.try_consume())
-        .unwrap_or(false);
+    let allowed = state.try_consume_rate(resolved_session_id, Limiter::UdpVideo);
     if !allowed {
         trace!(session_id, "UDP video rate limit exceeded, dropping packet");
         return;
@@ -187,15 +301,26 @@ async fn handle_video_packet(
         return;
     }
 
+    // Desktop audio only goes to viewers that can play it
+    let packet_type = data[0];
+    let required = if packet_type == VideoPacketType::ScreenShareAudio as u8
+        || packet_type == VideoPacketType::EncryptedScreenShareAudio as u8
+    {
+        Capabilities::SCREEN_SHARE_AUDIO
+    } else {
+        Capabilities::NONE
+    };
+
     // Get viewer addresses for this sharer (only viewers, not all channel members)
     let viewer_addrs = state
-        .get_screen_share_viewer_addrs(sharer_user_id, channel_id)
+        .get_screen_share_viewer_addrs(sharer_user_id, channel_id, required)
         .await;
 
     // Forward the raw packet to each viewer
     for viewer_addr in viewer_addrs {
-        if let Err(e) = socket.send_to(data, viewer_addr).await {
-            trace!("failed to forward video packet: {}", e);
+        match socket.send_to(data, viewer_addr).await {
+            Ok(_) => state.metrics.video_forwarded(data.len()),
+            Err(e) => trace!("failed to forward video packet: {}", e),
         }
     }
 }
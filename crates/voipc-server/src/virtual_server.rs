@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tracing::info;
+
+use voipc_protocol::types::SessionId;
+
+use crate::accounts::AccountStore;
+use crate::bans::BanStore;
+use crate::channels::load_and_prepare_channels;
+use crate::config::{ServerConfig, VirtualServerConfig, DEFAULT_SERVER_ID};
+use crate::settings::ServerSettings;
+use crate::state::ServerState;
+
+/// One server hosted by this process.
+pub struct VirtualServer {
+    pub id: String,
+    /// TLS server names that select this server, lowercase.
+    hostnames: Vec<String>,
+    pub state: Arc<ServerState>,
+}
+
+/// All servers hosted by this process, sharing the TCP listener and UDP
+/// socket. The first one is the default server.
+pub struct VirtualServers {
+    servers: Vec<VirtualServer>,
+}
+
+impl VirtualServers {
+    pub fn new(default: ServerState) -> Self {
+        Self {
+            servers: vec![VirtualServer {
+                id: DEFAULT_SERVER_ID.into(),
+                hostnames: Vec::new(),
+                state: Arc::new(default),
+            }],
+        }
+    }
+
+    /// Add a virtual server. Its session IDs are drawn from the same counter
+    /// as the others', so UDP packets can be routed by session ID alone.
+    pub fn add(&mut self, config: &VirtualServerConfig, mut state: ServerState) {
+        state.share_session_ids(&self.servers[0].state);
+        self.servers.push(VirtualServer {
+            id: config.id.clone(),
+            hostnames: config.hostnames.iter().map(|h| h.to_lowercase()).collect(),
+            state: Arc::new(state),
+        });
+    }
+
+    pub fn default_state(&self) -> &Arc<ServerState> {
+        &self.servers[0].state
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &VirtualServer> {
+        self.servers.iter()
+    }
+
+    /// A server by its id, matched case-insensitively.
+    pub fn by_id(&self, id: &str) -> Option<&Arc<ServerState>> {
+        self.servers
+            .iter()
+            .find(|vs| vs.id.eq_ignore_ascii_case(id))
+            .map(|vs| &vs.state)
+    }
+
+    /// The server a connection logs in to: the one named in `Authenticate`,
+    /// else the one serving the TLS server name, else the default. None if
+    /// the named server does not exist.
+    pub fn select(&self, requested: Option<&str>, server_name: Option<&str>) -> Option<&Arc<ServerState>> {
+        if let Some(id) = requested.filter(|id| !id.is_empty()) {
+            return self.by_id(id);
+        }
+        let by_name = server_name.and_then(|name| {
+            let name = name.to_lowercase();
+            self.servers.iter().find(|vs| vs.hostnames.contains(&name))
+        });
+        Some(by_name.map_or(self.default_state(), |vs| &vs.state))
+    }
+
+    /// The server a session belongs to.
+    pub fn by_session(&self, session_id: SessionId) -> Option<&Arc<ServerState>> {
+        if let [only] = self.servers.as_slice() {
+            return Some(&only.state);
+        }
+        self.servers
+            .iter()
+            .find(|vs| vs.state.sessions.contains_key(&session_id))
+            .map(|vs| &vs.state)
+    }
+}
+
+/// Load a virtual server from its data directory, which is created if
+/// missing. Files that don't exist yet mean defaults, as for the default
+/// server.
+pub fn load_virtual_server(config: &ServerConfig, vs: &VirtualServerConfig) -> Result<ServerState> {
+    let dir = Path::new(&vs.data_dir);
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create data directory: {}", dir.display()))?;
+
+    let settings_path = dir.join("server_settings.json");
+    let settings = if settings_path.exists() {
+        ServerSettings::load_from_file(&settings_path)
+            .with_context(|| format!("failed to load settings: {}", settings_path.display()))?
+    } else {
+        ServerSettings::default()
+    };
+    let channels_path = dir.join("channels.json");
+    let channels = if channels_path.exists() {
+        load_and_prepare_channels(&channels_path)
+            .with_context(|| format!("failed to load channels: {}", channels_path.display()))?
+    } else {
+        Vec::new()
+    };
+    let accounts_path = dir.join("accounts.json");
+    let accounts = AccountStore::load(&accounts_path)
+        .with_context(|| format!("failed to load accounts: {}", accounts_path.display()))?;
+    let bans_path = dir.join("bans.json");
+    let bans = BanStore::load(&bans_path)
+        .with_context(|| format!("failed to load bans: {}", bans_path.display()))?;
+
+    info!(
+        id = %vs.id,
+        data_dir = %dir.display(),
+        persistent_channels = channels.len(),
+        accounts = accounts.len(),
+        bans = bans.len(),
+        "loaded virtual server"
+    );
+
+    let config = ServerConfig {
+        max_users: vs.max_users.unwrap_or(config.max_users),
+        audit_log: config
+            .audit_log
+            .as_ref()
+            .map(|_| dir.join("audit.jsonl").to_string_lossy().into_owned()),
+        ..config.clone()
+    };
+    Ok(ServerState::new(
+        &config,
+        settings,
+        channels,
+        accounts,
+        bans,
+        Some(channels_path),
+        Some(settings_path),
+    ))
+}
+
+/// Picks the certificate for a TLS handshake by server name (SNI), falling
+/// back to the default server's certificate.
+#[derive(Debug)]
+pub struct SniCertResolver {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    default: Arc<CertifiedKey>,
+}
+
+impl SniCertResolver {
+    pub fn new(default: Arc<CertifiedKey>) -> Self {
+        Self {
+            by_name: HashMap::new(),
+            default,
+        }
+    }
+
+    pub fn add(&mut self, server_name: &str, key: Arc<CertifiedKey>) {
+        self.by_name.insert(server_name.to_lowercase(), key);
+    }
+}
+
+impl ResolvesServerCert for SniCertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let key = client_hello
+            .server_name()
+            .and_then(|name| self.by_name.get(&name.to_lowercase()));
+        Some(key.unwrap_or(&self.default).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vs_config(id: &str, hostname: &str) -> VirtualServerConfig {
+        VirtualServerConfig {
+            id: id.into(),
+            hostnames: vec![hostname.into()],
+            data_dir: String::new(),
+            max_users: None,
+            cert_path: None,
+            key_path: None,
+        }
+    }
+
+    fn make_state() -> ServerState {
+        ServerState::new(
+            &ServerConfig::default(),
+            ServerSettings::default(),
+            Vec::new(),
+            AccountStore::default(),
+            BanStore::default(),
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn selects_by_id_then_server_name() {
+        let mut servers = VirtualServers::new(make_state());
+        servers.add(&vs_config("gaming", "Gaming.example.com"), make_state());
+        let default = servers.default_state().clone();
+        let gaming = servers.by_id("GAMING").unwrap().clone();
+
+        let pick = |id, name| servers.select(id, name).map(Arc::as_ptr);
+        assert_eq!(pick(None, None), Some(Arc::as_ptr(&default)));
+        assert_eq!(pick(None, Some("gaming.example.com")), Some(Arc::as_ptr(&gaming)));
+        assert_eq!(pick(None, Some("other.example.com")), Some(Arc::as_ptr(&default)));
+        assert_eq!(pick(Some("default"), Some("gaming.example.com")), Some(Arc::as_ptr(&default)));
+        assert_eq!(pick(Some(""), Some("gaming.example.com")), Some(Arc::as_ptr(&gaming)));
+        assert_eq!(pick(Some("music"), None), None);
+    }
+
+    #[test]
+    fn session_ids_are_unique_across_servers() {
+        let mut servers = VirtualServers::new(make_state());
+        servers.add(&vs_config("gaming", "gaming.example.com"), make_state());
+        let default = servers.default_state().clone();
+        let gaming = servers.by_id("gaming").unwrap().clone();
+
+        let a = default.next_session_id();
+        let b = gaming.next_session_id();
+        assert_ne!(a, b);
+        assert!(servers.by_session(b).is_none());
+    }
+}